@@ -0,0 +1,314 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::fmt;
+use std::str::FromStr;
+use tokio::sync::Mutex;
+
+use crate::db::entities::{allocation_chain, allocation_lines, append_allocation, last_allocation_hash, AllocationLine, DemandClaim, StockAllocation};
+
+/// Allocations are chained, so appends must not interleave.
+static APPEND_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// How scarce stock is shared among competing demand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// In proportion to the quantity each request asks for.
+    ProRata,
+    /// Critical-care hospitals first, pro rata among them, then pro rata among the rest.
+    CriticalCare,
+    /// In proportion to what each requester dispensed of the product recently; what is left
+    /// once they are served goes pro rata to the rest.
+    HistoricalUsage,
+}
+
+impl Policy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Policy::ProRata => "pro_rata",
+            Policy::CriticalCare => "critical_care",
+            Policy::HistoricalUsage => "historical_usage",
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "pro_rata" => Ok(Policy::ProRata),
+            "critical_care" => Ok(Policy::CriticalCare),
+            "historical_usage" => Ok(Policy::HistoricalUsage),
+            other => Err(format!("Unknown allocation policy '{}'", other)),
+        }
+    }
+}
+
+/// Shares `available` units among claims of `(requested, weight)` in proportion to weight,
+/// never giving a claim more than it asked for. What a capped claim leaves over goes round
+/// again; single units left by rounding go to the largest remainders, earlier claims first.
+fn share(available: i64, claims: &[(i64, f64)]) -> Vec<i64> {
+    let mut given = vec![0_i64; claims.len()];
+    let mut left = available;
+    while left > 0 {
+        let open: Vec<usize> = (0..claims.len())
+            .filter(|&i| given[i] < claims[i].0 && claims[i].1 > 0.0)
+            .collect();
+        if open.is_empty() {
+            break;
+        }
+        let total: f64 = open.iter().map(|&i| claims[i].1).sum();
+        let mut remainders = Vec::with_capacity(open.len());
+        let mut handed_out = 0;
+        for &i in &open {
+            let exact = left as f64 * claims[i].1 / total;
+            let whole = (exact.floor() as i64).min(claims[i].0 - given[i]);
+            given[i] += whole;
+            handed_out += whole;
+            remainders.push((exact - exact.floor(), i));
+        }
+        left -= handed_out;
+        if handed_out == 0 {
+            remainders.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+            for (_, i) in remainders {
+                if left == 0 {
+                    break;
+                }
+                if given[i] < claims[i].0 {
+                    given[i] += 1;
+                    left -= 1;
+                }
+            }
+        }
+    }
+    given
+}
+
+/// Allocates `available` units among `claims` under `policy`. `usage` is each claim's recent
+/// usage, used only by [`Policy::HistoricalUsage`]; when nobody has any, requests are weighed
+/// by the quantity asked for instead.
+pub fn allocate(policy: Policy, available: i64, claims: &[DemandClaim], usage: &[i64]) -> Vec<AllocationLine> {
+    let weights: Vec<f64> = match policy {
+        Policy::HistoricalUsage if usage.iter().any(|u| *u > 0) => usage.iter().map(|u| *u as f64).collect(),
+        _ => claims.iter().map(|c| c.quantity as f64).collect(),
+    };
+    let allocated = match policy {
+        Policy::CriticalCare => {
+            let critical: Vec<usize> = (0..claims.len()).filter(|&i| claims[i].critical_care).collect();
+            let others: Vec<usize> = (0..claims.len()).filter(|&i| !claims[i].critical_care).collect();
+            let first = share(available, &critical.iter().map(|&i| (claims[i].quantity, weights[i])).collect::<Vec<_>>());
+            let left = available - first.iter().sum::<i64>();
+            let second = share(left, &others.iter().map(|&i| (claims[i].quantity, weights[i])).collect::<Vec<_>>());
+            let mut allocated = vec![0; claims.len()];
+            for (&i, given) in critical.iter().zip(first).chain(others.iter().zip(second)) {
+                allocated[i] = given;
+            }
+            allocated
+        }
+        Policy::HistoricalUsage => {
+            let mut allocated = share(available, &claims.iter().zip(&weights).map(|(c, w)| (c.quantity, *w)).collect::<Vec<_>>());
+            let left = available - allocated.iter().sum::<i64>();
+            let unmet: Vec<(i64, f64)> = claims.iter().zip(&allocated).map(|(c, given)| (c.quantity - given, c.quantity as f64)).collect();
+            for (given, extra) in allocated.iter_mut().zip(share(left, &unmet)) {
+                *given += extra;
+            }
+            allocated
+        }
+        Policy::ProRata => share(available, &claims.iter().zip(&weights).map(|(c, w)| (c.quantity, *w)).collect::<Vec<_>>()),
+    };
+
+    claims
+        .iter()
+        .zip(weights)
+        .zip(allocated)
+        .map(|((claim, weight), allocated_quantity)| AllocationLine {
+            demand_id: claim.demand_id,
+            organization: claim.organization.clone(),
+            requested_quantity: claim.quantity,
+            weight,
+            critical_care: claim.critical_care,
+            allocated_quantity,
+        })
+        .collect()
+}
+
+/// Hash over every field of an allocation, its lines and the hash of the one before it.
+pub fn allocation_hash(allocation: &StockAllocation, lines: &[AllocationLine]) -> String {
+    let lines: Vec<String> = lines
+        .iter()
+        .map(|l| {
+            format!(
+                "{}:{}:{}:{}:{}:{}",
+                l.demand_id, l.organization, l.requested_quantity, l.weight, l.critical_care, l.allocated_quantity
+            )
+        })
+        .collect();
+    let fields = [
+        allocation.previous_hash.as_str(),
+        &allocation.organization,
+        &allocation.medicine_name,
+        allocation.strength.as_deref().unwrap_or(""),
+        allocation.region.as_deref().unwrap_or(""),
+        &allocation.policy,
+        &allocation.usage_days.map(|d| d.to_string()).unwrap_or_default(),
+        &allocation.available_quantity.to_string(),
+        &allocation.requested_quantity.to_string(),
+        &allocation.allocated_quantity.to_string(),
+        allocation.note.as_deref().unwrap_or(""),
+        &allocation.created_by,
+        &allocation.created_at,
+        &lines.join(";"),
+    ];
+    hex::encode(Sha256::digest(fields.join("|").as_bytes()))
+}
+
+/// Chains an allocation onto the last one and stores it with its lines. Returns it as stored.
+pub async fn record(pool: &SqlitePool, mut allocation: StockAllocation, lines: &[AllocationLine]) -> Result<StockAllocation, sqlx::Error> {
+    let _guard = APPEND_LOCK.lock().await;
+    let mut tx = pool.begin().await?;
+    allocation.previous_hash = last_allocation_hash(&mut tx).await?.unwrap_or_else(|| "GENESIS".to_string());
+    allocation.hash = allocation_hash(&allocation, lines);
+    allocation.id = append_allocation(&mut tx, &allocation, lines).await?;
+    tx.commit().await?;
+    Ok(allocation)
+}
+
+#[derive(Serialize)]
+pub struct AllocationVerification {
+    pub valid: bool,
+    pub allocations: usize,
+    pub message: String,
+}
+
+/// Walks the allocations from the first, recomputing each hash and link.
+pub async fn verify(pool: &SqlitePool) -> Result<AllocationVerification, sqlx::Error> {
+    let allocations = allocation_chain(pool).await?;
+    let mut expected_previous = "GENESIS".to_string();
+    for allocation in &allocations {
+        let lines = allocation_lines(pool, allocation.id).await?;
+        let problem = if allocation.previous_hash != expected_previous {
+            Some("does not follow the allocation before it")
+        } else if allocation_hash(allocation, &lines) != allocation.hash {
+            Some("was altered: its hash no longer matches its fields and lines")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            return Ok(AllocationVerification {
+                valid: false,
+                allocations: allocations.len(),
+                message: format!("Allocation {} {}", allocation.id, problem),
+            });
+        }
+        expected_previous = allocation.hash.clone();
+    }
+    Ok(AllocationVerification {
+        valid: true,
+        allocations: allocations.len(),
+        message: format!("All {} allocations are intact and in order", allocations.len()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claim(demand_id: i64, quantity: i64, critical_care: bool) -> DemandClaim {
+        DemandClaim {
+            demand_id,
+            organization: format!("hospital-{}", demand_id),
+            organization_name: format!("Hospital {}", demand_id),
+            region: None,
+            critical_care,
+            medicine_name: "Paracetamol".to_string(),
+            strength: None,
+            quantity,
+            needed_by: None,
+            created_at: String::new(),
+        }
+    }
+
+    fn allocated(policy: Policy, available: i64, claims: &[DemandClaim], usage: &[i64]) -> Vec<i64> {
+        allocate(policy, available, claims, usage).iter().map(|l| l.allocated_quantity).collect()
+    }
+
+    #[test]
+    fn share_is_proportional_to_weight() {
+        assert_eq!(share(10, &[(10, 1.0), (10, 1.0)]), [5, 5]);
+        assert_eq!(share(60, &[(100, 1.0), (100, 2.0), (100, 3.0)]), [10, 20, 30]);
+    }
+
+    #[test]
+    fn rounding_remainders_go_to_the_largest_fractions_then_earlier_claims() {
+        // 3.33 each: the spare unit goes to the first claim.
+        assert_eq!(share(10, &[(10, 1.0), (10, 1.0), (10, 1.0)]), [4, 3, 3]);
+        // 3.33 and 6.67: the spare unit goes to the larger fraction.
+        assert_eq!(share(10, &[(100, 1.0), (100, 2.0)]), [3, 7]);
+        // Every unit is handed out even when no claim reaches a whole unit.
+        assert_eq!(share(2, &[(5, 1.0), (5, 1.0), (5, 1.0)]), [1, 1, 0]);
+    }
+
+    #[test]
+    fn capped_claims_pass_their_excess_on() {
+        assert_eq!(share(10, &[(2, 1.0), (100, 1.0)]), [2, 8]);
+        assert_eq!(share(100, &[(3, 1.0), (4, 1.0)]), [3, 4]);
+    }
+
+    #[test]
+    fn zero_supply_or_weight_gets_nothing() {
+        assert_eq!(share(0, &[(10, 1.0), (5, 2.0)]), [0, 0]);
+        assert_eq!(share(5, &[(5, 0.0), (5, 1.0)]), [0, 5]);
+        assert!(share(5, &[]).is_empty());
+        let claims = [claim(1, 10, true), claim(2, 10, false)];
+        for policy in [Policy::ProRata, Policy::CriticalCare, Policy::HistoricalUsage] {
+            assert_eq!(allocated(policy, 0, &claims, &[4, 1]), [0, 0]);
+        }
+    }
+
+    #[test]
+    fn everything_available_is_allocated_and_nobody_gets_more_than_asked() {
+        let claims = [claim(1, 7, false), claim(2, 13, true), claim(3, 1, false), claim(4, 29, false)];
+        let requested: i64 = claims.iter().map(|c| c.quantity).sum();
+        for policy in [Policy::ProRata, Policy::CriticalCare, Policy::HistoricalUsage] {
+            for available in 0..=requested + 5 {
+                let given = allocated(policy, available, &claims, &[0, 2, 9, 1]);
+                assert_eq!(given.iter().sum::<i64>(), available.min(requested), "{} of {}", policy, available);
+                assert!(given.iter().zip(&claims).all(|(g, c)| (0..=c.quantity).contains(g)), "{} of {}", policy, available);
+            }
+        }
+    }
+
+    #[test]
+    fn pro_rata_follows_the_quantities_asked_for() {
+        let claims = [claim(1, 30, false), claim(2, 60, true), claim(3, 90, false)];
+        assert_eq!(allocated(Policy::ProRata, 60, &claims, &[]), [10, 20, 30]);
+    }
+
+    #[test]
+    fn critical_care_is_served_first() {
+        let claims = [claim(1, 50, false), claim(2, 30, true), claim(3, 20, true)];
+        assert_eq!(allocated(Policy::CriticalCare, 40, &claims, &[]), [0, 24, 16]);
+        assert_eq!(allocated(Policy::CriticalCare, 60, &claims, &[]), [10, 30, 20]);
+    }
+
+    #[test]
+    fn historical_usage_weighs_by_past_dispensing() {
+        let claims = [claim(1, 100, false), claim(2, 100, false)];
+        assert_eq!(allocated(Policy::HistoricalUsage, 40, &claims, &[3, 1]), [30, 10]);
+        // Without any usage it falls back to the quantities asked for.
+        let claims = [claim(1, 10, false), claim(2, 30, false)];
+        assert_eq!(allocated(Policy::HistoricalUsage, 20, &claims, &[0, 0]), [5, 15]);
+        // A requester with no usage still gets what the others leave.
+        let claims = [claim(1, 5, false), claim(2, 10, false)];
+        let lines = allocate(Policy::HistoricalUsage, 12, &claims, &[4, 0]);
+        assert_eq!(lines.iter().map(|l| l.allocated_quantity).collect::<Vec<_>>(), [5, 7]);
+        assert_eq!(lines[0].weight, 4.0);
+    }
+}
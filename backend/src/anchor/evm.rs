@@ -0,0 +1,233 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha3::{Digest, Keccak256};
+
+pub const ANCHOR_SIGNATURE: &str = "anchor(bytes32,uint256)";
+pub const ANCHORED_AT_SIGNATURE: &str = "anchoredAt(bytes32)";
+pub const ANCHORED_EVENT: &str = "Anchored(bytes32,uint256)";
+
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    Keccak256::digest(data).into()
+}
+
+fn selector(signature: &str) -> [u8; 4] {
+    let hash = keccak256(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+pub fn from_hex(value: &str) -> Option<Vec<u8>> {
+    hex::decode(value.trim_start_matches("0x")).ok()
+}
+
+/// Parses a JSON-RPC quantity such as `"0x1b4"`.
+pub fn parse_quantity(value: &str) -> Option<u64> {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+/// A ledger Merkle root (64 hex chars of SHA-256) as a `bytes32` word.
+pub fn root_to_bytes32(merkle_root: &str) -> Option<[u8; 32]> {
+    from_hex(merkle_root)?.try_into().ok()
+}
+
+fn uint256(value: u64) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[24..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+/// Topic the contract logs each anchor under.
+pub fn anchored_topic() -> String {
+    to_hex(&keccak256(ANCHORED_EVENT.as_bytes()))
+}
+
+pub fn encode_anchor_call(root: &[u8; 32], ledger_height: u64) -> Vec<u8> {
+    let mut data = selector(ANCHOR_SIGNATURE).to_vec();
+    data.extend_from_slice(root);
+    data.extend_from_slice(&uint256(ledger_height));
+    data
+}
+
+pub fn encode_anchored_at_call(root: &[u8; 32]) -> Vec<u8> {
+    let mut data = selector(ANCHORED_AT_SIGNATURE).to_vec();
+    data.extend_from_slice(root);
+    data
+}
+
+/// Deployment code for the anchor contract, equivalent to:
+///
+/// ```solidity
+/// contract MerkleAnchor {
+///     mapping(bytes32 => uint256) public anchoredAt;
+///     event Anchored(bytes32 indexed root, uint256 ledgerHeight);
+///     function anchor(bytes32 root, uint256 ledgerHeight) external {
+///         require(anchoredAt[root] == 0);
+///         anchoredAt[root] = block.number;
+///         emit Anchored(root, ledgerHeight);
+///     }
+/// }
+/// ```
+///
+/// Hand-assembled so no Solidity toolchain is needed; the mapping lives at slot `root`.
+pub fn anchor_contract_init_code() -> Vec<u8> {
+    const JUMPDEST: u8 = 0x5b;
+    const PUSH1: u8 = 0x60;
+    const PUSH4: u8 = 0x63;
+    const PUSH32: u8 = 0x7f;
+
+    let anchor = selector(ANCHOR_SIGNATURE);
+    let anchored_at = selector(ANCHORED_AT_SIGNATURE);
+    let topic = keccak256(ANCHORED_EVENT.as_bytes());
+
+    // Jump targets are fixed offsets into the runtime code below.
+    const ANCHOR_DEST: u8 = 0x1a;
+    const STORE_DEST: u8 = 0x28;
+    const QUERY_DEST: u8 = 0x59;
+
+    let mut runtime = vec![
+        PUSH1, 0x00, 0x35, PUSH1, 0xe0, 0x1c, // selector = calldataload(0) >> 224
+        0x80, PUSH4, anchor[0], anchor[1], anchor[2], anchor[3], 0x14, PUSH1, ANCHOR_DEST, 0x57,
+        PUSH4, anchored_at[0], anchored_at[1], anchored_at[2], anchored_at[3], 0x14, PUSH1, QUERY_DEST, 0x57,
+        0xfe, // unknown selector: invalid
+        // 0x1a: anchor(root, ledgerHeight)
+        JUMPDEST, PUSH1, 0x04, 0x35, // root
+        0x80, 0x54, 0x15, PUSH1, STORE_DEST, 0x57, // if anchoredAt[root] == 0 goto store
+        PUSH1, 0x00, 0x80, 0xfd, // revert: already anchored
+        // 0x28: store
+        JUMPDEST, 0x43, 0x81, 0x55, // anchoredAt[root] = block.number
+        PUSH1, 0x24, 0x35, PUSH1, 0x00, 0x52, // mem[0] = ledgerHeight
+        PUSH32,
+    ];
+    runtime.extend_from_slice(&topic);
+    runtime.extend_from_slice(&[
+        PUSH1, 0x20, PUSH1, 0x00, 0xa2, // log2(0, 32, topic, root)
+        0x00, // stop
+        // 0x59: anchoredAt(root)
+        JUMPDEST, PUSH1, 0x04, 0x35, 0x54, PUSH1, 0x00, 0x52, PUSH1, 0x20, PUSH1, 0x00, 0xf3,
+    ]);
+    debug_assert_eq!(runtime[ANCHOR_DEST as usize], JUMPDEST);
+    debug_assert_eq!(runtime[STORE_DEST as usize], JUMPDEST);
+    debug_assert_eq!(runtime[QUERY_DEST as usize], JUMPDEST);
+
+    // Constructor: copy the runtime code to memory and return it.
+    let mut init = vec![
+        PUSH1, runtime.len() as u8, 0x80, PUSH1, 0x0c, PUSH1, 0x00, 0x39, // codecopy(0, 12, len)
+        PUSH1, 0x00, 0xf3, // return(0, len)
+        0x00,
+    ];
+    init.extend_from_slice(&runtime);
+    init
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Log {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Receipt {
+    pub block_number: String,
+    pub status: Option<String>,
+    pub to: Option<String>,
+    pub contract_address: Option<String>,
+    pub logs: Vec<Log>,
+}
+
+impl Receipt {
+    pub fn succeeded(&self) -> bool {
+        self.status.as_deref().and_then(parse_quantity) == Some(1)
+    }
+}
+
+/// Minimal Ethereum JSON-RPC client. Transactions are sent with `eth_sendTransaction`,
+/// so the `from` account must be unlocked on the node (as anvil and ganache dev accounts are).
+pub struct EvmRpc<'a> {
+    http: &'a reqwest::Client,
+    url: &'a str,
+}
+
+impl<'a> EvmRpc<'a> {
+    pub fn new(http: &'a reqwest::Client, url: &'a str) -> Self {
+        Self { http, url }
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let response: Value = self
+            .http
+            .post(self.url)
+            .json(&json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }))
+            .send()
+            .await
+            .map_err(|e| format!("{} failed: {}", method, e))?
+            .json()
+            .await
+            .map_err(|e| format!("{} failed: {}", method, e))?;
+
+        if let Some(error) = response.get("error") {
+            return Err(format!("{} failed: {}", method, error));
+        }
+        Ok(response.get("result").cloned().unwrap_or(Value::Null))
+    }
+
+    pub async fn chain_id(&self) -> Result<u64, String> {
+        let result = self.call("eth_chainId", json!([])).await?;
+        result
+            .as_str()
+            .and_then(parse_quantity)
+            .ok_or_else(|| format!("Unexpected eth_chainId result {}", result))
+    }
+
+    pub async fn accounts(&self) -> Result<Vec<String>, String> {
+        let result = self.call("eth_accounts", json!([])).await?;
+        serde_json::from_value(result).map_err(|e| e.to_string())
+    }
+
+    /// Sends a transaction (a contract creation when `to` is `None`) and returns its hash.
+    pub async fn send_transaction(&self, from: &str, to: Option<&str>, data: &[u8]) -> Result<String, String> {
+        let mut tx = json!({ "from": from, "data": to_hex(data) });
+        if let Some(to) = to {
+            tx["to"] = json!(to);
+        }
+        let result = self.call("eth_sendTransaction", json!([tx])).await?;
+        result
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| format!("Unexpected eth_sendTransaction result {}", result))
+    }
+
+    /// The receipt of a mined transaction, or `None` while it is still pending.
+    pub async fn receipt(&self, tx_hash: &str) -> Result<Option<Receipt>, String> {
+        let result = self.call("eth_getTransactionReceipt", json!([tx_hash])).await?;
+        if result.is_null() {
+            return Ok(None);
+        }
+        serde_json::from_value(result).map(Some).map_err(|e| e.to_string())
+    }
+
+    pub async fn eth_call(&self, to: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        let result = self
+            .call("eth_call", json!([{ "to": to, "data": to_hex(data) }, "latest"]))
+            .await?;
+        result
+            .as_str()
+            .and_then(from_hex)
+            .ok_or_else(|| format!("Unexpected eth_call result {}", result))
+    }
+
+    /// Polls for a receipt, for transactions we need before moving on (contract deployment).
+    pub async fn wait_for_receipt(&self, tx_hash: &str, attempts: u32) -> Result<Receipt, String> {
+        for _ in 0..attempts {
+            if let Some(receipt) = self.receipt(tx_hash).await? {
+                return Ok(receipt);
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        }
+        Err(format!("Transaction {} was not mined", tx_hash))
+    }
+}
@@ -0,0 +1,229 @@
+pub mod evm;
+
+use serde::Serialize;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::db::entities::{
+    anchor_contract, confirm_evm_anchor, fail_evm_anchor, find_evm_anchor, find_onchain_batch, insert_evm_anchor,
+    latest_block, latest_evm_anchor, ledger_merkle_root, save_anchor_contract, EvmAnchor,
+};
+use crate::p2p::Node;
+use evm::{
+    anchor_contract_init_code, anchored_topic, encode_anchor_call, encode_anchored_at_call, from_hex,
+    parse_quantity, root_to_bytes32, EvmRpc,
+};
+
+/// Settings for anchoring ledger Merkle roots to an external EVM chain.
+/// Anchoring is off unless `ANCHOR_RPC_URL` is set.
+#[derive(Debug, Clone)]
+pub struct AnchorConfig {
+    pub rpc_url: String,
+    /// Existing anchor contract; when unset one is deployed on first use and remembered per chain id.
+    pub contract: Option<String>,
+    /// Unlocked account to send from; defaults to the node's first `eth_accounts` entry.
+    pub from: Option<String>,
+    pub interval_secs: u64,
+}
+
+impl AnchorConfig {
+    pub fn from_env() -> Option<Self> {
+        let rpc_url = env::var("ANCHOR_RPC_URL").ok().filter(|v| !v.is_empty())?;
+        Some(Self {
+            rpc_url,
+            contract: env::var("ANCHOR_CONTRACT").ok().filter(|v| !v.is_empty()),
+            from: env::var("ANCHOR_FROM").ok().filter(|v| !v.is_empty()),
+            interval_secs: env::var("ANCHOR_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v: &u64| *v > 0)
+                .unwrap_or(60),
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct AnchorVerification {
+    pub valid: bool,
+    pub message: String,
+    pub anchor: Option<EvmAnchor>,
+}
+
+/// Low 8 bytes of a big-endian `uint256` word.
+fn be_i64(bytes: &[u8]) -> i64 {
+    i64::from_be_bytes(bytes.try_into().unwrap_or_default())
+}
+
+async fn sender(rpc: &EvmRpc<'_>, config: &AnchorConfig) -> Result<String, String> {
+    if let Some(from) = &config.from {
+        return Ok(from.clone());
+    }
+    rpc.accounts()
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| "The RPC node has no unlocked accounts; set ANCHOR_FROM".to_string())
+}
+
+/// The anchor contract for this chain: configured, previously deployed, or deployed now.
+async fn resolve_contract(node: &Node, rpc: &EvmRpc<'_>, config: &AnchorConfig, chain_id: i64) -> Result<String, String> {
+    if let Some(contract) = &config.contract {
+        return Ok(contract.clone());
+    }
+    if let Some(contract) = anchor_contract(&node.pool, chain_id).await.map_err(|e| e.to_string())? {
+        return Ok(contract);
+    }
+
+    let from = sender(rpc, config).await?;
+    let tx_hash = rpc.send_transaction(&from, None, &anchor_contract_init_code()).await?;
+    let receipt = rpc.wait_for_receipt(&tx_hash, 60).await?;
+    let contract = match receipt.contract_address {
+        Some(address) if receipt.succeeded() => address,
+        _ => return Err(format!("Deploying the anchor contract failed (tx {})", tx_hash)),
+    };
+
+    save_anchor_contract(&node.pool, chain_id, &contract).await.map_err(|e| e.to_string())?;
+    println!("⚓ Deployed anchor contract {} on chain {}", contract, chain_id);
+    Ok(contract)
+}
+
+/// One anchoring round: settle the pending anchor if there is one, otherwise submit
+/// the current ledger Merkle root if it changed since the last anchor.
+pub async fn anchor_once(node: &Node, config: &AnchorConfig) -> Result<(), String> {
+    let rpc = EvmRpc::new(&node.http, &config.rpc_url);
+    let db = |e: sqlx::Error| e.to_string();
+
+    let last = latest_evm_anchor(&node.pool).await.map_err(db)?;
+    if let Some(pending) = last.as_ref().filter(|a| a.status == "pending") {
+        match rpc.receipt(&pending.tx_hash).await? {
+            None => {}
+            Some(receipt) if receipt.succeeded() => {
+                let block_number = parse_quantity(&receipt.block_number)
+                    .ok_or_else(|| format!("Bad block number in receipt for {}", pending.tx_hash))?;
+                confirm_evm_anchor(&node.pool, pending, block_number as i64).await.map_err(db)?;
+                println!(
+                    "⚓ Anchored root {} (ledger height {}) in EVM block {}",
+                    pending.merkle_root, pending.ledger_height, block_number
+                );
+            }
+            Some(_) => {
+                eprintln!("Anchor transaction {} reverted", pending.tx_hash);
+                fail_evm_anchor(&node.pool, pending.id).await.map_err(db)?;
+            }
+        }
+        return Ok(());
+    }
+
+    let Some(tip) = latest_block(&node.pool).await.map_err(db)? else {
+        return Ok(());
+    };
+    let (merkle_root, count) = ledger_merkle_root(&node.pool, tip.height).await.map_err(db)?;
+    if count == 0 || last.is_some_and(|a| a.merkle_root == merkle_root) {
+        return Ok(());
+    }
+    let root = root_to_bytes32(&merkle_root).ok_or_else(|| format!("Merkle root {} is not 32 bytes", merkle_root))?;
+
+    let chain_id = rpc.chain_id().await? as i64;
+    let contract = resolve_contract(node, &rpc, config, chain_id).await?;
+    let from = sender(&rpc, config).await?;
+    let tx_hash = rpc
+        .send_transaction(&from, Some(&contract), &encode_anchor_call(&root, tip.height as u64))
+        .await?;
+
+    insert_evm_anchor(&node.pool, &merkle_root, tip.height, chain_id, &contract, &tx_hash)
+        .await
+        .map_err(db)
+}
+
+pub fn spawn_anchor_service(node: Arc<Node>) {
+    let Some(config) = node.config.anchor.clone() else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+        loop {
+            interval.tick().await;
+            if let Err(e) = anchor_once(&node, &config).await {
+                eprintln!("Anchoring failed: {}", e);
+            }
+        }
+    });
+}
+
+/// Checks a batch's anchor end to end: the anchored root still matches our ledger,
+/// and the EVM chain holds a successful transaction to the anchor contract logging that root.
+pub async fn verify_anchor(node: &Node, batch_id: &str) -> Result<Option<AnchorVerification>, String> {
+    let db = |e: sqlx::Error| e.to_string();
+    let Some(onchain) = find_onchain_batch(&node.pool, batch_id).await.map_err(db)? else {
+        return Ok(None);
+    };
+
+    let invalid = |message: String, anchor: Option<EvmAnchor>| {
+        Ok(Some(AnchorVerification { valid: false, message, anchor }))
+    };
+
+    let Some(tx_hash) = onchain.anchor_tx_hash else {
+        return invalid("Batch is sealed but not anchored yet".to_string(), None);
+    };
+    let Some(anchor) = find_evm_anchor(&node.pool, &tx_hash).await.map_err(db)? else {
+        return invalid(format!("No record of anchor transaction {}", tx_hash), None);
+    };
+    let Some(config) = &node.config.anchor else {
+        return invalid("Anchoring is not configured on this node (ANCHOR_RPC_URL)".to_string(), Some(anchor));
+    };
+
+    let (merkle_root, _) = ledger_merkle_root(&node.pool, anchor.ledger_height).await.map_err(db)?;
+    if merkle_root != anchor.merkle_root {
+        return invalid(
+            format!("Ledger up to height {} no longer matches the anchored root", anchor.ledger_height),
+            Some(anchor),
+        );
+    }
+
+    let rpc = EvmRpc::new(&node.http, &config.rpc_url);
+    if rpc.chain_id().await? as i64 != anchor.chain_id {
+        return invalid(format!("RPC node is not on chain {}", anchor.chain_id), Some(anchor));
+    }
+
+    let Some(receipt) = rpc.receipt(&anchor.tx_hash).await? else {
+        return invalid(format!("Anchor transaction {} not found on chain", anchor.tx_hash), Some(anchor));
+    };
+    let root_topic = format!("0x{}", anchor.merkle_root);
+    let logged = receipt.logs.iter().any(|log| {
+        log.address.eq_ignore_ascii_case(&anchor.contract)
+            && log.topics.first().is_some_and(|t| t.eq_ignore_ascii_case(&anchored_topic()))
+            && log.topics.get(1).is_some_and(|t| t.eq_ignore_ascii_case(&root_topic))
+            && from_hex(&log.data).and_then(|d| d.get(24..32).map(be_i64)) == Some(anchor.ledger_height)
+    });
+    let block_number = parse_quantity(&receipt.block_number).map(|n| n as i64);
+    if !receipt.succeeded()
+        || !receipt.to.as_deref().is_some_and(|to| to.eq_ignore_ascii_case(&anchor.contract))
+        || !logged
+        || block_number != anchor.evm_block_number
+    {
+        return invalid(
+            format!("Anchor transaction {} does not record root {}", anchor.tx_hash, anchor.merkle_root),
+            Some(anchor),
+        );
+    }
+
+    let root = root_to_bytes32(&anchor.merkle_root).unwrap_or_default();
+    let stored = rpc.eth_call(&anchor.contract, &encode_anchored_at_call(&root)).await?;
+    let stored_block = stored.get(24..32).map(be_i64);
+    if stored_block != anchor.evm_block_number {
+        return invalid("Anchor contract has no record of this root".to_string(), Some(anchor));
+    }
+
+    Ok(Some(AnchorVerification {
+        valid: true,
+        message: format!(
+            "Batch is covered by root {} anchored in EVM block {} (tx {})",
+            anchor.merkle_root,
+            anchor.evm_block_number.unwrap_or_default(),
+            anchor.tx_hash
+        ),
+        anchor: Some(anchor),
+    }))
+}
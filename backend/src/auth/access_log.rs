@@ -0,0 +1,97 @@
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use crate::auth::Auth;
+use crate::db::{access_log_chain, append_access_log, last_access_log_hash};
+use crate::models::AccessLogEntry;
+
+/// Entries are chained, so appends must not interleave.
+static APPEND_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// What a regulator did, before it is chained into the log.
+pub struct Access<'a> {
+    pub user_id: &'a str,
+    pub action: String,
+    pub method: &'a str,
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    pub status: StatusCode,
+    pub ip: Option<String>,
+}
+
+/// Hash over every field of an entry and the hash of the one before it.
+pub fn entry_hash(entry: &AccessLogEntry) -> String {
+    let fields = [
+        entry.previous_hash.as_str(),
+        &entry.user_id,
+        &entry.action,
+        &entry.method,
+        &entry.path,
+        entry.query.as_deref().unwrap_or(""),
+        &entry.status.to_string(),
+        entry.ip.as_deref().unwrap_or(""),
+        &entry.accessed_at,
+    ];
+    hex::encode(Sha256::digest(fields.join("|").as_bytes()))
+}
+
+/// Appends an access to the log.
+pub async fn record(auth: &Auth, access: Access<'_>) -> Result<(), sqlx::Error> {
+    let _guard = APPEND_LOCK.lock().await;
+    let mut tx = auth.pool.begin().await?;
+    let previous_hash = last_access_log_hash(&mut tx).await?.unwrap_or_else(|| "GENESIS".to_string());
+    let mut entry = AccessLogEntry {
+        id: 0,
+        user_id: access.user_id.to_string(),
+        action: access.action,
+        method: access.method.to_string(),
+        path: access.path.to_string(),
+        query: access.query.map(str::to_string),
+        status: i64::from(access.status.as_u16()),
+        ip: access.ip,
+        accessed_at: Utc::now().to_rfc3339(),
+        previous_hash,
+        hash: String::new(),
+    };
+    entry.hash = entry_hash(&entry);
+    append_access_log(&mut tx, &entry).await?;
+    tx.commit().await
+}
+
+#[derive(Serialize)]
+pub struct AccessLogVerification {
+    pub valid: bool,
+    pub entries: usize,
+    pub message: String,
+}
+
+/// Walks the log from the first entry, recomputing each hash and link.
+pub async fn verify(auth: &Auth) -> Result<AccessLogVerification, sqlx::Error> {
+    let entries = access_log_chain(&auth.pool).await?;
+    let mut expected_previous = "GENESIS".to_string();
+    for entry in &entries {
+        let problem = if entry.previous_hash != expected_previous {
+            Some("does not follow the entry before it")
+        } else if entry_hash(entry) != entry.hash {
+            Some("was altered: its hash no longer matches its fields")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            return Ok(AccessLogVerification {
+                valid: false,
+                entries: entries.len(),
+                message: format!("Entry {} {}", entry.id, problem),
+            });
+        }
+        expected_previous = entry.hash.clone();
+    }
+    Ok(AccessLogVerification {
+        valid: true,
+        entries: entries.len(),
+        message: format!("All {} entries are intact and in order", entries.len()),
+    })
+}
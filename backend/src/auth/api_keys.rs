@@ -0,0 +1,69 @@
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::auth::rbac::Scope;
+use crate::auth::{Auth, AuthUser};
+use crate::db::{find_api_key, touch_api_key};
+
+/// Marks a bearer token as an API key rather than a session JWT.
+pub const KEY_PREFIX: &str = "sck_";
+
+type AuthError = (StatusCode, String);
+
+fn unauthorized(message: &str) -> AuthError {
+    (StatusCode::UNAUTHORIZED, message.to_string())
+}
+
+pub fn hash_key_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// A new key as `(id, secret, key)`. The key handed out is `sck_<id>_<secret>`;
+/// only the id and the secret's hash are stored.
+pub fn generate_key() -> (String, String, String) {
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let secret = URL_SAFE_NO_PAD.encode(secret);
+    let key = format!("{}{}_{}", KEY_PREFIX, id, secret);
+    (id, secret, key)
+}
+
+/// Parses a stored comma-separated scope list, skipping anything unknown.
+pub fn parse_scopes(scopes: &str) -> Vec<Scope> {
+    scopes.split(',').filter_map(|s| s.parse().ok()).collect()
+}
+
+/// Authenticates an API key. The key acts for its organization with that organization's role,
+/// limited to its scopes; `user_id` names the key so ledger actions stay attributable.
+pub async fn authenticate_key(auth: &Auth, key: &str) -> Result<AuthUser, AuthError> {
+    let (id, secret) = key
+        .strip_prefix(KEY_PREFIX)
+        .and_then(|rest| rest.split_once('_'))
+        .ok_or_else(|| unauthorized("Malformed API key"))?;
+
+    let internal = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let api_key = find_api_key(&auth.pool, id)
+        .await
+        .map_err(internal)?
+        .filter(|k| k.revoked_at.is_none())
+        .ok_or_else(|| unauthorized("Unknown or revoked API key"))?;
+    if !bool::from(hash_key_secret(secret).as_bytes().ct_eq(api_key.key_hash.as_bytes())) {
+        return Err(unauthorized("Unknown or revoked API key"));
+    }
+
+    touch_api_key(&auth.pool, &api_key.id).await.map_err(internal)?;
+
+    Ok(AuthUser {
+        user_id: format!("apikey:{}", api_key.id),
+        role: api_key.organization_type,
+        organization: Some(api_key.organization_id),
+        session_id: api_key.id,
+        // Keys are exempt from the two-factor policy; they are scoped and revocable instead.
+        mfa: false,
+        api_key_scopes: Some(parse_scopes(&api_key.scopes)),
+    })
+}
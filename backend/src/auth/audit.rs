@@ -0,0 +1,58 @@
+use crate::auth::Auth;
+use crate::db::record_auth_event;
+
+/// What the auth event log records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthEvent {
+    LoginSuccess,
+    LoginFailure,
+    /// A login refused without checking the password because of backoff or a lockout.
+    LoginThrottled,
+    /// An account or IP address was locked after too many failures.
+    Lockout,
+    TokenRefresh,
+    TokenRefreshFailure,
+    EmailVerified,
+    PasswordResetRequested,
+    PasswordReset,
+}
+
+impl AuthEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuthEvent::LoginSuccess => "login_success",
+            AuthEvent::LoginFailure => "login_failure",
+            AuthEvent::LoginThrottled => "login_throttled",
+            AuthEvent::Lockout => "lockout",
+            AuthEvent::TokenRefresh => "token_refresh",
+            AuthEvent::TokenRefreshFailure => "token_refresh_failure",
+            AuthEvent::EmailVerified => "email_verified",
+            AuthEvent::PasswordResetRequested => "password_reset_requested",
+            AuthEvent::PasswordReset => "password_reset",
+        }
+    }
+}
+
+/// Who and where an event concerns; any part may be unknown.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EventSubject<'a> {
+    pub user_id: Option<&'a str>,
+    pub email: Option<&'a str>,
+    pub ip: Option<&'a str>,
+}
+
+/// Appends to the auth event log. A failure to log is reported but never fails the request.
+pub async fn record(auth: &Auth, event: AuthEvent, subject: EventSubject<'_>, detail: Option<&str>) {
+    if let Err(e) = record_auth_event(
+        &auth.pool,
+        event.as_str(),
+        subject.user_id,
+        subject.email,
+        subject.ip,
+        detail,
+    )
+    .await
+    {
+        eprintln!("Auth event log error ({}): {}", event.as_str(), e);
+    }
+}
@@ -0,0 +1,180 @@
+use axum::http::StatusCode;
+use chrono::Utc;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::auth::rbac::Role;
+use crate::auth::{Auth, AuthUser};
+use crate::db::{find_user_by_id, grant_unheld_role, mark_email_verified, revoke_user_sessions};
+use crate::mail::Mail;
+use crate::models::User;
+
+/// The `aud` of a link token says what it is for, so one kind cannot be used as another
+/// (and neither passes as an access token, which has no audience).
+const VERIFY_EMAIL: &str = "verify-email";
+const RESET_PASSWORD: &str = "reset-password";
+
+type AuthError = (StatusCode, String);
+
+fn internal(e: impl ToString) -> AuthError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// Claims of a signed, expiring link sent by email.
+#[derive(Serialize, Deserialize)]
+struct LinkClaims {
+    sub: String,
+    aud: String,
+    /// The address the link was sent to; a verification link is void once the email changes.
+    email: String,
+    /// Fingerprint of the password hash when a reset link was issued, so the link stops
+    /// working once the password has changed, including through the link itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pwd: Option<String>,
+    iss: String,
+    iat: i64,
+    exp: i64,
+}
+
+fn password_fingerprint(password_hash: &str) -> String {
+    hex::encode(&Sha256::digest(password_hash.as_bytes())[..8])
+}
+
+fn sign_link(auth: &Auth, user: &User, purpose: &str, ttl_secs: i64) -> Result<String, AuthError> {
+    let now = Utc::now().timestamp();
+    let claims = LinkClaims {
+        sub: user.id.clone(),
+        aud: purpose.to_string(),
+        email: user.email.clone(),
+        pwd: (purpose == RESET_PASSWORD).then(|| password_fingerprint(&user.password)),
+        iss: auth.config.issuer.clone(),
+        iat: now,
+        exp: now + ttl_secs,
+    };
+    encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(&auth.config.secret)).map_err(internal)
+}
+
+/// The user a link token was issued to, after checking its signature, purpose and expiry.
+async fn open_link(auth: &Auth, token: &str, purpose: &str) -> Result<(User, LinkClaims), AuthError> {
+    let invalid = || (StatusCode::BAD_REQUEST, "This link is invalid or has expired".to_string());
+
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[&auth.config.issuer]);
+    validation.set_audience(&[purpose]);
+    validation.leeway = 0;
+    let claims = decode::<LinkClaims>(token, &DecodingKey::from_secret(&auth.config.secret), &validation)
+        .map_err(|_| invalid())?
+        .claims;
+
+    let user = find_user_by_id(&auth.pool, &claims.sub)
+        .await
+        .map_err(internal)?
+        .filter(|u| u.email.eq_ignore_ascii_case(&claims.email))
+        .ok_or_else(invalid)?;
+    Ok((user, claims))
+}
+
+async fn deliver(auth: &Auth, mail: Mail) -> Result<(), AuthError> {
+    auth.mailer
+        .send(&mail)
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Could not send email: {}", e)))
+}
+
+pub async fn send_verification_email(auth: &Auth, user: &User) -> Result<(), AuthError> {
+    let token = sign_link(auth, user, VERIFY_EMAIL, auth.config.email_verification_ttl_secs)?;
+    let link = format!("{}/api/email/verify?token={}", auth.config.app_base_url, token);
+    deliver(
+        auth,
+        Mail {
+            to: user.email.clone(),
+            subject: "Confirm your email address".to_string(),
+            body: format!(
+                "Hello {},\n\nConfirm your email address by opening this link within {} hours:\n\n{}\n\n\
+                 Until you do, your account can read the ledger but not change it.\n",
+                user.username,
+                auth.config.email_verification_ttl_secs / 3600,
+                link
+            ),
+        },
+    )
+    .await
+}
+
+pub async fn send_password_reset(auth: &Auth, user: &User) -> Result<(), AuthError> {
+    let token = sign_link(auth, user, RESET_PASSWORD, auth.config.password_reset_ttl_secs)?;
+    let link = format!("{}?token={}", auth.config.password_reset_url, token);
+    deliver(
+        auth,
+        Mail {
+            to: user.email.clone(),
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Hello {},\n\nSomeone asked to reset the password for this account. If it was you, open this \
+                 link within {} minutes to choose a new one:\n\n{}\n\nOtherwise you can ignore this email; \
+                 your password has not changed.\n",
+                user.username,
+                auth.config.password_reset_ttl_secs / 60,
+                link
+            ),
+        },
+    )
+    .await
+}
+
+/// Marks the email a verification link was sent to as verified. Returns the user and whether
+/// this changed anything.
+pub async fn verify_email(auth: &Auth, token: &str) -> Result<(User, bool), AuthError> {
+    let (user, _) = open_link(auth, token, VERIFY_EMAIL).await?;
+    let changed = mark_email_verified(&auth.pool, &user.id).await.map_err(internal)?;
+    if !changed {
+        return Ok((user, changed));
+    }
+    Ok((promote_bootstrap_admin(auth, user).await?, changed))
+}
+
+/// The account whose address is `ADMIN_EMAIL` becomes the first admin once it has proven it
+/// owns that address, not at signup, and only while there is no admin yet. Its sessions are
+/// revoked so new tokens carry the role.
+async fn promote_bootstrap_admin(auth: &Auth, user: User) -> Result<User, AuthError> {
+    let is_admin_email = std::env::var("ADMIN_EMAIL")
+        .ok()
+        .is_some_and(|email| !email.is_empty() && email.eq_ignore_ascii_case(&user.email));
+    if !is_admin_email || !grant_unheld_role(&auth.pool, &user.id, Role::Admin.as_str()).await.map_err(internal)? {
+        return Ok(user);
+    }
+    revoke_user_sessions(&auth.pool, &user.id).await.map_err(internal)?;
+    Ok(User {
+        role: Role::Admin.as_str().to_string(),
+        ..user
+    })
+}
+
+/// The user a password reset link is for, as long as their password has not changed since.
+pub async fn reset_target(auth: &Auth, token: &str) -> Result<User, AuthError> {
+    let (user, claims) = open_link(auth, token, RESET_PASSWORD).await?;
+    if claims.pwd.as_deref() != Some(password_fingerprint(&user.password).as_str()) {
+        return Err((StatusCode::BAD_REQUEST, "This link has already been used".to_string()));
+    }
+    Ok(user)
+}
+
+/// Refuses write operations to accounts whose email is not verified yet. API keys belong to
+/// an organization rather than an account and are not affected.
+pub async fn require_verified_email(auth: &Auth, user: &AuthUser) -> Result<(), AuthError> {
+    if !auth.config.require_email_verification || user.api_key_scopes.is_some() {
+        return Ok(());
+    }
+    let verified = find_user_by_id(&auth.pool, &user.user_id)
+        .await
+        .map_err(internal)?
+        .is_some_and(|u| u.email_verified_at.is_some());
+    if !verified {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Verify your email address first; /api/email/verify/resend sends a new link".to_string(),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,224 @@
+pub mod access_log;
+pub mod api_keys;
+pub mod audit;
+pub mod email;
+pub mod oidc;
+pub mod rbac;
+pub mod throttle;
+pub mod tokens;
+pub mod totp;
+
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequestParts},
+    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::RngCore;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::mail::MailTransport;
+
+/// Session token settings, read from the environment.
+///
+/// Access tokens are HS256 JWTs signed with the secret at `AUTH_SECRET_PATH`
+/// (generated on first start, like the node key). Share the file between nodes
+/// that should accept each other's tokens.
+#[derive(Clone)]
+pub struct AuthConfig {
+    pub secret: Vec<u8>,
+    pub issuer: String,
+    pub access_ttl_secs: i64,
+    pub refresh_ttl_secs: i64,
+    /// Roles that must pass a TOTP check before their sessions can do anything but enroll.
+    pub mfa_required_roles: Vec<String>,
+    /// Failed logins for one account before it is locked.
+    pub login_max_failures: i64,
+    /// Failed logins from one IP address before it is locked.
+    pub login_ip_max_failures: i64,
+    /// How long a lockout lasts; failures older than this are forgotten.
+    pub lockout_secs: i64,
+    /// First delay after a failed login; it doubles with each further failure.
+    pub backoff_base_secs: i64,
+    /// Take the client address from the last `X-Forwarded-For` entry (behind a reverse proxy).
+    pub trust_forwarded_for: bool,
+    /// Where links in emails point: this node's public URL.
+    pub app_base_url: String,
+    /// Page that takes a reset token and posts it with the new password to `/api/password/reset`.
+    pub password_reset_url: String,
+    pub email_verification_ttl_secs: i64,
+    pub password_reset_ttl_secs: i64,
+    /// Bar accounts with an unverified email from write operations.
+    pub require_email_verification: bool,
+}
+
+impl AuthConfig {
+    pub fn mfa_required(&self, role: &str) -> bool {
+        self.mfa_required_roles.iter().any(|r| r == role)
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        let secret_path = env::var("AUTH_SECRET_PATH").unwrap_or_else(|_| "auth_secret.key".to_string());
+        let app_base_url = env::var("APP_BASE_URL")
+            .unwrap_or_else(|_| format!("http://{}", env::var("BIND_ADDR").unwrap_or_else(|_| "127.0.0.1:3001".to_string())))
+            .trim_end_matches('/')
+            .to_string();
+        Ok(Self {
+            secret: load_or_create_secret(&secret_path)?,
+            issuer: env::var("AUTH_ISSUER").unwrap_or_else(|_| "supply-chain".to_string()),
+            access_ttl_secs: env::var("ACCESS_TOKEN_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v: &i64| *v > 0)
+                .unwrap_or(15 * 60),
+            refresh_ttl_secs: env::var("REFRESH_TOKEN_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v: &i64| *v > 0)
+                .unwrap_or(14 * 24 * 60 * 60),
+            mfa_required_roles: env::var("MFA_REQUIRED_ROLES")
+                .unwrap_or_else(|_| "company,regulator".to_string())
+                .split(',')
+                .map(|r| r.trim().to_ascii_lowercase())
+                .filter(|r| !r.is_empty())
+                .collect(),
+            login_max_failures: env::var("LOGIN_MAX_FAILURES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v: &i64| *v > 0)
+                .unwrap_or(5),
+            login_ip_max_failures: env::var("LOGIN_IP_MAX_FAILURES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v: &i64| *v > 0)
+                .unwrap_or(20),
+            lockout_secs: env::var("LOGIN_LOCKOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v: &i64| *v > 0)
+                .unwrap_or(15 * 60),
+            backoff_base_secs: env::var("LOGIN_BACKOFF_BASE_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v: &i64| *v >= 0)
+                .unwrap_or(1),
+            trust_forwarded_for: env::var("TRUST_FORWARDED_FOR").is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true")),
+            password_reset_url: env::var("PASSWORD_RESET_URL").unwrap_or_else(|_| format!("{}/reset-password", app_base_url)),
+            app_base_url,
+            email_verification_ttl_secs: env::var("EMAIL_VERIFICATION_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v: &i64| *v > 0)
+                .unwrap_or(2 * 24 * 60 * 60),
+            password_reset_ttl_secs: env::var("PASSWORD_RESET_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v: &i64| *v > 0)
+                .unwrap_or(60 * 60),
+            require_email_verification: !env::var("REQUIRE_EMAIL_VERIFICATION")
+                .is_ok_and(|v| v == "0" || v.eq_ignore_ascii_case("false")),
+        })
+    }
+}
+
+/// Reads a base64 secret of at least 32 bytes from `path`, writing a new random one there if
+/// the file does not exist.
+pub(crate) fn load_or_create_secret(path: &str) -> Result<Vec<u8>, String> {
+    if Path::new(path).exists() {
+        let encoded = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let secret = STANDARD.decode(encoded.trim()).map_err(|e| format!("Reading {}: {}", path, e))?;
+        if secret.len() < 32 {
+            return Err(format!("{} must hold at least 32 bytes of secret", path));
+        }
+        return Ok(secret);
+    }
+
+    let mut secret = vec![0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    std::fs::write(path, STANDARD.encode(&secret)).map_err(|e| e.to_string())?;
+    Ok(secret)
+}
+
+/// What the auth extractor needs, attached to every request as an extension
+/// so it works whatever state a router carries.
+pub struct Auth {
+    pub pool: Arc<SqlitePool>,
+    pub config: AuthConfig,
+    /// Single sign-on through an OpenID provider, when configured.
+    pub oidc: Option<oidc::OidcClient>,
+    /// Delivers verification and password reset emails.
+    pub mailer: Box<dyn MailTransport>,
+}
+
+/// The caller behind a valid access token on a live session, or behind an API key.
+///
+/// Add it as a handler argument to read the caller. Routes are guarded with
+/// [`rbac::authorize`], which also checks the caller's role against the policy.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthUser {
+    pub user_id: String,
+    pub role: String,
+    pub organization: Option<String>,
+    /// Session id, or the key id for API keys.
+    pub session_id: String,
+    /// Whether the session passed a second factor at login or enrollment.
+    pub mfa: bool,
+    /// Scopes of the API key the caller authenticated with; `None` for user sessions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key_scopes: Option<Vec<rbac::Scope>>,
+}
+
+impl Auth {
+    /// Refuses user sessions whose role requires two-factor but that have not passed it.
+    /// Such a session can still enroll, which is all it is good for.
+    pub fn require_mfa(&self, user: &AuthUser) -> Result<(), (StatusCode, String)> {
+        if user.api_key_scopes.is_none() && !user.mfa && self.config.mfa_required(&user.role) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                format!(
+                    "The {} role requires two-factor authentication: enroll at /api/2fa/enroll, or log in with your code",
+                    user.role
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The token from an `Authorization: Bearer <token>` header, or an `X-API-Key` header.
+pub fn bearer_token(parts: &Parts) -> Option<&str> {
+    if let Some(key) = parts.headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        return Some(key.trim());
+    }
+    parts
+        .headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(user) = parts.extensions.get::<AuthUser>() {
+            return Ok(user.clone());
+        }
+        let Extension(auth) = Extension::<Arc<Auth>>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Auth is not configured".to_string()))?;
+        let token = bearer_token(parts)
+            .ok_or_else(|| (StatusCode::UNAUTHORIZED, "Missing bearer token".to_string()))?;
+        if token.starts_with(api_keys::KEY_PREFIX) {
+            return api_keys::authenticate_key(&auth, token).await;
+        }
+        tokens::authenticate(&auth, token).await
+    }
+}
@@ -0,0 +1,478 @@
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use rand::RngCore;
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration as StdDuration, Instant};
+use tokio::sync::Mutex;
+
+use crate::auth::rbac::Role;
+use crate::auth::Auth;
+use crate::db::entities::find_organization;
+use crate::db::{
+    add_membership, add_user, create_oidc_login, find_identity_user, find_membership, find_user_by_email,
+    find_user_by_id, link_identity, mark_email_verified, revoke_user_sessions, take_oidc_login, update_user_role,
+};
+use crate::models::User;
+use crate::utils::password::hash_password;
+
+/// How long a user has to come back from the identity provider.
+const LOGIN_TTL_MINUTES: i64 = 10;
+/// A token signed with a key we have not seen makes us refetch the JWKS, but not more often than this.
+const JWKS_MIN_REFRESH: StdDuration = StdDuration::from_secs(30);
+/// `amr` values that mean the identity provider checked more than a password.
+const MFA_METHODS: &[&str] = &["mfa", "otp", "hwk", "swk", "sms", "fpt"];
+
+type AuthError = (StatusCode, String);
+
+fn internal(e: impl ToString) -> AuthError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn bad_gateway(e: impl ToString) -> AuthError {
+    (StatusCode::BAD_GATEWAY, format!("Identity provider: {}", e.to_string()))
+}
+
+/// Single sign-on settings. Off unless `OIDC_ISSUER` is set.
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    /// Unset for public clients, which rely on PKCE alone.
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    pub scopes: String,
+    /// Claim holding the user's groups or roles at the identity provider.
+    pub role_claim: String,
+    /// IdP group → our role, first match wins.
+    pub role_map: Vec<(String, Role)>,
+    /// Role for users none of whose groups are mapped; `None` refuses them.
+    pub default_role: Option<Role>,
+    /// Claim holding the id of the organization the user works for.
+    pub organization_claim: String,
+    pub jwks_ttl_secs: u64,
+}
+
+/// Regulators and admins are appointed here, never by an identity provider.
+fn sso_role(value: &str) -> Result<Role, String> {
+    let role = value.parse::<Role>()?;
+    if role.is_privileged() {
+        return Err(format!("The {} role cannot be granted through single sign-on", role.as_str()));
+    }
+    Ok(role)
+}
+
+impl OidcConfig {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(issuer) = env::var("OIDC_ISSUER").ok().filter(|v| !v.is_empty()) else {
+            return Ok(None);
+        };
+        let required = |name: &str| {
+            env::var(name)
+                .ok()
+                .filter(|v| !v.is_empty())
+                .ok_or_else(|| format!("{} is required when OIDC_ISSUER is set", name))
+        };
+
+        let role_map = env::var("OIDC_ROLE_MAP")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|pair| !pair.trim().is_empty())
+            .map(|pair| {
+                let (group, role) = pair
+                    .split_once('=')
+                    .ok_or_else(|| format!("OIDC_ROLE_MAP entry '{}' is not group=role", pair))?;
+                Ok((group.trim().to_string(), sso_role(role)?))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let default_role = match env::var("OIDC_DEFAULT_ROLE") {
+            Ok(role) if role.trim().is_empty() => None,
+            Ok(role) => Some(sso_role(&role)?),
+            Err(_) => Some(Role::Hospital),
+        };
+
+        Ok(Some(Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id: required("OIDC_CLIENT_ID")?,
+            client_secret: env::var("OIDC_CLIENT_SECRET").ok().filter(|v| !v.is_empty()),
+            redirect_uri: required("OIDC_REDIRECT_URI")?,
+            scopes: env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid email profile".to_string()),
+            role_claim: env::var("OIDC_ROLE_CLAIM").unwrap_or_else(|_| "groups".to_string()),
+            role_map,
+            default_role,
+            organization_claim: env::var("OIDC_ORG_CLAIM").unwrap_or_else(|_| "org_id".to_string()),
+            jwks_ttl_secs: env::var("OIDC_JWKS_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(3600),
+        }))
+    }
+}
+
+/// The parts of the provider's discovery document we use.
+#[derive(Debug, Clone, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// ID token claims. Anything else the provider sends stays in `extra`, for role and organization mapping.
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: Option<bool>,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub azp: Option<String>,
+    #[serde(default)]
+    pub amr: Vec<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+/// A relying-party client for one OpenID provider. The discovery document is fetched once;
+/// signing keys are cached for `OIDC_JWKS_TTL_SECS` and refetched early when a token names
+/// a key we do not have, so the provider can rotate keys.
+pub struct OidcClient {
+    pub config: OidcConfig,
+    http: reqwest::Client,
+    discovery: Mutex<Option<Discovery>>,
+    jwks: Mutex<Option<(JwkSet, Instant)>>,
+}
+
+/// Where to send the user to log in.
+pub struct AuthorizationRequest {
+    pub url: String,
+    pub state: String,
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The S256 PKCE challenge for a verifier.
+fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+impl OidcClient {
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::builder()
+                .timeout(StdDuration::from_secs(10))
+                .build()
+                .expect("Failed to build HTTP client"),
+            discovery: Mutex::new(None),
+            jwks: Mutex::new(None),
+        }
+    }
+
+    async fn discovery(&self) -> Result<Discovery, AuthError> {
+        let mut cached = self.discovery.lock().await;
+        if let Some(discovery) = cached.as_ref() {
+            return Ok(discovery.clone());
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", self.config.issuer);
+        let discovery: Discovery = self
+            .http
+            .get(&url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(bad_gateway)?
+            .json()
+            .await
+            .map_err(bad_gateway)?;
+        if discovery.issuer.trim_end_matches('/') != self.config.issuer {
+            return Err(bad_gateway(format!(
+                "discovery document names issuer {}, expected {}",
+                discovery.issuer, self.config.issuer
+            )));
+        }
+        *cached = Some(discovery.clone());
+        Ok(discovery)
+    }
+
+    /// The decoding key for `kid`, from the cache when it is fresh and has the key.
+    async fn signing_key(&self, kid: Option<&str>) -> Result<DecodingKey, AuthError> {
+        let mut cached = self.jwks.lock().await;
+        let ttl = StdDuration::from_secs(self.config.jwks_ttl_secs);
+
+        let find = |set: &JwkSet| match kid {
+            Some(kid) => set.find(kid).cloned(),
+            None => set.keys.first().cloned(),
+        };
+        if let Some((set, fetched)) = cached.as_ref() {
+            let stale = fetched.elapsed() > ttl;
+            let may_refetch = fetched.elapsed() > JWKS_MIN_REFRESH;
+            match find(set) {
+                Some(jwk) if !stale => return DecodingKey::from_jwk(&jwk).map_err(bad_gateway),
+                None if !stale && !may_refetch => {
+                    return Err((StatusCode::UNAUTHORIZED, "ID token is signed with an unknown key".to_string()));
+                }
+                _ => {}
+            }
+        }
+
+        let discovery = self.discovery().await?;
+        let set: JwkSet = self
+            .http
+            .get(&discovery.jwks_uri)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(bad_gateway)?
+            .json()
+            .await
+            .map_err(bad_gateway)?;
+        let jwk = find(&set);
+        *cached = Some((set, Instant::now()));
+
+        let jwk = jwk.ok_or_else(|| (StatusCode::UNAUTHORIZED, "ID token is signed with an unknown key".to_string()))?;
+        DecodingKey::from_jwk(&jwk).map_err(bad_gateway)
+    }
+
+    /// Starts a login: stores a state, nonce and PKCE verifier and returns the provider URL.
+    pub async fn authorization_request(&self, auth: &Auth) -> Result<AuthorizationRequest, AuthError> {
+        let discovery = self.discovery().await?;
+        let state = random_token();
+        let nonce = random_token();
+        let verifier = random_token();
+        let expires_at = (Utc::now() + Duration::minutes(LOGIN_TTL_MINUTES)).to_rfc3339();
+        create_oidc_login(&auth.pool, &state, &nonce, &verifier, &expires_at)
+            .await
+            .map_err(internal)?;
+
+        let mut url = Url::parse(&discovery.authorization_endpoint).map_err(bad_gateway)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_uri)
+            .append_pair("scope", &self.config.scopes)
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &pkce_challenge(&verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok(AuthorizationRequest {
+            url: url.to_string(),
+            state,
+        })
+    }
+
+    /// Finishes a login: redeems the code with the PKCE verifier and validates the ID token.
+    pub async fn exchange_code(&self, auth: &Auth, code: &str, state: &str) -> Result<IdTokenClaims, AuthError> {
+        let login = take_oidc_login(&auth.pool, state)
+            .await
+            .map_err(internal)?
+            .ok_or((StatusCode::BAD_REQUEST, "Unknown or expired login state".to_string()))?;
+
+        let discovery = self.discovery().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_uri.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("code_verifier", login.code_verifier.as_str()),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let response = self
+            .http
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(bad_gateway)?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(bad_gateway(format!("token endpoint answered {}: {}", status, body)));
+        }
+        let tokens: TokenResponse = response.json().await.map_err(bad_gateway)?;
+
+        let claims = self.validate_id_token(&tokens.id_token).await?;
+        if claims.nonce.as_deref() != Some(login.nonce.as_str()) {
+            return Err((StatusCode::UNAUTHORIZED, "ID token nonce does not match this login".to_string()));
+        }
+        Ok(claims)
+    }
+
+    /// Checks an ID token's signature against the provider's keys, its issuer, audience and expiry.
+    async fn validate_id_token(&self, id_token: &str) -> Result<IdTokenClaims, AuthError> {
+        let invalid = |e: jsonwebtoken::errors::Error| (StatusCode::UNAUTHORIZED, format!("Invalid ID token: {}", e));
+        let header = decode_header(id_token).map_err(invalid)?;
+        // Only asymmetric algorithms: the provider's keys are public.
+        if !matches!(
+            header.alg,
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512
+                | Algorithm::ES256
+                | Algorithm::ES384
+                | Algorithm::EdDSA
+        ) {
+            return Err((StatusCode::UNAUTHORIZED, format!("ID token algorithm {:?} is not accepted", header.alg)));
+        }
+        let key = self.signing_key(header.kid.as_deref()).await?;
+        let discovery = self.discovery().await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&discovery.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.leeway = 60;
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation).map_err(invalid)?.claims;
+
+        if let Some(azp) = &claims.azp
+            && azp != &self.config.client_id
+        {
+            return Err((StatusCode::UNAUTHORIZED, "ID token was issued to another client".to_string()));
+        }
+        Ok(claims)
+    }
+
+    /// Our role for the user: the first of their IdP groups in `OIDC_ROLE_MAP`, else the default.
+    fn map_role(&self, claims: &IdTokenClaims) -> Option<Role> {
+        let groups: Vec<&str> = match claims.extra.get(&self.config.role_claim) {
+            Some(Value::String(group)) => vec![group.as_str()],
+            Some(Value::Array(groups)) => groups.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        self.config
+            .role_map
+            .iter()
+            .find(|(group, _)| groups.contains(&group.as_str()))
+            .map(|(_, role)| *role)
+            .or(self.config.default_role)
+    }
+
+    /// The local account for a validated identity, creating or linking it on first login.
+    ///
+    /// An existing account with the same email is linked only when the provider says the email
+    /// is verified. The account's role follows the IdP groups unless an admin appointed it to a
+    /// privileged role. An organization named by the organization claim is joined as a member
+    /// when it exists and matches the role.
+    pub async fn sign_in(&self, auth: &Auth, claims: &IdTokenClaims) -> Result<User, AuthError> {
+        let pool = &auth.pool;
+        let role = self.map_role(claims).ok_or((
+            StatusCode::FORBIDDEN,
+            "Your identity provider groups do not grant access here".to_string(),
+        ))?;
+
+        let user = match find_identity_user(pool, &self.config.issuer, &claims.sub).await.map_err(internal)? {
+            Some(user_id) => find_user_by_id(pool, &user_id).await.map_err(internal)?,
+            None => None,
+        };
+        let user = match user {
+            Some(user) => user,
+            None => {
+                let email = claims.email.as_deref().ok_or((
+                    StatusCode::FORBIDDEN,
+                    "The identity provider did not share an email address".to_string(),
+                ))?;
+                let user_id = match find_user_by_email(pool, email).await.map_err(internal)? {
+                    Some(_) if claims.email_verified != Some(true) => {
+                        return Err((
+                            StatusCode::CONFLICT,
+                            "An account with this email exists; the identity provider must verify the email to link it"
+                                .to_string(),
+                        ));
+                    }
+                    Some(existing) => existing.id,
+                    None => {
+                        // SSO accounts get a random password nobody knows; they log in through the provider.
+                        let password_hash = tokio::task::spawn_blocking(|| hash_password(&random_token()))
+                            .await
+                            .map_err(internal)?
+                            .map_err(internal)?;
+                        let username = claims
+                            .preferred_username
+                            .as_deref()
+                            .or(claims.name.as_deref())
+                            .unwrap_or(email);
+                        add_user(pool, username, email, &password_hash, role.as_str()).await.map_err(internal)?
+                    }
+                };
+                link_identity(pool, &self.config.issuer, &claims.sub, &user_id).await.map_err(internal)?;
+                find_user_by_id(pool, &user_id)
+                    .await
+                    .map_err(internal)?
+                    .ok_or_else(|| internal("Linked account disappeared"))?
+            }
+        };
+
+        let user = match user.role.parse::<Role>() {
+            Ok(current) if current.is_privileged() || current == role => user,
+            _ => {
+                // Same as an admin role change: tokens carrying the old role stop working.
+                update_user_role(pool, &user.id, role.as_str()).await.map_err(internal)?;
+                revoke_user_sessions(pool, &user.id).await.map_err(internal)?;
+                User {
+                    role: role.as_str().to_string(),
+                    ..user
+                }
+            }
+        };
+
+        // The provider vouches for the address, so no verification email is needed.
+        if user.email_verified_at.is_none()
+            && claims.email_verified == Some(true)
+            && claims.email.as_deref().is_some_and(|email| email.eq_ignore_ascii_case(&user.email))
+        {
+            mark_email_verified(pool, &user.id).await.map_err(internal)?;
+        }
+
+        if let Some(organization_id) = claims.extra.get(&self.config.organization_claim).and_then(Value::as_str) {
+            match find_organization(pool, organization_id).await.map_err(internal)? {
+                Some(org) if org.organization_type == user.role => {
+                    if find_membership(pool, &user.id, &org.id).await.map_err(internal)?.is_none() {
+                        add_membership(pool, &user.id, &org.id, &org.organization_type, "member")
+                            .await
+                            .map_err(internal)?;
+                    }
+                }
+                Some(org) => eprintln!(
+                    "SSO: {} is a {}, not joining it as a {}",
+                    org.id, org.organization_type, user.role
+                ),
+                None => eprintln!("SSO: organization {} from the ID token does not exist", organization_id),
+            }
+        }
+        Ok(user)
+    }
+}
+
+/// Whether the provider reports a second factor in `amr`.
+pub fn passed_mfa(claims: &IdTokenClaims) -> bool {
+    claims.amr.iter().any(|method| MFA_METHODS.contains(&method.as_str()))
+}
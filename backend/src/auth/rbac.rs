@@ -0,0 +1,380 @@
+use axum::{
+    extract::{ConnectInfo, Extension, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::auth::access_log::{self, Access};
+use crate::auth::email::require_verified_email;
+use crate::auth::throttle::client_ip;
+use crate::auth::{Auth, AuthUser};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Company,
+    Hospital,
+    Customer,
+    Regulator,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Company => "company",
+            Role::Hospital => "hospital",
+            Role::Customer => "customer",
+            Role::Regulator => "regulator",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// Roles that can only be granted by an admin, never picked at signup.
+    pub fn is_privileged(self) -> bool {
+        matches!(self, Role::Regulator | Role::Admin)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "company" => Ok(Role::Company),
+            "hospital" => Ok(Role::Hospital),
+            "customer" => Ok(Role::Customer),
+            "regulator" => Ok(Role::Regulator),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("Unknown role '{}'", other)),
+        }
+    }
+}
+
+/// Everything a route can be guarded by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Verify batches, walk the chain, read proofs and anchors.
+    ReadLedger,
+    CreateBatch,
+    TransferCustody,
+    AcknowledgeTransfer,
+    RegisterCompany,
+    RegisterHospital,
+    RegisterCustomer,
+    ViewCompanyDashboard,
+    ViewHospitalDashboard,
+    ViewCustomerDashboard,
+    /// Fork reports and other node health views.
+    InspectChain,
+    ManageUsers,
+    /// Read the log of logins, failures, lockouts and token refreshes, and the regulator access log.
+    ViewAuthEvents,
+    /// Approve, reject, suspend and reinstate organization registrations.
+    ReviewRegistrations,
+    /// Designate hospitals as critical care for stock allocation, or withdraw it.
+    DesignateCriticalCare,
+    /// Grant and revoke controlled-substance licenses, and set and remove quotas.
+    LicenseControlled,
+    /// Read every organization's batches, custody history and recalls, and export audit bundles.
+    InspectOrganizations,
+    /// Recall a batch the caller's company created, or close its recall.
+    IssueRecall,
+    /// Report temperature readings for batches in the caller's care.
+    RecordConditions,
+    /// Read the caller's organization's stock balances, movements and counts.
+    ViewInventory,
+    /// Split, destroy and count stock the caller's organization holds.
+    ManageInventory,
+    /// Raise purchase orders for the caller's hospital, or cancel them.
+    RaiseOrder,
+    /// Accept, reject and ship against orders sent to the caller's company.
+    FillOrder,
+    /// Read the purchase orders the caller's organization raised or received.
+    ViewOrders,
+    /// Read the caller's organization's demand requests, their supplier matches and price lists.
+    ViewDemand,
+    /// Record and close demand requests for the caller's organization.
+    ManageDemand,
+    /// Set the caller's company's prices.
+    SetPrices,
+    /// Read shortage reports and the stock allocations the caller can see.
+    ViewShortages,
+    /// Share the caller's company's scarce stock among open demand under an allocation policy.
+    AllocateStock,
+    /// Search and read the medicine catalog.
+    ViewCatalog,
+    /// Add and edit catalog entries, one at a time or from CSV.
+    ManageCatalog,
+    /// Approve companies' catalog entries, settling their controlled-substance schedule.
+    ReviewCatalog,
+    /// Propose, countersign, reject and cancel transfers of scheduled products.
+    TransferControlled,
+    /// Read controlled-substance transfers, licenses, quotas and movement reports.
+    ViewControlledReports,
+    /// Dispense from stock the caller's organization holds to a patient, against a prescription.
+    DispenseToPatient,
+    /// Read and trace the caller's organization's patient dispensings.
+    ViewDispensing,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Action::ReadLedger => "read the ledger",
+            Action::CreateBatch => "create batches",
+            Action::TransferCustody => "transfer custody",
+            Action::AcknowledgeTransfer => "acknowledge transfers",
+            Action::RegisterCompany => "register companies",
+            Action::RegisterHospital => "register hospitals",
+            Action::RegisterCustomer => "register customers",
+            Action::ViewCompanyDashboard => "view the company dashboard",
+            Action::ViewHospitalDashboard => "view the hospital dashboard",
+            Action::ViewCustomerDashboard => "view the customer dashboard",
+            Action::InspectChain => "inspect chain health",
+            Action::ManageUsers => "manage users",
+            Action::ViewAuthEvents => "view the auth event log",
+            Action::ReviewRegistrations => "review organization registrations",
+            Action::DesignateCriticalCare => "designate critical-care hospitals",
+            Action::LicenseControlled => "license controlled substances",
+            Action::InspectOrganizations => "inspect every organization",
+            Action::IssueRecall => "recall batches",
+            Action::RecordConditions => "record storage conditions",
+            Action::ViewInventory => "view inventory",
+            Action::ManageInventory => "manage inventory",
+            Action::RaiseOrder => "raise purchase orders",
+            Action::FillOrder => "fill purchase orders",
+            Action::ViewOrders => "view purchase orders",
+            Action::ViewDemand => "view demand and supply",
+            Action::ManageDemand => "record demand",
+            Action::SetPrices => "set prices",
+            Action::ViewShortages => "view shortages and allocations",
+            Action::AllocateStock => "allocate stock",
+            Action::ViewCatalog => "view the medicine catalog",
+            Action::ManageCatalog => "edit the medicine catalog",
+            Action::ReviewCatalog => "review catalog entries",
+            Action::TransferControlled => "transfer controlled substances",
+            Action::ViewControlledReports => "view controlled-substance reports",
+            Action::DispenseToPatient => "dispense to patients",
+            Action::ViewDispensing => "view patient dispensing records",
+        };
+        f.write_str(text)
+    }
+}
+
+impl Action {
+    /// Actions that change the ledger or register organizations, as opposed to reading.
+    pub fn is_write(self) -> bool {
+        matches!(
+            self,
+            Action::CreateBatch
+                | Action::IssueRecall
+                | Action::RecordConditions
+                | Action::ManageInventory
+                | Action::RaiseOrder
+                | Action::FillOrder
+                | Action::ManageDemand
+                | Action::SetPrices
+                | Action::AllocateStock
+                | Action::DesignateCriticalCare
+                | Action::LicenseControlled
+                | Action::ManageCatalog
+                | Action::ReviewCatalog
+                | Action::TransferControlled
+                | Action::DispenseToPatient
+                | Action::TransferCustody
+                | Action::AcknowledgeTransfer
+                | Action::RegisterCompany
+                | Action::RegisterHospital
+                | Action::RegisterCustomer
+        )
+    }
+}
+
+/// What an API key may be used for. Keys act with their organization's role,
+/// narrowed to their scopes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    BatchWrite,
+    CheckpointWrite,
+    Read,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::BatchWrite => "batch:write",
+            Scope::CheckpointWrite => "checkpoint:write",
+            Scope::Read => "read",
+        }
+    }
+
+    /// The action a scope unlocks, for checking a key's scopes against its organization's role.
+    pub fn action(self) -> Action {
+        match self {
+            Scope::BatchWrite => Action::CreateBatch,
+            Scope::CheckpointWrite => Action::TransferCustody,
+            Scope::Read => Action::ReadLedger,
+        }
+    }
+}
+
+impl Serialize for Scope {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "batch:write" => Ok(Scope::BatchWrite),
+            "checkpoint:write" => Ok(Scope::CheckpointWrite),
+            "read" => Ok(Scope::Read),
+            other => Err(format!("Unknown scope '{}'", other)),
+        }
+    }
+}
+
+/// Whether an API key holding `scopes` may perform `action`. Every scope includes reading;
+/// anything outside the ledger (dashboards, registration, administration) needs a user session.
+pub fn scope_permits(scopes: &[Scope], action: Action) -> bool {
+    match action {
+        Action::ReadLedger | Action::ViewInventory | Action::ViewCatalog => !scopes.is_empty(),
+        Action::CreateBatch => scopes.contains(&Scope::BatchWrite),
+        Action::TransferCustody
+        | Action::AcknowledgeTransfer
+        | Action::RecordConditions
+        | Action::ManageInventory
+        | Action::DispenseToPatient => {
+            scopes.contains(&Scope::CheckpointWrite)
+        }
+        _ => false,
+    }
+}
+
+/// The policy: which roles may perform which actions.
+pub fn permits(role: Role, action: Action) -> bool {
+    use Action::*;
+    use Role::*;
+
+    match action {
+        ReadLedger | ViewCatalog => true,
+        CreateBatch | IssueRecall => role == Company,
+        TransferCustody | AcknowledgeTransfer | RecordConditions => matches!(role, Company | Hospital),
+        ViewInventory | ManageInventory => matches!(role, Company | Hospital | Customer),
+        RaiseOrder => role == Hospital,
+        FillOrder => role == Company,
+        ViewOrders | ViewDemand | ManageDemand => matches!(role, Company | Hospital),
+        SetPrices | AllocateStock => role == Company,
+        ViewShortages => matches!(role, Company | Hospital | Regulator | Admin),
+        ManageCatalog => matches!(role, Company | Admin),
+        ReviewCatalog => matches!(role, Regulator | Admin),
+        TransferControlled => matches!(role, Company | Hospital | Customer),
+        ViewControlledReports => true,
+        DispenseToPatient => matches!(role, Hospital | Customer),
+        ViewDispensing => matches!(role, Hospital | Customer | Regulator | Admin),
+        RegisterCompany | ViewCompanyDashboard => role == Company,
+        RegisterHospital | ViewHospitalDashboard => role == Hospital,
+        RegisterCustomer | ViewCustomerDashboard => role == Customer,
+        InspectChain | ReviewRegistrations | DesignateCriticalCare | LicenseControlled => matches!(role, Regulator | Admin),
+        InspectOrganizations => role == Regulator,
+        ManageUsers | ViewAuthEvents => role == Admin,
+    }
+}
+
+/// Middleware guarding a route with `action`:
+/// `.route_layer(middleware::from_fn_with_state(Action::CreateBatch, authorize))`.
+///
+/// The authenticated caller is left in the request extensions, so a handler taking
+/// [`AuthUser`] gets it without checking the token again. Every request a regulator makes,
+/// answered or refused, is appended to the regulator access log before the answer goes out;
+/// if the entry cannot be written the regulator gets a 500 instead.
+pub async fn authorize(
+    State(action): State<Action>,
+    Extension(auth): Extension<Arc<Auth>>,
+    user: AuthUser,
+    request: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, String)> {
+    let role = user
+        .role
+        .parse::<Role>()
+        .map_err(|e| (StatusCode::FORBIDDEN, e))?;
+    if role != Role::Regulator {
+        return admit(action, role, &auth, user, request, next).await;
+    }
+
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let query = request.uri().query().map(str::to_string);
+    let ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|peer| client_ip(&auth.config, request.headers(), peer));
+    let user_id = user.user_id.clone();
+
+    let result = admit(action, role, &auth, user, request, next).await;
+    let status = match &result {
+        Ok(response) => response.status(),
+        Err((status, _)) => *status,
+    };
+    if let Err(e) = access_log::record(
+        &auth,
+        Access {
+            user_id: &user_id,
+            action: action.to_string(),
+            method: &method,
+            path: &path,
+            query: query.as_deref(),
+            status,
+            ip,
+        },
+    )
+    .await
+    {
+        eprintln!("⚠️ Failed to record regulator access to {}: {}", path, e);
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "The access could not be logged, so it was not answered".to_string(),
+        ));
+    }
+    result
+}
+
+/// The checks behind [`authorize`]: role, API key scopes, second factor and, for writes,
+/// a verified email.
+async fn admit(
+    action: Action,
+    role: Role,
+    auth: &Auth,
+    user: AuthUser,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, String)> {
+    if !permits(role, action) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("The {} role may not {}", role.as_str(), action),
+        ));
+    }
+    if let Some(scopes) = &user.api_key_scopes
+        && !scope_permits(scopes, action)
+    {
+        return Err((StatusCode::FORBIDDEN, format!("This API key's scopes do not allow it to {}", action)));
+    }
+    auth.require_mfa(&user)?;
+    if action.is_write() {
+        require_verified_email(auth, &user).await?;
+    }
+
+    request.extensions_mut().insert(user);
+    Ok(next.run(request).await)
+}
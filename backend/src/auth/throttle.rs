@@ -0,0 +1,172 @@
+use axum::extract::ConnectInfo;
+use axum::http::{HeaderMap, StatusCode};
+use chrono::Utc;
+use std::net::SocketAddr;
+
+use crate::auth::audit::{self, AuthEvent, EventSubject};
+use crate::auth::{Auth, AuthConfig};
+use crate::db::{clear_login_throttle, find_login_throttle, lock_login, record_login_failure};
+use crate::models::LoginThrottle;
+
+/// Backoff between attempts never grows past this; longer waits are what lockouts are for.
+const MAX_BACKOFF_SECS: i64 = 60;
+
+type AuthError = (StatusCode, String);
+
+fn internal(e: sqlx::Error) -> AuthError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// The client's address: the peer, or the last `X-Forwarded-For` hop when the node sits
+/// behind a trusted proxy (`TRUST_FORWARDED_FOR`).
+pub fn client_ip(config: &AuthConfig, headers: &HeaderMap, peer: &ConnectInfo<SocketAddr>) -> String {
+    if config.trust_forwarded_for
+        && let Some(forwarded) = headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+    {
+        return forwarded.to_string();
+    }
+    peer.0.ip().to_string()
+}
+
+fn account_key(email: &str) -> String {
+    format!("account:{}", email.trim().to_ascii_lowercase())
+}
+
+fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+/// Seconds until `state` allows another attempt: the rest of a lockout, or the backoff after
+/// the last failure. The first half of the `limit` failures are free, so a shared address is
+/// not slowed down by one person's typos; after that the base delay doubles with each failure.
+fn wait_secs(backoff_base_secs: i64, state: &LoginThrottle, limit: i64, now: i64) -> i64 {
+    if let Some(locked_until) = state.locked_until
+        && locked_until > now
+    {
+        return locked_until - now;
+    }
+    let counted = state.failures - limit / 2;
+    if counted <= 0 {
+        return 0;
+    }
+    let backoff = backoff_base_secs
+        .saturating_mul(1i64 << (counted - 1).min(32))
+        .min(MAX_BACKOFF_SECS);
+    (state.last_failure_at + backoff - now).max(0)
+}
+
+/// How long a login for `email` from `ip` must wait, if at all. Checked before the password,
+/// so a locked account stays locked even for the right password.
+pub async fn retry_after(auth: &Auth, email: &str, ip: &str) -> Result<Option<i64>, AuthError> {
+    let now = Utc::now().timestamp();
+    let mut wait = 0;
+    for (key, limit) in [
+        (account_key(email), auth.config.login_max_failures),
+        (ip_key(ip), auth.config.login_ip_max_failures),
+    ] {
+        if let Some(state) = find_login_throttle(&auth.pool, &key).await.map_err(internal)? {
+            wait = wait.max(wait_secs(auth.config.backoff_base_secs, &state, limit, now));
+        }
+    }
+    Ok((wait > 0).then_some(wait))
+}
+
+/// Counts a failed login against the account and the IP address, locking whichever
+/// reached its limit.
+pub async fn record_failure(auth: &Auth, email: &str, ip: &str, user_id: Option<&str>) -> Result<(), AuthError> {
+    let config = &auth.config;
+    let now = Utc::now().timestamp();
+    let forget_before = now - config.lockout_secs;
+
+    for (key, limit, what) in [
+        (account_key(email), config.login_max_failures, "account"),
+        (ip_key(ip), config.login_ip_max_failures, "ip"),
+    ] {
+        let failures = record_login_failure(&auth.pool, &key, now, forget_before).await.map_err(internal)?;
+        if failures >= limit {
+            lock_login(&auth.pool, &key, now + config.lockout_secs).await.map_err(internal)?;
+            let detail = format!("{} locked for {}s after {} failures", what, config.lockout_secs, failures);
+            let subject = EventSubject {
+                user_id,
+                email: Some(email),
+                ip: Some(ip),
+            };
+            audit::record(auth, AuthEvent::Lockout, subject, Some(&detail)).await;
+        }
+    }
+    Ok(())
+}
+
+/// A successful login clears the account's failures. The IP's are kept, so one good
+/// account cannot be used to reset guessing at others.
+pub async fn record_success(auth: &Auth, email: &str) -> Result<(), AuthError> {
+    clear_login_throttle(&auth.pool, &account_key(email)).await.map_err(internal)?;
+    Ok(())
+}
+
+/// Lifts an account's lockout and forgets its failures. Returns whether it had any.
+pub async fn unlock_account(auth: &Auth, email: &str) -> Result<bool, AuthError> {
+    clear_login_throttle(&auth.pool, &account_key(email)).await.map_err(internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_000_000;
+
+    fn failed(failures: i64) -> LoginThrottle {
+        LoginThrottle {
+            failures,
+            last_failure_at: NOW,
+            locked_until: None,
+        }
+    }
+
+    #[test]
+    fn the_first_half_of_the_limit_is_free() {
+        assert_eq!(wait_secs(2, &failed(5), 10, NOW), 0);
+    }
+
+    #[test]
+    fn the_backoff_doubles_with_each_further_failure() {
+        assert_eq!(wait_secs(2, &failed(6), 10, NOW), 2);
+        assert_eq!(wait_secs(2, &failed(7), 10, NOW), 4);
+        assert_eq!(wait_secs(2, &failed(8), 10, NOW), 8);
+        // Time since the last failure counts toward the wait.
+        assert_eq!(wait_secs(2, &failed(8), 10, NOW + 5), 3);
+        assert_eq!(wait_secs(2, &failed(8), 10, NOW + 20), 0);
+    }
+
+    #[test]
+    fn the_backoff_is_capped_at_a_minute() {
+        assert_eq!(wait_secs(20, &failed(9), 10, NOW), MAX_BACKOFF_SECS);
+        assert_eq!(wait_secs(2, &failed(500), 10, NOW), MAX_BACKOFF_SECS);
+    }
+
+    #[test]
+    fn a_lockout_waits_out_its_remaining_time() {
+        let locked = LoginThrottle {
+            locked_until: Some(NOW + 900),
+            ..failed(10)
+        };
+        assert_eq!(wait_secs(2, &locked, 10, NOW + 100), 800);
+        // Once it has run out, only the backoff remains.
+        assert_eq!(wait_secs(2, &locked, 10, NOW + 900), 0);
+    }
+
+    #[test]
+    fn accounts_and_addresses_are_counted_apart() {
+        assert_eq!(account_key(" Nurse@Test "), account_key("nurse@test"));
+        assert_ne!(account_key("10.0.0.1"), ip_key("10.0.0.1"));
+        // The same failures weigh differently against each key's limit.
+        let state = failed(6);
+        assert_eq!(wait_secs(2, &state, 10, NOW), 2);
+        assert_eq!(wait_secs(2, &state, 50, NOW), 0);
+    }
+}
@@ -0,0 +1,234 @@
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::auth::{Auth, AuthUser};
+use crate::db::{
+    create_session, find_membership, find_session, find_user_by_id, mark_session_mfa, revoke_session,
+    rotate_session, set_session_organization, user_memberships,
+};
+use crate::models::{Session, User};
+
+/// Access token claims.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Claims {
+    /// User id.
+    pub sub: String,
+    pub role: String,
+    /// Organization the user acts for, when they belong to one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+    /// Session the token was issued for; revoking the session invalidates the token.
+    pub sid: String,
+    /// Whether the session passed a second factor.
+    #[serde(default)]
+    pub mfa: bool,
+    pub jti: String,
+    pub iss: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+/// A fresh access/refresh pair.
+pub struct SessionTokens {
+    pub user_id: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+}
+
+type AuthError = (StatusCode, String);
+
+fn unauthorized(message: &str) -> AuthError {
+    (StatusCode::UNAUTHORIZED, message.to_string())
+}
+
+fn internal(e: impl ToString) -> AuthError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// A refresh token is `<session id>.<random secret>`; only the secret's hash is stored.
+fn new_refresh_secret() -> String {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    URL_SAFE_NO_PAD.encode(secret)
+}
+
+fn is_live(session: &Session, now: DateTime<Utc>) -> bool {
+    session.revoked_at.is_none()
+        && DateTime::parse_from_rfc3339(&session.expires_at).is_ok_and(|expires| expires > now)
+}
+
+/// The organization a session should act for: `preferred` while the user is still a member
+/// of it, otherwise the user's first organization.
+async fn active_organization(auth: &Auth, user_id: &str, preferred: Option<&str>) -> Result<Option<String>, AuthError> {
+    if let Some(organization_id) = preferred
+        && find_membership(&auth.pool, user_id, organization_id).await.map_err(internal)?.is_some()
+    {
+        return Ok(Some(organization_id.to_string()));
+    }
+    let memberships = user_memberships(&auth.pool, user_id).await.map_err(internal)?;
+    Ok(memberships.into_iter().next().map(|m| m.organization_id))
+}
+
+fn sign_access_token(
+    auth: &Auth,
+    user: &User,
+    session_id: &str,
+    organization: Option<&str>,
+    mfa: bool,
+) -> Result<String, AuthError> {
+    let now = Utc::now().timestamp();
+    let claims = Claims {
+        sub: user.id.clone(),
+        role: user.role.clone(),
+        org: organization.map(str::to_string),
+        sid: session_id.to_string(),
+        mfa,
+        jti: uuid::Uuid::new_v4().to_string(),
+        iss: auth.config.issuer.clone(),
+        iat: now,
+        exp: now + auth.config.access_ttl_secs,
+    };
+    encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(&auth.config.secret)).map_err(internal)
+}
+
+/// Opens a session for a user who just proved who they are; `mfa` if that included a second factor.
+pub async fn issue_session(auth: &Auth, user: &User, mfa: bool) -> Result<SessionTokens, AuthError> {
+    let session_id = uuid::Uuid::new_v4().to_string();
+    let secret = new_refresh_secret();
+    let expires_at = Utc::now() + Duration::seconds(auth.config.refresh_ttl_secs);
+    let organization = active_organization(auth, &user.id, None).await?;
+
+    create_session(
+        &auth.pool,
+        &session_id,
+        &user.id,
+        &hash_secret(&secret),
+        organization.as_deref(),
+        mfa,
+        &expires_at.to_rfc3339(),
+    )
+    .await
+    .map_err(internal)?;
+
+    Ok(SessionTokens {
+        user_id: user.id.clone(),
+        access_token: sign_access_token(auth, user, &session_id, organization.as_deref(), mfa)?,
+        refresh_token: format!("{}.{}", session_id, secret),
+        expires_in: auth.config.access_ttl_secs,
+    })
+}
+
+/// Trades a refresh token for a new pair, rotating the refresh secret. Presenting a secret
+/// that was already rotated out means the token leaked, so the whole session is revoked.
+pub async fn refresh_session(auth: &Auth, refresh_token: &str) -> Result<SessionTokens, AuthError> {
+    let (session_id, secret) = refresh_token
+        .split_once('.')
+        .ok_or_else(|| unauthorized("Malformed refresh token"))?;
+    let session = find_session(&auth.pool, session_id)
+        .await
+        .map_err(internal)?
+        .filter(|s| is_live(s, Utc::now()))
+        .ok_or_else(|| unauthorized("Session expired or revoked"))?;
+
+    let presented = hash_secret(secret);
+    if !bool::from(presented.as_bytes().ct_eq(session.refresh_hash.as_bytes())) {
+        revoke_session(&auth.pool, &session.id).await.map_err(internal)?;
+        eprintln!("Refresh token reuse on session {}; session revoked", session.id);
+        return Err(unauthorized("Refresh token was already used; session revoked"));
+    }
+
+    let user = find_user_by_id(&auth.pool, &session.user_id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| unauthorized("User no longer exists"))?;
+
+    let new_secret = new_refresh_secret();
+    let expires_at = Utc::now() + Duration::seconds(auth.config.refresh_ttl_secs);
+    let rotated = rotate_session(&auth.pool, &session.id, &presented, &hash_secret(&new_secret), &expires_at.to_rfc3339())
+        .await
+        .map_err(internal)?;
+    if !rotated {
+        return Err(unauthorized("Refresh token was already used"));
+    }
+
+    let organization = active_organization(auth, &user.id, session.organization_id.as_deref()).await?;
+    if organization != session.organization_id {
+        set_session_organization(&auth.pool, &session.id, organization.as_deref())
+            .await
+            .map_err(internal)?;
+    }
+
+    Ok(SessionTokens {
+        user_id: user.id.clone(),
+        access_token: sign_access_token(auth, &user, &session.id, organization.as_deref(), session.mfa)?,
+        refresh_token: format!("{}.{}", session.id, new_secret),
+        expires_in: auth.config.access_ttl_secs,
+    })
+}
+
+/// Points the caller's session at another of their organizations and returns an access
+/// token acting for it. The refresh token is unchanged.
+pub async fn switch_organization(auth: &Auth, caller: &AuthUser, organization_id: &str) -> Result<String, AuthError> {
+    if find_membership(&auth.pool, &caller.user_id, organization_id).await.map_err(internal)?.is_none() {
+        return Err((StatusCode::FORBIDDEN, "Not a member of that organization".to_string()));
+    }
+    let user = find_user_by_id(&auth.pool, &caller.user_id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| unauthorized("User no longer exists"))?;
+
+    set_session_organization(&auth.pool, &caller.session_id, Some(organization_id))
+        .await
+        .map_err(internal)?;
+    sign_access_token(auth, &user, &caller.session_id, Some(organization_id), caller.mfa)
+}
+
+/// Records that the caller's session has now passed a second factor (right after enrolling)
+/// and returns an access token saying so.
+pub async fn elevate_session(auth: &Auth, caller: &AuthUser) -> Result<String, AuthError> {
+    let user = find_user_by_id(&auth.pool, &caller.user_id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| unauthorized("User no longer exists"))?;
+
+    mark_session_mfa(&auth.pool, &caller.session_id).await.map_err(internal)?;
+    sign_access_token(auth, &user, &caller.session_id, caller.organization.as_deref(), true)
+}
+
+/// Checks an access token's signature, issuer and expiry, then that its session is still live.
+pub async fn authenticate(auth: &Auth, token: &str) -> Result<AuthUser, AuthError> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[&auth.config.issuer]);
+    validation.leeway = 0;
+    let claims = decode::<Claims>(token, &DecodingKey::from_secret(&auth.config.secret), &validation)
+        .map_err(|e| unauthorized(&format!("Invalid access token: {}", e)))?
+        .claims;
+
+    let live = find_session(&auth.pool, &claims.sid)
+        .await
+        .map_err(internal)?
+        .is_some_and(|s| s.user_id == claims.sub && is_live(&s, Utc::now()));
+    if !live {
+        return Err(unauthorized("Session expired or revoked"));
+    }
+
+    Ok(AuthUser {
+        user_id: claims.sub,
+        role: claims.role,
+        organization: claims.org,
+        session_id: claims.sid,
+        mfa: claims.mfa,
+        api_key_scopes: None,
+    })
+}
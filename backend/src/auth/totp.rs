@@ -0,0 +1,115 @@
+use axum::http::StatusCode;
+use chrono::Utc;
+use qrcode::render::svg;
+use qrcode::QrCode;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::auth::Auth;
+use crate::db::{find_totp, record_totp_step, use_recovery_code};
+
+/// RFC 6238 defaults, which every authenticator app understands.
+const DIGITS: usize = 6;
+const STEP_SECS: u64 = 30;
+/// Codes from one step either side of now are accepted, for clock drift.
+const SKEW_STEPS: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+type AuthError = (StatusCode, String);
+
+fn internal(e: impl ToString) -> AuthError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// A fresh 160-bit secret, base32 encoded as authenticator apps expect.
+pub fn new_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    Secret::Raw(secret.to_vec()).to_encoded().to_string()
+}
+
+/// The generator for a stored secret. `issuer` and `account` label the entry in the
+/// authenticator app; neither may contain a colon, so those are replaced.
+pub fn build(secret: &str, issuer: &str, account: &str) -> Result<TOTP, AuthError> {
+    let bytes = Secret::Encoded(secret.to_string()).to_bytes().map_err(|e| internal(format!("{:?}", e)))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        DIGITS,
+        SKEW_STEPS as u8,
+        STEP_SECS,
+        bytes,
+        Some(issuer.replace(':', " ")),
+        account.replace(':', " "),
+    )
+    .map_err(internal)
+}
+
+/// The `otpauth://` URI for a generator and the same URI as an SVG QR code.
+pub fn provisioning(totp: &TOTP) -> Result<(String, String), AuthError> {
+    let uri = totp.get_url();
+    let qr = QrCode::new(uri.as_bytes())
+        .map_err(internal)?
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+    Ok((uri, qr))
+}
+
+/// The time step `code` was generated for, if it is valid now.
+pub fn matching_step(totp: &TOTP, code: &str) -> Option<i64> {
+    let current = Utc::now().timestamp() / STEP_SECS as i64;
+    (-SKEW_STEPS..=SKEW_STEPS)
+        .map(|offset| current + offset)
+        .filter(|step| *step >= 0)
+        .find(|step| bool::from(totp.generate(*step as u64 * STEP_SECS).as_bytes().ct_eq(code.trim().as_bytes())))
+}
+
+/// A new set of single-use recovery codes, formatted `xxxxx-xxxxx`.
+pub fn new_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 7];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            let code = Secret::Raw(bytes.to_vec()).to_encoded().to_string().to_ascii_lowercase();
+            format!("{}-{}", &code[..5], &code[5..10])
+        })
+        .collect()
+}
+
+/// Recovery codes are stored as SHA-256, ignoring case, dashes and spaces as typed.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+/// Checks a user's second factor: a current TOTP code, or an unused recovery code, which is
+/// then spent. A TOTP code is accepted once; replaying it within its window fails.
+pub async fn verify_second_factor(auth: &Auth, user_id: &str, account: &str, code: &str) -> Result<bool, AuthError> {
+    let Some(stored) = find_totp(&auth.pool, user_id).await.map_err(internal)? else {
+        return Ok(false);
+    };
+
+    let code = code.trim();
+    if code.len() == DIGITS && code.chars().all(|c| c.is_ascii_digit()) {
+        let totp = build(&stored.secret, &auth.config.issuer, account)?;
+        return match matching_step(&totp, code) {
+            Some(step) if step > stored.last_used_step => {
+                record_totp_step(&auth.pool, user_id, step).await.map_err(internal)
+            }
+            _ => Ok(false),
+        };
+    }
+
+    if stored.enabled_at.is_none() {
+        return Ok(false);
+    }
+    use_recovery_code(&auth.pool, user_id, &hash_recovery_code(code))
+        .await
+        .map_err(internal)
+}
@@ -0,0 +1,225 @@
+//! A minimal OpenID provider for trying single sign-on locally:
+//! `cargo run --bin mock_idp`, then point the backend's `OIDC_ISSUER` at it.
+//!
+//! `/authorize` logs in whoever the query says, without asking: `login_hint` (email),
+//! `groups` (comma-separated), `org_id` and `mfa=1` are copied into the ID token.
+//! Everything else (PKCE, nonce, single-use codes, signed ID tokens, JWKS) behaves like
+//! a real provider, so the backend's checks are exercised.
+
+use axum::{
+    extract::{Form, Query, State},
+    http::StatusCode,
+    response::Redirect,
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use rand::RngCore;
+use reqwest::Url;
+use rsa::pkcs1::EncodeRsaPrivateKey;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPrivateKey;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const KEY_ID: &str = "mock-1";
+
+struct PendingCode {
+    client_id: String,
+    redirect_uri: String,
+    code_challenge: String,
+    claims: Value,
+}
+
+struct Provider {
+    issuer: String,
+    client_id: Option<String>,
+    encoding_key: EncodingKey,
+    jwk: Value,
+    codes: Mutex<HashMap<String, PendingCode>>,
+}
+
+#[derive(Deserialize)]
+struct AuthorizeParams {
+    response_type: String,
+    client_id: String,
+    redirect_uri: String,
+    state: String,
+    #[serde(default)]
+    nonce: Option<String>,
+    code_challenge: String,
+    code_challenge_method: String,
+    #[serde(default)]
+    login_hint: Option<String>,
+    #[serde(default)]
+    groups: Option<String>,
+    #[serde(default)]
+    org_id: Option<String>,
+    #[serde(default)]
+    mfa: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenParams {
+    grant_type: String,
+    code: String,
+    redirect_uri: String,
+    client_id: String,
+    code_verifier: String,
+}
+
+type ApiError = (StatusCode, Json<Value>);
+
+fn oauth_error(error: &str, description: &str) -> ApiError {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": error, "error_description": description })))
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+async fn discovery(State(provider): State<Arc<Provider>>) -> Json<Value> {
+    Json(json!({
+        "issuer": provider.issuer,
+        "authorization_endpoint": format!("{}/authorize", provider.issuer),
+        "token_endpoint": format!("{}/token", provider.issuer),
+        "jwks_uri": format!("{}/jwks", provider.issuer),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+        "code_challenge_methods_supported": ["S256"],
+    }))
+}
+
+async fn jwks(State(provider): State<Arc<Provider>>) -> Json<Value> {
+    Json(json!({ "keys": [provider.jwk] }))
+}
+
+async fn authorize(
+    State(provider): State<Arc<Provider>>,
+    Query(params): Query<AuthorizeParams>,
+) -> Result<Redirect, ApiError> {
+    if params.response_type != "code" {
+        return Err(oauth_error("unsupported_response_type", "Only the code flow is supported"));
+    }
+    if params.code_challenge_method != "S256" {
+        return Err(oauth_error("invalid_request", "PKCE with S256 is required"));
+    }
+    if provider.client_id.as_ref().is_some_and(|id| *id != params.client_id) {
+        return Err(oauth_error("unauthorized_client", "Unknown client_id"));
+    }
+
+    let email = params.login_hint.unwrap_or_else(|| "staff@hospital.test".to_string());
+    let now = chrono::Utc::now().timestamp();
+    let mut claims = json!({
+        "iss": provider.issuer,
+        "aud": params.client_id,
+        "sub": hex::encode(&Sha256::digest(email.as_bytes())[..8]),
+        "email": email,
+        "email_verified": true,
+        "preferred_username": email.split('@').next().unwrap_or_default(),
+        "iat": now,
+        "exp": now + 300,
+        "amr": if params.mfa.as_deref() == Some("1") { json!(["pwd", "mfa"]) } else { json!(["pwd"]) },
+        "groups": params.groups.as_deref().unwrap_or_default().split(',').filter(|g| !g.is_empty()).collect::<Vec<_>>(),
+    });
+    if let Some(nonce) = params.nonce {
+        claims["nonce"] = json!(nonce);
+    }
+    if let Some(org_id) = params.org_id {
+        claims["org_id"] = json!(org_id);
+    }
+
+    let code = random_token();
+    provider.codes.lock().await.insert(
+        code.clone(),
+        PendingCode {
+            client_id: params.client_id,
+            redirect_uri: params.redirect_uri.clone(),
+            code_challenge: params.code_challenge,
+            claims,
+        },
+    );
+
+    let mut redirect = Url::parse(&params.redirect_uri).map_err(|e| oauth_error("invalid_request", &e.to_string()))?;
+    redirect.query_pairs_mut().append_pair("code", &code).append_pair("state", &params.state);
+    Ok(Redirect::to(redirect.as_str()))
+}
+
+async fn token(
+    State(provider): State<Arc<Provider>>,
+    Form(params): Form<TokenParams>,
+) -> Result<Json<Value>, ApiError> {
+    if params.grant_type != "authorization_code" {
+        return Err(oauth_error("unsupported_grant_type", "Only authorization_code is supported"));
+    }
+    let pending = provider
+        .codes
+        .lock()
+        .await
+        .remove(&params.code)
+        .ok_or_else(|| oauth_error("invalid_grant", "Unknown or used code"))?;
+    if pending.client_id != params.client_id || pending.redirect_uri != params.redirect_uri {
+        return Err(oauth_error("invalid_grant", "Code was issued to another client or redirect_uri"));
+    }
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(params.code_verifier.as_bytes()));
+    if challenge != pending.code_challenge {
+        return Err(oauth_error("invalid_grant", "PKCE verification failed"));
+    }
+
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(KEY_ID.to_string());
+    let id_token = encode(&header, &pending.claims, &provider.encoding_key)
+        .map_err(|e| oauth_error("server_error", &e.to_string()))?;
+    Ok(Json(json!({
+        "access_token": random_token(),
+        "token_type": "Bearer",
+        "expires_in": 300,
+        "id_token": id_token,
+    })))
+}
+
+#[tokio::main]
+async fn main() {
+    let addr = env::var("MOCK_IDP_ADDR").unwrap_or_else(|_| "127.0.0.1:4400".to_string());
+    let issuer = env::var("MOCK_IDP_ISSUER").unwrap_or_else(|_| format!("http://{}", addr));
+
+    println!("🔑 Generating the mock provider's signing key...");
+    let key = RsaPrivateKey::new(&mut rand::rngs::OsRng, 2048).expect("failed to generate a key");
+    let der = key.to_pkcs1_der().expect("failed to encode the key");
+    let jwk = json!({
+        "kty": "RSA",
+        "use": "sig",
+        "alg": "RS256",
+        "kid": KEY_ID,
+        "n": URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+        "e": URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+    });
+
+    let provider = Arc::new(Provider {
+        issuer: issuer.clone(),
+        client_id: env::var("MOCK_IDP_CLIENT_ID").ok().filter(|v| !v.is_empty()),
+        encoding_key: EncodingKey::from_rsa_der(der.as_bytes()),
+        jwk,
+        codes: Mutex::new(HashMap::new()),
+    });
+
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/jwks", get(jwks))
+        .route("/authorize", get(authorize))
+        .route("/token", post(token))
+        .with_state(provider);
+
+    println!("🪪 Mock OpenID provider at {}", issuer);
+    let listener = tokio::net::TcpListener::bind(&addr).await.expect("Failed to bind address");
+    axum::serve(listener, app).await.unwrap();
+}
@@ -0,0 +1,345 @@
+use crate::db::entities::ProductDetails;
+
+/// Columns a catalog CSV may have; the first six are required, and `gtin` may be left empty
+/// on rows whose NDC gives one.
+const COLUMNS: [&str; 11] = [
+    "gtin",
+    "name",
+    "active_ingredient",
+    "strength",
+    "dosage_form",
+    "pack_size",
+    "ndc",
+    "storage_min_celsius",
+    "storage_max_celsius",
+    "storage_conditions",
+    "schedule",
+];
+
+/// A CSV row's line and either the entry it holds or why it is invalid.
+pub type CsvRow = (usize, Result<ProductDetails, String>);
+
+fn gtin_check_digit(body: &[u32]) -> u32 {
+    // Weights alternate 3 and 1, starting with 3 next to the check digit.
+    let sum: u32 = body.iter().rev().enumerate().map(|(i, d)| if i % 2 == 0 { d * 3 } else { *d }).sum();
+    (10 - sum % 10) % 10
+}
+
+/// A GTIN-8, -12, -13 or -14 as the 14 digits it is stored under, its check digit verified.
+pub fn normalize_gtin(value: &str) -> Result<String, String> {
+    let compact: String = value.chars().filter(|c| !c.is_whitespace() && *c != '-').collect();
+    if !compact.chars().all(|c| c.is_ascii_digit()) || ![8, 12, 13, 14].contains(&compact.len()) {
+        return Err(format!("GTIN '{}' must be 8, 12, 13 or 14 digits", value.trim()));
+    }
+    let gtin = format!("{:0>14}", compact);
+    let digits: Vec<u32> = gtin.chars().filter_map(|c| c.to_digit(10)).collect();
+    if gtin_check_digit(&digits[..13]) != digits[13] {
+        return Err(format!("GTIN '{}' has a wrong check digit", value.trim()));
+    }
+    Ok(gtin)
+}
+
+/// A US National Drug Code in the 11-digit `5-4-2` form it is stored under, with the GTIN-14
+/// it is packed into when it was given in one of its 10-digit forms (`4-4-2`, `5-3-2`, `5-4-1`).
+fn normalize_ndc(value: &str) -> Result<(String, Option<String>), String> {
+    let value = value.trim();
+    let invalid = || format!("NDC '{}' must be 4-4-2, 5-3-2, 5-4-1 or 5-4-2 digits", value);
+    let segments: Vec<&str> = if value.contains('-') {
+        value.split('-').collect()
+    } else if value.len() == 11 {
+        vec![&value[..5], &value[5..9], &value[9..]]
+    } else {
+        return Err(invalid());
+    };
+    if segments.len() != 3 || !segments.iter().all(|s| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())) {
+        return Err(invalid());
+    }
+    let lengths = (segments[0].len(), segments[1].len(), segments[2].len());
+    if !matches!(lengths, (4, 4, 2) | (5, 3, 2) | (5, 4, 1) | (5, 4, 2)) {
+        return Err(invalid());
+    }
+    let ndc = format!("{:0>5}-{:0>4}-{:0>2}", segments[0], segments[1], segments[2]);
+    let gtin = (lengths != (5, 4, 2)).then(|| {
+        let body = format!("003{}", segments.concat());
+        let digits: Vec<u32> = body.chars().filter_map(|c| c.to_digit(10)).collect();
+        format!("{}{}", body, gtin_check_digit(&digits))
+    });
+    Ok((ndc, gtin))
+}
+
+/// A controlled-substance schedule as `I` to `V`; `CII`, `C-II`, `Schedule 2` and `2` all
+/// read as `II`. Empty means the product is not controlled.
+pub fn normalize_schedule(value: &str) -> Result<Option<String>, String> {
+    let compact: String = value.chars().filter(|c| !c.is_whitespace() && *c != '-').collect::<String>().to_ascii_uppercase();
+    let compact = compact.strip_prefix("SCHEDULE").unwrap_or(&compact);
+    let compact = compact.strip_prefix('C').unwrap_or(compact);
+    let schedule = match compact {
+        "" => return Ok(None),
+        "I" | "1" => "I",
+        "II" | "2" => "II",
+        "III" | "3" => "III",
+        "IV" | "4" => "IV",
+        "V" | "5" => "V",
+        _ => return Err(format!("Schedule '{}' is not one of I, II, III, IV or V", value.trim())),
+    };
+    Ok(Some(schedule.to_string()))
+}
+
+fn required(field: &str, value: &str) -> Result<String, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(format!("{} is required", field));
+    }
+    Ok(value.to_string())
+}
+
+/// Normalizes a catalog entry: codes in their stored form, text trimmed, a positive pack size
+/// and a storage range that makes sense. The GTIN may be left empty when a 10-digit NDC gives it.
+pub fn check_product(product: ProductDetails) -> Result<ProductDetails, String> {
+    let (ndc, ndc_gtin) = match product.ndc.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+        Some(ndc) => normalize_ndc(ndc).map(|(ndc, gtin)| (Some(ndc), gtin))?,
+        None => (None, None),
+    };
+    let gtin = match product.gtin.trim() {
+        "" => ndc_gtin.ok_or("A GTIN, or an NDC in a 10-digit form, is required".to_string())?,
+        gtin => normalize_gtin(gtin)?,
+    };
+    if product.pack_size <= 0 {
+        return Err("pack_size must be positive".to_string());
+    }
+    let limits = [product.storage_min_celsius, product.storage_max_celsius];
+    if limits.iter().flatten().any(|t| !t.is_finite()) {
+        return Err("Storage temperatures must be numbers".to_string());
+    }
+    if let [Some(min), Some(max)] = limits
+        && min > max
+    {
+        return Err("storage_min_celsius is above storage_max_celsius".to_string());
+    }
+    Ok(ProductDetails {
+        gtin,
+        ndc,
+        name: required("name", &product.name)?,
+        active_ingredient: required("active_ingredient", &product.active_ingredient)?,
+        strength: required("strength", &product.strength)?,
+        dosage_form: required("dosage_form", &product.dosage_form)?,
+        pack_size: product.pack_size,
+        storage_min_celsius: product.storage_min_celsius,
+        storage_max_celsius: product.storage_max_celsius,
+        storage_conditions: product.storage_conditions.map(|s| s.trim().to_string()).filter(|s| !s.is_empty()),
+        schedule: match product.schedule.as_deref() {
+            Some(schedule) => normalize_schedule(schedule)?,
+            None => None,
+        },
+    })
+}
+
+/// Splits CSV text into records, each with the line it starts on. Fields may be quoted with
+/// `"`, doubling any `"` inside; quoted fields may span lines. Blank lines are skipped.
+fn parse_csv(text: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let (mut line, mut start) = (1, 1);
+    let (mut quoted, mut was_quoted) = (false, false);
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() && !was_quoted => (quoted, was_quoted) = (true, true),
+            ',' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                was_quoted = false;
+            }
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                if record.len() > 1 || !record[0].is_empty() || was_quoted {
+                    records.push((start, std::mem::take(&mut record)));
+                }
+                record.clear();
+                was_quoted = false;
+                line += 1;
+                start = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if quoted {
+        return Err(format!("The quoted field starting on line {} is never closed", start));
+    }
+    if !record.is_empty() || !field.is_empty() || was_quoted {
+        record.push(field);
+        records.push((start, record));
+    }
+    Ok(records)
+}
+
+fn parse_number<T: std::str::FromStr>(column: &str, value: &str) -> Result<Option<T>, String> {
+    match value.trim() {
+        "" => Ok(None),
+        v => v.parse().map(Some).map_err(|_| format!("{} '{}' is not a number", column, v)),
+    }
+}
+
+/// Reads catalog entries from CSV with a header row naming [`COLUMNS`] in any order. Each
+/// row comes back with its line and either the entry, normalized, or why it is invalid;
+/// a header that cannot be used fails the whole file.
+pub fn products_from_csv(text: &str) -> Result<Vec<CsvRow>, String> {
+    let mut records = parse_csv(text)?.into_iter();
+    let (_, header) = records.next().ok_or("The file is empty".to_string())?;
+    let header: Vec<String> = header.iter().map(|h| h.trim().to_ascii_lowercase()).collect();
+    if let Some(unknown) = header.iter().find(|h| !COLUMNS.contains(&h.as_str())) {
+        return Err(format!("Unknown column '{}'; columns are {}", unknown, COLUMNS.join(", ")));
+    }
+    if let Some(missing) = COLUMNS[..6].iter().find(|c| !header.iter().any(|h| h == *c)) {
+        return Err(format!("The header has no '{}' column", missing));
+    }
+    if let Some(repeated) = header.iter().enumerate().find(|(i, h)| header[..*i].contains(h)) {
+        return Err(format!("Column '{}' appears twice", repeated.1));
+    }
+
+    Ok(records.map(|(line, values)| (line, product_from_row(&header, &values))).collect())
+}
+
+fn product_from_row(header: &[String], values: &[String]) -> Result<ProductDetails, String> {
+    if values.len() != header.len() {
+        return Err(format!("Expected {} fields, found {}", header.len(), values.len()));
+    }
+    let get = |column: &str| header.iter().position(|h| h == column).map(|i| values[i].as_str()).unwrap_or("");
+    let optional = |column: &str| Some(get(column).to_string()).filter(|v| !v.trim().is_empty());
+    check_product(ProductDetails {
+        gtin: get("gtin").to_string(),
+        ndc: optional("ndc"),
+        name: get("name").to_string(),
+        active_ingredient: get("active_ingredient").to_string(),
+        strength: get("strength").to_string(),
+        dosage_form: get("dosage_form").to_string(),
+        pack_size: parse_number("pack_size", get("pack_size"))?.ok_or("pack_size is required".to_string())?,
+        storage_min_celsius: parse_number("storage_min_celsius", get("storage_min_celsius"))?,
+        storage_max_celsius: parse_number("storage_max_celsius", get("storage_max_celsius"))?,
+        storage_conditions: optional("storage_conditions"),
+        schedule: optional("schedule"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gtins_are_padded_to_14_digits() {
+        assert_eq!(normalize_gtin("96385074").unwrap(), "00000096385074");
+        assert_eq!(normalize_gtin("036000291452").unwrap(), "00036000291452");
+        assert_eq!(normalize_gtin("4006381333931").unwrap(), "04006381333931");
+        assert_eq!(normalize_gtin("04006381333931").unwrap(), "04006381333931");
+        assert_eq!(normalize_gtin(" 400-6381 333931 ").unwrap(), "04006381333931");
+    }
+
+    #[test]
+    fn gtins_with_a_wrong_check_digit_or_length_are_refused() {
+        assert!(normalize_gtin("4006381333932").unwrap_err().contains("check digit"));
+        assert!(normalize_gtin("036000291453").unwrap_err().contains("check digit"));
+        assert!(normalize_gtin("4006381333").unwrap_err().contains("8, 12, 13 or 14 digits"));
+        assert!(normalize_gtin("40063813339x1").is_err());
+        assert!(normalize_gtin("").is_err());
+    }
+
+    #[test]
+    fn ten_digit_ndcs_are_stored_as_5_4_2_and_packed_into_a_gtin() {
+        let gtin = Some("00312345678906".to_string());
+        assert_eq!(normalize_ndc("1234-5678-90").unwrap(), ("01234-5678-90".to_string(), gtin.clone()));
+        assert_eq!(normalize_ndc("12345-678-90").unwrap(), ("12345-0678-90".to_string(), gtin.clone()));
+        assert_eq!(normalize_ndc("12345-6789-0").unwrap(), ("12345-6789-00".to_string(), gtin.clone()));
+        // The packed GTIN carries a valid check digit.
+        assert_eq!(normalize_gtin(gtin.as_deref().unwrap()).unwrap(), "00312345678906");
+    }
+
+    #[test]
+    fn eleven_digit_ndcs_have_no_gtin() {
+        assert_eq!(normalize_ndc("12345-6789-01").unwrap(), ("12345-6789-01".to_string(), None));
+        assert_eq!(normalize_ndc("12345678901").unwrap(), ("12345-6789-01".to_string(), None));
+    }
+
+    #[test]
+    fn other_ndc_shapes_are_refused() {
+        for ndc in ["1234-567-89", "123-4567-89", "1234567890", "12345-6789", "12a45-6789-01", "12345--01", ""] {
+            assert!(normalize_ndc(ndc).is_err(), "{}", ndc);
+        }
+    }
+
+    #[test]
+    fn schedules_read_in_any_common_spelling() {
+        for value in ["II", "ii", "CII", "C-II", "c 2", "Schedule 2", "schedule II", "2"] {
+            assert_eq!(normalize_schedule(value).unwrap().as_deref(), Some("II"), "{}", value);
+        }
+        assert_eq!(normalize_schedule("CV").unwrap().as_deref(), Some("V"));
+        assert_eq!(normalize_schedule("1").unwrap().as_deref(), Some("I"));
+        assert_eq!(normalize_schedule("  ").unwrap(), None);
+        for value in ["VI", "6", "0", "controlled", "C-X"] {
+            assert!(normalize_schedule(value).is_err(), "{}", value);
+        }
+    }
+
+    fn fields(records: &[(usize, Vec<String>)]) -> Vec<(usize, Vec<&str>)> {
+        records.iter().map(|(line, r)| (*line, r.iter().map(String::as_str).collect())).collect()
+    }
+
+    #[test]
+    fn csv_fields_may_be_quoted_with_commas_and_doubled_quotes() {
+        let records = parse_csv("a,b,c\n\"x, y\",\"say \"\"hi\"\"\",\"\"\r\nplain,,end\n").unwrap();
+        assert_eq!(
+            fields(&records),
+            [(1, vec!["a", "b", "c"]), (2, vec!["x, y", "say \"hi\"", ""]), (3, vec!["plain", "", "end"])]
+        );
+    }
+
+    #[test]
+    fn quoted_csv_fields_may_span_lines() {
+        let records = parse_csv("name,notes\nAspirin,\"keep dry\nand cool\"\n\nIbuprofen,\"\"\n").unwrap();
+        assert_eq!(
+            fields(&records),
+            [(1, vec!["name", "notes"]), (2, vec!["Aspirin", "keep dry\nand cool"]), (5, vec!["Ibuprofen", ""])]
+        );
+    }
+
+    #[test]
+    fn csv_without_a_final_newline_or_with_a_byte_order_mark_is_read() {
+        let records = parse_csv("\u{feff}a,b\n1,2").unwrap();
+        assert_eq!(fields(&records), [(1, vec!["a", "b"]), (2, vec!["1", "2"])]);
+    }
+
+    #[test]
+    fn an_unclosed_quote_fails_the_file() {
+        assert!(parse_csv("a,b\n1,\"open\n2,3\n").unwrap_err().contains("line 2"));
+    }
+
+    #[test]
+    fn csv_rows_become_checked_products() {
+        let text = "name,gtin,active_ingredient,strength,dosage_form,pack_size,ndc,schedule\n\
+                    Aspirin,4006381333931,Acetylsalicylic acid,500mg,tablet,20,,\n\
+                    Oxy,,Oxycodone,10mg,tablet,30,1234-5678-90,CII\n\
+                    Broken,4006381333932,X,1mg,tablet,1,,\n";
+        let rows = products_from_csv(text).unwrap();
+        let first = rows[0].1.as_ref().unwrap();
+        assert_eq!((rows[0].0, first.gtin.as_str(), first.schedule.as_deref()), (2, "04006381333931", None));
+        let second = rows[1].1.as_ref().unwrap();
+        assert_eq!((second.gtin.as_str(), second.schedule.as_deref()), ("00312345678906", Some("II")));
+        assert!(rows[2].1.as_ref().unwrap_err().contains("check digit"));
+        assert!(products_from_csv("name,gtin\nA,1\n").unwrap_err().contains("active_ingredient"));
+        assert!(products_from_csv("name,colour\n").unwrap_err().contains("Unknown column"));
+    }
+}
@@ -0,0 +1,233 @@
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use rsa::RsaPrivateKey;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::fmt;
+use std::str::FromStr;
+use tokio::sync::Mutex;
+
+use crate::catalog::normalize_schedule;
+use crate::db::entities::{
+    controlled_quota_used, find_batch, find_catalog_product, find_controlled_license, find_controlled_quota, medicine_key, LedgerTx,
+    Organization, ProductDetails,
+};
+use crate::p2p::Node;
+use crate::utils::signatures::sign_data;
+
+type ApiError = (StatusCode, String);
+
+fn internal(e: sqlx::Error) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// Quota checks and the signatures that spend quota happen one at a time, so two receipts
+/// cannot both fit under the same remaining quota.
+pub static CONTROLLED_LOCK: Mutex<()> = Mutex::const_new(());
+
+const SCHEDULES: [&str; 5] = ["I", "II", "III", "IV", "V"];
+
+/// The calendar period a quota covers, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Period {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Period::Month => "month",
+            Period::Quarter => "quarter",
+            Period::Year => "year",
+        }
+    }
+
+    /// The start of the period holding `at`, and the start of the next.
+    pub fn bounds(self, at: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        let (year, month) = (at.year(), at.month());
+        let first = match self {
+            Period::Month => month,
+            Period::Quarter => (month - 1) / 3 * 3 + 1,
+            Period::Year => 1,
+        };
+        let length = match self {
+            Period::Month => 1,
+            Period::Quarter => 3,
+            Period::Year => 12,
+        };
+        let start_of = |year: i32, month: u32| Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap();
+        let next = first + length;
+        let end = if next > 12 { start_of(year + 1, next - 12) } else { start_of(year, next) };
+        (start_of(year, first), end)
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "month" => Ok(Period::Month),
+            "quarter" => Ok(Period::Quarter),
+            "year" => Ok(Period::Year),
+            other => Err(format!("Unknown quota period '{}'; use month, quarter or year", other)),
+        }
+    }
+}
+
+/// Schedules as a license stores them: normalized, in order, each once, comma-separated.
+pub fn license_schedules(values: &[String]) -> Result<String, String> {
+    let mut schedules = Vec::new();
+    for value in values {
+        if let Some(schedule) = normalize_schedule(value)? {
+            schedules.push(schedule);
+        }
+    }
+    if schedules.is_empty() {
+        return Err("A license must cover at least one schedule".to_string());
+    }
+    Ok(SCHEDULES
+        .iter()
+        .filter(|s| schedules.iter().any(|c| c == *s))
+        .copied()
+        .collect::<Vec<_>>()
+        .join(","))
+}
+
+/// Refuses unless `organization` holds an unexpired controlled-substance license covering `schedule`.
+pub async fn require_licensed(pool: &SqlitePool, organization: &Organization, schedule: &str) -> Result<(), ApiError> {
+    let license = find_controlled_license(pool, &organization.id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::FORBIDDEN, format!("{} holds no controlled-substance license", organization.name)))?;
+    if let Some(expires_at) = &license.expires_at
+        && NaiveDate::parse_from_str(expires_at, "%Y-%m-%d").is_ok_and(|d| d <= Utc::now().date_naive())
+    {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("The controlled-substance license of {} expired on {}", organization.name, expires_at),
+        ));
+    }
+    if !license.schedules.split(',').any(|s| s == schedule) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("The controlled-substance license of {} does not cover schedule {}", organization.name, schedule),
+        ));
+    }
+    Ok(())
+}
+
+/// The catalog product behind `gtin`, if it has a schedule.
+pub async fn scheduled_product(pool: &SqlitePool, gtin: Option<&str>) -> Result<Option<ProductDetails>, sqlx::Error> {
+    let Some(gtin) = gtin else {
+        return Ok(None);
+    };
+    Ok(find_catalog_product(pool, gtin).await?.map(|p| p.product).filter(|p| p.schedule.is_some()))
+}
+
+/// The scheduled product a batch holds, whether the batch is sealed or still pending here.
+pub async fn batch_schedule(node: &Node, batch_id: &str) -> Result<Option<ProductDetails>, ApiError> {
+    let gtin = match find_batch(&node.pool, batch_id).await.map_err(internal)? {
+        Some(batch) => batch.terms.gtin,
+        None => node.mempool.lock().await.pending().iter().find_map(|tx| match tx {
+            LedgerTx::Batch(b) if b.batch_id == batch_id => b.terms.gtin.clone(),
+            _ => None,
+        }),
+    };
+    scheduled_product(&node.pool, gtin.as_deref()).await.map_err(internal)
+}
+
+/// Refuses unless `organization` is licensed for the batch's schedule, if it has one. Every
+/// path that moves scheduled stock out of or within an organization checks this.
+pub async fn require_licensed_batch(node: &Node, organization: &Organization, batch_id: &str) -> Result<(), ApiError> {
+    if let Some(product) = batch_schedule(node, batch_id).await? {
+        require_licensed(&node.pool, organization, product.schedule.as_deref().unwrap_or_default()).await?;
+    }
+    Ok(())
+}
+
+/// Refuses if taking in `quantity` more units of `active_ingredient` would put the
+/// organization over its quota for the current period. Without a quota there is no limit.
+/// Hold [`CONTROLLED_LOCK`] from this check until the units are recorded.
+pub async fn check_quota(node: &Node, organization: &Organization, active_ingredient: &str, quantity: i64) -> Result<(), ApiError> {
+    let key = medicine_key(active_ingredient);
+    let Some(quota) = find_controlled_quota(&node.pool, &organization.id, &key).await.map_err(internal)? else {
+        return Ok(());
+    };
+    let period: Period = quota.period.parse().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let (start, end) = period.bounds(Utc::now());
+    let mut used = controlled_quota_used(&node.pool, &organization.id, &key, &start.to_rfc3339(), &end.to_rfc3339())
+        .await
+        .map_err(internal)?;
+    // Batches made here but not sealed yet count too.
+    let pending: Vec<(Option<String>, i64)> = node
+        .mempool
+        .lock()
+        .await
+        .pending()
+        .iter()
+        .filter_map(|tx| match tx {
+            LedgerTx::Batch(b) if b.organization == organization.id && b.terms.parent_batch_id.is_none() => {
+                Some((b.terms.gtin.clone(), b.terms.quantity.unwrap_or(0)))
+            }
+            _ => None,
+        })
+        .collect();
+    for (gtin, made) in pending {
+        if scheduled_product(&node.pool, gtin.as_deref())
+            .await
+            .map_err(internal)?
+            .is_some_and(|p| medicine_key(&p.active_ingredient) == key)
+        {
+            used += made;
+        }
+    }
+    if used + quantity > quota.quantity {
+        return Err((
+            StatusCode::CONFLICT,
+            format!(
+                "{} may take in {} units of {} per {}; {} are used this {}, so {} more would exceed it",
+                organization.name, quota.quantity, quota.active_ingredient, quota.period, used, quota.period, quantity
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// The terms a party signs for a controlled transfer.
+pub struct SignedTerms<'a> {
+    pub transfer_id: &'a str,
+    pub batch_id: &'a str,
+    pub gtin: &'a str,
+    pub quantity: i64,
+    pub from_organization: &'a str,
+    pub to_organization: &'a str,
+}
+
+fn signed_data(terms: &SignedTerms, party: &str, user_id: &str, signed_at: &str) -> String {
+    let data = format!(
+        "{}|{}|{}|{}|{}|{}|{}|{}|{}",
+        terms.transfer_id, terms.batch_id, terms.gtin, terms.quantity, terms.from_organization, terms.to_organization, party, user_id, signed_at
+    );
+    hex::encode(Sha256::digest(data.as_bytes()))
+}
+
+/// The node's RSA signature, base64, over the transfer's terms, the signing party (`sender` or
+/// `receiver`), the user and the time.
+pub fn signature(private_key: &RsaPrivateKey, terms: &SignedTerms, party: &str, user_id: &str, signed_at: &str) -> String {
+    STANDARD.encode(sign_data(private_key, signed_data(terms, party, user_id, signed_at).as_bytes()))
+}
+
+/// Whether `signature` is [`signature`] over the same terms, made with the key behind `public_key`.
+pub fn signature_holds(public_key: &str, terms: &SignedTerms, party: &str, user_id: &str, signed_at: &str, signature: &str) -> bool {
+    crate::db::entities::signature_holds(public_key, signature, &signed_data(terms, party, user_id, signed_at))
+}
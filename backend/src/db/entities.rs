@@ -1,41 +1,47 @@
-use sqlx::SqlitePool;
+use sqlx::{FromRow, Row, SqliteConnection, SqlitePool};
 use uuid::Uuid;
-use chrono::Utc;
 use sha2::{Sha256, Digest};
-use rsa::{RsaPrivateKey, RsaPublicKey, pkcs1::DecodeRsaPublicKey};
-use rsa::signature::Verifier;
-use base64::{decode, encode};
+use rsa::{RsaPublicKey, pkcs1::DecodeRsaPublicKey};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
 
-/// Structs
-#[derive(sqlx::FromRow, Debug)]
-pub struct Company {
-    pub id: String,
-    pub name: String,
-    pub location: String,
-    pub license_id: String,
-    pub stock_needed: String,
-}
+use crate::db::{add_missing_columns, make_append_only};
+use crate::utils::merkle::build_merkle_root;
+use crate::utils::signatures::verify_signature;
 
-#[derive(sqlx::FromRow, Debug)]
-pub struct Hospital {
+/// A company, hospital or customer record, seen as something users can belong to.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct Organization {
     pub id: String,
+    /// `company`, `hospital` or `customer`: the table the record lives in.
+    pub organization_type: String,
     pub name: String,
-    pub location: String,
-    pub registration_id: String,
 }
 
-#[derive(sqlx::FromRow, Debug)]
-pub struct Customer {
-    pub id: String,
-    pub name: String,
-    pub location: String,
-    pub registration_id: String,
+/// The license an organization registered with and where the regulator's review of it stands.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct Registration {
+    pub organization_id: String,
+    pub organization_type: String,
+    pub organization_name: String,
+    pub jurisdiction: String,
+    pub license_number: String,
+    /// `YYYY-MM-DD`; the organization stops being active on that day.
+    pub license_expires_at: Option<String>,
+    /// `pending`, `approved`, `rejected` or `suspended`.
+    pub status: String,
+    pub status_reason: Option<String>,
+    pub submitted_by: String,
+    pub submitted_at: String,
+    pub reviewed_by: Option<String>,
+    pub reviewed_at: Option<String>,
 }
 
-#[derive(sqlx::FromRow, Debug)]
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
 pub struct MedicineBatch {
-    pub id: i64,
     pub batch_id: String,
+    /// Company that created the batch; covered by the batch hash and so by its signature.
+    pub organization: String,
     pub medicine_name: String,
     pub source: String,
     pub destination: String,
@@ -44,28 +50,406 @@ pub struct MedicineBatch {
     pub previous_hash: String,
     pub signature: Option<String>,
     pub public_key: Option<String>,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub terms: BatchTerms,
+}
+
+/// Optional terms a batch is created with. Each one that is set is covered by the batch hash;
+/// a batch without any hashes as batches did before terms existed.
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BatchTerms {
+    /// `YYYY-MM-DD`; the batch may not be used from that day.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    /// Storage range; a temperature reading outside it is a cold-chain excursion.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_min_celsius: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_max_celsius: Option<f64>,
+    /// Units in the batch when created. Batches without one are left out of inventory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantity: Option<i64>,
+    /// The batch this one was split from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_batch_id: Option<String>,
+    /// GTIN-14 of the catalog product the batch is of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gtin: Option<String>,
+    /// Serial numbers of the batch's packs (GS1 AI 21), one per unit. Only packs listed here
+    /// can be dispensed by serial from the batch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(json)]
+    pub serials: Option<Vec<String>>,
+}
+
+impl BatchTerms {
+    /// `|name=value` for each term that is set, in a fixed order, for hashing.
+    fn canonical(&self) -> String {
+        let mut out = String::new();
+        if let Some(expires_at) = &self.expires_at {
+            out.push_str(&format!("|expires_at={expires_at}"));
+        }
+        if let Some(min) = self.storage_min_celsius {
+            out.push_str(&format!("|storage_min_celsius={min}"));
+        }
+        if let Some(max) = self.storage_max_celsius {
+            out.push_str(&format!("|storage_max_celsius={max}"));
+        }
+        if let Some(quantity) = self.quantity {
+            out.push_str(&format!("|quantity={quantity}"));
+        }
+        if let Some(parent_batch_id) = &self.parent_batch_id {
+            out.push_str(&format!("|parent_batch_id={parent_batch_id}"));
+        }
+        if let Some(gtin) = &self.gtin {
+            out.push_str(&format!("|gtin={gtin}"));
+        }
+        // Serials hold no spaces, so joining on one is unambiguous.
+        if let Some(serials) = &self.serials {
+            out.push_str(&format!("|serials={}", serials.join(" ")));
+        }
+        out
+    }
+}
+
+/// A batch changing hands; chained to the batch's previous transaction.
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct CustodyEvent {
+    pub batch_id: String,
+    pub from_location: String,
+    pub to_location: String,
+    pub timestamp: String,
+    pub hash: String,
+    pub previous_hash: String,
+    pub signature: String,
+    pub public_key: String,
+    /// RFC 3339 arrival time the sender expects; covered by the hash when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_arrival: Option<String>,
+}
+
+/// Units of a batch dispensed to patients. The ledger records where and how many, and which
+/// packs; the patient and prescription stay in the dispensing record on the node that made it.
+/// It does not move the batch, so it is not chained to the batch's transactions.
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct DispensingEvent {
+    pub dispensing_id: String,
+    pub batch_id: String,
+    /// The batch's location, as custody records it: the dispensing organization.
+    pub location: String,
+    pub quantity: i64,
+    /// Serials decommissioned; empty when dispensed by quantity.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[sqlx(json)]
+    pub serials: Vec<String>,
+    pub timestamp: String,
+    pub hash: String,
+    pub signature: String,
+    pub public_key: String,
+}
+
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct BlockHeader {
+    pub height: i64,
+    pub hash: String,
+    pub previous_hash: String,
+    pub merkle_root: String,
+    pub timestamp: String,
+    pub producer: String,
+    pub signature: String,
+    pub tx_count: i64,
 }
 
-#[derive(sqlx::FromRow, Debug)]
+/// A ledger transaction, as held in the mempool and sealed into blocks.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LedgerTx {
+    Batch(MedicineBatch),
+    Custody(CustodyEvent),
+    Dispensing(DispensingEvent),
+}
+
+impl LedgerTx {
+    pub fn hash(&self) -> &str {
+        match self {
+            LedgerTx::Batch(b) => &b.hash,
+            LedgerTx::Custody(c) => &c.hash,
+            LedgerTx::Dispensing(d) => &d.hash,
+        }
+    }
+
+    pub fn batch_id(&self) -> &str {
+        match self {
+            LedgerTx::Batch(b) => &b.batch_id,
+            LedgerTx::Custody(c) => &c.batch_id,
+            LedgerTx::Dispensing(d) => &d.batch_id,
+        }
+    }
+
+    /// Hash recomputed from the transaction's fields.
+    pub fn recompute_hash(&self) -> String {
+        match self {
+            LedgerTx::Batch(b) => compute_batch_hash(b),
+            LedgerTx::Custody(c) => compute_custody_hash(
+                &c.batch_id, &c.from_location, &c.to_location, &c.timestamp, &c.previous_hash, c.expected_arrival.as_deref(),
+            ),
+            LedgerTx::Dispensing(d) => compute_dispensing_hash(d),
+        }
+    }
+
+    pub fn signature(&self) -> Option<&str> {
+        match self {
+            LedgerTx::Batch(b) => b.signature.as_deref(),
+            LedgerTx::Custody(c) => Some(&c.signature),
+            LedgerTx::Dispensing(d) => Some(&d.signature),
+        }
+    }
+
+    pub fn public_key(&self) -> Option<&str> {
+        match self {
+            LedgerTx::Batch(b) => b.public_key.as_deref(),
+            LedgerTx::Custody(c) => Some(&c.public_key),
+            LedgerTx::Dispensing(d) => Some(&d.public_key),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub transactions: Vec<LedgerTx>,
+}
+
+/// A stored block as seen in fork reports: which branch it is on and who sealed it.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct ForkBlock {
+    pub height: i64,
+    pub hash: String,
+    pub previous_hash: String,
+    pub producer: String,
+    pub timestamp: String,
+    pub status: String,
+}
+
+/// Two or more blocks built on the same parent.
+#[derive(Serialize, Debug)]
+pub struct Fork {
+    pub fork_height: i64,
+    pub parent_hash: String,
+    pub blocks: Vec<ForkBlock>,
+}
+
+#[derive(sqlx::FromRow, Serialize, Debug)]
+pub struct ChainReorg {
+    pub id: i64,
+    pub timestamp: String,
+    pub fork_height: i64,
+    pub old_tip: String,
+    pub new_tip: String,
+    pub orphaned_blocks: i64,
+}
+
+#[derive(sqlx::FromRow, Serialize, Debug)]
 pub struct OnchainBatch {
     pub batch_id: String,
     pub batch_hash: String,
     pub merkle_root: String,
     pub timestamp: String,
+    pub block_height: i64,
+    pub tx_index: i64,
+    pub anchor_tx_hash: Option<String>,
+    pub anchor_block_number: Option<i64>,
+}
+
+#[derive(sqlx::FromRow, Serialize, Debug)]
+pub struct TimestampToken {
+    pub batch_id: String,
+    pub batch_hash: String,
+    pub token: String,
+    pub gen_time: String,
+    pub tsa_url: String,
+}
+
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct EvmAnchor {
+    pub id: i64,
+    pub merkle_root: String,
+    pub ledger_height: i64,
+    pub chain_id: i64,
+    pub contract: String,
+    pub tx_hash: String,
+    pub evm_block_number: Option<i64>,
+    pub status: String,
+    pub submitted_at: String,
+}
+
+/// A recall a company issued for one of its batches. Kept by the node that recorded it, like
+/// acknowledgements; it does not change the batch's ledger history.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct Recall {
+    pub id: i64,
+    pub batch_id: String,
+    pub organization: String,
+    pub reason: String,
+    /// `active` or `closed`.
+    pub status: String,
+    pub issued_by: String,
+    pub issued_at: String,
+    pub closed_by: Option<String>,
+    pub closed_at: Option<String>,
+    pub close_reason: Option<String>,
+}
+
+/// A batch as regulators list it: who made it, where it last went and whether it is recalled.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct BatchSummary {
+    pub batch_id: String,
+    pub organization: String,
+    pub medicine_name: String,
+    pub source: String,
+    pub destination: String,
+    pub timestamp: String,
+    /// Unset while the batch waits in the mempool.
+    pub block_height: Option<i64>,
+    /// `to_location` of the latest sealed transfer, or the batch's destination before any.
+    pub last_location: String,
+    pub transfers: i64,
+    /// Status of the batch's latest recall, if it ever had one.
+    pub recall_status: Option<String>,
+}
+
+/// A sealed custody transfer with its position in the ledger and its receipt, if confirmed.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct CustodyRecord {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub event: CustodyEvent,
+    pub block_height: i64,
+    pub tx_index: i64,
+    pub acknowledged_by: Option<String>,
+    pub acknowledged_at: Option<String>,
+}
+
+/// A batch's latest sealed transfer while nobody has acknowledged receiving it.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct Shipment {
+    pub batch_id: String,
+    pub medicine_name: String,
+    pub from_location: String,
+    /// The last checkpoint the batch was handed to.
+    pub to_location: String,
+    pub shipped_at: String,
+    /// When the sender expects it to arrive, if they said.
+    pub expected_arrival: Option<String>,
+    pub block_height: i64,
+    pub custody_hash: String,
+}
+
+/// Batches held of one medicine, with how soon they expire.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct StockLevel {
+    pub medicine_name: String,
+    pub batches: i64,
+    /// How many of them are under an active recall.
+    pub recalled: i64,
+    /// How many are past their expiry date.
+    pub expired: i64,
+    /// The soonest expiry among the batches not yet expired.
+    pub earliest_expiry: Option<String>,
+}
+
+/// A temperature reading taken for a batch at a checkpoint. Kept by the node that recorded it.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct ConditionReading {
+    pub id: i64,
+    pub batch_id: String,
+    pub location: String,
+    pub temperature_celsius: f64,
+    /// Whether the reading fell outside the batch's storage range.
+    pub excursion: bool,
+    pub recorded_at: String,
+    pub recorded_by: String,
+}
+
+/// Batch counts for one medicine.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct MedicineBatches {
+    pub medicine_name: String,
+    pub batches: i64,
+    /// How many of them are under an active recall.
+    pub recalled: i64,
+}
+
+/// A sealed transaction whose hash or signature no longer checks out.
+#[derive(Serialize, Debug, Clone)]
+pub struct SignatureFailure {
+    pub batch_id: String,
+    /// `batch`, `custody` or `dispensing`.
+    pub transaction: String,
+    pub hash: String,
+    pub problem: String,
+}
+
+/// Hash computation (with chaining). Covers every field of the batch except its hash and signature.
+pub fn compute_batch_hash(batch: &MedicineBatch) -> String {
+    let data = format!(
+        "{}|{}|{}|{}|{}|{}|{}{}",
+        batch.batch_id,
+        batch.organization,
+        batch.medicine_name,
+        batch.source,
+        batch.destination,
+        batch.timestamp,
+        batch.previous_hash,
+        batch.terms.canonical()
+    );
+    let mut hasher = Sha256::new();
+    hasher.update(data.as_bytes());
+    format!("{:x}", hasher.finalize())
 }
 
-/// Hash computation (with chaining)
-pub fn compute_batch_hash(
+pub fn compute_custody_hash(
     batch_id: &str,
-    medicine_name: &str,
-    source: &str,
-    destination: &str,
+    from_location: &str,
+    to_location: &str,
     timestamp: &str,
     previous_hash: &str,
+    expected_arrival: Option<&str>,
 ) -> String {
-    let data = format!(
-        "{batch_id}|{medicine_name}|{source}|{destination}|{timestamp}|{previous_hash}"
+    let mut data = format!("{batch_id}|{from_location}|{to_location}|{timestamp}|{previous_hash}");
+    if let Some(expected_arrival) = expected_arrival {
+        data.push_str(&format!("|expected_arrival={expected_arrival}"));
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(data.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Covers every field of a dispensing event except its hash and signature.
+pub fn compute_dispensing_hash(event: &DispensingEvent) -> String {
+    let mut data = format!(
+        "{}|{}|{}|{}|{}",
+        event.dispensing_id, event.batch_id, event.location, event.quantity, event.timestamp
     );
+    // Serials hold no spaces, so joining on one is unambiguous.
+    if !event.serials.is_empty() {
+        data.push_str(&format!("|serials={}", event.serials.join(" ")));
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(data.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Block hash covers the header only; transactions are committed through `merkle_root`.
+pub fn compute_block_hash(
+    height: i64,
+    previous_hash: &str,
+    merkle_root: &str,
+    timestamp: &str,
+    producer: &str,
+) -> String {
+    let data = format!("{height}|{previous_hash}|{merkle_root}|{timestamp}|{producer}");
     let mut hasher = Sha256::new();
     hasher.update(data.as_bytes());
     format!("{:x}", hasher.finalize())
@@ -104,10 +488,31 @@ pub async fn create_tables(pool: &SqlitePool) -> Result<(), sqlx::Error> {
     )
     .execute(pool).await?;
 
+    // One license per organization; a license number can be registered once per
+    // jurisdiction and kind of organization
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS organization_registrations (
+            organization_id TEXT PRIMARY KEY,
+            organization_type TEXT NOT NULL,
+            jurisdiction TEXT NOT NULL,
+            license_number TEXT NOT NULL,
+            license_expires_at TEXT,
+            status TEXT NOT NULL DEFAULT 'pending',
+            status_reason TEXT,
+            submitted_by TEXT NOT NULL,
+            submitted_at TEXT NOT NULL,
+            reviewed_by TEXT,
+            reviewed_at TEXT,
+            UNIQUE (organization_type, jurisdiction, license_number)
+        )"
+    )
+    .execute(pool).await?;
+
     sqlx::query(
         "CREATE TABLE IF NOT EXISTS medicine_batches (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
             batch_id TEXT NOT NULL UNIQUE,
+            organization TEXT NOT NULL,
             medicine_name TEXT NOT NULL,
             source TEXT NOT NULL,
             destination TEXT NOT NULL,
@@ -115,178 +520,3528 @@ pub async fn create_tables(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             hash TEXT NOT NULL,
             previous_hash TEXT NOT NULL,
             signature TEXT NOT NULL,
-            public_key TEXT NOT NULL
+            public_key TEXT NOT NULL,
+            expires_at TEXT,
+            storage_min_celsius REAL,
+            storage_max_celsius REAL,
+            quantity INTEGER,
+            parent_batch_id TEXT,
+            gtin TEXT,
+            serials TEXT NOT NULL DEFAULT 'null'
         )"
     )
     .execute(pool).await?;
+    // Batches recorded before these columns existed name no organization
+    add_missing_columns(
+        pool,
+        "medicine_batches",
+        &[
+            ("organization", "TEXT NOT NULL DEFAULT ''"),
+            ("expires_at", "TEXT"),
+            ("storage_min_celsius", "REAL"),
+            ("storage_max_celsius", "REAL"),
+            ("quantity", "INTEGER"),
+            ("parent_batch_id", "TEXT"),
+            ("gtin", "TEXT"),
+            ("serials", "TEXT NOT NULL DEFAULT 'null'"),
+        ],
+    )
+    .await?;
 
     sqlx::query(
-        "CREATE TABLE IF NOT EXISTS onchain_batches (
-            batch_id TEXT PRIMARY KEY,
-            batch_hash TEXT NOT NULL,
-            merkle_root TEXT NOT NULL,
-            timestamp TEXT NOT NULL
+        "CREATE TABLE IF NOT EXISTS custody_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            batch_id TEXT NOT NULL,
+            from_location TEXT NOT NULL,
+            to_location TEXT NOT NULL,
+            timestamp TEXT NOT NULL,
+            hash TEXT NOT NULL UNIQUE,
+            previous_hash TEXT NOT NULL,
+            signature TEXT NOT NULL,
+            public_key TEXT NOT NULL,
+            expected_arrival TEXT,
+            block_height INTEGER NOT NULL,
+            tx_index INTEGER NOT NULL
         )"
     )
     .execute(pool).await?;
 
-    Ok(())
-}
-
-/// Add records
-pub async fn add_company(pool: &SqlitePool, name: &str, location: &str, license_id: &str, stock_needed: &str) -> Result<(), sqlx::Error> {
     sqlx::query(
-        "INSERT INTO companies (id, name, location, license_id, stock_needed)
-         VALUES (?, ?, ?, ?, ?)"
+        "CREATE TABLE IF NOT EXISTS dispensing_events (
+            dispensing_id TEXT PRIMARY KEY,
+            batch_id TEXT NOT NULL,
+            location TEXT NOT NULL,
+            quantity INTEGER NOT NULL,
+            serials TEXT NOT NULL,
+            timestamp TEXT NOT NULL,
+            hash TEXT NOT NULL UNIQUE,
+            signature TEXT NOT NULL,
+            public_key TEXT NOT NULL,
+            block_height INTEGER NOT NULL,
+            tx_index INTEGER NOT NULL
+        )"
     )
-    .bind(Uuid::new_v4().to_string())
-    .bind(name)
-    .bind(location)
-    .bind(license_id)
-    .bind(stock_needed)
     .execute(pool).await?;
 
-    Ok(())
-}
+    // Dashboards look batches up by creator and transfers by batch and by recipient
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_medicine_batches_organization ON medicine_batches (organization)")
+        .execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_medicine_batches_parent ON medicine_batches (parent_batch_id)")
+        .execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_medicine_batches_gtin ON medicine_batches (gtin)")
+        .execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_custody_events_batch ON custody_events (batch_id, block_height, tx_index)")
+        .execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_dispensing_events_block ON dispensing_events (block_height)")
+        .execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_custody_events_to ON custody_events (to_location)")
+        .execute(pool).await?;
 
-pub async fn add_hospital(pool: &SqlitePool, name: &str, location: &str, registration_id: &str) -> Result<(), sqlx::Error> {
+    // Every validly sealed block is kept, including losing fork branches ('orphaned'),
+    // so a branch can be re-adopted if it later becomes the longest.
     sqlx::query(
-        "INSERT INTO hospitals (id, name, location, registration_id)
-         VALUES (?, ?, ?, ?)"
+        "CREATE TABLE IF NOT EXISTS blocks (
+            hash TEXT PRIMARY KEY,
+            height INTEGER NOT NULL,
+            previous_hash TEXT NOT NULL,
+            merkle_root TEXT NOT NULL,
+            timestamp TEXT NOT NULL,
+            producer TEXT NOT NULL,
+            signature TEXT NOT NULL,
+            tx_count INTEGER NOT NULL,
+            status TEXT NOT NULL,
+            body TEXT NOT NULL
+        )"
     )
-    .bind(Uuid::new_v4().to_string())
-    .bind(name)
-    .bind(location)
-    .bind(registration_id)
     .execute(pool).await?;
 
-    Ok(())
-}
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_blocks_previous_hash ON blocks (previous_hash)")
+        .execute(pool).await?;
 
-pub async fn add_customer(pool: &SqlitePool, name: &str, location: &str, registration_id: &str) -> Result<(), sqlx::Error> {
+    // Each sealed transaction's hash and signature are checked once, when its block is adopted;
+    // `problem` is NULL when both hold. The check depends only on the transaction, so it is
+    // keyed by hash and stays valid across reorganizations.
     sqlx::query(
-        "INSERT INTO customers (id, name, location, registration_id)
-         VALUES (?, ?, ?, ?)"
+        "CREATE TABLE IF NOT EXISTS transaction_checks (
+            hash TEXT PRIMARY KEY,
+            problem TEXT,
+            checked_at TEXT NOT NULL
+        )"
     )
-    .bind(Uuid::new_v4().to_string())
-    .bind(name)
-    .bind(location)
-    .bind(registration_id)
     .execute(pool).await?;
+    // Transactions sealed before checks were stored are checked now, once
+    check_unchecked_transactions(pool).await?;
 
-    Ok(())
-}
-
-/// Add batch + hash chaining + signature + on-chain proof
-pub async fn add_batch_with_hash(
-    pool: &SqlitePool,
-    batch_id: &str,
-    medicine_name: &str,
-    source: &str,
-    destination: &str,
-    merkle_root: &str,
-) -> Result<(), sqlx::Error> {
-    let timestamp = Utc::now().to_rfc3339();
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS chain_reorgs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp TEXT NOT NULL,
+            fork_height INTEGER NOT NULL,
+            old_tip TEXT NOT NULL,
+            new_tip TEXT NOT NULL,
+            orphaned_blocks INTEGER NOT NULL
+        )"
+    )
+    .execute(pool).await?;
 
-    let previous_hash: Option<String> = sqlx::query_scalar(
-        "SELECT hash FROM medicine_batches ORDER BY timestamp DESC LIMIT 1"
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS onchain_batches (
+            batch_id TEXT PRIMARY KEY,
+            batch_hash TEXT NOT NULL,
+            merkle_root TEXT NOT NULL,
+            timestamp TEXT NOT NULL,
+            block_height INTEGER NOT NULL,
+            tx_index INTEGER NOT NULL,
+            anchor_tx_hash TEXT,
+            anchor_block_number INTEGER
+        )"
+    )
+    .execute(pool).await?;
+    // Entries from before blocks were sealed have no position in the chain
+    add_missing_columns(
+        pool,
+        "onchain_batches",
+        &[
+            ("block_height", "INTEGER NOT NULL DEFAULT 0"),
+            ("tx_index", "INTEGER NOT NULL DEFAULT 0"),
+            ("anchor_tx_hash", "TEXT"),
+            ("anchor_block_number", "INTEGER"),
+        ],
     )
-    .fetch_optional(pool)
     .await?;
 
-    let previous_hash = previous_hash.unwrap_or_else(|| "GENESIS".to_string());
-
-    let batch_hash = compute_batch_hash(
-        batch_id, medicine_name, source, destination, &timestamp, &previous_hash,
-    );
+    // Ledger Merkle roots submitted to the external EVM chain ('pending' until mined).
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS evm_anchors (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            merkle_root TEXT NOT NULL,
+            ledger_height INTEGER NOT NULL,
+            chain_id INTEGER NOT NULL,
+            contract TEXT NOT NULL,
+            tx_hash TEXT NOT NULL UNIQUE,
+            evm_block_number INTEGER,
+            status TEXT NOT NULL,
+            submitted_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool).await?;
 
-    let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
-    let public_key = RsaPublicKey::from(&private_key);
-    let signature_bytes = private_key.sign(rsa::Pkcs1v15Sign::new::<Sha256>(), batch_hash.as_bytes()).unwrap();
+    // RFC 3161 tokens (base64 DER) proving when a batch hash existed.
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS timestamp_tokens (
+            batch_id TEXT PRIMARY KEY,
+            batch_hash TEXT NOT NULL,
+            token TEXT NOT NULL,
+            gen_time TEXT NOT NULL,
+            tsa_url TEXT NOT NULL
+        )"
+    )
+    .execute(pool).await?;
 
-    let signature_base64 = encode(&signature_bytes);
-    let public_key_pem = encode(public_key.to_pkcs1_der().unwrap());
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS anchor_contracts (
+            chain_id INTEGER PRIMARY KEY,
+            address TEXT NOT NULL
+        )"
+    )
+    .execute(pool).await?;
 
+    // Receipts confirmed by the receiving organization; kept by the node that recorded them
     sqlx::query(
-        "INSERT INTO medicine_batches (
-            batch_id, medicine_name, source, destination, timestamp, hash, previous_hash, signature, public_key
-        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        "CREATE TABLE IF NOT EXISTS custody_acknowledgements (
+            custody_hash TEXT PRIMARY KEY,
+            batch_id TEXT NOT NULL,
+            organization TEXT NOT NULL,
+            acknowledged_by TEXT NOT NULL,
+            acknowledged_at TEXT NOT NULL
+        )"
     )
-    .bind(batch_id)
-    .bind(medicine_name)
-    .bind(source)
-    .bind(destination)
-    .bind(&timestamp)
-    .bind(&batch_hash)
-    .bind(&previous_hash)
-    .bind(&signature_base64)
-    .bind(&public_key_pem)
-    .execute(pool)
-    .await?;
+    .execute(pool).await?;
 
-    store_onchain_proof(pool, batch_id, &batch_hash, merkle_root, &timestamp).await?;
+    // At most one active recall per batch
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS batch_recalls (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            batch_id TEXT NOT NULL,
+            organization TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'active',
+            issued_by TEXT NOT NULL,
+            issued_at TEXT NOT NULL,
+            closed_by TEXT,
+            closed_at TEXT,
+            close_reason TEXT
+        )"
+    )
+    .execute(pool).await?;
 
-    Ok(())
-}
+    sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_batch_recalls_active ON batch_recalls (batch_id) WHERE status = 'active'")
+        .execute(pool).await?;
 
-pub async fn store_onchain_proof(pool: &SqlitePool, batch_id: &str, batch_hash: &str, merkle_root: &str, timestamp: &str) -> Result<(), sqlx::Error> {
+    // Temperature readings from checkpoints and loggers; kept by the node that recorded them
     sqlx::query(
-        "INSERT INTO onchain_batches (batch_id, batch_hash, merkle_root, timestamp)
-         VALUES (?, ?, ?, ?)"
+        "CREATE TABLE IF NOT EXISTS condition_readings (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            batch_id TEXT NOT NULL,
+            location TEXT NOT NULL,
+            temperature_celsius REAL NOT NULL,
+            excursion INTEGER NOT NULL,
+            recorded_at TEXT NOT NULL,
+            recorded_by TEXT NOT NULL
+        )"
     )
-    .bind(batch_id)
-    .bind(batch_hash)
-    .bind(merkle_root)
-    .bind(timestamp)
-    .execute(pool)
-    .await?;
+    .execute(pool).await?;
 
-    Ok(())
-}
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_condition_readings_batch ON condition_readings (batch_id, recorded_at)")
+        .execute(pool).await?;
 
-/// Verify batch
-pub async fn verify_batch_signature(pool: &SqlitePool, batch_id: &str) -> Result<(bool, String), sqlx::Error> {
-    let batch = sqlx::query_as!(
-        MedicineBatch,
-        "SELECT * FROM medicine_batches WHERE batch_id = ?",
-        batch_id
+    // Every organization a custody location can name, by id or name
+    sqlx::query(
+        "CREATE VIEW IF NOT EXISTS organizations AS
+         SELECT id, 'company' AS organization_type, name FROM companies
+         UNION ALL SELECT id, 'hospital', name FROM hospitals
+         UNION ALL SELECT id, 'customer', name FROM customers"
     )
-    .fetch_one(pool)
-    .await?;
+    .execute(pool).await?;
 
-    let onchain = sqlx::query_as!(
-        OnchainBatch,
-        "SELECT * FROM onchain_batches WHERE batch_id = ?",
-        batch_id
+    // Stock leaving or correcting a batch outside the ledger: dispensing, destruction and count
+    // adjustments; `quantity` is the signed change. Kept by the node that recorded them.
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS inventory_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            organization TEXT NOT NULL,
+            batch_id TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            quantity INTEGER NOT NULL,
+            reason TEXT,
+            reference TEXT,
+            recorded_by TEXT NOT NULL,
+            occurred_at TEXT NOT NULL
+        )"
     )
-    .fetch_one(pool)
-    .await?;
-
-    let recomputed_hash = compute_batch_hash(
-        &batch.batch_id,
-        &batch.medicine_name,
-        &batch.source,
-        &batch.destination,
-        &batch.timestamp,
-        &batch.previous_hash,
-    );
-
-    if recomputed_hash != onchain.batch_hash {
-        return Ok((false, "Batch hash mismatch (Merkle root invalid)".to_string()));
-    }
-
-    let pub_key_der = decode(&batch.public_key.unwrap()).unwrap();
-    let pub_key = RsaPublicKey::from_pkcs1_der(&pub_key_der).unwrap();
+    .execute(pool).await?;
 
-    let signature = decode(&batch.signature.unwrap()).unwrap();
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_inventory_events_batch ON inventory_events (batch_id, occurred_at)")
+        .execute(pool).await?;
 
-    let verified = pub_key
-        .verify(rsa::Pkcs1v15Sign::new::<Sha256>(), recomputed_hash.as_bytes(), &signature)
-        .is_ok();
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS inventory_reconciliations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            organization TEXT NOT NULL,
+            note TEXT,
+            performed_by TEXT NOT NULL,
+            performed_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool).await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS inventory_counts (
+            reconciliation_id INTEGER NOT NULL REFERENCES inventory_reconciliations (id),
+            batch_id TEXT NOT NULL,
+            medicine_name TEXT NOT NULL,
+            expected_quantity INTEGER NOT NULL,
+            counted_quantity INTEGER NOT NULL,
+            difference INTEGER NOT NULL,
+            reason TEXT,
+            PRIMARY KEY (reconciliation_id, batch_id)
+        )"
+    )
+    .execute(pool).await?;
+
+    // Orders from hospitals to companies, kept by the node they were raised on
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS purchase_orders (
+            id TEXT PRIMARY KEY,
+            hospital TEXT NOT NULL,
+            company TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'submitted',
+            needed_by TEXT,
+            note TEXT,
+            response_note TEXT,
+            created_by TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            responded_by TEXT,
+            responded_at TEXT,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool).await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_purchase_orders_hospital ON purchase_orders (hospital, created_at)")
+        .execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_purchase_orders_company ON purchase_orders (company, created_at)")
+        .execute(pool).await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS purchase_order_lines (
+            order_id TEXT NOT NULL REFERENCES purchase_orders (id),
+            line_no INTEGER NOT NULL,
+            medicine_name TEXT NOT NULL,
+            quantity INTEGER NOT NULL,
+            accepted_quantity INTEGER,
+            PRIMARY KEY (order_id, line_no)
+        )"
+    )
+    .execute(pool).await?;
+
+    // A batch ships against at most one order line
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS purchase_order_shipments (
+            batch_id TEXT PRIMARY KEY,
+            order_id TEXT NOT NULL,
+            line_no INTEGER NOT NULL,
+            quantity INTEGER NOT NULL,
+            linked_by TEXT NOT NULL,
+            linked_at TEXT NOT NULL,
+            delivered_at TEXT,
+            FOREIGN KEY (order_id, line_no) REFERENCES purchase_order_lines (order_id, line_no)
+        )"
+    )
+    .execute(pool).await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_purchase_order_shipments_order ON purchase_order_shipments (order_id, line_no)")
+        .execute(pool).await?;
+
+    // Where an organization is, for ranking suppliers by distance and grouping demand by region
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS organization_sites (
+            organization_id TEXT PRIMARY KEY,
+            latitude REAL NOT NULL,
+            longitude REAL NOT NULL,
+            region TEXT,
+            updated_by TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool).await?;
+
+    // A company's price per unit for a product, keyed by the normalized product name
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS supply_prices (
+            organization TEXT NOT NULL,
+            medicine_key TEXT NOT NULL,
+            medicine_name TEXT NOT NULL,
+            unit_price REAL NOT NULL,
+            currency TEXT NOT NULL,
+            updated_by TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            PRIMARY KEY (organization, medicine_key)
+        )"
+    )
+    .execute(pool).await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS demand_requests (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            organization TEXT NOT NULL,
+            medicine_name TEXT NOT NULL,
+            strength TEXT,
+            quantity INTEGER NOT NULL,
+            needed_by TEXT,
+            note TEXT,
+            currency TEXT NOT NULL DEFAULT 'USD',
+            status TEXT NOT NULL DEFAULT 'open',
+            created_by TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool).await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_demand_requests_organization ON demand_requests (organization, status)")
+        .execute(pool).await?;
+
+    // Hospitals a regulator has designated as critical care, served first under that policy
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS critical_care_designations (
+            organization_id TEXT PRIMARY KEY,
+            note TEXT,
+            designated_by TEXT NOT NULL,
+            designated_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool).await?;
+
+    // How a company shared out scarce stock. Append-only: each allocation carries the hash of
+    // the one before it, and triggers refuse updates and deletes
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS stock_allocations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            organization TEXT NOT NULL,
+            medicine_name TEXT NOT NULL,
+            strength TEXT,
+            region TEXT,
+            policy TEXT NOT NULL,
+            usage_days INTEGER,
+            available_quantity INTEGER NOT NULL,
+            requested_quantity INTEGER NOT NULL,
+            allocated_quantity INTEGER NOT NULL,
+            note TEXT,
+            created_by TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            previous_hash TEXT NOT NULL UNIQUE,
+            hash TEXT NOT NULL
+        )"
+    )
+    .execute(pool).await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS stock_allocation_lines (
+            allocation_id INTEGER NOT NULL REFERENCES stock_allocations (id),
+            demand_id INTEGER NOT NULL,
+            organization TEXT NOT NULL,
+            requested_quantity INTEGER NOT NULL,
+            weight REAL NOT NULL,
+            critical_care INTEGER NOT NULL,
+            allocated_quantity INTEGER NOT NULL,
+            PRIMARY KEY (allocation_id, demand_id)
+        )"
+    )
+    .execute(pool).await?;
+
+    for table in ["stock_allocations", "stock_allocation_lines"] {
+        make_append_only(pool, table, "stock allocations are append-only").await?;
+    }
+
+    // Controlled-substance licenses regulators record, one per organization, listing the
+    // schedules (`II,III`…) it covers
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS controlled_licenses (
+            organization_id TEXT PRIMARY KEY,
+            license_number TEXT NOT NULL,
+            schedules TEXT NOT NULL,
+            expires_at TEXT,
+            granted_by TEXT NOT NULL,
+            granted_at TEXT NOT NULL
+        )"
+    )
+    .execute(pool).await?;
+
+    // How many units of an active ingredient an organization may make or receive per period
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS controlled_quotas (
+            organization_id TEXT NOT NULL,
+            ingredient_key TEXT NOT NULL,
+            active_ingredient TEXT NOT NULL,
+            period TEXT NOT NULL,
+            quantity INTEGER NOT NULL,
+            set_by TEXT NOT NULL,
+            set_at TEXT NOT NULL,
+            PRIMARY KEY (organization_id, ingredient_key)
+        )"
+    )
+    .execute(pool).await?;
+
+    // Transfers of scheduled products, signed by the sender and countersigned by the receiver
+    // before the custody transaction is queued. A batch has at most one awaiting countersignature
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS controlled_transfers (
+            id TEXT PRIMARY KEY,
+            batch_id TEXT NOT NULL,
+            gtin TEXT NOT NULL,
+            schedule TEXT NOT NULL,
+            active_ingredient TEXT NOT NULL,
+            quantity INTEGER NOT NULL,
+            from_organization TEXT NOT NULL,
+            to_organization TEXT NOT NULL,
+            from_location TEXT NOT NULL,
+            expected_arrival TEXT,
+            note TEXT,
+            status TEXT NOT NULL DEFAULT 'awaiting_countersignature',
+            sender_signed_by TEXT NOT NULL,
+            sender_signed_at TEXT NOT NULL,
+            sender_signature TEXT NOT NULL,
+            public_key TEXT NOT NULL,
+            receiver_signed_by TEXT,
+            receiver_signed_at TEXT,
+            receiver_signature TEXT,
+            custody_hash TEXT,
+            closed_by TEXT,
+            closed_at TEXT,
+            close_reason TEXT
+        )"
+    )
+    .execute(pool).await?;
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_controlled_transfers_open ON controlled_transfers (batch_id)
+         WHERE status = 'awaiting_countersignature'"
+    )
+    .execute(pool).await?;
+
+    // Dispensing to patients: the prescription it fills and the patient only as a salted hash
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS dispensing_records (
+            id TEXT PRIMARY KEY,
+            organization TEXT NOT NULL,
+            batch_id TEXT NOT NULL,
+            gtin TEXT,
+            quantity INTEGER NOT NULL,
+            prescription_reference TEXT NOT NULL,
+            prescriber TEXT,
+            patient_hash TEXT NOT NULL,
+            note TEXT,
+            dispensed_by TEXT NOT NULL,
+            dispensed_at TEXT NOT NULL,
+            ledger_hash TEXT NOT NULL
+        )"
+    )
+    .execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_dispensing_records_patient ON dispensing_records (patient_hash)")
+        .execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_dispensing_records_batch ON dispensing_records (batch_id)")
+        .execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_dispensing_records_prescription ON dispensing_records (prescription_reference)")
+        .execute(pool).await?;
+
+    // Pack serials decommissioned by dispensing; a serial identifies one pack of its GTIN
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS dispensed_serials (
+            dispensing_id TEXT NOT NULL,
+            batch_id TEXT NOT NULL,
+            gtin TEXT NOT NULL,
+            serial TEXT NOT NULL,
+            UNIQUE (gtin, serial)
+        )"
+    )
+    .execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_dispensed_serials_dispensing ON dispensed_serials (dispensing_id)")
+        .execute(pool).await?;
+
+    // Medicine master data, keyed by GTIN-14. `organization` is the company that added the
+    // entry, or unset when an admin did. A company's entries wait for a regulator to approve
+    // them, settling their schedule, before batches may be made of them
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS catalog_products (
+            gtin TEXT PRIMARY KEY,
+            ndc TEXT UNIQUE,
+            name TEXT NOT NULL,
+            active_ingredient TEXT NOT NULL,
+            strength TEXT NOT NULL,
+            dosage_form TEXT NOT NULL,
+            pack_size INTEGER NOT NULL,
+            storage_min_celsius REAL,
+            storage_max_celsius REAL,
+            storage_conditions TEXT,
+            schedule TEXT,
+            organization TEXT,
+            created_by TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_by TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            approved_by TEXT,
+            approved_at TEXT
+        )"
+    )
+    .execute(pool).await?;
+
+    Ok(())
+}
+
+/// Add records
+pub async fn add_company(conn: &mut SqliteConnection, name: &str, location: &str, license_id: &str, stock_needed: &str) -> Result<String, sqlx::Error> {
+    let id = Uuid::new_v4().to_string();
+    sqlx::query(
+        "INSERT INTO companies (id, name, location, license_id, stock_needed)
+         VALUES (?, ?, ?, ?, ?)"
+    )
+    .bind(&id)
+    .bind(name)
+    .bind(location)
+    .bind(license_id)
+    .bind(stock_needed)
+    .execute(&mut *conn).await?;
+
+    Ok(id)
+}
+
+pub async fn add_hospital(conn: &mut SqliteConnection, name: &str, location: &str, registration_id: &str) -> Result<String, sqlx::Error> {
+    let id = Uuid::new_v4().to_string();
+    sqlx::query(
+        "INSERT INTO hospitals (id, name, location, registration_id)
+         VALUES (?, ?, ?, ?)"
+    )
+    .bind(&id)
+    .bind(name)
+    .bind(location)
+    .bind(registration_id)
+    .execute(&mut *conn).await?;
+
+    Ok(id)
+}
+
+pub async fn add_customer(conn: &mut SqliteConnection, name: &str, location: &str, registration_id: &str) -> Result<String, sqlx::Error> {
+    let id = Uuid::new_v4().to_string();
+    sqlx::query(
+        "INSERT INTO customers (id, name, location, registration_id)
+         VALUES (?, ?, ?, ?)"
+    )
+    .bind(&id)
+    .bind(name)
+    .bind(location)
+    .bind(registration_id)
+    .execute(&mut *conn).await?;
+
+    Ok(id)
+}
+
+/// Looks an organization up by id across the company, hospital and customer tables.
+pub async fn find_organization(pool: &SqlitePool, organization_id: &str) -> Result<Option<Organization>, sqlx::Error> {
+    sqlx::query_as::<_, Organization>(
+        "SELECT id, 'company' AS organization_type, name FROM companies WHERE id = ?1
+         UNION ALL SELECT id, 'hospital', name FROM hospitals WHERE id = ?1
+         UNION ALL SELECT id, 'customer', name FROM customers WHERE id = ?1"
+    )
+    .bind(organization_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Resolves a custody location that names an organization, by id or by exact name.
+pub async fn find_organization_by_ref(pool: &SqlitePool, reference: &str) -> Result<Option<Organization>, sqlx::Error> {
+    if let Some(organization) = find_organization(pool, reference).await? {
+        return Ok(Some(organization));
+    }
+    sqlx::query_as::<_, Organization>(
+        "SELECT id, 'company' AS organization_type, name FROM companies WHERE name = ?1
+         UNION ALL SELECT id, 'hospital', name FROM hospitals WHERE name = ?1
+         UNION ALL SELECT id, 'customer', name FROM customers WHERE name = ?1
+         LIMIT 1"
+    )
+    .bind(reference)
+    .fetch_optional(pool)
+    .await
+}
+
+const REGISTRATION_SELECT: &str = "SELECT r.*, COALESCE(c.name, h.name, cu.name, '') AS organization_name
+     FROM organization_registrations r
+     LEFT JOIN companies c ON r.organization_type = 'company' AND c.id = r.organization_id
+     LEFT JOIN hospitals h ON r.organization_type = 'hospital' AND h.id = r.organization_id
+     LEFT JOIN customers cu ON r.organization_type = 'customer' AND cu.id = r.organization_id";
+
+/// Whether a license number is already registered for this kind of organization in the jurisdiction.
+pub async fn license_registered(
+    pool: &SqlitePool,
+    organization_type: &str,
+    jurisdiction: &str,
+    license_number: &str,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT 1 FROM organization_registrations
+         WHERE organization_type = ?1 AND jurisdiction = ?2 AND license_number = ?3"
+    )
+    .bind(organization_type)
+    .bind(jurisdiction)
+    .bind(license_number)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.is_some())
+}
+
+/// A license submitted with a new organization.
+pub struct NewRegistration<'a> {
+    pub organization_id: &'a str,
+    pub organization_type: &'a str,
+    pub jurisdiction: &'a str,
+    pub license_number: &'a str,
+    pub license_expires_at: Option<&'a str>,
+    pub status: &'a str,
+    pub submitted_by: &'a str,
+}
+
+pub async fn add_registration(conn: &mut SqliteConnection, registration: &NewRegistration<'_>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO organization_registrations
+             (organization_id, organization_type, jurisdiction, license_number, license_expires_at, status, submitted_by, submitted_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
+    )
+    .bind(registration.organization_id)
+    .bind(registration.organization_type)
+    .bind(registration.jurisdiction)
+    .bind(registration.license_number)
+    .bind(registration.license_expires_at)
+    .bind(registration.status)
+    .bind(registration.submitted_by)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn find_registration(pool: &SqlitePool, organization_id: &str) -> Result<Option<Registration>, sqlx::Error> {
+    sqlx::query_as::<_, Registration>(&format!("{} WHERE r.organization_id = ?1", REGISTRATION_SELECT))
+        .bind(organization_id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Registrations in a status (all when `None`), and those whose license expires on or before
+/// a date, oldest submissions first.
+pub async fn registrations(
+    pool: &SqlitePool,
+    status: Option<&str>,
+    expiring_by: Option<&str>,
+) -> Result<Vec<Registration>, sqlx::Error> {
+    sqlx::query_as::<_, Registration>(&format!(
+        "{} WHERE (?1 IS NULL OR r.status = ?1)
+              AND (?2 IS NULL OR (r.license_expires_at IS NOT NULL AND r.license_expires_at <= ?2))
+         ORDER BY r.submitted_at",
+        REGISTRATION_SELECT
+    ))
+    .bind(status)
+    .bind(expiring_by)
+    .fetch_all(pool)
+    .await
+}
+
+/// Moves a registration from one of `from` to `to`, recording the reviewer. A new expiry date
+/// replaces the stored one. Returns false when the registration was not in an allowed status.
+pub async fn review_registration(
+    pool: &SqlitePool,
+    organization_id: &str,
+    from: &[&str],
+    to: &str,
+    reason: Option<&str>,
+    reviewed_by: &str,
+    license_expires_at: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let current: Option<String> = sqlx::query_scalar("SELECT status FROM organization_registrations WHERE organization_id = ?1")
+        .bind(organization_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if !current.is_some_and(|status| from.contains(&status.as_str())) {
+        return Ok(false);
+    }
+    sqlx::query(
+        "UPDATE organization_registrations
+         SET status = ?2, status_reason = ?3, reviewed_by = ?4, reviewed_at = ?5,
+             license_expires_at = COALESCE(?6, license_expires_at)
+         WHERE organization_id = ?1"
+    )
+    .bind(organization_id)
+    .bind(to)
+    .bind(reason)
+    .bind(reviewed_by)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(license_expires_at)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Replaces an organization's license with a renewed one and sends it back for review.
+/// Suspended organizations stay suspended until a regulator reinstates them.
+pub async fn renew_registration(
+    pool: &SqlitePool,
+    organization_id: &str,
+    license_number: &str,
+    license_expires_at: Option<&str>,
+    submitted_by: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE organization_registrations
+         SET license_number = ?2, license_expires_at = ?3, status = 'pending', status_reason = NULL,
+             submitted_by = ?4, submitted_at = ?5, reviewed_by = NULL, reviewed_at = NULL
+         WHERE organization_id = ?1 AND status != 'suspended'"
+    )
+    .bind(organization_id)
+    .bind(license_number)
+    .bind(license_expires_at)
+    .bind(submitted_by)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Canonical blocks (ordered by height, which replicas agree on)
+pub async fn latest_block(pool: &SqlitePool) -> Result<Option<BlockHeader>, sqlx::Error> {
+    sqlx::query_as::<_, BlockHeader>(
+        "SELECT * FROM blocks WHERE status = 'canonical' ORDER BY height DESC LIMIT 1"
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn block_at_height(pool: &SqlitePool, height: i64) -> Result<Option<Block>, sqlx::Error> {
+    let header = sqlx::query_as::<_, BlockHeader>(
+        "SELECT * FROM blocks WHERE height = ? AND status = 'canonical'"
+    )
+    .bind(height)
+    .fetch_optional(pool)
+    .await?;
+
+    match header {
+        Some(header) => {
+            let transactions = block_transactions(pool, header.height).await?;
+            Ok(Some(Block { header, transactions }))
+        }
+        None => Ok(None),
+    }
+}
+
+pub async fn blocks_from_height(pool: &SqlitePool, from: i64, limit: i64) -> Result<Vec<Block>, sqlx::Error> {
+    let headers = sqlx::query_as::<_, BlockHeader>(
+        "SELECT * FROM blocks WHERE height >= ? AND status = 'canonical' ORDER BY height ASC LIMIT ?"
+    )
+    .bind(from)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    let mut blocks = Vec::with_capacity(headers.len());
+    for header in headers {
+        let transactions = block_transactions(pool, header.height).await?;
+        blocks.push(Block { header, transactions });
+    }
+    Ok(blocks)
+}
+
+/// Transactions of one block in their sealed order.
+pub async fn block_transactions(pool: &SqlitePool, height: i64) -> Result<Vec<LedgerTx>, sqlx::Error> {
+    let mut indexed: Vec<(i64, LedgerTx)> = Vec::new();
+
+    let batch_rows = sqlx::query(
+        "SELECT m.*, o.tx_index FROM medicine_batches m
+         JOIN onchain_batches o ON o.batch_id = m.batch_id
+         WHERE o.block_height = ?"
+    )
+    .bind(height)
+    .fetch_all(pool)
+    .await?;
+    for row in batch_rows {
+        indexed.push((row.try_get("tx_index")?, LedgerTx::Batch(MedicineBatch::from_row(&row)?)));
+    }
+
+    let custody_rows = sqlx::query("SELECT * FROM custody_events WHERE block_height = ?")
+        .bind(height)
+        .fetch_all(pool)
+        .await?;
+    for row in custody_rows {
+        indexed.push((row.try_get("tx_index")?, LedgerTx::Custody(CustodyEvent::from_row(&row)?)));
+    }
+
+    let dispensing_rows = sqlx::query("SELECT * FROM dispensing_events WHERE block_height = ?")
+        .bind(height)
+        .fetch_all(pool)
+        .await?;
+    for row in dispensing_rows {
+        indexed.push((row.try_get("tx_index")?, LedgerTx::Dispensing(DispensingEvent::from_row(&row)?)));
+    }
+
+    indexed.sort_by_key(|(index, _)| *index);
+    Ok(indexed.into_iter().map(|(_, tx)| tx).collect())
+}
+
+/// Any stored block, canonical or not, with its status.
+pub async fn stored_block(pool: &SqlitePool, hash: &str) -> Result<Option<(Block, String)>, sqlx::Error> {
+    let row = sqlx::query("SELECT * FROM blocks WHERE hash = ?")
+        .bind(hash)
+        .fetch_optional(pool)
+        .await?;
+
+    match row {
+        Some(row) => {
+            let body: String = row.try_get("body")?;
+            let transactions = serde_json::from_str(&body).map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
+            let block = Block { header: BlockHeader::from_row(&row)?, transactions };
+            Ok(Some((block, row.try_get("status")?)))
+        }
+        None => Ok(None),
+    }
+}
+
+async fn save_block(conn: &mut SqliteConnection, block: &Block, status: &str) -> Result<(), sqlx::Error> {
+    let header = &block.header;
+    let body = serde_json::to_string(&block.transactions).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+
+    sqlx::query(
+        "INSERT INTO blocks (hash, height, previous_hash, merkle_root, timestamp, producer, signature, tx_count, status, body)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(hash) DO UPDATE SET status = excluded.status"
+    )
+    .bind(&header.hash)
+    .bind(header.height)
+    .bind(&header.previous_hash)
+    .bind(&header.merkle_root)
+    .bind(&header.timestamp)
+    .bind(&header.producer)
+    .bind(&header.signature)
+    .bind(header.tx_count)
+    .bind(status)
+    .bind(body)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Keeps a valid block that lost fork choice so it can be reported or re-adopted later.
+pub async fn store_side_block(pool: &SqlitePool, block: &Block) -> Result<(), sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    save_block(&mut conn, block, "orphaned").await
+}
+
+/// Writes the batches, custody events and dispensings of a canonical block into the ledger tables.
+async fn insert_block_state(conn: &mut SqliteConnection, block: &Block) -> Result<(), sqlx::Error> {
+    let header = &block.header;
+
+    for (index, ledger_tx) in block.transactions.iter().enumerate() {
+        match ledger_tx {
+            LedgerTx::Batch(batch) => {
+                sqlx::query(
+                    "INSERT INTO medicine_batches (
+                        batch_id, organization, medicine_name, source, destination, timestamp, hash, previous_hash, signature, public_key,
+                        expires_at, storage_min_celsius, storage_max_celsius, quantity, parent_batch_id, gtin, serials
+                    ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+                )
+                .bind(&batch.batch_id)
+                .bind(&batch.organization)
+                .bind(&batch.medicine_name)
+                .bind(&batch.source)
+                .bind(&batch.destination)
+                .bind(&batch.timestamp)
+                .bind(&batch.hash)
+                .bind(&batch.previous_hash)
+                .bind(&batch.signature)
+                .bind(&batch.public_key)
+                .bind(&batch.terms.expires_at)
+                .bind(batch.terms.storage_min_celsius)
+                .bind(batch.terms.storage_max_celsius)
+                .bind(batch.terms.quantity)
+                .bind(&batch.terms.parent_batch_id)
+                .bind(&batch.terms.gtin)
+                .bind(sqlx::types::Json(&batch.terms.serials))
+                .execute(&mut *conn)
+                .await?;
+
+                sqlx::query(
+                    "INSERT INTO onchain_batches (batch_id, batch_hash, merkle_root, timestamp, block_height, tx_index)
+                     VALUES (?, ?, ?, ?, ?, ?)"
+                )
+                .bind(&batch.batch_id)
+                .bind(&batch.hash)
+                .bind(&header.merkle_root)
+                .bind(&header.timestamp)
+                .bind(header.height)
+                .bind(index as i64)
+                .execute(&mut *conn)
+                .await?;
+            }
+            LedgerTx::Custody(event) => {
+                sqlx::query(
+                    "INSERT INTO custody_events (
+                        batch_id, from_location, to_location, timestamp, hash, previous_hash, signature, public_key, expected_arrival,
+                        block_height, tx_index
+                    ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+                )
+                .bind(&event.batch_id)
+                .bind(&event.from_location)
+                .bind(&event.to_location)
+                .bind(&event.timestamp)
+                .bind(&event.hash)
+                .bind(&event.previous_hash)
+                .bind(&event.signature)
+                .bind(&event.public_key)
+                .bind(&event.expected_arrival)
+                .bind(header.height)
+                .bind(index as i64)
+                .execute(&mut *conn)
+                .await?;
+            }
+            LedgerTx::Dispensing(event) => {
+                sqlx::query(
+                    "INSERT INTO dispensing_events (
+                        dispensing_id, batch_id, location, quantity, serials, timestamp, hash, signature, public_key,
+                        block_height, tx_index
+                    ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+                )
+                .bind(&event.dispensing_id)
+                .bind(&event.batch_id)
+                .bind(&event.location)
+                .bind(event.quantity)
+                .bind(sqlx::types::Json(&event.serials))
+                .bind(&event.timestamp)
+                .bind(&event.hash)
+                .bind(&event.signature)
+                .bind(&event.public_key)
+                .bind(header.height)
+                .bind(index as i64)
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes `branch` (consecutive blocks whose first one builds on canonical height `fork_height`)
+/// the canonical chain. Canonical blocks above the fork point are orphaned and their
+/// transactions removed from the ledger tables; all of it happens in one transaction.
+/// Returns the blocks that were orphaned.
+pub async fn adopt_branch(pool: &SqlitePool, fork_height: i64, branch: &[Block]) -> Result<Vec<Block>, sqlx::Error> {
+    let displaced: Vec<String> = sqlx::query_scalar(
+        "SELECT hash FROM blocks WHERE status = 'canonical' AND height > ? ORDER BY height"
+    )
+    .bind(fork_height)
+    .fetch_all(pool)
+    .await?;
+
+    let mut orphaned = Vec::with_capacity(displaced.len());
+    for hash in &displaced {
+        if let Some((block, _)) = stored_block(pool, hash).await? {
+            orphaned.push(block);
+        }
+    }
+
+    let checks = check_transactions(branch.iter().flat_map(|block| block.transactions.iter().cloned()).collect()).await?;
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM custody_events WHERE block_height > ?")
+        .bind(fork_height)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM dispensing_events WHERE block_height > ?")
+        .bind(fork_height)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "DELETE FROM medicine_batches WHERE batch_id IN (SELECT batch_id FROM onchain_batches WHERE block_height > ?)"
+    )
+    .bind(fork_height)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM onchain_batches WHERE block_height > ?")
+        .bind(fork_height)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("UPDATE blocks SET status = 'orphaned' WHERE status = 'canonical' AND height > ?")
+        .bind(fork_height)
+        .execute(&mut *tx)
+        .await?;
+
+    for block in branch {
+        save_block(&mut tx, block, "canonical").await?;
+        insert_block_state(&mut tx, block).await?;
+    }
+    record_checks(&mut tx, &checks).await?;
+
+    if let (Some(old_tip), Some(new_tip)) = (orphaned.last(), branch.last()) {
+        sqlx::query(
+            "INSERT INTO chain_reorgs (timestamp, fork_height, old_tip, new_tip, orphaned_blocks)
+             VALUES (?, ?, ?, ?, ?)"
+        )
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(fork_height)
+        .bind(&old_tip.header.hash)
+        .bind(&new_tip.header.hash)
+        .bind(orphaned.len() as i64)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(orphaned)
+}
+
+/// Headers of every stored block at a height, on any branch.
+pub async fn block_headers_at_height(pool: &SqlitePool, height: i64) -> Result<Vec<BlockHeader>, sqlx::Error> {
+    sqlx::query_as::<_, BlockHeader>("SELECT * FROM blocks WHERE height = ?")
+        .bind(height)
+        .fetch_all(pool)
+        .await
+}
+
+/// Every point where blocks share a `previous_hash`, with the competing blocks.
+pub async fn find_forks(pool: &SqlitePool) -> Result<Vec<Fork>, sqlx::Error> {
+    let blocks = sqlx::query_as::<_, ForkBlock>(
+        "SELECT height, hash, previous_hash, producer, timestamp, status FROM blocks
+         WHERE previous_hash IN (SELECT previous_hash FROM blocks GROUP BY previous_hash HAVING COUNT(*) > 1)
+         ORDER BY height ASC, status ASC, hash ASC"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut forks: Vec<Fork> = Vec::new();
+    for block in blocks {
+        match forks.iter_mut().find(|f| f.parent_hash == block.previous_hash) {
+            Some(fork) => fork.blocks.push(block),
+            None => forks.push(Fork {
+                fork_height: block.height - 1,
+                parent_hash: block.previous_hash.clone(),
+                blocks: vec![block],
+            }),
+        }
+    }
+    Ok(forks)
+}
+
+pub async fn orphaned_blocks(pool: &SqlitePool) -> Result<Vec<ForkBlock>, sqlx::Error> {
+    sqlx::query_as::<_, ForkBlock>(
+        "SELECT height, hash, previous_hash, producer, timestamp, status FROM blocks
+         WHERE status = 'orphaned' ORDER BY height ASC"
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn chain_reorgs(pool: &SqlitePool) -> Result<Vec<ChainReorg>, sqlx::Error> {
+    sqlx::query_as::<_, ChainReorg>("SELECT * FROM chain_reorgs ORDER BY id DESC")
+        .fetch_all(pool)
+        .await
+}
+
+/// The batch's latest sealed custody transfer on the canonical chain.
+pub async fn latest_custody_event(pool: &SqlitePool, batch_id: &str) -> Result<Option<CustodyEvent>, sqlx::Error> {
+    sqlx::query_as::<_, CustodyEvent>(
+        "SELECT batch_id, from_location, to_location, timestamp, hash, previous_hash, signature, public_key, expected_arrival
+         FROM custody_events WHERE batch_id = ? ORDER BY block_height DESC, tx_index DESC LIMIT 1"
+    )
+    .bind(batch_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Records that `organization` received the transfer `custody_hash`.
+/// Returns the acknowledgement time, or `None` if it was already acknowledged.
+pub async fn acknowledge_custody(
+    pool: &SqlitePool,
+    custody_hash: &str,
+    batch_id: &str,
+    organization: &str,
+    user_id: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let acknowledged_at = chrono::Utc::now().to_rfc3339();
+    let result = sqlx::query(
+        "INSERT OR IGNORE INTO custody_acknowledgements (custody_hash, batch_id, organization, acknowledged_by, acknowledged_at)
+         VALUES (?, ?, ?, ?, ?)"
+    )
+    .bind(custody_hash)
+    .bind(batch_id)
+    .bind(organization)
+    .bind(user_id)
+    .bind(&acknowledged_at)
+    .execute(pool)
+    .await?;
+
+    Ok((result.rows_affected() == 1).then_some(acknowledged_at))
+}
+
+/// Hash of the batch's latest transaction sealed at or below `max_height`, and where the batch was then.
+pub async fn batch_chain_head(pool: &SqlitePool, batch_id: &str, max_height: i64) -> Result<Option<(String, String)>, sqlx::Error> {
+    let custody: Option<(String, String)> = sqlx::query_as(
+        "SELECT hash, to_location FROM custody_events WHERE batch_id = ? AND block_height <= ?
+         ORDER BY block_height DESC, tx_index DESC LIMIT 1"
+    )
+    .bind(batch_id)
+    .bind(max_height)
+    .fetch_optional(pool)
+    .await?;
+
+    if custody.is_some() {
+        return Ok(custody);
+    }
+
+    sqlx::query_as(
+        "SELECT m.hash, m.destination FROM medicine_batches m
+         JOIN onchain_batches o ON o.batch_id = m.batch_id
+         WHERE m.batch_id = ? AND o.block_height <= ?"
+    )
+    .bind(batch_id)
+    .bind(max_height)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Merkle root over every sealed batch hash up to `max_height`, in ledger order, and how many there are.
+pub async fn ledger_merkle_root(pool: &SqlitePool, max_height: i64) -> Result<(String, usize), sqlx::Error> {
+    let hashes: Vec<String> = sqlx::query_scalar(
+        "SELECT batch_hash FROM onchain_batches WHERE block_height <= ? ORDER BY block_height ASC, tx_index ASC"
+    )
+    .bind(max_height)
+    .fetch_all(pool)
+    .await?;
+
+    let count = hashes.len();
+    Ok((build_merkle_root(hashes), count))
+}
+
+pub async fn latest_evm_anchor(pool: &SqlitePool) -> Result<Option<EvmAnchor>, sqlx::Error> {
+    sqlx::query_as::<_, EvmAnchor>(
+        "SELECT * FROM evm_anchors WHERE status != 'failed' ORDER BY id DESC LIMIT 1"
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn evm_anchors(pool: &SqlitePool) -> Result<Vec<EvmAnchor>, sqlx::Error> {
+    sqlx::query_as::<_, EvmAnchor>("SELECT * FROM evm_anchors ORDER BY id DESC")
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn find_evm_anchor(pool: &SqlitePool, tx_hash: &str) -> Result<Option<EvmAnchor>, sqlx::Error> {
+    sqlx::query_as::<_, EvmAnchor>("SELECT * FROM evm_anchors WHERE tx_hash = ?")
+        .bind(tx_hash)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn insert_evm_anchor(
+    pool: &SqlitePool,
+    merkle_root: &str,
+    ledger_height: i64,
+    chain_id: i64,
+    contract: &str,
+    tx_hash: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO evm_anchors (merkle_root, ledger_height, chain_id, contract, tx_hash, status, submitted_at)
+         VALUES (?, ?, ?, ?, ?, 'pending', ?)"
+    )
+    .bind(merkle_root)
+    .bind(ledger_height)
+    .bind(chain_id)
+    .bind(contract)
+    .bind(tx_hash)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Records a mined anchor and stamps its transaction onto every batch it covers
+/// that was not already covered by an earlier anchor.
+pub async fn confirm_evm_anchor(pool: &SqlitePool, anchor: &EvmAnchor, evm_block_number: i64) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("UPDATE evm_anchors SET status = 'confirmed', evm_block_number = ? WHERE id = ?")
+        .bind(evm_block_number)
+        .bind(anchor.id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        "UPDATE onchain_batches SET anchor_tx_hash = ?, anchor_block_number = ?
+         WHERE block_height <= ? AND anchor_tx_hash IS NULL"
+    )
+    .bind(&anchor.tx_hash)
+    .bind(evm_block_number)
+    .bind(anchor.ledger_height)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+pub async fn fail_evm_anchor(pool: &SqlitePool, anchor_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE evm_anchors SET status = 'failed' WHERE id = ?")
+        .bind(anchor_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn anchor_contract(pool: &SqlitePool, chain_id: i64) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT address FROM anchor_contracts WHERE chain_id = ?")
+        .bind(chain_id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn save_anchor_contract(pool: &SqlitePool, chain_id: i64, address: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT OR REPLACE INTO anchor_contracts (chain_id, address) VALUES (?, ?)")
+        .bind(chain_id)
+        .bind(address)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn find_batch(pool: &SqlitePool, batch_id: &str) -> Result<Option<MedicineBatch>, sqlx::Error> {
+    sqlx::query_as::<_, MedicineBatch>("SELECT * FROM medicine_batches WHERE batch_id = ?")
+        .bind(batch_id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Sealed batches with no timestamp token yet, oldest first.
+pub async fn batches_without_timestamp(pool: &SqlitePool, limit: i64) -> Result<Vec<(String, String)>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT o.batch_id, o.batch_hash FROM onchain_batches o
+         LEFT JOIN timestamp_tokens t ON t.batch_id = o.batch_id
+         WHERE t.batch_id IS NULL
+         ORDER BY o.block_height ASC, o.tx_index ASC LIMIT ?"
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn save_timestamp_token(
+    pool: &SqlitePool,
+    batch_id: &str,
+    batch_hash: &str,
+    token: &str,
+    gen_time: &str,
+    tsa_url: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO timestamp_tokens (batch_id, batch_hash, token, gen_time, tsa_url)
+         VALUES (?, ?, ?, ?, ?)"
+    )
+    .bind(batch_id)
+    .bind(batch_hash)
+    .bind(token)
+    .bind(gen_time)
+    .bind(tsa_url)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn find_timestamp_token(pool: &SqlitePool, batch_id: &str) -> Result<Option<TimestampToken>, sqlx::Error> {
+    sqlx::query_as::<_, TimestampToken>("SELECT * FROM timestamp_tokens WHERE batch_id = ?")
+        .bind(batch_id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn find_onchain_batch(pool: &SqlitePool, batch_id: &str) -> Result<Option<OnchainBatch>, sqlx::Error> {
+    sqlx::query_as::<_, OnchainBatch>(
+        "SELECT * FROM onchain_batches WHERE batch_id = ?"
+    )
+    .bind(batch_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Whether a base64 signature over `hash` verifies against a base64 PKCS#1 public key.
+pub fn signature_holds(public_key: &str, signature: &str, hash: &str) -> bool {
+    let public_key = STANDARD
+        .decode(public_key)
+        .ok()
+        .and_then(|der| RsaPublicKey::from_pkcs1_der(&der).ok());
+    let signature = STANDARD.decode(signature).unwrap_or_default();
+    public_key.is_some_and(|key| verify_signature(&key, hash.as_bytes(), &signature))
+}
+
+/// Verify batch: its hash, its place under the block's Merkle root, and its signature
+pub async fn verify_batch_signature(pool: &SqlitePool, batch_id: &str) -> Result<(bool, String), sqlx::Error> {
+    let batch = sqlx::query_as::<_, MedicineBatch>(
+        "SELECT * FROM medicine_batches WHERE batch_id = ?"
+    )
+    .bind(batch_id)
+    .fetch_one(pool)
+    .await?;
+
+    let onchain = sqlx::query_as::<_, OnchainBatch>(
+        "SELECT * FROM onchain_batches WHERE batch_id = ?"
+    )
+    .bind(batch_id)
+    .fetch_one(pool)
+    .await?;
+
+    let recomputed_hash = compute_batch_hash(&batch);
+
+    if recomputed_hash != onchain.batch_hash {
+        return Ok((false, "Batch hash mismatch (Merkle root invalid)".to_string()));
+    }
+
+    let tx_hashes: Vec<String> = block_transactions(pool, onchain.block_height)
+        .await?
+        .iter()
+        .map(|tx| tx.hash().to_string())
+        .collect();
+
+    if tx_hashes.get(onchain.tx_index as usize) != Some(&recomputed_hash) {
+        return Ok((false, format!(
+            "Batch is not at index {} of block {}",
+            onchain.tx_index, onchain.block_height
+        )));
+    }
+
+    let block_root: Option<String> = sqlx::query_scalar("SELECT merkle_root FROM blocks WHERE height = ? AND status = 'canonical'")
+        .bind(onchain.block_height)
+        .fetch_optional(pool)
+        .await?;
+    let recomputed_root = build_merkle_root(tx_hashes);
+
+    if block_root.as_deref() != Some(recomputed_root.as_str()) || onchain.merkle_root != recomputed_root {
+        return Ok((false, format!("Merkle root mismatch for block {}", onchain.block_height)));
+    }
+
+    let verified = match (batch.public_key.as_deref(), batch.signature.as_deref()) {
+        (Some(public_key), Some(signature)) => signature_holds(public_key, signature, &recomputed_hash),
+        _ => false,
+    };
+
+    if verified {
+        Ok((true, format!(
+            "Batch signature and Merkle proof valid (block {}, index {})",
+            onchain.block_height, onchain.tx_index
+        )))
+    } else {
+        Ok((false, "Signature verification failed".to_string()))
+    }
+}
+
+/// Records a recall; `None` if the batch already has an active one.
+pub async fn add_recall(
+    pool: &SqlitePool,
+    batch_id: &str,
+    organization: &str,
+    reason: &str,
+    issued_by: &str,
+) -> Result<Option<Recall>, sqlx::Error> {
+    sqlx::query_as::<_, Recall>(
+        "INSERT INTO batch_recalls (batch_id, organization, reason, issued_by, issued_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (batch_id) WHERE status = 'active' DO NOTHING
+         RETURNING *"
+    )
+    .bind(batch_id)
+    .bind(organization)
+    .bind(reason)
+    .bind(issued_by)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .fetch_optional(pool)
+    .await
+}
+
+/// Closes a batch's active recall; `None` if it has none.
+pub async fn close_recall(
+    pool: &SqlitePool,
+    batch_id: &str,
+    closed_by: &str,
+    close_reason: Option<&str>,
+) -> Result<Option<Recall>, sqlx::Error> {
+    sqlx::query_as::<_, Recall>(
+        "UPDATE batch_recalls SET status = 'closed', closed_by = ?2, closed_at = ?3, close_reason = ?4
+         WHERE batch_id = ?1 AND status = 'active'
+         RETURNING *"
+    )
+    .bind(batch_id)
+    .bind(closed_by)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(close_reason)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Recalls, newest first, narrowed by status, batch and issuing organization when set.
+pub async fn recalls(
+    pool: &SqlitePool,
+    status: Option<&str>,
+    batch_id: Option<&str>,
+    organization: Option<&str>,
+) -> Result<Vec<Recall>, sqlx::Error> {
+    sqlx::query_as::<_, Recall>(
+        "SELECT * FROM batch_recalls
+         WHERE (?1 IS NULL OR status = ?1)
+           AND (?2 IS NULL OR batch_id = ?2)
+           AND (?3 IS NULL OR organization = ?3)
+         ORDER BY id DESC"
+    )
+    .bind(status)
+    .bind(batch_id)
+    .bind(organization)
+    .fetch_all(pool)
+    .await
+}
+
+/// Filters for [`batch_summaries`]; unset fields match everything.
+#[derive(Deserialize, Debug, Default)]
+pub struct BatchFilter {
+    pub organization: Option<String>,
+    pub medicine_name: Option<String>,
+    /// RFC 3339; batches created at or after it.
+    pub since: Option<String>,
+    /// RFC 3339; batches created before it.
+    pub until: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Batches of every organization, newest first.
+pub async fn batch_summaries(pool: &SqlitePool, filter: &BatchFilter) -> Result<Vec<BatchSummary>, sqlx::Error> {
+    sqlx::query_as::<_, BatchSummary>(
+        "SELECT b.batch_id, b.organization, b.medicine_name, b.source, b.destination, b.timestamp, o.block_height,
+                COALESCE((SELECT c.to_location FROM custody_events c WHERE c.batch_id = b.batch_id
+                          ORDER BY c.block_height DESC, c.tx_index DESC LIMIT 1), b.destination) AS last_location,
+                (SELECT COUNT(*) FROM custody_events c WHERE c.batch_id = b.batch_id) AS transfers,
+                (SELECT r.status FROM batch_recalls r WHERE r.batch_id = b.batch_id ORDER BY r.id DESC LIMIT 1) AS recall_status
+         FROM medicine_batches b
+         LEFT JOIN onchain_batches o ON o.batch_id = b.batch_id
+         WHERE (?1 IS NULL OR b.organization = ?1)
+           AND (?2 IS NULL OR b.medicine_name = ?2 COLLATE NOCASE)
+           AND (?3 IS NULL OR b.timestamp >= ?3)
+           AND (?4 IS NULL OR b.timestamp < ?4)
+         ORDER BY b.id DESC
+         LIMIT ?5 OFFSET ?6"
+    )
+    .bind(&filter.organization)
+    .bind(&filter.medicine_name)
+    .bind(&filter.since)
+    .bind(&filter.until)
+    .bind(filter.limit.unwrap_or(100).clamp(1, 1000))
+    .bind(filter.offset.unwrap_or(0).max(0))
+    .fetch_all(pool)
+    .await
+}
+
+const CUSTODY_RECORD_SELECT: &str = "SELECT c.batch_id, c.from_location, c.to_location, c.timestamp, c.hash, c.previous_hash,
+            c.signature, c.public_key, c.expected_arrival, c.block_height, c.tx_index, a.acknowledged_by, a.acknowledged_at
+     FROM custody_events c
+     LEFT JOIN custody_acknowledgements a ON a.custody_hash = c.hash";
+
+/// A batch's sealed transfers in ledger order.
+pub async fn batch_custody_history(pool: &SqlitePool, batch_id: &str) -> Result<Vec<CustodyRecord>, sqlx::Error> {
+    sqlx::query_as::<_, CustodyRecord>(&format!(
+        "{} WHERE c.batch_id = ?1 ORDER BY c.block_height, c.tx_index",
+        CUSTODY_RECORD_SELECT
+    ))
+    .bind(batch_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Sealed transfers sent or received by an organization, named by id or name, newest first.
+pub async fn organization_custody_history(
+    pool: &SqlitePool,
+    organization: &Organization,
+    since: Option<&str>,
+    limit: i64,
+) -> Result<Vec<CustodyRecord>, sqlx::Error> {
+    sqlx::query_as::<_, CustodyRecord>(&format!(
+        "{} WHERE (c.from_location IN (?1, ?2) OR c.to_location IN (?1, ?2))
+              AND (?3 IS NULL OR c.timestamp >= ?3)
+         ORDER BY c.block_height DESC, c.tx_index DESC
+         LIMIT ?4",
+        CUSTODY_RECORD_SELECT
+    ))
+    .bind(&organization.id)
+    .bind(&organization.name)
+    .bind(since)
+    .bind(limit.clamp(1, 1000))
+    .fetch_all(pool)
+    .await
+}
+
+/// Walks a batch's sealed transfers from its creation, checking each links to the one before,
+/// still hashes to what was sealed and carries a valid signature.
+pub async fn verify_custody_chain(pool: &SqlitePool, batch_id: &str) -> Result<(bool, String), sqlx::Error> {
+    let Some(batch) = find_batch(pool, batch_id).await? else {
+        return Ok((false, "Batch not found".to_string()));
+    };
+    let history = batch_custody_history(pool, batch_id).await?;
+
+    let mut expected_previous = batch.hash;
+    for (i, record) in history.iter().enumerate() {
+        let event = &record.event;
+        if event.previous_hash != expected_previous {
+            return Ok((false, format!("Transfer {} does not follow the batch's previous transaction", i + 1)));
+        }
+        let recomputed = compute_custody_hash(
+            &event.batch_id, &event.from_location, &event.to_location, &event.timestamp, &event.previous_hash,
+            event.expected_arrival.as_deref(),
+        );
+        if recomputed != event.hash {
+            return Ok((false, format!("Transfer {} was altered: its hash no longer matches its fields", i + 1)));
+        }
+        if !signature_holds(&event.public_key, &event.signature, &recomputed) {
+            return Ok((false, format!("Transfer {} has an invalid signature", i + 1)));
+        }
+        expected_previous = event.hash.clone();
+    }
+
+    Ok((true, format!("{} transfer(s) correctly chained and signed", history.len())))
+}
+
+/// Each batch's latest sealed transfer, as `heads` rows with `position = 1`.
+const CUSTODY_HEADS: &str = "WITH heads AS (
+         SELECT c.*, ROW_NUMBER() OVER (PARTITION BY c.batch_id ORDER BY c.block_height DESC, c.tx_index DESC) AS position
+         FROM custody_events c
+     )";
+
+/// Who holds batch `b` while it is at `location`: the organization the location names, or,
+/// while the batch is still at a starting point that names none, the company that created it.
+fn holder(location: &str) -> String {
+    format!(
+        "CASE WHEN {location} IN (SELECT id FROM organizations UNION ALL SELECT name FROM organizations) THEN {location}
+              WHEN {location} = b.destination THEN b.organization
+              ELSE {location} END"
+    )
+}
+
+/// Follows [`CUSTODY_HEADS`] with `held`: the batches the organization `?1` (id) / `?2` (name)
+/// holds now, except those whose latest transfer to it is still expected to arrive and not yet
+/// acknowledged.
+fn held_batches() -> String {
+    format!(
+        ",
+     held AS (
+         SELECT b.* FROM medicine_batches b
+         LEFT JOIN heads h ON h.batch_id = b.batch_id AND h.position = 1
+         WHERE {} IN (?1, ?2)
+           AND (h.batch_id IS NULL
+                OR h.expected_arrival IS NULL
+                OR julianday(h.expected_arrival) <= julianday('now')
+                OR h.hash IN (SELECT custody_hash FROM custody_acknowledgements))
+     )",
+        holder("COALESCE(h.to_location, b.destination)")
+    )
+}
+
+const SHIPMENT_SELECT: &str = "SELECT h.batch_id, b.medicine_name, h.from_location, h.to_location, h.timestamp AS shipped_at,
+            h.expected_arrival, h.block_height, h.hash AS custody_hash
+     FROM heads h
+     JOIN medicine_batches b ON b.batch_id = h.batch_id
+     LEFT JOIN custody_acknowledgements a ON a.custody_hash = h.hash
+     WHERE h.position = 1 AND a.custody_hash IS NULL";
+
+/// Batches an organization made or sent on whose latest transfer, to someone else, is not yet acknowledged.
+pub async fn shipments_in_transit(pool: &SqlitePool, organization: &Organization) -> Result<Vec<Shipment>, sqlx::Error> {
+    sqlx::query_as::<_, Shipment>(&format!(
+        "{} {} AND (b.organization = ?1 OR h.from_location IN (?1, ?2)) AND h.to_location NOT IN (?1, ?2)
+         ORDER BY h.block_height DESC, h.tx_index DESC",
+        CUSTODY_HEADS, SHIPMENT_SELECT
+    ))
+    .bind(&organization.id)
+    .bind(&organization.name)
+    .fetch_all(pool)
+    .await
+}
+
+/// Batches handed to an organization that it has not acknowledged receiving.
+pub async fn shipments_awaiting_acknowledgement(pool: &SqlitePool, organization: &Organization) -> Result<Vec<Shipment>, sqlx::Error> {
+    sqlx::query_as::<_, Shipment>(&format!(
+        "{} {} AND h.to_location IN (?1, ?2)
+         ORDER BY h.block_height, h.tx_index",
+        CUSTODY_HEADS, SHIPMENT_SELECT
+    ))
+    .bind(&organization.id)
+    .bind(&organization.name)
+    .fetch_all(pool)
+    .await
+}
+
+/// Sealed batches a company created, per medicine.
+pub async fn batches_produced(pool: &SqlitePool, organization_id: &str) -> Result<Vec<MedicineBatches>, sqlx::Error> {
+    sqlx::query_as::<_, MedicineBatches>(
+        "SELECT b.medicine_name, COUNT(*) AS batches, COUNT(r.batch_id) AS recalled
+         FROM medicine_batches b
+         LEFT JOIN batch_recalls r ON r.batch_id = b.batch_id AND r.status = 'active'
+         WHERE b.organization = ?1
+         GROUP BY b.medicine_name
+         ORDER BY b.medicine_name"
+    )
+    .bind(organization_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Batches an organization holds, per medicine, with their expiry.
+pub async fn stock_by_medicine(pool: &SqlitePool, organization: &Organization) -> Result<Vec<StockLevel>, sqlx::Error> {
+    sqlx::query_as::<_, StockLevel>(&format!(
+        "{}{}
+         SELECT b.medicine_name, COUNT(*) AS batches, COUNT(r.batch_id) AS recalled,
+                COALESCE(SUM(b.expires_at <= date('now')), 0) AS expired,
+                MIN(CASE WHEN b.expires_at > date('now') THEN b.expires_at END) AS earliest_expiry
+         FROM held b
+         LEFT JOIN batch_recalls r ON r.batch_id = b.batch_id AND r.status = 'active'
+         GROUP BY b.medicine_name
+         ORDER BY b.medicine_name",
+        CUSTODY_HEADS, held_batches()
+    ))
+    .bind(&organization.id)
+    .bind(&organization.name)
+    .fetch_all(pool)
+    .await
+}
+
+/// Active and closed recall counts for the organization that issued them.
+pub async fn recall_counts(pool: &SqlitePool, organization_id: &str) -> Result<(i64, i64), sqlx::Error> {
+    sqlx::query_as(
+        "SELECT COALESCE(SUM(status = 'active'), 0), COALESCE(SUM(status = 'closed'), 0)
+         FROM batch_recalls WHERE organization = ?"
+    )
+    .bind(organization_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Why a transaction's hash or signature does not check out, if it does not.
+fn transaction_problem(tx: &LedgerTx) -> Option<&'static str> {
+    let recomputed = tx.recompute_hash();
+    if recomputed != tx.hash() {
+        return Some("Hash no longer matches the transaction's fields");
+    }
+    match (tx.public_key(), tx.signature()) {
+        (Some(key), Some(signature)) if signature_holds(key, signature, &recomputed) => None,
+        _ => Some("Signature verification failed"),
+    }
+}
+
+/// Checks the hash and signature of each transaction, off the async executor since RSA
+/// verification is CPU-bound. Returns each hash with its problem, if any.
+async fn check_transactions(transactions: Vec<LedgerTx>) -> Result<Vec<(String, Option<&'static str>)>, sqlx::Error> {
+    tokio::task::spawn_blocking(move || {
+        transactions
+            .iter()
+            .map(|tx| (tx.hash().to_string(), transaction_problem(tx)))
+            .collect()
+    })
+    .await
+    .map_err(|e| sqlx::Error::Protocol(e.to_string()))
+}
+
+async fn record_checks(conn: &mut SqliteConnection, checks: &[(String, Option<&str>)]) -> Result<(), sqlx::Error> {
+    let checked_at = chrono::Utc::now().to_rfc3339();
+    for (hash, problem) in checks {
+        sqlx::query("INSERT OR IGNORE INTO transaction_checks (hash, problem, checked_at) VALUES (?, ?, ?)")
+            .bind(hash)
+            .bind(problem)
+            .bind(&checked_at)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Checks and records every sealed transaction that has no stored check yet.
+async fn check_unchecked_transactions(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let batches = sqlx::query_as::<_, MedicineBatch>(
+        "SELECT b.* FROM medicine_batches b LEFT JOIN transaction_checks k ON k.hash = b.hash WHERE k.hash IS NULL"
+    )
+    .fetch_all(pool)
+    .await?;
+    let transfers = sqlx::query_as::<_, CustodyEvent>(
+        "SELECT c.batch_id, c.from_location, c.to_location, c.timestamp, c.hash, c.previous_hash, c.signature, c.public_key,
+                c.expected_arrival
+         FROM custody_events c LEFT JOIN transaction_checks k ON k.hash = c.hash
+         WHERE k.hash IS NULL"
+    )
+    .fetch_all(pool)
+    .await?;
+    let dispensings = sqlx::query_as::<_, DispensingEvent>(
+        "SELECT d.* FROM dispensing_events d LEFT JOIN transaction_checks k ON k.hash = d.hash WHERE k.hash IS NULL"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let transactions: Vec<LedgerTx> = batches
+        .into_iter()
+        .map(LedgerTx::Batch)
+        .chain(transfers.into_iter().map(LedgerTx::Custody))
+        .chain(dispensings.into_iter().map(LedgerTx::Dispensing))
+        .collect();
+    if transactions.is_empty() {
+        return Ok(());
+    }
+    let checks = check_transactions(transactions).await?;
+    let mut conn = pool.acquire().await?;
+    record_checks(&mut conn, &checks).await
+}
+
+/// The checks stored when each sealed transaction on a company's batches was adopted.
+/// Returns how many were checked and those that failed.
+pub async fn signature_failures(pool: &SqlitePool, organization_id: &str) -> Result<(usize, Vec<SignatureFailure>), sqlx::Error> {
+    let checks: Vec<(String, String, String, Option<String>)> = sqlx::query_as(
+        "WITH sealed (batch_id, kind, hash) AS (
+             SELECT batch_id, 'batch', hash FROM medicine_batches WHERE organization = ?
+             UNION ALL
+             SELECT c.batch_id, 'custody', c.hash
+             FROM custody_events c JOIN medicine_batches b ON b.batch_id = c.batch_id
+             WHERE b.organization = ?
+             UNION ALL
+             SELECT d.batch_id, 'dispensing', d.hash
+             FROM dispensing_events d JOIN medicine_batches b ON b.batch_id = d.batch_id
+             WHERE b.organization = ?
+         )
+         SELECT s.batch_id, s.kind, s.hash, k.problem FROM sealed s JOIN transaction_checks k ON k.hash = s.hash"
+    )
+    .bind(organization_id)
+    .bind(organization_id)
+    .bind(organization_id)
+    .fetch_all(pool)
+    .await?;
+
+    let checked = checks.len();
+    let failures = checks
+        .into_iter()
+        .filter_map(|(batch_id, transaction, hash, problem)| {
+            Some(SignatureFailure {
+                batch_id,
+                transaction,
+                hash,
+                problem: problem?,
+            })
+        })
+        .collect();
+    Ok((checked, failures))
+}
+
+/// Active recalls on batches an organization holds.
+pub async fn recalls_affecting(pool: &SqlitePool, organization: &Organization) -> Result<Vec<Recall>, sqlx::Error> {
+    sqlx::query_as::<_, Recall>(&format!(
+        "{}{} SELECT r.* FROM batch_recalls r JOIN held b ON b.batch_id = r.batch_id
+         WHERE r.status = 'active'
+         ORDER BY r.id DESC",
+        CUSTODY_HEADS, held_batches()
+    ))
+    .bind(&organization.id)
+    .bind(&organization.name)
+    .fetch_all(pool)
+    .await
+}
+
+/// Out-of-range readings on batches an organization holds, newest first.
+pub async fn excursions_affecting(pool: &SqlitePool, organization: &Organization) -> Result<Vec<ConditionReading>, sqlx::Error> {
+    sqlx::query_as::<_, ConditionReading>(&format!(
+        "{}{} SELECT x.* FROM condition_readings x JOIN held b ON b.batch_id = x.batch_id
+         WHERE x.excursion = 1
+         ORDER BY x.recorded_at DESC, x.id DESC",
+        CUSTODY_HEADS, held_batches()
+    ))
+    .bind(&organization.id)
+    .bind(&organization.name)
+    .fetch_all(pool)
+    .await
+}
+
+/// Stores a temperature reading for a sealed batch, judged against the batch's storage range.
+pub async fn add_condition_reading(
+    pool: &SqlitePool,
+    batch: &MedicineBatch,
+    location: &str,
+    temperature_celsius: f64,
+    recorded_at: &str,
+    recorded_by: &str,
+) -> Result<ConditionReading, sqlx::Error> {
+    let excursion = batch.terms.storage_min_celsius.is_some_and(|min| temperature_celsius < min)
+        || batch.terms.storage_max_celsius.is_some_and(|max| temperature_celsius > max);
+    sqlx::query_as::<_, ConditionReading>(
+        "INSERT INTO condition_readings (batch_id, location, temperature_celsius, excursion, recorded_at, recorded_by)
+         VALUES (?, ?, ?, ?, ?, ?)
+         RETURNING *"
+    )
+    .bind(&batch.batch_id)
+    .bind(location)
+    .bind(temperature_celsius)
+    .bind(excursion)
+    .bind(recorded_at)
+    .bind(recorded_by)
+    .fetch_one(pool)
+    .await
+}
+
+/// A batch's readings in the order they were taken.
+pub async fn condition_readings(pool: &SqlitePool, batch_id: &str) -> Result<Vec<ConditionReading>, sqlx::Error> {
+    sqlx::query_as::<_, ConditionReading>(
+        "SELECT * FROM condition_readings WHERE batch_id = ? ORDER BY recorded_at, id"
+    )
+    .bind(batch_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// How much of one batch an organization holds.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct BatchBalance {
+    pub batch_id: String,
+    pub medicine_name: String,
+    pub expires_at: Option<String>,
+    pub quantity: i64,
+}
+
+/// Where a batch is now and how much of it is left.
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct BatchStock {
+    pub batch_id: String,
+    pub medicine_name: String,
+    /// Organization id or name, or a free-text location while in transit.
+    pub holder: String,
+    /// Unset for batches created without a quantity.
+    pub quantity: Option<i64>,
+}
+
+/// One change to an organization's stock, derived from the ledger and inventory events.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct InventoryMovement {
+    pub batch_id: String,
+    pub medicine_name: String,
+    /// `created`, `split_in`, `split_out`, `transfer_in`, `transfer_out`, `dispensed`, `destroyed` or `adjusted`.
+    pub kind: String,
+    /// Signed change to the organization's stock.
+    pub quantity: i64,
+    /// The other end of a transfer.
+    pub counterparty: Option<String>,
+    /// The custody hash, the batch split from or into, or the reconciliation.
+    pub reference: Option<String>,
+    pub reason: Option<String>,
+    pub occurred_at: String,
+}
+
+pub struct NewInventoryEvent<'a> {
+    pub organization: &'a str,
+    pub batch_id: &'a str,
+    pub kind: &'a str,
+    pub quantity: i64,
+    pub reason: Option<&'a str>,
+    pub reference: Option<&'a str>,
+    pub recorded_by: &'a str,
+    pub occurred_at: &'a str,
+}
+
+/// A physical stock count and who made it.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct Reconciliation {
+    pub id: i64,
+    pub organization: String,
+    pub note: Option<String>,
+    pub performed_by: String,
+    pub performed_at: String,
+}
+
+/// One batch's line in a stock count.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct InventoryCount {
+    pub batch_id: String,
+    pub medicine_name: String,
+    /// The derived balance when counted.
+    pub expected_quantity: i64,
+    pub counted_quantity: i64,
+    /// `counted_quantity - expected_quantity`, recorded as an adjustment when not zero.
+    pub difference: i64,
+    pub reason: Option<String>,
+}
+
+/// Batch `b`'s quantity left at `at`: what it was created with, less what was split off, plus
+/// inventory events.
+fn remaining_at(at: &str) -> String {
+    format!(
+        "b.quantity
+         - COALESCE((SELECT SUM(k.quantity) FROM medicine_batches k
+                     WHERE k.parent_batch_id = b.batch_id AND julianday(k.timestamp) <= julianday({at})), 0)
+         + COALESCE((SELECT SUM(e.quantity) FROM inventory_events e
+                     WHERE e.batch_id = b.batch_id AND julianday(e.occurred_at) <= julianday({at})), 0)"
+    )
+}
+
+/// Where batch `b` was at `at`: its latest transfer by then, or where it started.
+fn location_at(at: &str) -> String {
+    format!(
+        "COALESCE((SELECT c.to_location FROM custody_events c
+                   WHERE c.batch_id = b.batch_id AND julianday(c.timestamp) <= julianday({at})
+                   ORDER BY c.block_height DESC, c.tx_index DESC LIMIT 1), b.destination)"
+    )
+}
+
+/// Every batch created by `at`, with `balance` and `holder` as of `at`.
+fn batch_positions(at: &str) -> String {
+    format!(
+        "SELECT b.batch_id, b.medicine_name, b.gtin, b.expires_at, b.balance, {} AS holder
+         FROM (
+             SELECT b.*, {} AS balance, {} AS location
+             FROM medicine_batches b
+             WHERE julianday(b.timestamp) <= julianday({at})
+         ) b",
+        holder("b.location"),
+        remaining_at(at),
+        location_at(at)
+    )
+}
+
+/// Batches an organization held at `at` (RFC 3339), with what was left of each.
+pub async fn inventory_balances(pool: &SqlitePool, organization: &Organization, at: &str) -> Result<Vec<BatchBalance>, sqlx::Error> {
+    sqlx::query_as::<_, BatchBalance>(&format!(
+        "SELECT batch_id, medicine_name, expires_at, balance AS quantity FROM ({})
+         WHERE holder IN (?1, ?2) AND balance IS NOT NULL AND balance <> 0
+         ORDER BY medicine_name, expires_at IS NULL, expires_at, batch_id",
+        batch_positions("?3")
+    ))
+    .bind(&organization.id)
+    .bind(&organization.name)
+    .bind(at)
+    .fetch_all(pool)
+    .await
+}
+
+/// A sealed batch's holder and quantity now.
+pub async fn batch_stock(pool: &SqlitePool, batch_id: &str) -> Result<Option<BatchStock>, sqlx::Error> {
+    sqlx::query_as::<_, BatchStock>(&format!(
+        "SELECT batch_id, medicine_name, holder, balance AS quantity FROM ({}) WHERE batch_id = ?1",
+        batch_positions("?2")
+    ))
+    .bind(batch_id)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .fetch_optional(pool)
+    .await
+}
+
+/// Filters for [`inventory_movements`]; unset fields match everything.
+#[derive(Deserialize, Debug, Default)]
+pub struct MovementFilter {
+    /// RFC 3339; movements at or after it.
+    pub since: Option<String>,
+    /// RFC 3339; movements before it.
+    pub until: Option<String>,
+    pub batch_id: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Every stock movement, each with the `holder` whose stock it changed and the batch's `gtin`.
+/// Transfers move whatever was left of the batch at the time.
+fn stock_movements() -> String {
+    format!(
+        "SELECT b.batch_id, b.medicine_name, b.gtin,
+                CASE WHEN b.parent_batch_id IS NULL THEN 'created' ELSE 'split_in' END AS kind,
+                b.quantity, NULL AS counterparty, b.parent_batch_id AS reference, NULL AS reason,
+                b.timestamp AS occurred_at, {creator} AS holder
+         FROM medicine_batches b WHERE b.quantity IS NOT NULL
+         UNION ALL
+         SELECT b.batch_id, b.medicine_name, b.gtin, 'split_out', -k.quantity, NULL, k.batch_id, NULL, k.timestamp, {splitter}
+         FROM medicine_batches k JOIN medicine_batches b ON b.batch_id = k.parent_batch_id
+         UNION ALL
+         SELECT b.batch_id, b.medicine_name, b.gtin, 'transfer_out', -({moved}), c.to_location, c.hash, NULL, c.timestamp, {sender}
+         FROM custody_events c JOIN medicine_batches b ON b.batch_id = c.batch_id WHERE b.quantity IS NOT NULL
+         UNION ALL
+         SELECT b.batch_id, b.medicine_name, b.gtin, 'transfer_in', {moved}, c.from_location, c.hash, NULL, c.timestamp, {receiver}
+         FROM custody_events c JOIN medicine_batches b ON b.batch_id = c.batch_id WHERE b.quantity IS NOT NULL
+         UNION ALL
+         SELECT b.batch_id, b.medicine_name, b.gtin, e.kind, e.quantity, NULL, e.reference, e.reason, e.occurred_at, e.organization
+         FROM inventory_events e JOIN medicine_batches b ON b.batch_id = e.batch_id",
+        creator = holder("b.destination"),
+        splitter = holder("k.destination"),
+        moved = remaining_at("c.timestamp"),
+        sender = holder("c.from_location"),
+        receiver = holder("c.to_location"),
+    )
+}
+
+/// An organization's stock movements in the order they happened.
+pub async fn inventory_movements(
+    pool: &SqlitePool,
+    organization: &Organization,
+    filter: &MovementFilter,
+) -> Result<Vec<InventoryMovement>, sqlx::Error> {
+    sqlx::query_as::<_, InventoryMovement>(&format!(
+        "SELECT batch_id, medicine_name, kind, quantity, counterparty, reference, reason, occurred_at FROM ({})
+         WHERE holder IN (?1, ?2)
+           AND (?3 IS NULL OR julianday(occurred_at) >= julianday(?3))
+           AND (?4 IS NULL OR julianday(occurred_at) < julianday(?4))
+           AND (?5 IS NULL OR batch_id = ?5)
+         ORDER BY julianday(occurred_at), batch_id
+         LIMIT ?6",
+        stock_movements()
+    ))
+    .bind(&organization.id)
+    .bind(&organization.name)
+    .bind(&filter.since)
+    .bind(&filter.until)
+    .bind(&filter.batch_id)
+    .bind(filter.limit.unwrap_or(500).clamp(1, 5000))
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn add_inventory_event(conn: &mut SqliteConnection, event: &NewInventoryEvent<'_>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO inventory_events (organization, batch_id, kind, quantity, reason, reference, recorded_by, occurred_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(event.organization)
+    .bind(event.batch_id)
+    .bind(event.kind)
+    .bind(event.quantity)
+    .bind(event.reason)
+    .bind(event.reference)
+    .bind(event.recorded_by)
+    .bind(event.occurred_at)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Stores a stock count with its lines; the caller records the adjustments.
+pub async fn add_reconciliation(
+    conn: &mut SqliteConnection,
+    organization: &str,
+    note: Option<&str>,
+    performed_by: &str,
+    performed_at: &str,
+    counts: &[InventoryCount],
+) -> Result<Reconciliation, sqlx::Error> {
+    let reconciliation = sqlx::query_as::<_, Reconciliation>(
+        "INSERT INTO inventory_reconciliations (organization, note, performed_by, performed_at)
+         VALUES (?, ?, ?, ?)
+         RETURNING *"
+    )
+    .bind(organization)
+    .bind(note)
+    .bind(performed_by)
+    .bind(performed_at)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    for count in counts {
+        sqlx::query(
+            "INSERT INTO inventory_counts (reconciliation_id, batch_id, medicine_name, expected_quantity, counted_quantity, difference, reason)
+             VALUES (?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(reconciliation.id)
+        .bind(&count.batch_id)
+        .bind(&count.medicine_name)
+        .bind(count.expected_quantity)
+        .bind(count.counted_quantity)
+        .bind(count.difference)
+        .bind(&count.reason)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(reconciliation)
+}
+
+/// An organization's stock counts, newest first.
+pub async fn reconciliations(pool: &SqlitePool, organization: &str) -> Result<Vec<Reconciliation>, sqlx::Error> {
+    sqlx::query_as::<_, Reconciliation>("SELECT * FROM inventory_reconciliations WHERE organization = ? ORDER BY id DESC")
+        .bind(organization)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn find_reconciliation(pool: &SqlitePool, organization: &str, id: i64) -> Result<Option<Reconciliation>, sqlx::Error> {
+    sqlx::query_as::<_, Reconciliation>("SELECT * FROM inventory_reconciliations WHERE id = ? AND organization = ?")
+        .bind(id)
+        .bind(organization)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn inventory_counts(pool: &SqlitePool, reconciliation_id: i64) -> Result<Vec<InventoryCount>, sqlx::Error> {
+    sqlx::query_as::<_, InventoryCount>(
+        "SELECT batch_id, medicine_name, expected_quantity, counted_quantity, difference, reason
+         FROM inventory_counts WHERE reconciliation_id = ? ORDER BY medicine_name, batch_id"
+    )
+    .bind(reconciliation_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// A hospital's order to a company.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct PurchaseOrder {
+    pub id: String,
+    pub hospital: String,
+    pub hospital_name: String,
+    pub company: String,
+    pub company_name: String,
+    /// `submitted`, then `accepted`, `partially_accepted`, `rejected` or `cancelled`; accepted
+    /// orders move to `partially_fulfilled` and `fulfilled` as the hospital acknowledges shipments.
+    pub status: String,
+    /// `YYYY-MM-DD`.
+    pub needed_by: Option<String>,
+    pub note: Option<String>,
+    /// The company's note when accepting or rejecting.
+    pub response_note: Option<String>,
+    pub created_by: String,
+    pub created_at: String,
+    pub responded_by: Option<String>,
+    pub responded_at: Option<String>,
+    pub updated_at: String,
+}
+
+/// One medicine on a purchase order, with how much of it has shipped and arrived.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct PurchaseOrderLine {
+    pub line_no: i64,
+    pub medicine_name: String,
+    pub quantity: i64,
+    /// Set when the company responds; below `quantity` for a partial fill.
+    pub accepted_quantity: Option<i64>,
+    /// Linked to shipments.
+    pub shipped_quantity: i64,
+    /// In shipments the hospital has acknowledged.
+    pub delivered_quantity: i64,
+}
+
+/// A batch shipped against an order line.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct OrderShipment {
+    pub batch_id: String,
+    pub order_id: String,
+    pub line_no: i64,
+    pub quantity: i64,
+    pub linked_by: String,
+    pub linked_at: String,
+    /// When the hospital acknowledged the transfer that brought the batch to it.
+    pub delivered_at: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NewOrderLine {
+    pub medicine_name: String,
+    pub quantity: i64,
+}
+
+pub struct NewPurchaseOrder<'a> {
+    pub hospital: &'a str,
+    pub company: &'a str,
+    pub needed_by: Option<&'a str>,
+    pub note: Option<&'a str>,
+    pub created_by: &'a str,
+    pub created_at: &'a str,
+    pub lines: &'a [NewOrderLine],
+}
+
+const PURCHASE_ORDER_SELECT: &str = "SELECT o.*, COALESCE(h.name, '') AS hospital_name, COALESCE(c.name, '') AS company_name
+     FROM purchase_orders o
+     LEFT JOIN hospitals h ON h.id = o.hospital
+     LEFT JOIN companies c ON c.id = o.company";
+
+/// Stores an order and its lines, numbered from 1. Returns the order id.
+pub async fn add_purchase_order(conn: &mut SqliteConnection, order: &NewPurchaseOrder<'_>) -> Result<String, sqlx::Error> {
+    let id = Uuid::new_v4().to_string();
+    sqlx::query(
+        "INSERT INTO purchase_orders (id, hospital, company, needed_by, note, created_by, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)"
+    )
+    .bind(&id)
+    .bind(order.hospital)
+    .bind(order.company)
+    .bind(order.needed_by)
+    .bind(order.note)
+    .bind(order.created_by)
+    .bind(order.created_at)
+    .execute(&mut *conn)
+    .await?;
+
+    for (line_no, line) in (1_i64..).zip(order.lines) {
+        sqlx::query("INSERT INTO purchase_order_lines (order_id, line_no, medicine_name, quantity) VALUES (?, ?, ?, ?)")
+            .bind(&id)
+            .bind(line_no)
+            .bind(&line.medicine_name)
+            .bind(line.quantity)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(id)
+}
+
+/// Orders an organization raised or received, newest first, optionally with one status.
+pub async fn purchase_orders(pool: &SqlitePool, organization_id: &str, status: Option<&str>) -> Result<Vec<PurchaseOrder>, sqlx::Error> {
+    sqlx::query_as::<_, PurchaseOrder>(&format!(
+        "{} WHERE (o.hospital = ?1 OR o.company = ?1) AND (?2 IS NULL OR o.status = ?2)
+         ORDER BY o.created_at DESC",
+        PURCHASE_ORDER_SELECT
+    ))
+    .bind(organization_id)
+    .bind(status)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn find_purchase_order(pool: &SqlitePool, id: &str) -> Result<Option<PurchaseOrder>, sqlx::Error> {
+    sqlx::query_as::<_, PurchaseOrder>(&format!("{} WHERE o.id = ?", PURCHASE_ORDER_SELECT))
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn purchase_order_lines(pool: &SqlitePool, order_id: &str) -> Result<Vec<PurchaseOrderLine>, sqlx::Error> {
+    sqlx::query_as::<_, PurchaseOrderLine>(
+        "SELECT l.line_no, l.medicine_name, l.quantity, l.accepted_quantity,
+                COALESCE(SUM(s.quantity), 0) AS shipped_quantity,
+                COALESCE(SUM(CASE WHEN s.delivered_at IS NOT NULL THEN s.quantity END), 0) AS delivered_quantity
+         FROM purchase_order_lines l
+         LEFT JOIN purchase_order_shipments s ON s.order_id = l.order_id AND s.line_no = l.line_no
+         WHERE l.order_id = ?
+         GROUP BY l.line_no
+         ORDER BY l.line_no"
+    )
+    .bind(order_id)
+    .fetch_all(pool)
+    .await
+}
 
-    if verified {
-        Ok((true, "Batch signature and Merkle proof valid".to_string()))
-    } else {
-        Ok((false, "Signature verification failed".to_string()))
+pub async fn order_shipments(pool: &SqlitePool, order_id: &str) -> Result<Vec<OrderShipment>, sqlx::Error> {
+    sqlx::query_as::<_, OrderShipment>("SELECT * FROM purchase_order_shipments WHERE order_id = ? ORDER BY line_no, linked_at")
+        .bind(order_id)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn find_order_shipment(pool: &SqlitePool, batch_id: &str) -> Result<Option<OrderShipment>, sqlx::Error> {
+    sqlx::query_as::<_, OrderShipment>("SELECT * FROM purchase_order_shipments WHERE batch_id = ?")
+        .bind(batch_id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Records the company's answer to a submitted order: `accepted` lists each line's accepted
+/// quantity (ignored when rejecting). Returns false if the order was no longer `submitted`.
+pub async fn respond_to_purchase_order(
+    conn: &mut SqliteConnection,
+    order_id: &str,
+    status: &str,
+    accepted: &[(i64, i64)],
+    note: Option<&str>,
+    responded_by: &str,
+    responded_at: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE purchase_orders SET status = ?2, response_note = ?3, responded_by = ?4, responded_at = ?5, updated_at = ?5
+         WHERE id = ?1 AND status = 'submitted'"
+    )
+    .bind(order_id)
+    .bind(status)
+    .bind(note)
+    .bind(responded_by)
+    .bind(responded_at)
+    .execute(&mut *conn)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    for (line_no, quantity) in accepted {
+        sqlx::query("UPDATE purchase_order_lines SET accepted_quantity = ? WHERE order_id = ? AND line_no = ?")
+            .bind(quantity)
+            .bind(order_id)
+            .bind(line_no)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(true)
+}
+
+/// Withdraws an order the company has not answered yet. Returns false if it already had.
+pub async fn cancel_purchase_order(pool: &SqlitePool, order_id: &str, cancelled_at: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE purchase_orders SET status = 'cancelled', updated_at = ? WHERE id = ? AND status = 'submitted'")
+        .bind(cancelled_at)
+        .bind(order_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn add_order_shipment(pool: &SqlitePool, shipment: &OrderShipment) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO purchase_order_shipments (batch_id, order_id, line_no, quantity, linked_by, linked_at)
+         VALUES (?, ?, ?, ?, ?, ?)"
+    )
+    .bind(&shipment.batch_id)
+    .bind(&shipment.order_id)
+    .bind(shipment.line_no)
+    .bind(shipment.quantity)
+    .bind(&shipment.linked_by)
+    .bind(&shipment.linked_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Marks order shipments of `batch_id` to `hospital` as delivered and moves their orders to
+/// `partially_fulfilled` or `fulfilled`. Returns the orders that changed.
+pub async fn record_order_delivery(
+    pool: &SqlitePool,
+    batch_id: &str,
+    hospital: &str,
+    delivered_at: &str,
+) -> Result<Vec<String>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let orders: Vec<String> = sqlx::query_scalar(
+        "UPDATE purchase_order_shipments SET delivered_at = ?3
+         WHERE batch_id = ?1 AND delivered_at IS NULL
+           AND order_id IN (SELECT id FROM purchase_orders WHERE hospital = ?2)
+         RETURNING order_id"
+    )
+    .bind(batch_id)
+    .bind(hospital)
+    .bind(delivered_at)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for order_id in &orders {
+        sqlx::query(
+            "UPDATE purchase_orders SET updated_at = ?2, status = CASE
+                 WHEN NOT EXISTS (
+                     SELECT 1 FROM purchase_order_lines l
+                     WHERE l.order_id = ?1
+                       AND COALESCE(l.accepted_quantity, 0) > (
+                           SELECT COALESCE(SUM(s.quantity), 0) FROM purchase_order_shipments s
+                           WHERE s.order_id = l.order_id AND s.line_no = l.line_no AND s.delivered_at IS NOT NULL))
+                 THEN 'fulfilled' ELSE 'partially_fulfilled' END
+             WHERE id = ?1 AND status IN ('accepted', 'partially_accepted', 'partially_fulfilled')"
+        )
+        .bind(order_id)
+        .bind(delivered_at)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(orders)
+}
+
+/// When the hospital acknowledged the latest transfer of `batch_id`, if that transfer went to it.
+pub async fn delivered_to(pool: &SqlitePool, batch_id: &str, hospital: &Organization) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar(&format!(
+        "{} SELECT a.acknowledged_at FROM heads h
+         JOIN custody_acknowledgements a ON a.custody_hash = h.hash
+         WHERE h.batch_id = ?1 AND h.position = 1 AND h.to_location IN (?2, ?3)",
+        CUSTODY_HEADS
+    ))
+    .bind(batch_id)
+    .bind(&hospital.id)
+    .bind(&hospital.name)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Where an organization is.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct OrganizationSite {
+    pub organization_id: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Free text, such as a state or health district; demand and supply are grouped by it.
+    pub region: Option<String>,
+    pub updated_by: String,
+    pub updated_at: String,
+}
+
+pub async fn find_organization_site(pool: &SqlitePool, organization_id: &str) -> Result<Option<OrganizationSite>, sqlx::Error> {
+    sqlx::query_as::<_, OrganizationSite>("SELECT * FROM organization_sites WHERE organization_id = ?")
+        .bind(organization_id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn save_organization_site(pool: &SqlitePool, site: &OrganizationSite) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO organization_sites (organization_id, latitude, longitude, region, updated_by, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT (organization_id) DO UPDATE SET
+             latitude = excluded.latitude, longitude = excluded.longitude, region = excluded.region,
+             updated_by = excluded.updated_by, updated_at = excluded.updated_at"
+    )
+    .bind(&site.organization_id)
+    .bind(site.latitude)
+    .bind(site.longitude)
+    .bind(&site.region)
+    .bind(&site.updated_by)
+    .bind(&site.updated_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// A product name folded for comparison: lowercase with whitespace removed, so
+/// "Paracetamol 500mg" and "paracetamol 500 mg" compare equal.
+pub fn medicine_key(name: &str) -> String {
+    name.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).collect()
+}
+
+/// Whether a batch of `batch_medicine` is the product `medicine_name` at `strength`; the
+/// rule [`supply_batches`] applies in SQL.
+pub fn product_matches(batch_medicine: &str, medicine_name: &str, strength: Option<&str>) -> bool {
+    let batch = medicine_key(batch_medicine);
+    let name = medicine_key(medicine_name);
+    match strength {
+        Some(strength) => batch == format!("{}{}", name, medicine_key(strength)),
+        None => batch
+            .strip_prefix(name.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(|c: char| c.is_ascii_digit())),
+    }
+}
+
+/// The same folding as [`medicine_key`], in SQL.
+fn medicine_key_sql(column: &str) -> String {
+    format!("REPLACE(REPLACE(REPLACE(LOWER({column}), ' ', ''), char(9), ''), char(10), '')")
+}
+
+/// A company's price for a product.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct SupplyPrice {
+    pub organization: String,
+    pub medicine_key: String,
+    pub medicine_name: String,
+    pub unit_price: f64,
+    pub currency: String,
+    pub updated_by: String,
+    pub updated_at: String,
+}
+
+pub async fn save_supply_price(pool: &SqlitePool, price: &SupplyPrice) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO supply_prices (organization, medicine_key, medicine_name, unit_price, currency, updated_by, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT (organization, medicine_key) DO UPDATE SET
+             medicine_name = excluded.medicine_name, unit_price = excluded.unit_price, currency = excluded.currency,
+             updated_by = excluded.updated_by, updated_at = excluded.updated_at"
+    )
+    .bind(&price.organization)
+    .bind(&price.medicine_key)
+    .bind(&price.medicine_name)
+    .bind(price.unit_price)
+    .bind(&price.currency)
+    .bind(&price.updated_by)
+    .bind(&price.updated_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn supply_prices(pool: &SqlitePool, organization: &str) -> Result<Vec<SupplyPrice>, sqlx::Error> {
+    sqlx::query_as::<_, SupplyPrice>("SELECT * FROM supply_prices WHERE organization = ? ORDER BY medicine_key")
+        .bind(organization)
+        .fetch_all(pool)
+        .await
+}
+
+/// Structured demand: how much of a product an organization needs, and by when.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct DemandRequest {
+    pub id: i64,
+    pub organization: String,
+    pub medicine_name: String,
+    /// Such as `500mg`; any strength matches when unset.
+    pub strength: Option<String>,
+    pub quantity: i64,
+    /// `YYYY-MM-DD`; stock must not expire before it.
+    pub needed_by: Option<String>,
+    pub note: Option<String>,
+    /// ISO 4217; suppliers are ranked on their prices in it.
+    pub currency: String,
+    /// `open`, `fulfilled` or `cancelled`.
+    pub status: String,
+    pub created_by: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+pub struct NewDemandRequest<'a> {
+    pub organization: &'a str,
+    pub medicine_name: &'a str,
+    pub strength: Option<&'a str>,
+    pub quantity: i64,
+    pub needed_by: Option<&'a str>,
+    pub note: Option<&'a str>,
+    pub currency: &'a str,
+    pub created_by: &'a str,
+    pub created_at: &'a str,
+}
+
+pub async fn add_demand_request(pool: &SqlitePool, demand: &NewDemandRequest<'_>) -> Result<DemandRequest, sqlx::Error> {
+    sqlx::query_as::<_, DemandRequest>(
+        "INSERT INTO demand_requests (organization, medicine_name, strength, quantity, needed_by, note, currency, created_by, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?9)
+         RETURNING *"
+    )
+    .bind(demand.organization)
+    .bind(demand.medicine_name)
+    .bind(demand.strength)
+    .bind(demand.quantity)
+    .bind(demand.needed_by)
+    .bind(demand.note)
+    .bind(demand.currency)
+    .bind(demand.created_by)
+    .bind(demand.created_at)
+    .fetch_one(pool)
+    .await
+}
+
+/// An organization's demand, newest first, optionally with one status.
+pub async fn demand_requests(pool: &SqlitePool, organization: &str, status: Option<&str>) -> Result<Vec<DemandRequest>, sqlx::Error> {
+    sqlx::query_as::<_, DemandRequest>(
+        "SELECT * FROM demand_requests WHERE organization = ?1 AND (?2 IS NULL OR status = ?2) ORDER BY id DESC"
+    )
+    .bind(organization)
+    .bind(status)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn find_demand_request(pool: &SqlitePool, organization: &str, id: i64) -> Result<Option<DemandRequest>, sqlx::Error> {
+    sqlx::query_as::<_, DemandRequest>("SELECT * FROM demand_requests WHERE id = ? AND organization = ?")
+        .bind(id)
+        .bind(organization)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Closes an open demand request as `fulfilled` or `cancelled`. Returns false if it was not open.
+pub async fn close_demand_request(pool: &SqlitePool, id: i64, status: &str, closed_at: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE demand_requests SET status = ?, updated_at = ? WHERE id = ? AND status = 'open'")
+        .bind(status)
+        .bind(closed_at)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// A batch a company holds that could meet a demand.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct SupplyBatch {
+    #[serde(skip)]
+    pub organization_id: String,
+    #[serde(skip)]
+    pub organization_name: String,
+    pub batch_id: String,
+    pub medicine_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gtin: Option<String>,
+    pub expires_at: Option<String>,
+    /// What is left of the batch, less what is promised to undelivered purchase orders.
+    pub available: i64,
+    pub unit_price: Option<f64>,
+    pub currency: Option<String>,
+    #[serde(skip)]
+    pub latitude: Option<f64>,
+    #[serde(skip)]
+    pub longitude: Option<f64>,
+    #[serde(skip)]
+    pub region: Option<String>,
+}
+
+/// Batches approved companies hold now that match the product `medicine_name` (and `strength`
+/// when given), have stock not promised elsewhere, are not recalled, and do not expire by
+/// `usable_until` (`YYYY-MM-DD`). Without a strength, a product name followed by a strength
+/// ("Paracetamol 500mg" for "Paracetamol") matches as well. A batch of a catalog product is
+/// named by the product's active ingredient and strength rather than its own medicine name.
+pub async fn supply_batches(
+    pool: &SqlitePool,
+    medicine_name: &str,
+    strength: Option<&str>,
+    usable_until: &str,
+) -> Result<Vec<SupplyBatch>, sqlx::Error> {
+    let key = medicine_key_sql("COALESCE(c.active_ingredient || c.strength, p.medicine_name)");
+    sqlx::query_as::<_, SupplyBatch>(&format!(
+        "SELECT o.id AS organization_id, o.name AS organization_name, p.batch_id, p.medicine_name, p.gtin, p.expires_at,
+                p.balance - COALESCE((SELECT SUM(s.quantity) FROM purchase_order_shipments s
+                                      WHERE s.batch_id = p.batch_id AND s.delivered_at IS NULL), 0) AS available,
+                sp.unit_price, sp.currency, site.latitude, site.longitude, site.region
+         FROM ({positions}) p
+         JOIN organizations o ON p.holder IN (o.id, o.name) AND o.organization_type = 'company'
+         JOIN organization_registrations r ON r.organization_id = o.id
+         LEFT JOIN catalog_products c ON c.gtin = p.gtin
+         LEFT JOIN supply_prices sp ON sp.organization = o.id AND sp.medicine_key = {key}
+         LEFT JOIN organization_sites site ON site.organization_id = o.id
+         WHERE r.status = 'approved' AND (r.license_expires_at IS NULL OR r.license_expires_at > date('now'))
+           AND (CASE WHEN ?3 IS NULL THEN {key} = ?2 OR {key} GLOB ?2 || '[0-9]*' ELSE {key} = ?2 || ?3 END)
+           AND (p.expires_at IS NULL OR p.expires_at > ?4)
+           AND p.batch_id NOT IN (SELECT batch_id FROM batch_recalls WHERE status = 'active')
+           AND available > 0
+         ORDER BY o.id, p.expires_at IS NULL, p.expires_at, p.batch_id",
+        positions = batch_positions("?1"),
+    ))
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(medicine_key(medicine_name))
+    .bind(strength.map(medicine_key))
+    .bind(usable_until)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn designate_critical_care(
+    pool: &SqlitePool,
+    organization_id: &str,
+    note: Option<&str>,
+    designated_by: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO critical_care_designations (organization_id, note, designated_by, designated_at)
+         VALUES (?, ?, ?, ?)
+         ON CONFLICT (organization_id) DO UPDATE SET
+             note = excluded.note, designated_by = excluded.designated_by, designated_at = excluded.designated_at"
+    )
+    .bind(organization_id)
+    .bind(note)
+    .bind(designated_by)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns false if the hospital was not designated.
+pub async fn remove_critical_care(pool: &SqlitePool, organization_id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM critical_care_designations WHERE organization_id = ?")
+        .bind(organization_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// A hospital designated as critical care.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct CriticalCareDesignation {
+    pub organization_id: String,
+    pub organization_name: String,
+    pub note: Option<String>,
+    pub designated_by: String,
+    pub designated_at: String,
+}
+
+pub async fn critical_care_designations(pool: &SqlitePool) -> Result<Vec<CriticalCareDesignation>, sqlx::Error> {
+    sqlx::query_as::<_, CriticalCareDesignation>(
+        "SELECT d.organization_id, COALESCE(h.name, '') AS organization_name, d.note, d.designated_by, d.designated_at
+         FROM critical_care_designations d
+         LEFT JOIN hospitals h ON h.id = d.organization_id
+         ORDER BY organization_name"
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// An open demand request with what allocation and shortage reports need about its requester.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct DemandClaim {
+    pub demand_id: i64,
+    pub organization: String,
+    pub organization_name: String,
+    pub region: Option<String>,
+    pub critical_care: bool,
+    pub medicine_name: String,
+    pub strength: Option<String>,
+    pub quantity: i64,
+    pub needed_by: Option<String>,
+    pub created_at: String,
+}
+
+/// Every open demand request, oldest first.
+pub async fn open_demand(pool: &SqlitePool) -> Result<Vec<DemandClaim>, sqlx::Error> {
+    sqlx::query_as::<_, DemandClaim>(
+        "SELECT d.id AS demand_id, d.organization, COALESCE(o.name, '') AS organization_name, s.region,
+                c.organization_id IS NOT NULL AS critical_care,
+                d.medicine_name, d.strength, d.quantity, d.needed_by, d.created_at
+         FROM demand_requests d
+         LEFT JOIN organizations o ON o.id = d.organization
+         LEFT JOIN organization_sites s ON s.organization_id = d.organization
+         LEFT JOIN critical_care_designations c ON c.organization_id = d.organization
+         WHERE d.status = 'open'
+         ORDER BY d.created_at, d.id"
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Units each organization dispensed since `since` (RFC 3339), per medicine as named on the
+/// batch, or as its catalog product's active ingredient and strength.
+pub async fn dispensed_since(pool: &SqlitePool, since: &str) -> Result<Vec<(String, String, i64)>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT e.organization, COALESCE(c.active_ingredient || ' ' || c.strength, b.medicine_name) AS product, -SUM(e.quantity)
+         FROM inventory_events e
+         JOIN medicine_batches b ON b.batch_id = e.batch_id
+         LEFT JOIN catalog_products c ON c.gtin = b.gtin
+         WHERE e.kind = 'dispensed' AND julianday(e.occurred_at) >= julianday(?)
+         GROUP BY e.organization, product"
+    )
+    .bind(since)
+    .fetch_all(pool)
+    .await
+}
+
+/// How a company shared out stock of one product among open demand.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct StockAllocation {
+    pub id: i64,
+    pub organization: String,
+    pub medicine_name: String,
+    pub strength: Option<String>,
+    /// Only demand from organizations in this region was considered.
+    pub region: Option<String>,
+    /// `pro_rata`, `critical_care` or `historical_usage`.
+    pub policy: String,
+    /// The usage window for `historical_usage`.
+    pub usage_days: Option<i64>,
+    pub available_quantity: i64,
+    pub requested_quantity: i64,
+    pub allocated_quantity: i64,
+    pub note: Option<String>,
+    pub created_by: String,
+    pub created_at: String,
+    pub previous_hash: String,
+    pub hash: String,
+}
+
+/// One demand request's share of an allocation.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct AllocationLine {
+    pub demand_id: i64,
+    pub organization: String,
+    pub requested_quantity: i64,
+    /// What the policy weighed the request by.
+    pub weight: f64,
+    pub critical_care: bool,
+    pub allocated_quantity: i64,
+}
+
+pub async fn last_allocation_hash(conn: &mut SqliteConnection) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT hash FROM stock_allocations ORDER BY id DESC LIMIT 1")
+        .fetch_optional(&mut *conn)
+        .await
+}
+
+/// Appends an allocation and its lines. Returns the allocation's id.
+pub async fn append_allocation(
+    conn: &mut SqliteConnection,
+    allocation: &StockAllocation,
+    lines: &[AllocationLine],
+) -> Result<i64, sqlx::Error> {
+    let id: i64 = sqlx::query_scalar(
+        "INSERT INTO stock_allocations (organization, medicine_name, strength, region, policy, usage_days, available_quantity,
+                                        requested_quantity, allocated_quantity, note, created_by, created_at, previous_hash, hash)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         RETURNING id"
+    )
+    .bind(&allocation.organization)
+    .bind(&allocation.medicine_name)
+    .bind(&allocation.strength)
+    .bind(&allocation.region)
+    .bind(&allocation.policy)
+    .bind(allocation.usage_days)
+    .bind(allocation.available_quantity)
+    .bind(allocation.requested_quantity)
+    .bind(allocation.allocated_quantity)
+    .bind(&allocation.note)
+    .bind(&allocation.created_by)
+    .bind(&allocation.created_at)
+    .bind(&allocation.previous_hash)
+    .bind(&allocation.hash)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    for line in lines {
+        sqlx::query(
+            "INSERT INTO stock_allocation_lines (allocation_id, demand_id, organization, requested_quantity, weight, critical_care, allocated_quantity)
+             VALUES (?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(id)
+        .bind(line.demand_id)
+        .bind(&line.organization)
+        .bind(line.requested_quantity)
+        .bind(line.weight)
+        .bind(line.critical_care)
+        .bind(line.allocated_quantity)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(id)
+}
+
+/// Allocations an organization made or has a share in, newest first; every allocation when
+/// `organization` is unset.
+pub async fn stock_allocations(pool: &SqlitePool, organization: Option<&str>) -> Result<Vec<StockAllocation>, sqlx::Error> {
+    sqlx::query_as::<_, StockAllocation>(
+        "SELECT * FROM stock_allocations a
+         WHERE ?1 IS NULL OR a.organization = ?1
+            OR EXISTS (SELECT 1 FROM stock_allocation_lines l WHERE l.allocation_id = a.id AND l.organization = ?1)
+         ORDER BY a.id DESC"
+    )
+    .bind(organization)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn find_stock_allocation(pool: &SqlitePool, id: i64) -> Result<Option<StockAllocation>, sqlx::Error> {
+    sqlx::query_as::<_, StockAllocation>("SELECT * FROM stock_allocations WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn allocation_lines(pool: &SqlitePool, allocation_id: i64) -> Result<Vec<AllocationLine>, sqlx::Error> {
+    sqlx::query_as::<_, AllocationLine>(
+        "SELECT demand_id, organization, requested_quantity, weight, critical_care, allocated_quantity
+         FROM stock_allocation_lines WHERE allocation_id = ? ORDER BY demand_id"
+    )
+    .bind(allocation_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Every allocation in the order it was recorded, for checking the chain.
+pub async fn allocation_chain(pool: &SqlitePool) -> Result<Vec<StockAllocation>, sqlx::Error> {
+    sqlx::query_as::<_, StockAllocation>("SELECT * FROM stock_allocations ORDER BY id")
+        .fetch_all(pool)
+        .await
+}
+
+/// What the catalog says about a product. The GTIN-14 identifies it; batches refer to it by that.
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ProductDetails {
+    #[serde(default)]
+    pub gtin: String,
+    /// US National Drug Code, stored in its 11-digit `5-4-2` form.
+    #[serde(default)]
+    pub ndc: Option<String>,
+    pub name: String,
+    pub active_ingredient: String,
+    /// Such as `500mg`.
+    pub strength: String,
+    /// Such as `tablet` or `solution for injection`.
+    pub dosage_form: String,
+    /// Units in one pack.
+    pub pack_size: i64,
+    #[serde(default)]
+    pub storage_min_celsius: Option<f64>,
+    #[serde(default)]
+    pub storage_max_celsius: Option<f64>,
+    /// Such as "protect from light".
+    #[serde(default)]
+    pub storage_conditions: Option<String>,
+    /// Controlled-substance schedule, `I` to `V`; unset when the product is not controlled.
+    #[serde(default)]
+    pub schedule: Option<String>,
+}
+
+/// A catalog entry with who added and last changed it.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct CatalogProduct {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub product: ProductDetails,
+    /// The company that added the entry; unset when an admin did.
+    pub organization: Option<String>,
+    pub created_by: String,
+    pub created_at: String,
+    pub updated_by: String,
+    pub updated_at: String,
+    /// Who settled the entry's schedule; unset while it awaits review.
+    pub approved_by: Option<String>,
+    pub approved_at: Option<String>,
+}
+
+pub async fn find_catalog_product(pool: &SqlitePool, gtin: &str) -> Result<Option<CatalogProduct>, sqlx::Error> {
+    sqlx::query_as::<_, CatalogProduct>("SELECT * FROM catalog_products WHERE gtin = ?")
+        .bind(gtin)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn find_catalog_product_by_ndc(pool: &SqlitePool, ndc: &str) -> Result<Option<CatalogProduct>, sqlx::Error> {
+    sqlx::query_as::<_, CatalogProduct>("SELECT * FROM catalog_products WHERE ndc = ?")
+        .bind(ndc)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Adds an entry, or updates the one with its GTIN while keeping who added it.
+pub async fn save_catalog_product(conn: &mut SqliteConnection, entry: &CatalogProduct) -> Result<(), sqlx::Error> {
+    let product = &entry.product;
+    sqlx::query(
+        "INSERT INTO catalog_products (
+             gtin, ndc, name, active_ingredient, strength, dosage_form, pack_size, storage_min_celsius, storage_max_celsius,
+             storage_conditions, schedule, organization, created_by, created_at, updated_by, updated_at, approved_by, approved_at
+         ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT (gtin) DO UPDATE SET
+             ndc = excluded.ndc, name = excluded.name, active_ingredient = excluded.active_ingredient,
+             strength = excluded.strength, dosage_form = excluded.dosage_form, pack_size = excluded.pack_size,
+             storage_min_celsius = excluded.storage_min_celsius, storage_max_celsius = excluded.storage_max_celsius,
+             storage_conditions = excluded.storage_conditions, schedule = excluded.schedule,
+             updated_by = excluded.updated_by, updated_at = excluded.updated_at,
+             approved_by = excluded.approved_by, approved_at = excluded.approved_at"
+    )
+    .bind(&product.gtin)
+    .bind(&product.ndc)
+    .bind(&product.name)
+    .bind(&product.active_ingredient)
+    .bind(&product.strength)
+    .bind(&product.dosage_form)
+    .bind(product.pack_size)
+    .bind(product.storage_min_celsius)
+    .bind(product.storage_max_celsius)
+    .bind(&product.storage_conditions)
+    .bind(&product.schedule)
+    .bind(&entry.organization)
+    .bind(&entry.created_by)
+    .bind(&entry.created_at)
+    .bind(&entry.updated_by)
+    .bind(&entry.updated_at)
+    .bind(&entry.approved_by)
+    .bind(&entry.approved_at)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Approves an entry with the schedule the reviewer settled on. Returns false if there is no such entry.
+pub async fn approve_catalog_product(pool: &SqlitePool, gtin: &str, schedule: Option<&str>, by: &str, at: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE catalog_products SET schedule = ?1, approved_by = ?2, approved_at = ?3, updated_by = ?2, updated_at = ?3
+         WHERE gtin = ?4"
+    )
+    .bind(schedule)
+    .bind(by)
+    .bind(at)
+    .bind(gtin)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Filters for searching the catalog; every one that is set must hold.
+pub struct CatalogSearch<'a> {
+    /// Digits from a GTIN or NDC, or part of a name, or of an active ingredient with its strength.
+    pub text: Option<&'a str>,
+    pub schedule: Option<&'a str>,
+    pub dosage_form: Option<&'a str>,
+    pub controlled_only: bool,
+    /// Only entries awaiting review.
+    pub pending_only: bool,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+pub async fn search_catalog(pool: &SqlitePool, search: &CatalogSearch<'_>) -> Result<Vec<CatalogProduct>, sqlx::Error> {
+    let digits = search
+        .text
+        .map(|t| t.chars().filter(|c| *c != '-' && !c.is_whitespace()).collect::<String>())
+        .filter(|t| !t.is_empty() && t.chars().all(|c| c.is_ascii_digit()));
+    sqlx::query_as::<_, CatalogProduct>(&format!(
+        "SELECT * FROM catalog_products
+         WHERE (?1 IS NULL
+                OR instr({name}, ?1) > 0
+                OR instr({product}, ?1) > 0
+                OR (?2 IS NOT NULL AND (instr(gtin, ?2) > 0 OR instr(REPLACE(ndc, '-', ''), ?2) > 0)))
+           AND (?3 IS NULL OR schedule = ?3)
+           AND (?4 IS NULL OR LOWER(dosage_form) = LOWER(?4))
+           AND (?5 = 0 OR schedule IS NOT NULL)
+           AND (?8 = 0 OR approved_at IS NULL)
+         ORDER BY name, gtin
+         LIMIT ?6 OFFSET ?7",
+        name = medicine_key_sql("name"),
+        product = medicine_key_sql("active_ingredient || strength"),
+    ))
+    .bind(search.text.map(medicine_key))
+    .bind(digits)
+    .bind(search.schedule)
+    .bind(search.dosage_form)
+    .bind(search.controlled_only)
+    .bind(search.limit)
+    .bind(search.offset)
+    .bind(search.pending_only)
+    .fetch_all(pool)
+    .await
+}
+
+/// A controlled-substance license: which schedules an organization may handle.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct ControlledLicense {
+    pub organization_id: String,
+    pub license_number: String,
+    /// Comma-separated, such as `II,III,IV`.
+    pub schedules: String,
+    /// `YYYY-MM-DD`; the license stops covering anything on that day.
+    pub expires_at: Option<String>,
+    pub granted_by: String,
+    pub granted_at: String,
+}
+
+pub async fn find_controlled_license(pool: &SqlitePool, organization_id: &str) -> Result<Option<ControlledLicense>, sqlx::Error> {
+    sqlx::query_as::<_, ControlledLicense>("SELECT * FROM controlled_licenses WHERE organization_id = ?")
+        .bind(organization_id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn controlled_licenses(pool: &SqlitePool) -> Result<Vec<ControlledLicense>, sqlx::Error> {
+    sqlx::query_as::<_, ControlledLicense>("SELECT * FROM controlled_licenses ORDER BY organization_id")
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn save_controlled_license(pool: &SqlitePool, license: &ControlledLicense) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO controlled_licenses (organization_id, license_number, schedules, expires_at, granted_by, granted_at)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT (organization_id) DO UPDATE SET
+             license_number = excluded.license_number, schedules = excluded.schedules, expires_at = excluded.expires_at,
+             granted_by = excluded.granted_by, granted_at = excluded.granted_at"
+    )
+    .bind(&license.organization_id)
+    .bind(&license.license_number)
+    .bind(&license.schedules)
+    .bind(&license.expires_at)
+    .bind(&license.granted_by)
+    .bind(&license.granted_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns false if the organization had no license.
+pub async fn remove_controlled_license(pool: &SqlitePool, organization_id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM controlled_licenses WHERE organization_id = ?")
+        .bind(organization_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// How many units of an active ingredient an organization may make or receive per period.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct ControlledQuota {
+    pub organization_id: String,
+    #[serde(skip)]
+    pub ingredient_key: String,
+    pub active_ingredient: String,
+    /// `month`, `quarter` or `year`, by the calendar in UTC.
+    pub period: String,
+    pub quantity: i64,
+    pub set_by: String,
+    pub set_at: String,
+}
+
+pub async fn save_controlled_quota(pool: &SqlitePool, quota: &ControlledQuota) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO controlled_quotas (organization_id, ingredient_key, active_ingredient, period, quantity, set_by, set_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT (organization_id, ingredient_key) DO UPDATE SET
+             active_ingredient = excluded.active_ingredient, period = excluded.period, quantity = excluded.quantity,
+             set_by = excluded.set_by, set_at = excluded.set_at"
+    )
+    .bind(&quota.organization_id)
+    .bind(&quota.ingredient_key)
+    .bind(&quota.active_ingredient)
+    .bind(&quota.period)
+    .bind(quota.quantity)
+    .bind(&quota.set_by)
+    .bind(&quota.set_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns false if there was no such quota.
+pub async fn remove_controlled_quota(pool: &SqlitePool, organization_id: &str, ingredient_key: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM controlled_quotas WHERE organization_id = ? AND ingredient_key = ?")
+        .bind(organization_id)
+        .bind(ingredient_key)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Every quota, or one organization's.
+pub async fn controlled_quotas(pool: &SqlitePool, organization_id: Option<&str>) -> Result<Vec<ControlledQuota>, sqlx::Error> {
+    sqlx::query_as::<_, ControlledQuota>(
+        "SELECT * FROM controlled_quotas WHERE ?1 IS NULL OR organization_id = ?1 ORDER BY organization_id, ingredient_key"
+    )
+    .bind(organization_id)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn find_controlled_quota(pool: &SqlitePool, organization_id: &str, ingredient_key: &str) -> Result<Option<ControlledQuota>, sqlx::Error> {
+    sqlx::query_as::<_, ControlledQuota>("SELECT * FROM controlled_quotas WHERE organization_id = ? AND ingredient_key = ?")
+        .bind(organization_id)
+        .bind(ingredient_key)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Units of `ingredient_key` the organization made in sealed batches, or received through
+/// countersigned transfers, between `from` and `until` (RFC 3339).
+pub async fn controlled_quota_used(
+    pool: &SqlitePool,
+    organization_id: &str,
+    ingredient_key: &str,
+    from: &str,
+    until: &str,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(&format!(
+        "SELECT COALESCE((SELECT SUM(b.quantity) FROM medicine_batches b JOIN catalog_products c ON c.gtin = b.gtin
+                          WHERE b.organization = ?1 AND b.parent_batch_id IS NULL AND {product} = ?2
+                            AND julianday(b.timestamp) >= julianday(?3) AND julianday(b.timestamp) < julianday(?4)), 0)
+              + COALESCE((SELECT SUM(t.quantity) FROM controlled_transfers t
+                          WHERE t.to_organization = ?1 AND t.status = 'submitted' AND {transferred} = ?2
+                            AND julianday(t.receiver_signed_at) >= julianday(?3) AND julianday(t.receiver_signed_at) < julianday(?4)), 0)",
+        product = medicine_key_sql("c.active_ingredient"),
+        transferred = medicine_key_sql("t.active_ingredient"),
+    ))
+    .bind(organization_id)
+    .bind(ingredient_key)
+    .bind(from)
+    .bind(until)
+    .fetch_one(pool)
+    .await
+}
+
+/// A transfer of a scheduled product and the two signatures it needs.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct ControlledTransfer {
+    pub id: String,
+    pub batch_id: String,
+    pub gtin: String,
+    pub schedule: String,
+    pub active_ingredient: String,
+    /// What was left of the batch when the sender signed.
+    pub quantity: i64,
+    pub from_organization: String,
+    pub to_organization: String,
+    /// Where the batch was when the sender signed; the custody transfer's `from_location`.
+    pub from_location: String,
+    pub expected_arrival: Option<String>,
+    pub note: Option<String>,
+    /// `awaiting_countersignature`, `submitted`, `rejected` or `cancelled`.
+    pub status: String,
+    pub sender_signed_by: String,
+    pub sender_signed_at: String,
+    /// The node's signature over the transfer's terms and the signer.
+    pub sender_signature: String,
+    /// The node key both signatures are made with, base64 PKCS#1 DER.
+    pub public_key: String,
+    pub receiver_signed_by: Option<String>,
+    pub receiver_signed_at: Option<String>,
+    pub receiver_signature: Option<String>,
+    /// The custody transaction queued once both have signed.
+    pub custody_hash: Option<String>,
+    pub closed_by: Option<String>,
+    pub closed_at: Option<String>,
+    pub close_reason: Option<String>,
+    /// Whether the signatures verify against `public_key`; checked on every read.
+    #[sqlx(skip)]
+    pub signatures_valid: bool,
+}
+
+pub async fn add_controlled_transfer(pool: &SqlitePool, transfer: &ControlledTransfer) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO controlled_transfers (
+             id, batch_id, gtin, schedule, active_ingredient, quantity, from_organization, to_organization, from_location,
+             expected_arrival, note, status, sender_signed_by, sender_signed_at, sender_signature, public_key
+         ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(&transfer.id)
+    .bind(&transfer.batch_id)
+    .bind(&transfer.gtin)
+    .bind(&transfer.schedule)
+    .bind(&transfer.active_ingredient)
+    .bind(transfer.quantity)
+    .bind(&transfer.from_organization)
+    .bind(&transfer.to_organization)
+    .bind(&transfer.from_location)
+    .bind(&transfer.expected_arrival)
+    .bind(&transfer.note)
+    .bind(&transfer.status)
+    .bind(&transfer.sender_signed_by)
+    .bind(&transfer.sender_signed_at)
+    .bind(&transfer.sender_signature)
+    .bind(&transfer.public_key)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn find_controlled_transfer(pool: &SqlitePool, id: &str) -> Result<Option<ControlledTransfer>, sqlx::Error> {
+    sqlx::query_as::<_, ControlledTransfer>("SELECT * FROM controlled_transfers WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Transfers an organization sent or is to receive (every transfer when unset), newest first.
+pub async fn controlled_transfers(pool: &SqlitePool, organization: Option<&str>, status: Option<&str>) -> Result<Vec<ControlledTransfer>, sqlx::Error> {
+    sqlx::query_as::<_, ControlledTransfer>(
+        "SELECT * FROM controlled_transfers
+         WHERE (?1 IS NULL OR ?1 IN (from_organization, to_organization)) AND (?2 IS NULL OR status = ?2)
+         ORDER BY sender_signed_at DESC"
+    )
+    .bind(organization)
+    .bind(status)
+    .fetch_all(pool)
+    .await
+}
+
+/// Records the receiver's signature and the custody transaction it released. Returns false if
+/// the transfer was no longer awaiting it.
+pub async fn countersign_controlled_transfer(
+    pool: &SqlitePool,
+    id: &str,
+    signed_by: &str,
+    signed_at: &str,
+    signature: &str,
+    custody_hash: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE controlled_transfers
+         SET status = 'submitted', receiver_signed_by = ?, receiver_signed_at = ?, receiver_signature = ?, custody_hash = ?
+         WHERE id = ? AND status = 'awaiting_countersignature'"
+    )
+    .bind(signed_by)
+    .bind(signed_at)
+    .bind(signature)
+    .bind(custody_hash)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Closes a transfer awaiting countersignature as `rejected` or `cancelled`. Returns false if
+/// it was not awaiting one.
+pub async fn close_controlled_transfer(pool: &SqlitePool, id: &str, status: &str, closed_by: &str, reason: Option<&str>) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE controlled_transfers SET status = ?, closed_by = ?, closed_at = ?, close_reason = ?
+         WHERE id = ? AND status = 'awaiting_countersignature'"
+    )
+    .bind(status)
+    .bind(closed_by)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(reason)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// A stock movement of a scheduled product, attributed to an organization where the location
+/// names one.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct ControlledMovement {
+    pub batch_id: String,
+    pub gtin: String,
+    pub product_name: String,
+    pub active_ingredient: String,
+    pub strength: String,
+    pub schedule: String,
+    pub organization_id: String,
+    pub organization_name: String,
+    /// As in inventory movements: `created`, `transfer_in`, `dispensed`…
+    pub kind: String,
+    pub quantity: i64,
+    pub counterparty: Option<String>,
+    pub reference: Option<String>,
+    pub reason: Option<String>,
+    pub occurred_at: String,
+}
+
+/// Movements of scheduled products (of `schedule` when set) before `until` (RFC 3339), for one
+/// organization (`organization` id and name) or all, oldest first.
+pub async fn controlled_movements(
+    pool: &SqlitePool,
+    schedule: Option<&str>,
+    until: &str,
+    organization: Option<&Organization>,
+) -> Result<Vec<ControlledMovement>, sqlx::Error> {
+    sqlx::query_as::<_, ControlledMovement>(&format!(
+        "SELECT m.batch_id, m.gtin, c.name AS product_name, c.active_ingredient, c.strength, c.schedule,
+                COALESCE(o.id, m.holder) AS organization_id, COALESCE(o.name, m.holder) AS organization_name,
+                m.kind, m.quantity, m.counterparty, m.reference, m.reason, m.occurred_at
+         FROM ({}) m
+         JOIN catalog_products c ON c.gtin = m.gtin
+         LEFT JOIN organizations o ON m.holder IN (o.id, o.name)
+         WHERE c.schedule IS NOT NULL AND (?1 IS NULL OR c.schedule = ?1)
+           AND julianday(m.occurred_at) < julianday(?2)
+           AND (?3 IS NULL OR m.holder IN (?3, ?4))
+         ORDER BY julianday(m.occurred_at), m.batch_id",
+        stock_movements()
+    ))
+    .bind(schedule)
+    .bind(until)
+    .bind(organization.map(|o| o.id.as_str()))
+    .bind(organization.map(|o| o.name.as_str()))
+    .fetch_all(pool)
+    .await
+}
+
+/// A dispensing to a patient. The patient appears only as a salted hash of their identifier.
+#[derive(sqlx::FromRow, Serialize, Debug, Clone)]
+pub struct DispensingRecord {
+    pub id: String,
+    pub organization: String,
+    pub batch_id: String,
+    pub gtin: Option<String>,
+    pub quantity: i64,
+    pub prescription_reference: String,
+    pub prescriber: Option<String>,
+    pub patient_hash: String,
+    pub note: Option<String>,
+    pub dispensed_by: String,
+    pub dispensed_at: String,
+    /// The ledger transaction recording the units and serials dispensed.
+    pub ledger_hash: String,
+}
+
+/// Stores a dispensing and decommissions its serials, which need the record's GTIN; the caller
+/// records the stock withdrawal in the same transaction. Fails on a serial already decommissioned.
+pub async fn add_dispensing(conn: &mut SqliteConnection, record: &DispensingRecord, serials: &[String]) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO dispensing_records (
+             id, organization, batch_id, gtin, quantity, prescription_reference, prescriber, patient_hash, note, dispensed_by, dispensed_at,
+             ledger_hash
+         ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(&record.id)
+    .bind(&record.organization)
+    .bind(&record.batch_id)
+    .bind(&record.gtin)
+    .bind(record.quantity)
+    .bind(&record.prescription_reference)
+    .bind(&record.prescriber)
+    .bind(&record.patient_hash)
+    .bind(&record.note)
+    .bind(&record.dispensed_by)
+    .bind(&record.dispensed_at)
+    .bind(&record.ledger_hash)
+    .execute(&mut *conn)
+    .await?;
+    for serial in serials {
+        sqlx::query("INSERT INTO dispensed_serials (dispensing_id, batch_id, gtin, serial) VALUES (?, ?, ?, ?)")
+            .bind(&record.id)
+            .bind(&record.batch_id)
+            .bind(&record.gtin)
+            .bind(serial)
+            .execute(&mut *conn)
+            .await?;
     }
+    Ok(())
+}
+
+pub async fn find_dispensing(pool: &SqlitePool, id: &str) -> Result<Option<DispensingRecord>, sqlx::Error> {
+    sqlx::query_as::<_, DispensingRecord>("SELECT * FROM dispensing_records WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Filters for [`dispensing_records`]; unset fields match everything.
+#[derive(Debug, Default)]
+pub struct DispensingFilter<'a> {
+    pub organization: Option<&'a str>,
+    pub batch_id: Option<&'a str>,
+    pub prescription_reference: Option<&'a str>,
+    pub patient_hash: Option<&'a str>,
+    /// RFC 3339; dispensings at or after it.
+    pub since: Option<&'a str>,
+    /// RFC 3339; dispensings before it.
+    pub until: Option<&'a str>,
+    pub limit: i64,
+}
+
+/// Matching dispensings, newest first.
+pub async fn dispensing_records(pool: &SqlitePool, filter: &DispensingFilter<'_>) -> Result<Vec<DispensingRecord>, sqlx::Error> {
+    sqlx::query_as::<_, DispensingRecord>(
+        "SELECT * FROM dispensing_records
+         WHERE (?1 IS NULL OR organization = ?1)
+           AND (?2 IS NULL OR batch_id = ?2)
+           AND (?3 IS NULL OR prescription_reference = ?3)
+           AND (?4 IS NULL OR patient_hash = ?4)
+           AND (?5 IS NULL OR julianday(dispensed_at) >= julianday(?5))
+           AND (?6 IS NULL OR julianday(dispensed_at) < julianday(?6))
+         ORDER BY julianday(dispensed_at) DESC
+         LIMIT ?7"
+    )
+    .bind(filter.organization)
+    .bind(filter.batch_id)
+    .bind(filter.prescription_reference)
+    .bind(filter.patient_hash)
+    .bind(filter.since)
+    .bind(filter.until)
+    .bind(filter.limit)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn dispensing_serials(pool: &SqlitePool, dispensing_id: &str) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT serial FROM dispensed_serials WHERE dispensing_id = ? ORDER BY rowid")
+        .bind(dispensing_id)
+        .fetch_all(pool)
+        .await
+}
+
+/// Serials no longer in a batch: split off into sealed child batches, or dispensed from it.
+pub async fn serials_gone_from(pool: &SqlitePool, batch_id: &str) -> Result<Vec<String>, sqlx::Error> {
+    let split: Vec<sqlx::types::Json<Option<Vec<String>>>> = sqlx::query_scalar("SELECT serials FROM medicine_batches WHERE parent_batch_id = ?")
+        .bind(batch_id)
+        .fetch_all(pool)
+        .await?;
+    let mut gone: Vec<String> = split.into_iter().flat_map(|s| s.0.unwrap_or_default()).collect();
+    let dispensed: Vec<String> = sqlx::query_scalar("SELECT serial FROM dispensed_serials WHERE batch_id = ?")
+        .bind(batch_id)
+        .fetch_all(pool)
+        .await?;
+    gone.extend(dispensed);
+    Ok(gone)
+}
+
+/// The dispensings that decommissioned `serial`, of `gtin` when given. Without a GTIN the same
+/// serial may belong to several products.
+pub async fn dispensings_by_serial(pool: &SqlitePool, serial: &str, gtin: Option<&str>) -> Result<Vec<DispensingRecord>, sqlx::Error> {
+    sqlx::query_as::<_, DispensingRecord>(
+        "SELECT d.* FROM dispensed_serials s JOIN dispensing_records d ON d.id = s.dispensing_id
+         WHERE s.serial = ?1 AND (?2 IS NULL OR s.gtin = ?2)
+         ORDER BY julianday(d.dispensed_at)"
+    )
+    .bind(serial)
+    .bind(gtin)
+    .fetch_all(pool)
+    .await
 }
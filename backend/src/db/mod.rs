@@ -1,9 +1,11 @@
 pub mod entities;
 
-use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use sqlx::{sqlite::SqlitePoolOptions, SqliteConnection, SqlitePool};
 use std::env;
 
-use crate::db::entities::User;
+use crate::models::{
+    AccessLogEntry, AccessLogFilter, ApiKey, AuthEventFilter, AuthEventRecord, Invite, LoginThrottle, Membership, OidcLogin, Session, User, UserTotp,
+};
 use crate::db::entities::create_tables;
 
 /// Initializes the database by creating necessary tables.
@@ -18,11 +20,174 @@ pub async fn init_db() -> Result<(), sqlx::Error> {
             username TEXT NOT NULL,
             email TEXT NOT NULL UNIQUE,
             password TEXT NOT NULL,
-            role TEXT NOT NULL
+            role TEXT NOT NULL,
+            email_verified_at TEXT
         )",
     )
     .execute(&pool)
     .await?;
+    // Databases from before email verification gain the column in place
+    add_missing_columns(&pool, "users", &[("email_verified_at", "TEXT")]).await?;
+
+    // Login sessions: one row per refresh token chain, so revoking it cuts off its access tokens too
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            refresh_hash TEXT NOT NULL,
+            organization_id TEXT,
+            mfa INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL,
+            expires_at TEXT NOT NULL,
+            revoked_at TEXT
+        )",
+    )
+    .execute(&pool)
+    .await?;
+
+    // Which organizations a user acts for, and their role within each
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS organization_members (
+            user_id TEXT NOT NULL,
+            organization_id TEXT NOT NULL,
+            organization_type TEXT NOT NULL,
+            role TEXT NOT NULL,
+            joined_at TEXT NOT NULL,
+            PRIMARY KEY (user_id, organization_id)
+        )",
+    )
+    .execute(&pool)
+    .await?;
+
+    // Single-use codes for joining an organization; only the code's SHA-256 is stored
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS organization_invites (
+            code_hash TEXT PRIMARY KEY,
+            organization_id TEXT NOT NULL,
+            organization_type TEXT NOT NULL,
+            role TEXT NOT NULL,
+            created_by TEXT NOT NULL,
+            expires_at TEXT NOT NULL,
+            used_by TEXT
+        )",
+    )
+    .execute(&pool)
+    .await?;
+
+    // Machine credentials for integrations, one organization each; only the secret's SHA-256 is stored
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS api_keys (
+            id TEXT PRIMARY KEY,
+            organization_id TEXT NOT NULL,
+            organization_type TEXT NOT NULL,
+            name TEXT NOT NULL,
+            scopes TEXT NOT NULL,
+            key_hash TEXT NOT NULL,
+            created_by TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            last_used_at TEXT,
+            revoked_at TEXT
+        )",
+    )
+    .execute(&pool)
+    .await?;
+
+    // Identity provider accounts (issuer + subject) linked to local users
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS user_identities (
+            issuer TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            linked_at TEXT NOT NULL,
+            PRIMARY KEY (issuer, subject)
+        )",
+    )
+    .execute(&pool)
+    .await?;
+
+    // Single sign-on logins in flight: state, nonce and PKCE verifier until the callback
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS oidc_logins (
+            state TEXT PRIMARY KEY,
+            nonce TEXT NOT NULL,
+            code_verifier TEXT NOT NULL,
+            expires_at TEXT NOT NULL
+        )",
+    )
+    .execute(&pool)
+    .await?;
+
+    // Failed-login counters per account and per IP; times are unix seconds
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS login_throttle (
+            key TEXT PRIMARY KEY,
+            failures INTEGER NOT NULL,
+            last_failure_at INTEGER NOT NULL,
+            locked_until INTEGER
+        )",
+    )
+    .execute(&pool)
+    .await?;
+
+    // Logins, failures, lockouts and token refreshes, for admins to review
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS auth_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            event TEXT NOT NULL,
+            user_id TEXT,
+            email TEXT,
+            ip TEXT,
+            detail TEXT,
+            created_at TEXT NOT NULL
+        )",
+    )
+    .execute(&pool)
+    .await?;
+
+    // TOTP second factor and its one-time recovery codes (stored as SHA-256)
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS user_totp (
+            user_id TEXT PRIMARY KEY,
+            secret TEXT NOT NULL,
+            enabled_at TEXT,
+            last_used_step INTEGER NOT NULL DEFAULT 0
+        )",
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS recovery_codes (
+            user_id TEXT NOT NULL,
+            code_hash TEXT NOT NULL,
+            used_at TEXT,
+            PRIMARY KEY (user_id, code_hash)
+        )",
+    )
+    .execute(&pool)
+    .await?;
+
+    // Every request a regulator makes. Append-only: each entry carries the hash of the one
+    // before it, and triggers refuse updates and deletes
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS regulator_access_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id TEXT NOT NULL,
+            action TEXT NOT NULL,
+            method TEXT NOT NULL,
+            path TEXT NOT NULL,
+            query TEXT,
+            status INTEGER NOT NULL,
+            ip TEXT,
+            accessed_at TEXT NOT NULL,
+            previous_hash TEXT NOT NULL UNIQUE,
+            hash TEXT NOT NULL
+        )",
+    )
+    .execute(&pool)
+    .await?;
+
+    make_append_only(&pool, "regulator_access_log", "the regulator access log is append-only").await?;
 
     // 🔥 Add this line to create other tables (companies, hospitals, customers)
     create_tables(&pool).await?;
@@ -30,6 +195,35 @@ pub async fn init_db() -> Result<(), sqlx::Error> {
     Ok(())
 }
 
+/// Adds triggers that abort any UPDATE or DELETE on `table` with `message`.
+pub async fn make_append_only(pool: &SqlitePool, table: &str, message: &str) -> Result<(), sqlx::Error> {
+    for operation in ["UPDATE", "DELETE"] {
+        sqlx::query(&format!(
+            "CREATE TRIGGER IF NOT EXISTS {table}_no_{} BEFORE {operation} ON {table}
+             BEGIN SELECT RAISE(ABORT, '{message}'); END",
+            operation.to_lowercase()
+        ))
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Adds each `(name, definition)` column that `table` lacks, so a database created before
+/// the column joined the table's CREATE TABLE can still be read and written.
+pub async fn add_missing_columns(pool: &SqlitePool, table: &str, columns: &[(&str, &str)]) -> Result<(), sqlx::Error> {
+    let existing: Vec<String> = sqlx::query_scalar(&format!("SELECT name FROM pragma_table_info('{table}')"))
+        .fetch_all(pool)
+        .await?;
+    for (name, definition) in columns {
+        if !existing.iter().any(|column| column == name) {
+            sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN {name} {definition}"))
+                .execute(pool)
+                .await?;
+        }
+    }
+    Ok(())
+}
 
 /// Returns a connection pool to the SQLite database.
 pub async fn get_db_pool() -> Result<SqlitePool, sqlx::Error> {
@@ -41,26 +235,28 @@ pub async fn get_db_pool() -> Result<SqlitePool, sqlx::Error> {
     Ok(pool)
 }
 
-/// Adds a new user to the database.
+/// Adds a new user to the database and returns their id.
+/// `password_hash` is the Argon2 PHC string, never the password.
 pub async fn add_user(
     pool: &SqlitePool,
     username: &str,
     email: &str,
-    password: &str,
+    password_hash: &str,
     role: &str,
-) -> Result<(), sqlx::Error> {
+) -> Result<String, sqlx::Error> {
+    let id = uuid::Uuid::new_v4().to_string();
     sqlx::query(
         "INSERT INTO users (id, username, email, password, role) VALUES (?, ?, ?, ?, ?)",
     )
-    .bind(uuid::Uuid::new_v4().to_string())
+    .bind(&id)
     .bind(username)
     .bind(email)
-    .bind(password)
+    .bind(password_hash)
     .bind(role)
     .execute(pool)
     .await?;
 
-    Ok(())
+    Ok(id)
 }
 
 /// Finds a user by their email address.
@@ -69,7 +265,7 @@ pub async fn find_user_by_email(
     email: &str,
 ) -> Result<Option<User>, sqlx::Error> {
     let user = sqlx::query_as::<_, User>(
-        "SELECT id, username, email, password, role FROM users WHERE email = ?",
+        "SELECT id, username, email, password, role, email_verified_at FROM users WHERE email = ?",
     )
     .bind(email)
     .fetch_optional(pool)
@@ -77,3 +273,636 @@ pub async fn find_user_by_email(
 
     Ok(user)
 }
+
+/// Marks the user's current email as verified. Returns false if it was already.
+pub async fn mark_email_verified(pool: &SqlitePool, user_id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE users SET email_verified_at = ? WHERE id = ? AND email_verified_at IS NULL")
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Replaces a user's stored credential, e.g. when upgrading a legacy plaintext password.
+pub async fn update_password_hash(
+    pool: &SqlitePool,
+    user_id: &str,
+    password_hash: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET password = ? WHERE id = ?")
+        .bind(password_hash)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Finds a user by id, e.g. to refresh the role carried in a session's tokens.
+pub async fn find_user_by_id(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(
+        "SELECT id, username, email, password, role, email_verified_at FROM users WHERE id = ?",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Changes a user's role. Their open sessions should be revoked so the new role applies at once.
+pub async fn update_user_role(pool: &SqlitePool, user_id: &str, role: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET role = ? WHERE id = ?")
+        .bind(role)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Gives a user `role` as long as nobody holds it yet. Returns whether it did.
+pub async fn grant_unheld_role(pool: &SqlitePool, user_id: &str, role: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE users SET role = ? WHERE id = ? AND NOT EXISTS (SELECT 1 FROM users WHERE role = ?)")
+        .bind(role)
+        .bind(user_id)
+        .bind(role)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Opens a login session. `refresh_hash` is the SHA-256 of the refresh secret, never the secret.
+pub async fn create_session(
+    pool: &SqlitePool,
+    session_id: &str,
+    user_id: &str,
+    refresh_hash: &str,
+    organization_id: Option<&str>,
+    mfa: bool,
+    expires_at: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO sessions (id, user_id, refresh_hash, organization_id, mfa, created_at, expires_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .bind(refresh_hash)
+    .bind(organization_id)
+    .bind(mfa)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn find_session(
+    pool: &SqlitePool,
+    session_id: &str,
+) -> Result<Option<Session>, sqlx::Error> {
+    sqlx::query_as::<_, Session>(
+        "SELECT id, user_id, refresh_hash, organization_id, mfa, expires_at, revoked_at FROM sessions WHERE id = ?",
+    )
+    .bind(session_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Swaps in a new refresh secret, but only if the session still holds `old_hash`
+/// and is not revoked. Returns false when another refresh won the race.
+pub async fn rotate_session(
+    pool: &SqlitePool,
+    session_id: &str,
+    old_hash: &str,
+    new_hash: &str,
+    expires_at: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE sessions SET refresh_hash = ?, expires_at = ?
+         WHERE id = ? AND refresh_hash = ? AND revoked_at IS NULL",
+    )
+    .bind(new_hash)
+    .bind(expires_at)
+    .bind(session_id)
+    .bind(old_hash)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn revoke_session(pool: &SqlitePool, session_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE sessions SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL")
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(session_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Revokes every open session of a user. Returns how many were revoked.
+pub async fn revoke_user_sessions(pool: &SqlitePool, user_id: &str) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("UPDATE sessions SET revoked_at = ? WHERE user_id = ? AND revoked_at IS NULL")
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Sets the organization a session acts for; later access tokens carry it.
+pub async fn set_session_organization(
+    pool: &SqlitePool,
+    session_id: &str,
+    organization_id: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE sessions SET organization_id = ? WHERE id = ?")
+        .bind(organization_id)
+        .bind(session_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Adds a user to an organization, or changes their role there if already a member.
+pub async fn add_membership(
+    pool: &SqlitePool,
+    user_id: &str,
+    organization_id: &str,
+    organization_type: &str,
+    role: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO organization_members (user_id, organization_id, organization_type, role, joined_at)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT (user_id, organization_id) DO UPDATE SET role = excluded.role",
+    )
+    .bind(user_id)
+    .bind(organization_id)
+    .bind(organization_type)
+    .bind(role)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+const MEMBERSHIP_SELECT: &str =
+    "SELECT m.organization_id, m.organization_type, o.name AS organization_name, m.role, m.joined_at
+     FROM organization_members m
+     JOIN (SELECT id, name FROM companies
+           UNION ALL SELECT id, name FROM hospitals
+           UNION ALL SELECT id, name FROM customers) o ON o.id = m.organization_id";
+
+/// A user's organizations, oldest membership first.
+pub async fn user_memberships(pool: &SqlitePool, user_id: &str) -> Result<Vec<Membership>, sqlx::Error> {
+    sqlx::query_as::<_, Membership>(&format!("{} WHERE m.user_id = ? ORDER BY m.joined_at ASC", MEMBERSHIP_SELECT))
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn find_membership(
+    pool: &SqlitePool,
+    user_id: &str,
+    organization_id: &str,
+) -> Result<Option<Membership>, sqlx::Error> {
+    sqlx::query_as::<_, Membership>(&format!("{} WHERE m.user_id = ? AND m.organization_id = ?", MEMBERSHIP_SELECT))
+        .bind(user_id)
+        .bind(organization_id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn create_invite(
+    pool: &SqlitePool,
+    code_hash: &str,
+    organization_id: &str,
+    organization_type: &str,
+    role: &str,
+    created_by: &str,
+    expires_at: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO organization_invites (code_hash, organization_id, organization_type, role, created_by, expires_at)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(code_hash)
+    .bind(organization_id)
+    .bind(organization_type)
+    .bind(role)
+    .bind(created_by)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// An unused, unexpired invite.
+pub async fn find_invite(pool: &SqlitePool, code_hash: &str) -> Result<Option<Invite>, sqlx::Error> {
+    sqlx::query_as::<_, Invite>(
+        "SELECT organization_id, organization_type, role FROM organization_invites
+         WHERE code_hash = ? AND used_by IS NULL AND expires_at > ?",
+    )
+    .bind(code_hash)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .fetch_optional(pool)
+    .await
+}
+
+/// Marks an unused, unexpired invite as used by `user_id` and returns it.
+/// `None` if no such invite exists or it was already taken.
+pub async fn redeem_invite(pool: &SqlitePool, code_hash: &str, user_id: &str) -> Result<Option<Invite>, sqlx::Error> {
+    sqlx::query_as::<_, Invite>(
+        "UPDATE organization_invites SET used_by = ?
+         WHERE code_hash = ? AND used_by IS NULL AND expires_at > ?
+         RETURNING organization_id, organization_type, role",
+    )
+    .bind(user_id)
+    .bind(code_hash)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn create_api_key(pool: &SqlitePool, key: &ApiKey) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO api_keys (id, organization_id, organization_type, name, scopes, key_hash, created_by, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&key.id)
+    .bind(&key.organization_id)
+    .bind(&key.organization_type)
+    .bind(&key.name)
+    .bind(&key.scopes)
+    .bind(&key.key_hash)
+    .bind(&key.created_by)
+    .bind(&key.created_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn find_api_key(pool: &SqlitePool, id: &str) -> Result<Option<ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// An organization's keys, newest first, including revoked ones.
+pub async fn organization_api_keys(pool: &SqlitePool, organization_id: &str) -> Result<Vec<ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys WHERE organization_id = ? ORDER BY created_at DESC")
+        .bind(organization_id)
+        .fetch_all(pool)
+        .await
+}
+
+/// Records a key's use. Writes at most once a minute per key to keep busy integrations cheap.
+pub async fn touch_api_key(pool: &SqlitePool, id: &str) -> Result<(), sqlx::Error> {
+    let now = chrono::Utc::now();
+    sqlx::query("UPDATE api_keys SET last_used_at = ? WHERE id = ? AND (last_used_at IS NULL OR last_used_at < ?)")
+        .bind(now.to_rfc3339())
+        .bind(id)
+        .bind((now - chrono::Duration::seconds(60)).to_rfc3339())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Revokes one of an organization's keys. Returns false if it has no such live key.
+pub async fn revoke_api_key(pool: &SqlitePool, id: &str, organization_id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE api_keys SET revoked_at = ? WHERE id = ? AND organization_id = ? AND revoked_at IS NULL",
+    )
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(id)
+    .bind(organization_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Marks a session as having passed a second factor, e.g. right after enrolling.
+pub async fn mark_session_mfa(pool: &SqlitePool, session_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE sessions SET mfa = 1 WHERE id = ?")
+        .bind(session_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn find_totp(pool: &SqlitePool, user_id: &str) -> Result<Option<UserTotp>, sqlx::Error> {
+    sqlx::query_as::<_, UserTotp>("SELECT secret, enabled_at, last_used_step FROM user_totp WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Stores a new, not yet confirmed, TOTP secret. Does nothing if two-factor is already enabled.
+pub async fn save_pending_totp(pool: &SqlitePool, user_id: &str, secret: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "INSERT INTO user_totp (user_id, secret) VALUES (?, ?)
+         ON CONFLICT (user_id) DO UPDATE SET secret = excluded.secret, last_used_step = 0
+         WHERE user_totp.enabled_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(secret)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn enable_totp(pool: &SqlitePool, user_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE user_totp SET enabled_at = ? WHERE user_id = ?")
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Records the time step of an accepted code. Returns false if that step (or a later one)
+/// was already used, i.e. the code is being replayed.
+pub async fn record_totp_step(pool: &SqlitePool, user_id: &str, step: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE user_totp SET last_used_step = ? WHERE user_id = ? AND last_used_step < ?")
+        .bind(step)
+        .bind(user_id)
+        .bind(step)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Removes a user's second factor and recovery codes.
+pub async fn delete_totp(pool: &SqlitePool, user_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM user_totp WHERE user_id = ?")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Replaces a user's recovery codes with a new set of hashes.
+pub async fn replace_recovery_codes(pool: &SqlitePool, user_id: &str, code_hashes: &[String]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    for code_hash in code_hashes {
+        sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) VALUES (?, ?)")
+            .bind(user_id)
+            .bind(code_hash)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await
+}
+
+/// Uses up a recovery code. Returns false if it does not exist or was already used.
+pub async fn use_recovery_code(pool: &SqlitePool, user_id: &str, code_hash: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE recovery_codes SET used_at = ? WHERE user_id = ? AND code_hash = ? AND used_at IS NULL",
+    )
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(user_id)
+    .bind(code_hash)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Records a login sent to the identity provider, dropping any that were never completed.
+pub async fn create_oidc_login(
+    pool: &SqlitePool,
+    state: &str,
+    nonce: &str,
+    code_verifier: &str,
+    expires_at: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM oidc_logins WHERE expires_at <= ?")
+        .bind(chrono::Utc::now().to_rfc3339())
+        .execute(pool)
+        .await?;
+    sqlx::query("INSERT INTO oidc_logins (state, nonce, code_verifier, expires_at) VALUES (?, ?, ?, ?)")
+        .bind(state)
+        .bind(nonce)
+        .bind(code_verifier)
+        .bind(expires_at)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Removes and returns a pending login, so each state is accepted once.
+pub async fn take_oidc_login(pool: &SqlitePool, state: &str) -> Result<Option<OidcLogin>, sqlx::Error> {
+    sqlx::query_as::<_, OidcLogin>(
+        "DELETE FROM oidc_logins WHERE state = ? AND expires_at > ? RETURNING nonce, code_verifier",
+    )
+    .bind(state)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn find_identity_user(pool: &SqlitePool, issuer: &str, subject: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT user_id FROM user_identities WHERE issuer = ? AND subject = ?")
+        .bind(issuer)
+        .bind(subject)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn link_identity(pool: &SqlitePool, issuer: &str, subject: &str, user_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO user_identities (issuer, subject, user_id, linked_at) VALUES (?, ?, ?, ?)")
+        .bind(issuer)
+        .bind(subject)
+        .bind(user_id)
+        .bind(chrono::Utc::now().to_rfc3339())
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn find_login_throttle(pool: &SqlitePool, key: &str) -> Result<Option<LoginThrottle>, sqlx::Error> {
+    sqlx::query_as::<_, LoginThrottle>("SELECT failures, last_failure_at, locked_until FROM login_throttle WHERE key = ?")
+        .bind(key)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Counts a failed login against `key` and returns the new count. Failures before
+/// `forget_before` no longer count, so the tally starts again at one.
+pub async fn record_login_failure(pool: &SqlitePool, key: &str, now: i64, forget_before: i64) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "INSERT INTO login_throttle (key, failures, last_failure_at) VALUES (?1, 1, ?2)
+         ON CONFLICT (key) DO UPDATE SET
+            failures = CASE WHEN last_failure_at < ?3 THEN 1 ELSE failures + 1 END,
+            last_failure_at = ?2
+         RETURNING failures",
+    )
+    .bind(key)
+    .bind(now)
+    .bind(forget_before)
+    .fetch_one(pool)
+    .await
+}
+
+/// Locks `key` until `locked_until` and restarts its failure count for afterwards.
+pub async fn lock_login(pool: &SqlitePool, key: &str, locked_until: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE login_throttle SET failures = 0, locked_until = ? WHERE key = ?")
+        .bind(locked_until)
+        .bind(key)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Forgets failures and lifts any lockout for `key`. Returns whether there was anything to clear.
+pub async fn clear_login_throttle(pool: &SqlitePool, key: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM login_throttle WHERE key = ?")
+        .bind(key)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn record_auth_event(
+    pool: &SqlitePool,
+    event: &str,
+    user_id: Option<&str>,
+    email: Option<&str>,
+    ip: Option<&str>,
+    detail: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO auth_events (event, user_id, email, ip, detail, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(event)
+    .bind(user_id)
+    .bind(email)
+    .bind(ip)
+    .bind(detail)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Auth events matching every filter that is set, newest first.
+pub async fn auth_events(pool: &SqlitePool, filter: &AuthEventFilter) -> Result<Vec<AuthEventRecord>, sqlx::Error> {
+    sqlx::query_as::<_, AuthEventRecord>(
+        "SELECT id, event, user_id, email, ip, detail, created_at FROM auth_events
+         WHERE (?1 IS NULL OR event = ?1)
+           AND (?2 IS NULL OR user_id = ?2)
+           AND (?3 IS NULL OR email = ?3 COLLATE NOCASE)
+           AND (?4 IS NULL OR ip = ?4)
+           AND (?5 IS NULL OR created_at >= ?5)
+           AND (?6 IS NULL OR id < ?6)
+         ORDER BY id DESC
+         LIMIT ?7",
+    )
+    .bind(&filter.event)
+    .bind(&filter.user_id)
+    .bind(&filter.email)
+    .bind(&filter.ip)
+    .bind(&filter.since)
+    .bind(filter.before_id)
+    .bind(filter.limit.unwrap_or(100).clamp(1, 1000))
+    .fetch_all(pool)
+    .await
+}
+
+/// Hash of the newest regulator access log entry, if there is one.
+pub async fn last_access_log_hash(conn: &mut SqliteConnection) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT hash FROM regulator_access_log ORDER BY id DESC LIMIT 1")
+        .fetch_optional(&mut *conn)
+        .await
+}
+
+pub async fn append_access_log(conn: &mut SqliteConnection, entry: &AccessLogEntry) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO regulator_access_log
+             (user_id, action, method, path, query, status, ip, accessed_at, previous_hash, hash)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&entry.user_id)
+    .bind(&entry.action)
+    .bind(&entry.method)
+    .bind(&entry.path)
+    .bind(&entry.query)
+    .bind(entry.status)
+    .bind(&entry.ip)
+    .bind(&entry.accessed_at)
+    .bind(&entry.previous_hash)
+    .bind(&entry.hash)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Regulator access log entries matching every filter that is set, newest first.
+pub async fn access_log(pool: &SqlitePool, filter: &AccessLogFilter) -> Result<Vec<AccessLogEntry>, sqlx::Error> {
+    sqlx::query_as::<_, AccessLogEntry>(
+        "SELECT * FROM regulator_access_log
+         WHERE (?1 IS NULL OR user_id = ?1)
+           AND (?2 IS NULL OR accessed_at >= ?2)
+           AND (?3 IS NULL OR id < ?3)
+         ORDER BY id DESC
+         LIMIT ?4",
+    )
+    .bind(&filter.user_id)
+    .bind(&filter.since)
+    .bind(filter.before_id)
+    .bind(filter.limit.unwrap_or(100).clamp(1, 1000))
+    .fetch_all(pool)
+    .await
+}
+
+/// The whole regulator access log, oldest first, for checking its hash chain.
+pub async fn access_log_chain(pool: &SqlitePool) -> Result<Vec<AccessLogEntry>, sqlx::Error> {
+    sqlx::query_as::<_, AccessLogEntry>("SELECT * FROM regulator_access_log ORDER BY id")
+        .fetch_all(pool)
+        .await
+}
+
+/// When `event` was last recorded for a user, if ever.
+pub async fn last_auth_event_at(pool: &SqlitePool, event: &str, user_id: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT created_at FROM auth_events WHERE event = ? AND user_id = ? ORDER BY id DESC LIMIT 1")
+        .bind(event)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+}
@@ -0,0 +1,108 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::env;
+
+use crate::auth::load_or_create_secret;
+
+/// Turns patient identifiers into keyed hashes, so records can be matched to a patient without
+/// the identifier ever being stored.
+pub struct PatientHasher {
+    salt: Vec<u8>,
+}
+
+impl PatientHasher {
+    /// Uses the secret salt at `PATIENT_SALT_PATH` (`patient_salt.key` by default), creating it on
+    /// first start. Nodes that should match each other's patients share the file.
+    pub fn from_env() -> Result<Self, String> {
+        let path = env::var("PATIENT_SALT_PATH").unwrap_or_else(|_| "patient_salt.key".to_string());
+        Ok(Self { salt: load_or_create_secret(&path)? })
+    }
+
+    /// HMAC-SHA256 of the identifier keyed by the salt, compared without case, spaces or `-`.
+    pub fn hash(&self, patient_id: &str) -> Result<String, String> {
+        let normalized: String = patient_id
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .flat_map(char::to_uppercase)
+            .collect();
+        if normalized.is_empty() {
+            return Err("patient_id is required".to_string());
+        }
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.salt).map_err(|e| e.to_string())?;
+        mac.update(normalized.as_bytes());
+        Ok(hex::encode(mac.finalize().into_bytes()))
+    }
+}
+
+/// Pack serial numbers as they are recorded: trimmed, each given once, printable ASCII of at
+/// most 20 characters as in a GS1 serial (AI 21).
+pub fn normalize_serials(serials: &[String]) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for serial in serials {
+        let serial = serial.trim();
+        if serial.is_empty() || serial.len() > 20 || !serial.chars().all(|c| c.is_ascii_graphic()) {
+            return Err(format!("Serial '{}' must be 1 to 20 printable ASCII characters", serial));
+        }
+        if normalized.iter().any(|s| s == serial) {
+            return Err(format!("Serial {} is listed twice", serial));
+        }
+        normalized.push(serial.to_string());
+    }
+    Ok(normalized)
+}
+
+/// Refuses any serial that is not one of the packs still in batch `batch_id`.
+pub fn serials_in_batch(serials: &[String], batch_id: &str, in_batch: &[String]) -> Result<(), String> {
+    match serials.iter().find(|s| !in_batch.contains(s)) {
+        Some(serial) => Err(format!("Serial {} is not a pack left in batch {}", serial, batch_id)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hasher(salt: &str) -> PatientHasher {
+        PatientHasher { salt: salt.as_bytes().to_vec() }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn patient_hashes_are_hmac_sha256_keyed_by_the_salt() {
+        // printf 'MRN12345' | openssl dgst -sha256 -hmac 'ward-salt'
+        assert_eq!(
+            hasher("ward-salt").hash("MRN12345").unwrap(),
+            "2463eb6aabb50239cdfd19ac0c234b488d48ef567e0a1a49115191c7caec9f34"
+        );
+        assert_ne!(hasher("other-salt").hash("MRN12345").unwrap(), hasher("ward-salt").hash("MRN12345").unwrap());
+    }
+
+    #[test]
+    fn patient_ids_match_without_case_spaces_or_dashes() {
+        let hasher = hasher("ward-salt");
+        assert_eq!(hasher.hash(" mrn-123 45 ").unwrap(), hasher.hash("MRN12345").unwrap());
+        assert!(hasher.hash(" - ").is_err());
+    }
+
+    #[test]
+    fn serials_are_trimmed_and_listed_once() {
+        assert_eq!(normalize_serials(&strings(&[" A1 ", "B2"])).unwrap(), strings(&["A1", "B2"]));
+        assert!(normalize_serials(&strings(&["A1", "A1 "])).unwrap_err().contains("twice"));
+        assert!(normalize_serials(&strings(&["123456789012345678901"])).is_err());
+        assert!(normalize_serials(&strings(&["A 1"])).is_err());
+        assert!(normalize_serials(&strings(&[""])).is_err());
+    }
+
+    #[test]
+    fn only_packs_left_in_the_batch_are_accepted() {
+        let in_batch = strings(&["A1", "A2", "A3"]);
+        assert!(serials_in_batch(&strings(&["A1", "A3"]), "B1", &in_batch).is_ok());
+        assert!(serials_in_batch(&[], "B1", &in_batch).is_ok());
+        let refused = serials_in_batch(&strings(&["A2", "Z9"]), "B1", &in_batch).unwrap_err();
+        assert!(refused.contains("Z9") && refused.contains("B1"), "{}", refused);
+    }
+}
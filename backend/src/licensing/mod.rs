@@ -0,0 +1,362 @@
+use axum::http::StatusCode;
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::env;
+
+use crate::db::entities::{find_organization_by_ref, find_registration, license_registered};
+
+type ApiError = (StatusCode, String);
+
+/// Check digits some registries build into their numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Checksum {
+    /// US DEA registration: two letters, six digits and a check digit.
+    Dea,
+    /// US National Provider Identifier: Luhn over `80840` and the first nine digits.
+    Npi,
+}
+
+/// How license or registration numbers look in one jurisdiction, for one kind of organization.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LicenseRule {
+    /// ISO country code, optionally with a subdivision (`US`, `US-CA`), or `*` for anywhere.
+    pub jurisdiction: String,
+    /// `company`, `hospital` or `customer`.
+    pub organization_type: String,
+    /// Masks separated by `|`: `A` is a letter, `9` a digit, `X` either, anything else itself.
+    /// Unset accepts 4 to 32 letters, digits, `-`, `/` and `.`.
+    #[serde(default)]
+    pub pattern: Option<String>,
+    #[serde(default)]
+    pub checksum: Option<Checksum>,
+    /// Whether registering needs a license expiry date.
+    #[serde(default)]
+    pub expiry_required: bool,
+    #[serde(default)]
+    pub description: String,
+}
+
+fn rule(jurisdiction: &str, organization_type: &str, pattern: Option<&str>, checksum: Option<Checksum>, expiry_required: bool, description: &str) -> LicenseRule {
+    LicenseRule {
+        jurisdiction: jurisdiction.to_string(),
+        organization_type: organization_type.to_string(),
+        pattern: pattern.map(str::to_string),
+        checksum,
+        expiry_required,
+        description: description.to_string(),
+    }
+}
+
+fn builtin_rules() -> Vec<LicenseRule> {
+    vec![
+        rule("US", "company", Some("AA9999999"), Some(Checksum::Dea), true, "DEA registration number"),
+        rule("US", "hospital", Some("9999999999"), Some(Checksum::Npi), false, "National Provider Identifier"),
+        rule("*", "company", None, None, true, "Manufacturing or wholesale license"),
+        rule("*", "hospital", None, None, false, "Facility registration"),
+        rule("*", "customer", None, None, false, "Pharmacy or customer registration"),
+    ]
+}
+
+fn matches_mask(mask: &str, value: &str) -> bool {
+    mask.chars().count() == value.chars().count()
+        && mask.chars().zip(value.chars()).all(|(m, c)| match m {
+            'A' => c.is_ascii_alphabetic(),
+            '9' => c.is_ascii_digit(),
+            'X' => c.is_ascii_alphanumeric(),
+            literal => c == literal,
+        })
+}
+
+fn digits(value: &str) -> Vec<u32> {
+    value.chars().filter_map(|c| c.to_digit(10)).collect()
+}
+
+fn checksum_holds(checksum: Checksum, value: &str) -> bool {
+    match checksum {
+        Checksum::Dea => {
+            let d = digits(value);
+            d.len() == 7 && ((d[0] + d[2] + d[4]) + 2 * (d[1] + d[3] + d[5])) % 10 == d[6]
+        }
+        Checksum::Npi => {
+            let d = digits(value);
+            if d.len() != 10 {
+                return false;
+            }
+            // Luhn over the card-issuer prefix 80840 followed by the NPI, check digit included.
+            let mut all = vec![8, 0, 8, 4, 0];
+            all.extend(&d);
+            let sum: u32 = all
+                .iter()
+                .rev()
+                .enumerate()
+                .map(|(i, &n)| if i % 2 == 1 { if n * 2 > 9 { n * 2 - 9 } else { n * 2 } } else { n })
+                .sum();
+            sum.is_multiple_of(10)
+        }
+    }
+}
+
+/// Licenses are compared and stored upper-case, without spaces.
+pub fn normalize_license(value: &str) -> String {
+    value.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_ascii_uppercase()
+}
+
+pub fn normalize_jurisdiction(value: &str) -> String {
+    value.trim().to_ascii_uppercase()
+}
+
+/// Registration settings and the format rules, read from the environment.
+pub struct Licensing {
+    pub rules: Vec<LicenseRule>,
+    /// Used when a registration names no jurisdiction.
+    pub default_jurisdiction: Option<String>,
+    /// Approve registrations straight away instead of queueing them for a regulator.
+    pub auto_approve: bool,
+}
+
+/// A license that passed the format rules.
+pub struct ValidLicense {
+    pub jurisdiction: String,
+    pub number: String,
+    pub expires_at: Option<String>,
+}
+
+impl Licensing {
+    /// The built-in rules, overridden or extended by the JSON list at `LICENSE_RULES_PATH`.
+    pub fn from_env() -> Result<Self, String> {
+        let mut rules = builtin_rules();
+        if let Ok(path) = env::var("LICENSE_RULES_PATH") {
+            let text = std::fs::read_to_string(&path).map_err(|e| format!("Reading {}: {}", path, e))?;
+            let custom: Vec<LicenseRule> = serde_json::from_str(&text).map_err(|e| format!("Parsing {}: {}", path, e))?;
+            for mut custom_rule in custom {
+                custom_rule.jurisdiction = normalize_jurisdiction(&custom_rule.jurisdiction);
+                rules.retain(|r| {
+                    r.jurisdiction != custom_rule.jurisdiction || r.organization_type != custom_rule.organization_type
+                });
+                rules.push(custom_rule);
+            }
+        }
+
+        Ok(Self {
+            rules,
+            default_jurisdiction: env::var("DEFAULT_JURISDICTION")
+                .ok()
+                .map(|v| normalize_jurisdiction(&v))
+                .filter(|v| !v.is_empty()),
+            auto_approve: env::var("REGISTRATION_AUTO_APPROVE").is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true")),
+        })
+    }
+
+    /// The most specific rule: the exact jurisdiction, then its country, then `*`.
+    pub fn rule_for(&self, organization_type: &str, jurisdiction: &str) -> Option<&LicenseRule> {
+        let country = jurisdiction.split('-').next().unwrap_or(jurisdiction);
+        [jurisdiction, country, "*"].into_iter().find_map(|j| {
+            self.rules
+                .iter()
+                .find(|r| r.organization_type == organization_type && r.jurisdiction == j)
+        })
+    }
+
+    /// Checks a license number and expiry date against the rule for the jurisdiction.
+    pub fn validate(
+        &self,
+        organization_type: &str,
+        jurisdiction: Option<&str>,
+        license: &str,
+        expires_at: Option<&str>,
+    ) -> Result<ValidLicense, String> {
+        let jurisdiction = jurisdiction
+            .map(normalize_jurisdiction)
+            .filter(|j| !j.is_empty())
+            .or_else(|| self.default_jurisdiction.clone())
+            .ok_or("A jurisdiction is required")?;
+        let rule = self
+            .rule_for(organization_type, &jurisdiction)
+            .ok_or_else(|| format!("No {} registrations are accepted in {}", organization_type, jurisdiction))?;
+        let number = normalize_license(license);
+
+        let format_ok = match &rule.pattern {
+            Some(pattern) => pattern.split('|').any(|mask| matches_mask(mask, &number)),
+            None => {
+                (4..=32).contains(&number.len())
+                    && number.chars().all(|c| c.is_ascii_alphanumeric() || "-/.".contains(c))
+            }
+        };
+        if !format_ok {
+            let expected = rule.pattern.as_deref().unwrap_or("4-32 letters, digits, '-', '/' or '.'");
+            return Err(format!("'{}' is not a valid {} for {} (expected {})", number, rule.description, jurisdiction, expected));
+        }
+        if let Some(checksum) = rule.checksum
+            && !checksum_holds(checksum, &number)
+        {
+            return Err(format!("'{}' fails the {} check digit", number, rule.description));
+        }
+
+        let expires_at = match expires_at {
+            Some(date) => {
+                let date = NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+                    .map_err(|_| format!("License expiry '{}' is not a YYYY-MM-DD date", date))?;
+                if date <= Utc::now().date_naive() {
+                    return Err(format!("The license expired on {}", date));
+                }
+                Some(date.to_string())
+            }
+            None if rule.expiry_required => {
+                return Err(format!("A license expiry date is required for a {} in {}", organization_type, jurisdiction));
+            }
+            None => None,
+        };
+
+        Ok(ValidLicense {
+            jurisdiction,
+            number,
+            expires_at,
+        })
+    }
+
+    /// [`validate`](Self::validate), then refuses license numbers already registered.
+    pub async fn check(
+        &self,
+        pool: &SqlitePool,
+        organization_type: &str,
+        jurisdiction: Option<&str>,
+        license: &str,
+        expires_at: Option<&str>,
+    ) -> Result<ValidLicense, ApiError> {
+        let license = self
+            .validate(organization_type, jurisdiction, license, expires_at)
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        if license_registered(pool, organization_type, &license.jurisdiction, &license.number)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        {
+            return Err(already_registered(organization_type, &license));
+        }
+        Ok(license)
+    }
+
+    /// The status a new registration starts in.
+    pub fn initial_status(&self) -> &'static str {
+        if self.auto_approve { "approved" } else { "pending" }
+    }
+}
+
+fn already_registered(organization_type: &str, license: &ValidLicense) -> ApiError {
+    (
+        StatusCode::CONFLICT,
+        format!("A {} with license {} is already registered in {}", organization_type, license.number, license.jurisdiction),
+    )
+}
+
+/// Maps a failed registration insert, where losing a race on the uniqueness constraint
+/// is a conflict rather than an internal error.
+pub fn registration_error(e: sqlx::Error, organization_type: &str, license: &ValidLicense) -> ApiError {
+    match &e {
+        sqlx::Error::Database(db) if db.is_unique_violation() => already_registered(organization_type, license),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// Why an organization may not move stock right now, if it may not.
+pub async fn inactive_reason(pool: &SqlitePool, organization_id: &str) -> Result<Option<String>, sqlx::Error> {
+    let Some(registration) = find_registration(pool, organization_id).await? else {
+        return Ok(Some("has no registration on file".to_string()));
+    };
+    let reason = match registration.status.as_str() {
+        "approved" => registration
+            .license_expires_at
+            .filter(|expires| expires.as_str() <= Utc::now().date_naive().to_string().as_str())
+            .map(|expires| format!("has a license that expired on {}", expires)),
+        "pending" => Some("is awaiting regulator approval".to_string()),
+        "suspended" => Some(format!(
+            "is suspended: {}",
+            registration.status_reason.as_deref().unwrap_or("no reason given")
+        )),
+        other => Some(format!("is {}", other)),
+    };
+    Ok(reason)
+}
+
+/// Refuses organizations that are not approved with a current license.
+pub async fn require_active(pool: &SqlitePool, organization_id: &str) -> Result<(), ApiError> {
+    match inactive_reason(pool, organization_id).await {
+        Ok(None) => Ok(()),
+        Ok(Some(reason)) => Err((StatusCode::FORBIDDEN, format!("Organization {} {}", organization_id, reason))),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+/// Refuses a transfer to a registered organization that may not receive stock. A destination
+/// that names no organization (a free-text location) is left alone.
+pub async fn require_active_receiver(pool: &SqlitePool, to_location: &str) -> Result<(), ApiError> {
+    let organization = find_organization_by_ref(pool, to_location)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    match organization {
+        Some(organization) => require_active(pool, &organization.id).await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builtin() -> Licensing {
+        Licensing {
+            rules: builtin_rules(),
+            default_jurisdiction: None,
+            auto_approve: false,
+        }
+    }
+
+    #[test]
+    fn dea_numbers_carry_their_check_digit() {
+        // 1 + 3 + 5 + 2 * (2 + 4 + 6) = 33, so the check digit is 3.
+        assert!(checksum_holds(Checksum::Dea, "AB1234563"));
+        assert!(checksum_holds(Checksum::Dea, "FN5623740"));
+        assert!(!checksum_holds(Checksum::Dea, "AB1234564"));
+        assert!(!checksum_holds(Checksum::Dea, "AB123456"));
+    }
+
+    #[test]
+    fn npi_numbers_pass_luhn_with_the_80840_prefix() {
+        assert!(checksum_holds(Checksum::Npi, "1234567893"));
+        assert!(checksum_holds(Checksum::Npi, "1245319599"));
+        assert!(!checksum_holds(Checksum::Npi, "1234567890"));
+        // Luhn over the bare ten digits would accept this one; the prefix is what rejects it.
+        assert!(!checksum_holds(Checksum::Npi, "1234567897"));
+        assert!(!checksum_holds(Checksum::Npi, "123456789"));
+    }
+
+    #[test]
+    fn masks_match_letters_digits_and_literals_by_position() {
+        assert!(matches_mask("AA9999999", "AB1234563"));
+        assert!(!matches_mask("AA9999999", "A11234563"));
+        assert!(!matches_mask("AA9999999", "AB123456"));
+        assert!(matches_mask("XX-99", "A1-42"));
+        assert!(!matches_mask("XX-99", "A1/42"));
+    }
+
+    #[test]
+    fn licenses_and_jurisdictions_are_normalized() {
+        assert_eq!(normalize_license(" ab 123 456 3 "), "AB1234563");
+        assert_eq!(normalize_jurisdiction(" us-ca "), "US-CA");
+    }
+
+    #[test]
+    fn validation_applies_the_most_specific_rule() {
+        let licensing = builtin();
+        let license = licensing
+            .validate("company", Some("us-ca"), "ab 1234563", Some("2999-01-01"))
+            .unwrap();
+        assert_eq!(license.jurisdiction, "US-CA");
+        assert_eq!(license.number, "AB1234563");
+        assert!(licensing.validate("company", Some("US"), "AB1234564", Some("2999-01-01")).is_err());
+        assert!(licensing.validate("hospital", Some("US"), "1234567890", None).is_err());
+        // Elsewhere any plausible number goes.
+        assert!(licensing.validate("hospital", Some("FR"), "1234567890", None).is_ok());
+    }
+}
@@ -0,0 +1,116 @@
+use axum::async_trait;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::env;
+use std::path::PathBuf;
+
+/// An outgoing plain-text email.
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Somewhere mail can be delivered. Pick one with `MAIL_TRANSPORT`.
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    async fn send(&self, mail: &Mail) -> Result<(), String>;
+}
+
+fn build_message(from: &Mailbox, mail: &Mail) -> Result<Message, String> {
+    let to: Mailbox = mail.to.parse().map_err(|e| format!("Bad recipient {}: {}", mail.to, e))?;
+    Message::builder()
+        .from(from.clone())
+        .to(to)
+        .subject(&mail.subject)
+        .body(mail.body.clone())
+        .map_err(|e| e.to_string())
+}
+
+/// Delivers through an SMTP relay.
+pub struct SmtpMailTransport {
+    from: Mailbox,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+#[async_trait]
+impl MailTransport for SmtpMailTransport {
+    async fn send(&self, mail: &Mail) -> Result<(), String> {
+        let message = build_message(&self.from, mail)?;
+        self.transport.send(message).await.map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+/// Writes each message as an `.eml` file, for tests and local development.
+pub struct FileMailTransport {
+    from: Mailbox,
+    dir: PathBuf,
+}
+
+#[async_trait]
+impl MailTransport for FileMailTransport {
+    async fn send(&self, mail: &Mail) -> Result<(), String> {
+        let message = build_message(&self.from, mail)?;
+        tokio::fs::create_dir_all(&self.dir).await.map_err(|e| e.to_string())?;
+        let name = format!(
+            "{}-{}.eml",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+            uuid::Uuid::new_v4().simple()
+        );
+        tokio::fs::write(self.dir.join(name), message.formatted())
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Prints messages to the node's log instead of sending them.
+pub struct LogMailTransport;
+
+#[async_trait]
+impl MailTransport for LogMailTransport {
+    async fn send(&self, mail: &Mail) -> Result<(), String> {
+        println!("📧 To: {}\n   Subject: {}\n{}", mail.to, mail.subject, mail.body);
+        Ok(())
+    }
+}
+
+/// The transport named by `MAIL_TRANSPORT`: `smtp`, `file` or `log` (the default).
+pub fn transport_from_env() -> Result<Box<dyn MailTransport>, String> {
+    let from: Mailbox = env::var("MAIL_FROM")
+        .unwrap_or_else(|_| "PharmaChain <no-reply@pharmachain.local>".to_string())
+        .parse()
+        .map_err(|e| format!("MAIL_FROM: {}", e))?;
+
+    match env::var("MAIL_TRANSPORT").unwrap_or_else(|_| "log".to_string()).as_str() {
+        "smtp" => {
+            let host = env::var("SMTP_HOST").map_err(|_| "SMTP_HOST is required for the smtp transport".to_string())?;
+            let builder = match env::var("SMTP_TLS").unwrap_or_else(|_| "starttls".to_string()).as_str() {
+                "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host).map_err(|e| e.to_string())?,
+                "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&host).map_err(|e| e.to_string())?,
+                // Local catchers such as MailHog speak plain SMTP.
+                "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+                other => return Err(format!("SMTP_TLS must be starttls, tls or none, not '{}'", other)),
+            };
+            let builder = match env::var("SMTP_PORT").ok().and_then(|v| v.parse().ok()) {
+                Some(port) => builder.port(port),
+                None => builder,
+            };
+            let builder = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+                (Ok(username), Ok(password)) => builder.credentials(Credentials::new(username, password)),
+                _ => builder,
+            };
+            Ok(Box::new(SmtpMailTransport {
+                from,
+                transport: builder.build(),
+            }))
+        }
+        "file" => Ok(Box::new(FileMailTransport {
+            from,
+            dir: PathBuf::from(env::var("MAIL_DIR").unwrap_or_else(|_| "mail".to_string())),
+        })),
+        "log" => Ok(Box::new(LogMailTransport)),
+        other => Err(format!("MAIL_TRANSPORT must be smtp, file or log, not '{}'", other)),
+    }
+}
@@ -1,12 +1,20 @@
-use axum::Router;
 use dotenv::dotenv;
-use std::net::SocketAddr;
 use std::sync::Arc;
-use sqlx::SqlitePool;
 
+mod allocation;
+mod anchor;
+mod auth;
+mod catalog;
+mod controlled;
+mod dispensing;
+mod mail;
 mod db;
+mod licensing;
 mod routes;
 mod models;
+mod p2p;
+mod timestamp;
+mod utils;
 
 #[tokio::main]
 async fn main() {
@@ -20,14 +28,50 @@ async fn main() {
     );
     db::init_db().await.expect("DB init failed");
 
+    // Load this node's identity and the authority set it replicates with
+    let config = p2p::NodeConfig::from_env();
+    let node = Arc::new(p2p::Node::new(pool.clone(), config).expect("Node setup failed"));
+    println!(
+        "🔑 Node {} ({}) public key: {}",
+        node.config.node_id, node.config.organization, node.public_key_b64
+    );
+
+    p2p::sync::sync_with_peers(&node).await;
+    p2p::sync::spawn_sync_loop(node.clone());
+    p2p::poa::spawn_block_producer(node.clone());
+    anchor::spawn_anchor_service(node.clone());
+    timestamp::spawn_timestamp_service(node.clone());
+
+    // Signed session tokens for the API
+    let auth_config = auth::AuthConfig::from_env().expect("Auth setup failed");
+    let oidc = auth::oidc::OidcConfig::from_env().expect("OIDC setup failed");
+    if let Some(oidc) = &oidc {
+        println!("🔓 Single sign-on through {}", oidc.issuer);
+    }
+    let auth = Arc::new(auth::Auth {
+        pool: pool.clone(),
+        config: auth_config,
+        oidc: oidc.map(auth::oidc::OidcClient::new),
+        mailer: mail::transport_from_env().expect("Mail setup failed"),
+    });
+
+    // License format rules and the registration review policy
+    let licensing = Arc::new(licensing::Licensing::from_env().expect("Licensing setup failed"));
+
+    // Salt for the patient identifiers dispensing records hash
+    let patients = Arc::new(dispensing::PatientHasher::from_env().expect("Patient salt setup failed"));
+
     // Use the modular route setup
-    let app = routes::create_routes(pool.clone());
+    let app = routes::create_routes(pool.clone(), node.clone(), auth, licensing, patients);
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3001));
+    let addr = node.config.bind_addr.clone();
     println!("🚀 Server running at http://{}", addr);
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .expect("Failed to bind address");
+    // Peer addresses feed login throttling
+    axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>())
         .await
         .unwrap();
 }
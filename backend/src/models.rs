@@ -7,12 +7,18 @@ pub struct SignupData {
     pub email: String,
     pub password: String,
     pub role: String, // 👈 Added for role selection (customer/hospital/company)
+    /// Joins an existing organization straight away.
+    #[serde(default)]
+    pub invite_code: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct LoginData {
     pub email: String,
     pub password: String,
+    /// Current TOTP code or an unused recovery code, for accounts with two-factor enabled.
+    #[serde(default)]
+    pub otp: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -22,9 +28,19 @@ pub struct ApiResponse {
 
 #[derive(Serialize)]
 pub struct LoginResponse {
+    /// Short-lived signed access token, sent as `Authorization: Bearer <token>`.
     pub token: String,
     pub user: String,
     pub role: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// Seconds until `token` expires.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<i64>,
+    /// `code_required` when the login needs an `otp`; `enroll` when the role requires
+    /// two-factor and the account has none yet, so the session can only enroll.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub two_factor: Option<String>,
 }
 
 
@@ -35,4 +51,138 @@ pub struct User {
     pub email: String,
     pub password: String,
     pub role: String, // 👈 Added role to user struct
+    /// Unset until the user follows the link in their verification email.
+    pub email_verified_at: Option<String>,
+}
+
+/// A login session; its id is the `sid` claim of every access token issued for it.
+#[derive(Debug, FromRow)]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub refresh_hash: String,
+    /// Organization the session acts for, if the user belongs to any.
+    pub organization_id: Option<String>,
+    /// Whether the login passed a second factor.
+    pub mfa: bool,
+    pub expires_at: String,
+    pub revoked_at: Option<String>,
+}
+
+/// A user's place in an organization. `role` is `owner` or `member`; owners can invite.
+#[derive(Debug, Serialize, FromRow)]
+pub struct Membership {
+    pub organization_id: String,
+    pub organization_type: String,
+    pub organization_name: String,
+    pub role: String,
+    pub joined_at: String,
+}
+
+#[derive(Debug, FromRow)]
+pub struct Invite {
+    pub organization_id: String,
+    pub organization_type: String,
+    pub role: String,
+}
+
+/// An organization's machine credential. `key_hash` is the SHA-256 of the key's secret.
+#[derive(Debug, Serialize, FromRow)]
+pub struct ApiKey {
+    pub id: String,
+    pub organization_id: String,
+    pub organization_type: String,
+    pub name: String,
+    /// Comma-separated scopes, e.g. `batch:write,read`.
+    pub scopes: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub created_by: String,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+    pub revoked_at: Option<String>,
+}
+
+/// A user's TOTP secret (base32). `enabled_at` is unset until the first code is confirmed.
+#[derive(Debug, FromRow)]
+pub struct UserTotp {
+    pub secret: String,
+    pub enabled_at: Option<String>,
+    /// Last time step a code was accepted for, so a code cannot be replayed.
+    pub last_used_step: i64,
+}
+
+/// A single sign-on login waiting for the identity provider's callback.
+#[derive(Debug, FromRow)]
+pub struct OidcLogin {
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+/// Failed-login state for one account or IP address.
+#[derive(Debug, FromRow)]
+pub struct LoginThrottle {
+    pub failures: i64,
+    pub last_failure_at: i64,
+    pub locked_until: Option<i64>,
+}
+
+/// One entry of the auth event log.
+#[derive(Debug, Serialize, FromRow)]
+pub struct AuthEventRecord {
+    pub id: i64,
+    /// `login_success`, `login_failure`, `login_throttled`, `lockout`, `token_refresh`,
+    /// `token_refresh_failure`, `email_verified`, `password_reset_requested` or `password_reset`.
+    pub event: String,
+    pub user_id: Option<String>,
+    pub email: Option<String>,
+    pub ip: Option<String>,
+    pub detail: Option<String>,
+    pub created_at: String,
+}
+
+/// Query parameters for the auth event log; unset fields match everything.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuthEventFilter {
+    pub event: Option<String>,
+    pub user_id: Option<String>,
+    pub email: Option<String>,
+    pub ip: Option<String>,
+    /// RFC 3339 timestamp; only events at or after it.
+    pub since: Option<String>,
+    /// Page backwards: only events with a smaller id.
+    pub before_id: Option<i64>,
+    /// At most this many events (default 100, max 1000).
+    pub limit: Option<i64>,
+}
+
+/// One request made by a regulator, chained to the entry before it.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AccessLogEntry {
+    pub id: i64,
+    pub user_id: String,
+    /// The action guarding the route, e.g. `inspect every organization`.
+    pub action: String,
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    /// HTTP status of the answer, including refusals.
+    pub status: i64,
+    pub ip: Option<String>,
+    pub accessed_at: String,
+    /// `hash` of the previous entry, or `GENESIS` for the first.
+    pub previous_hash: String,
+    pub hash: String,
+}
+
+/// Query parameters for the regulator access log; unset fields match everything.
+#[derive(Debug, Default, Deserialize)]
+pub struct AccessLogFilter {
+    pub user_id: Option<String>,
+    /// RFC 3339 timestamp; only accesses at or after it.
+    pub since: Option<String>,
+    /// Page backwards: only entries with a smaller id.
+    pub before_id: Option<i64>,
+    /// At most this many entries (default 100, max 1000).
+    pub limit: Option<i64>,
 }
@@ -0,0 +1,191 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::db::entities::{batch_chain_head, LedgerTx};
+use crate::p2p::{decode_public_key, Authority};
+use crate::utils::signatures::verify_signature;
+
+/// Transactions accepted by this node but not yet sealed into a block.
+#[derive(Default)]
+pub struct Mempool {
+    pending: Vec<LedgerTx>,
+    oldest: Option<Instant>,
+}
+
+impl Mempool {
+    pub fn contains(&self, hash: &str) -> bool {
+        self.pending.iter().any(|tx| tx.hash() == hash)
+    }
+
+    /// Adds a transaction unless it is already pending. Returns whether it was added.
+    pub fn insert(&mut self, tx: LedgerTx) -> bool {
+        if self.contains(tx.hash()) {
+            return false;
+        }
+        self.oldest.get_or_insert_with(Instant::now);
+        self.pending.push(tx);
+        true
+    }
+
+    pub fn pending(&self) -> &[LedgerTx] {
+        &self.pending
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// How long the oldest pending transaction has been waiting.
+    pub fn oldest_age(&self) -> Option<Duration> {
+        self.oldest.map(|t| t.elapsed())
+    }
+
+    pub fn remove(&mut self, hashes: &HashSet<String>) {
+        self.pending.retain(|tx| !hashes.contains(tx.hash()));
+        if self.pending.is_empty() {
+            self.oldest = None;
+        }
+    }
+}
+
+/// Per-batch chain state layered over the database, so a run of transactions
+/// (a block, or the mempool plus a new submission) can be validated in order.
+pub struct LedgerView<'a> {
+    pool: &'a SqlitePool,
+    /// Only transactions sealed at or below this height count as already on the ledger.
+    max_height: i64,
+    /// Latest transaction hash and current location for each batch touched so far.
+    heads: HashMap<String, Option<(String, String)>>,
+}
+
+impl<'a> LedgerView<'a> {
+    pub fn new(pool: &'a SqlitePool) -> Self {
+        Self::at_height(pool, i64::MAX)
+    }
+
+    /// A view of the ledger as it was at `height`, used to validate a fork branch from its fork point.
+    pub fn at_height(pool: &'a SqlitePool, height: i64) -> Self {
+        Self {
+            pool,
+            max_height: height,
+            heads: HashMap::new(),
+        }
+    }
+
+    async fn head(&mut self, batch_id: &str) -> Result<Option<(String, String)>, sqlx::Error> {
+        if let Some(head) = self.heads.get(batch_id) {
+            return Ok(head.clone());
+        }
+        let head = batch_chain_head(self.pool, batch_id, self.max_height).await?;
+        self.heads.insert(batch_id.to_string(), head.clone());
+        Ok(head)
+    }
+
+    /// Checks a transaction and, if valid, advances the view past it.
+    /// Returns the reason the transaction is invalid, if it is.
+    pub async fn apply(&mut self, authorities: &[Authority], tx: &LedgerTx) -> Result<Option<String>, sqlx::Error> {
+        if tx.recompute_hash() != tx.hash() {
+            return Ok(Some(format!("Hash mismatch for transaction on batch {}", tx.batch_id())));
+        }
+
+        let Some(public_key_b64) = tx.public_key() else {
+            return Ok(Some(format!("Unsigned transaction on batch {}", tx.batch_id())));
+        };
+        if !authorities.iter().any(|a| a.public_key == public_key_b64) {
+            return Ok(Some(format!("Transaction on batch {} not signed by an authority", tx.batch_id())));
+        }
+        let signature = STANDARD.decode(tx.signature().unwrap_or_default()).unwrap_or_default();
+        let signed = decode_public_key(public_key_b64)
+            .is_some_and(|key| verify_signature(&key, tx.hash().as_bytes(), &signature));
+        if !signed {
+            return Ok(Some(format!("Bad signature on transaction for batch {}", tx.batch_id())));
+        }
+
+        let head = self.head(tx.batch_id()).await?;
+        let next_head = match (tx, head) {
+            (LedgerTx::Batch(batch), None) => {
+                if batch.previous_hash != "GENESIS" {
+                    return Ok(Some(format!("Batch {} must start a new chain", batch.batch_id)));
+                }
+                (batch.hash.clone(), batch.destination.clone())
+            }
+            (LedgerTx::Batch(batch), Some(_)) => {
+                return Ok(Some(format!("Batch {} already exists", batch.batch_id)));
+            }
+            (LedgerTx::Custody(event), None) => {
+                return Ok(Some(format!("Unknown batch {}", event.batch_id)));
+            }
+            (LedgerTx::Custody(event), Some((head_hash, location))) => {
+                if event.previous_hash != head_hash {
+                    return Ok(Some(format!("Custody event does not follow batch {}'s latest transaction", event.batch_id)));
+                }
+                if event.from_location != location {
+                    return Ok(Some(format!("Batch {} is at {}, not {}", event.batch_id, location, event.from_location)));
+                }
+                (event.hash.clone(), event.to_location.clone())
+            }
+            (LedgerTx::Dispensing(event), None) => {
+                return Ok(Some(format!("Unknown batch {}", event.batch_id)));
+            }
+            // Dispensing leaves the batch where it is.
+            (LedgerTx::Dispensing(event), Some(head)) => {
+                if event.location != head.1 {
+                    return Ok(Some(format!("Batch {} is at {}, not {}", event.batch_id, head.1, event.location)));
+                }
+                head
+            }
+        };
+
+        self.heads.insert(tx.batch_id().to_string(), Some(next_head));
+        Ok(None)
+    }
+
+    /// Current head for a batch, including anything applied to this view.
+    pub async fn current(&mut self, batch_id: &str) -> Result<Option<(String, String)>, sqlx::Error> {
+        self.head(batch_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::entities::CustodyEvent;
+
+    fn custody(hash: &str) -> LedgerTx {
+        LedgerTx::Custody(CustodyEvent {
+            batch_id: "B1".to_string(),
+            from_location: "a".to_string(),
+            to_location: "b".to_string(),
+            timestamp: "2024-01-01T00:00:00+00:00".to_string(),
+            hash: hash.to_string(),
+            previous_hash: "p".to_string(),
+            signature: String::new(),
+            public_key: String::new(),
+            expected_arrival: None,
+        })
+    }
+
+    #[test]
+    fn a_transaction_is_pending_once_in_arrival_order() {
+        let mut mempool = Mempool::default();
+        assert!(mempool.insert(custody("h1")));
+        assert!(mempool.insert(custody("h2")));
+        assert!(!mempool.insert(custody("h1")));
+        let hashes: Vec<&str> = mempool.pending().iter().map(|tx| tx.hash()).collect();
+        assert_eq!(hashes, ["h1", "h2"]);
+    }
+
+    #[test]
+    fn the_wait_restarts_once_the_mempool_empties() {
+        let mut mempool = Mempool::default();
+        assert!(mempool.oldest_age().is_none());
+        mempool.insert(custody("h1"));
+        mempool.insert(custody("h2"));
+        mempool.remove(&HashSet::from(["h1".to_string()]));
+        assert!(mempool.oldest_age().is_some());
+        mempool.remove(&HashSet::from(["h2".to_string()]));
+        assert!(mempool.oldest_age().is_none() && mempool.len() == 0);
+    }
+}
@@ -0,0 +1,248 @@
+pub mod mempool;
+pub mod poa;
+pub mod sync;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey, EncodeRsaPrivateKey, EncodeRsaPublicKey, LineEnding};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::anchor::AnchorConfig;
+use crate::db::entities::LedgerTx;
+use crate::p2p::mempool::{LedgerView, Mempool};
+use crate::timestamp::TimestampConfig;
+use crate::utils::signatures::generate_keys;
+
+/// A known organization allowed to produce blocks.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Authority {
+    pub node_id: String,
+    pub organization: String,
+    pub url: String,
+    pub public_key: String, // base64 PKCS#1 DER, same encoding as medicine_batches.public_key
+}
+
+/// Node settings, read from the environment.
+///
+/// `AUTHORITIES_PATH` points to a JSON array of [`Authority`] entries shared by every node.
+/// When the file is missing the node runs alone and is its own single authority.
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    pub node_id: String,
+    pub organization: String,
+    pub bind_addr: String,
+    pub key_path: String,
+    pub authorities_path: String,
+    pub slot_secs: i64,
+    pub block_interval_secs: u64,
+    pub block_max_txs: usize,
+    pub sync_interval_secs: u64,
+    /// How long a peer caught misbehaving is shut out before we listen to it again.
+    pub rejection_secs: u64,
+    pub anchor: Option<AnchorConfig>,
+    pub timestamp: Option<TimestampConfig>,
+}
+
+impl NodeConfig {
+    pub fn from_env() -> Self {
+        Self {
+            node_id: env::var("NODE_ID").unwrap_or_else(|_| "node-1".to_string()),
+            organization: env::var("NODE_ORG").unwrap_or_else(|_| "local".to_string()),
+            bind_addr: env::var("BIND_ADDR").unwrap_or_else(|_| "127.0.0.1:3001".to_string()),
+            key_path: env::var("NODE_KEY_PATH").unwrap_or_else(|_| "node_key.pem".to_string()),
+            authorities_path: env::var("AUTHORITIES_PATH").unwrap_or_else(|_| "authorities.json".to_string()),
+            slot_secs: env::var("SLOT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v: &i64| *v > 0)
+                .unwrap_or(2),
+            block_interval_secs: env::var("BLOCK_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(2),
+            block_max_txs: env::var("BLOCK_MAX_TXS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v: &usize| *v > 0)
+                .unwrap_or(100),
+            sync_interval_secs: env::var("SYNC_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(5),
+            rejection_secs: env::var("PEER_REJECTION_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(600),
+            anchor: AnchorConfig::from_env(),
+            timestamp: TimestampConfig::from_env(),
+        }
+    }
+}
+
+/// Why a peer was shut out, and until when.
+struct Rejection {
+    reason: String,
+    until: Instant,
+}
+
+/// A peer currently shut out, as listed in the node status.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RejectedPeer {
+    pub node_id: String,
+    pub reason: String,
+    pub seconds_left: u64,
+}
+
+/// Shared state for one ledger node: its database, signing key and view of the authority set.
+pub struct Node {
+    pub pool: Arc<SqlitePool>,
+    pub config: NodeConfig,
+    pub private_key: RsaPrivateKey,
+    pub public_key_b64: String,
+    pub authorities: Vec<Authority>,
+    /// Peers caught diverging from our chain, keyed by node id. Rejections lapse after
+    /// `rejection_secs`, so a peer blamed for a passing fault is heard again.
+    rejected: Mutex<HashMap<String, Rejection>>,
+    /// Serializes sealing and applying blocks so heights never race.
+    pub ledger_lock: Mutex<()>,
+    pub mempool: Mutex<Mempool>,
+    pub http: reqwest::Client,
+}
+
+impl Node {
+    pub fn new(pool: Arc<SqlitePool>, config: NodeConfig) -> Result<Self, String> {
+        let private_key = load_or_create_key(&config.key_path)?;
+        let public_key_b64 = encode_public_key(&RsaPublicKey::from(&private_key))?;
+
+        let authorities = if Path::new(&config.authorities_path).exists() {
+            let raw = std::fs::read_to_string(&config.authorities_path).map_err(|e| e.to_string())?;
+            serde_json::from_str::<Vec<Authority>>(&raw).map_err(|e| e.to_string())?
+        } else {
+            vec![Authority {
+                node_id: config.node_id.clone(),
+                organization: config.organization.clone(),
+                url: format!("http://{}", config.bind_addr),
+                public_key: public_key_b64.clone(),
+            }]
+        };
+
+        match authorities.iter().find(|a| a.node_id == config.node_id) {
+            Some(me) if me.public_key != public_key_b64 => {
+                return Err(format!(
+                    "Key at {} does not match the public key listed for {}",
+                    config.key_path, config.node_id
+                ));
+            }
+            Some(_) => {}
+            None => return Err(format!("{} is not in the authority set", config.node_id)),
+        }
+
+        Ok(Self {
+            pool,
+            config,
+            private_key,
+            public_key_b64,
+            authorities,
+            rejected: Mutex::new(HashMap::new()),
+            ledger_lock: Mutex::new(()),
+            mempool: Mutex::new(Mempool::default()),
+            http: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(5))
+                .build()
+                .map_err(|e| e.to_string())?,
+        })
+    }
+
+    pub fn authority(&self, node_id: &str) -> Option<&Authority> {
+        self.authorities.iter().find(|a| a.node_id == node_id)
+    }
+
+    /// Authorities other than this node.
+    pub fn peers(&self) -> impl Iterator<Item = &Authority> {
+        self.authorities.iter().filter(|a| a.node_id != self.config.node_id)
+    }
+
+    pub async fn reject_peer(&self, node_id: &str, reason: &str) {
+        if node_id == self.config.node_id {
+            return;
+        }
+        eprintln!("Rejecting node {} for {}s: {}", node_id, self.config.rejection_secs, reason);
+        self.rejected.lock().await.insert(
+            node_id.to_string(),
+            Rejection {
+                reason: reason.to_string(),
+                until: Instant::now() + Duration::from_secs(self.config.rejection_secs),
+            },
+        );
+    }
+
+    pub async fn is_rejected(&self, node_id: &str) -> bool {
+        let mut rejected = self.rejected.lock().await;
+        rejected.retain(|_, r| r.until > Instant::now());
+        rejected.contains_key(node_id)
+    }
+
+    /// Peers currently shut out.
+    pub async fn rejected_peers(&self) -> Vec<RejectedPeer> {
+        let mut rejected = self.rejected.lock().await;
+        let now = Instant::now();
+        rejected.retain(|_, r| r.until > now);
+        rejected
+            .iter()
+            .map(|(node_id, r)| RejectedPeer {
+                node_id: node_id.clone(),
+                reason: r.reason.clone(),
+                seconds_left: (r.until - now).as_secs(),
+            })
+            .collect()
+    }
+
+    /// Validates a transaction against the ledger plus everything already pending, then queues it.
+    /// Returns `Ok(false)` if it was already pending, `Err` with the reason if it is invalid.
+    pub async fn submit_tx(&self, tx: LedgerTx) -> Result<bool, String> {
+        let mut mempool = self.mempool.lock().await;
+        if mempool.contains(tx.hash()) {
+            return Ok(false);
+        }
+
+        let mut view = LedgerView::new(&self.pool);
+        for pending in mempool.pending() {
+            view.apply(&self.authorities, pending).await.map_err(|e| e.to_string())?;
+        }
+        if let Some(reason) = view.apply(&self.authorities, &tx).await.map_err(|e| e.to_string())? {
+            return Err(reason);
+        }
+
+        Ok(mempool.insert(tx))
+    }
+}
+
+pub fn encode_public_key(public_key: &RsaPublicKey) -> Result<String, String> {
+    let der = public_key.to_pkcs1_der().map_err(|e| e.to_string())?;
+    Ok(STANDARD.encode(der.as_bytes()))
+}
+
+pub fn decode_public_key(public_key_b64: &str) -> Option<RsaPublicKey> {
+    let der = STANDARD.decode(public_key_b64).ok()?;
+    RsaPublicKey::from_pkcs1_der(&der).ok()
+}
+
+/// Loads the node's signing key, generating and saving one on first start.
+fn load_or_create_key(path: &str) -> Result<RsaPrivateKey, String> {
+    if Path::new(path).exists() {
+        let pem = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        return RsaPrivateKey::from_pkcs1_pem(&pem).map_err(|e| e.to_string());
+    }
+
+    let (private_key, _) = generate_keys();
+    let pem = private_key.to_pkcs1_pem(LineEnding::LF).map_err(|e| e.to_string())?;
+    std::fs::write(path, pem.as_bytes()).map_err(|e| e.to_string())?;
+    Ok(private_key)
+}
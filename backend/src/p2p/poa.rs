@@ -0,0 +1,354 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::db::entities::{
+    adopt_branch, block_headers_at_height, compute_block_hash, latest_block, store_side_block, stored_block,
+    Block, BlockHeader,
+};
+use crate::p2p::mempool::LedgerView;
+use crate::p2p::sync::broadcast_block;
+use crate::p2p::{decode_public_key, Authority, Node};
+use crate::utils::merkle::build_merkle_root;
+use crate::utils::signatures::{sign_data, verify_signature};
+
+/// Round-robin proof-of-authority: time is cut into slots of `slot_secs` and slot `s`
+/// may only be sealed by `authorities[s % n]`, so an offline authority only costs its own slots.
+pub fn leader_for_slot(authorities: &[Authority], slot: i64) -> &Authority {
+    let index = slot.rem_euclid(authorities.len() as i64) as usize;
+    &authorities[index]
+}
+
+pub fn current_slot(slot_secs: i64) -> i64 {
+    Utc::now().timestamp().div_euclid(slot_secs)
+}
+
+/// Slot a block was sealed in, derived from its RFC 3339 timestamp.
+pub fn slot_of(timestamp: &str, slot_secs: i64) -> Option<i64> {
+    let parsed = DateTime::parse_from_rfc3339(timestamp).ok()?;
+    Some(parsed.timestamp().div_euclid(slot_secs))
+}
+
+/// Height, hash and slot of the block the next one has to build on.
+pub async fn chain_tip(node: &Node) -> Result<(i64, String, i64), sqlx::Error> {
+    Ok(match latest_block(&node.pool).await? {
+        Some(tip) => {
+            let slot = slot_of(&tip.timestamp, node.config.slot_secs).unwrap_or(0);
+            (tip.height, tip.hash, slot)
+        }
+        None => (0, "GENESIS".to_string(), 0),
+    })
+}
+
+/// Outcome of offering a replicated block to the local ledger.
+#[derive(Debug, PartialEq)]
+pub enum ApplyResult {
+    /// The block extended our chain, possibly after a reorganization onto its branch.
+    Applied,
+    AlreadyKnown,
+    /// Valid, but on a branch that lost fork choice; kept as an orphaned block.
+    SideChain,
+    /// We are behind the block's height and need to sync first.
+    Gap,
+    /// The block fails the seal check, so nothing proves its producer made it.
+    Invalid(String),
+    /// The block carries a valid seal but is invalid or conflicts with our chain,
+    /// which its producer has signed for.
+    Diverged(String),
+}
+
+/// Seals pending transactions into a block on top of our tip.
+/// Returns `None` outside this node's slots or when nothing valid is pending.
+pub async fn seal_block(node: &Node) -> Result<Option<Block>, sqlx::Error> {
+    let _guard = node.ledger_lock.lock().await;
+
+    let (tip_height, previous_hash, tip_slot) = chain_tip(node).await?;
+    let slot = current_slot(node.config.slot_secs);
+    let leader = leader_for_slot(&node.authorities, slot);
+    if leader.node_id != node.config.node_id || slot < tip_slot {
+        return Ok(None);
+    }
+
+    let candidates = node.mempool.lock().await.pending().to_vec();
+    let mut view = LedgerView::new(&node.pool);
+    let mut transactions = Vec::new();
+    let mut dropped = HashSet::new();
+    for tx in candidates {
+        if transactions.len() >= node.config.block_max_txs {
+            break;
+        }
+        match view.apply(&node.authorities, &tx).await? {
+            None => transactions.push(tx),
+            Some(reason) => {
+                eprintln!("Dropping pending transaction: {}", reason);
+                dropped.insert(tx.hash().to_string());
+            }
+        }
+    }
+    node.mempool.lock().await.remove(&dropped);
+
+    if transactions.is_empty() {
+        return Ok(None);
+    }
+
+    let height = tip_height + 1;
+    let timestamp = Utc::now().to_rfc3339();
+    let merkle_root = build_merkle_root(transactions.iter().map(|tx| tx.hash().to_string()).collect());
+    let hash = compute_block_hash(height, &previous_hash, &merkle_root, &timestamp, &node.config.node_id);
+    let signature = STANDARD.encode(sign_data(&node.private_key, hash.as_bytes()));
+
+    let block = Block {
+        header: BlockHeader {
+            height,
+            hash,
+            previous_hash,
+            merkle_root,
+            timestamp,
+            producer: node.config.node_id.clone(),
+            signature,
+            tx_count: transactions.len() as i64,
+        },
+        transactions,
+    };
+
+    adopt_branch(&node.pool, tip_height, std::slice::from_ref(&block)).await?;
+    let sealed: HashSet<String> = block.transactions.iter().map(|tx| tx.hash().to_string()).collect();
+    node.mempool.lock().await.remove(&sealed);
+
+    Ok(Some(block))
+}
+
+/// Checks a block's header: sealed by the slot's authority, signed, and committing to its transactions.
+pub fn check_seal(authorities: &[Authority], slot_secs: i64, block: &Block) -> Result<(), String> {
+    let header = &block.header;
+    let slot = slot_of(&header.timestamp, slot_secs)
+        .ok_or_else(|| format!("Unreadable timestamp on block {}", header.height))?;
+    if slot > current_slot(slot_secs) + 1 {
+        return Err(format!("Block {} is sealed in a future slot", header.height));
+    }
+
+    let leader = leader_for_slot(authorities, slot);
+    if header.producer != leader.node_id {
+        return Err(format!(
+            "Block {} sealed by {} in slot {}, expected {}",
+            header.height, header.producer, slot, leader.node_id
+        ));
+    }
+
+    let merkle_root = build_merkle_root(block.transactions.iter().map(|tx| tx.hash().to_string()).collect());
+    if merkle_root != header.merkle_root || header.tx_count != block.transactions.len() as i64 {
+        return Err(format!("Block {} does not match its Merkle root", header.height));
+    }
+
+    let recomputed = compute_block_hash(
+        header.height,
+        &header.previous_hash,
+        &header.merkle_root,
+        &header.timestamp,
+        &header.producer,
+    );
+    if recomputed != header.hash {
+        return Err(format!("Hash mismatch on block {}", header.height));
+    }
+
+    let public_key = decode_public_key(&leader.public_key)
+        .ok_or_else(|| format!("Unreadable public key for {}", leader.node_id))?;
+    let signature = STANDARD.decode(&header.signature).unwrap_or_default();
+    if !verify_signature(&public_key, header.hash.as_bytes(), &signature) {
+        return Err(format!("Bad producer signature on block {}", header.height));
+    }
+
+    Ok(())
+}
+
+/// Deterministic fork choice: the longer chain wins, and between equally long
+/// chains the one whose tip hash sorts lowest.
+pub fn prefers(candidate: &BlockHeader, tip_height: i64, tip_hash: &str) -> bool {
+    candidate.height > tip_height || (candidate.height == tip_height && candidate.hash.as_str() < tip_hash)
+}
+
+/// Validates a replicated block and stores it. A block on a competing branch is kept as
+/// orphaned, and if its branch wins fork choice the ledger is reorganized onto it.
+pub async fn apply_block(node: &Node, block: &Block) -> Result<ApplyResult, sqlx::Error> {
+    let _guard = node.ledger_lock.lock().await;
+    let header = &block.header;
+
+    if let Err(reason) = check_seal(&node.authorities, node.config.slot_secs, block) {
+        return Ok(ApplyResult::Invalid(reason));
+    }
+
+    if stored_block(&node.pool, &header.hash).await?.is_some() {
+        return Ok(ApplyResult::AlreadyKnown);
+    }
+
+    let slot = slot_of(&header.timestamp, node.config.slot_secs).unwrap_or(0);
+    for other in block_headers_at_height(&node.pool, header.height).await? {
+        if other.producer == header.producer && slot_of(&other.timestamp, node.config.slot_secs) == Some(slot) {
+            return Ok(ApplyResult::Diverged(format!(
+                "{} sealed two blocks at height {} in slot {} ({} and {})",
+                header.producer, header.height, slot, other.hash, header.hash
+            )));
+        }
+    }
+
+    let parent = if header.previous_hash == "GENESIS" {
+        None
+    } else {
+        match stored_block(&node.pool, &header.previous_hash).await? {
+            Some(parent) => Some(parent),
+            None => return Ok(ApplyResult::Gap),
+        }
+    };
+    if let Some((parent, _)) = &parent {
+        if header.height != parent.header.height + 1 {
+            return Ok(ApplyResult::Diverged(format!("Block {} has a bad height for its parent", header.height)));
+        }
+        if slot < slot_of(&parent.header.timestamp, node.config.slot_secs).unwrap_or(0) {
+            return Ok(ApplyResult::Diverged(format!("Block {} goes back in time", header.height)));
+        }
+    } else if header.height != 1 {
+        return Ok(ApplyResult::Diverged(format!("Block {} claims to start the chain", header.height)));
+    }
+
+    // Walk back to the first canonical ancestor, collecting the branch this block extends.
+    let mut branch = vec![block.clone()];
+    let mut next = parent;
+    let fork_height = loop {
+        match next {
+            None => break 0,
+            Some((ancestor, status)) if status == "canonical" => break ancestor.header.height,
+            Some((ancestor, _)) => {
+                next = stored_block(&node.pool, &ancestor.header.previous_hash).await?;
+                if next.is_none() && ancestor.header.previous_hash != "GENESIS" {
+                    return Ok(ApplyResult::Gap);
+                }
+                branch.push(ancestor);
+            }
+        }
+    };
+    branch.reverse();
+
+    let (tip_height, tip_hash, _) = chain_tip(node).await?;
+    if !prefers(header, tip_height, &tip_hash) {
+        store_side_block(&node.pool, block).await?;
+        return Ok(ApplyResult::SideChain);
+    }
+
+    let mut view = LedgerView::at_height(&node.pool, fork_height);
+    for branch_block in &branch {
+        for tx in &branch_block.transactions {
+            if let Some(reason) = view.apply(&node.authorities, tx).await? {
+                return Ok(ApplyResult::Diverged(format!("Block {}: {}", branch_block.header.height, reason)));
+            }
+        }
+    }
+
+    let orphaned = adopt_branch(&node.pool, fork_height, &branch).await?;
+    if !orphaned.is_empty() {
+        println!(
+            "🔀 Reorganized at height {}: {} block(s) orphaned, new tip {} at height {}",
+            fork_height, orphaned.len(), header.hash, header.height
+        );
+    }
+
+    let sealed: HashSet<String> = branch
+        .iter()
+        .flat_map(|b| b.transactions.iter().map(|tx| tx.hash().to_string()))
+        .collect();
+    let mut mempool = node.mempool.lock().await;
+    mempool.remove(&sealed);
+    // Transactions only the losing branch had go back to the mempool; the next
+    // sealer drops any that no longer fit the new chain.
+    for tx in orphaned.into_iter().flat_map(|b| b.transactions) {
+        if !sealed.contains(tx.hash()) {
+            mempool.insert(tx);
+        }
+    }
+
+    Ok(ApplyResult::Applied)
+}
+
+/// Seals a block whenever this node holds the slot and the mempool is full enough or old enough.
+pub fn spawn_block_producer(node: Arc<Node>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(250));
+        let max_wait = Duration::from_secs(node.config.block_interval_secs);
+        loop {
+            interval.tick().await;
+
+            let due = {
+                let mempool = node.mempool.lock().await;
+                mempool.len() >= node.config.block_max_txs
+                    || mempool.oldest_age().is_some_and(|age| age >= max_wait)
+            };
+            if !due {
+                continue;
+            }
+
+            match seal_block(&node).await {
+                Ok(Some(block)) => broadcast_block(&node, &block).await,
+                Ok(None) => {}
+                Err(e) => eprintln!("Sealing block failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(height: i64, hash: &str) -> BlockHeader {
+        BlockHeader {
+            height,
+            hash: hash.to_string(),
+            previous_hash: "GENESIS".to_string(),
+            merkle_root: "EMPTY_TREE".to_string(),
+            timestamp: "2024-01-01T00:00:00+00:00".to_string(),
+            producer: "node-1".to_string(),
+            signature: String::new(),
+            tx_count: 0,
+        }
+    }
+
+    fn authorities(count: usize) -> Vec<Authority> {
+        (1..=count)
+            .map(|i| Authority {
+                node_id: format!("node-{}", i),
+                organization: format!("Org {}", i),
+                url: String::new(),
+                public_key: String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn slots_rotate_through_the_authorities() {
+        let authorities = authorities(3);
+        let leaders: Vec<&str> = (0..7).map(|slot| leader_for_slot(&authorities, slot).node_id.as_str()).collect();
+        assert_eq!(leaders, ["node-1", "node-2", "node-3", "node-1", "node-2", "node-3", "node-1"]);
+        assert_eq!(leader_for_slot(&authorities, -1).node_id, "node-3");
+    }
+
+    #[test]
+    fn a_block_is_in_the_slot_its_timestamp_falls_in() {
+        assert_eq!(slot_of("1970-01-01T00:00:09+00:00", 5), Some(1));
+        assert_eq!(slot_of("1970-01-01T00:00:10+00:00", 5), Some(2));
+        assert_eq!(slot_of("yesterday", 5), None);
+    }
+
+    #[test]
+    fn a_longer_chain_wins_whatever_its_hash() {
+        assert!(prefers(&header(4, "ff"), 3, "00"));
+        assert!(!prefers(&header(2, "00"), 3, "ff"));
+    }
+
+    #[test]
+    fn equally_long_chains_go_to_the_lowest_tip_hash() {
+        assert!(prefers(&header(3, "0a"), 3, "0b"));
+        assert!(!prefers(&header(3, "0c"), 3, "0b"));
+        assert!(!prefers(&header(3, "0b"), 3, "0b"));
+    }
+}
@@ -0,0 +1,224 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::db::entities::{latest_block, Block, LedgerTx};
+use crate::p2p::poa::{apply_block, ApplyResult};
+use crate::p2p::{decode_public_key, Authority, Node, RejectedPeer};
+use crate::utils::signatures::{sign_data, verify_signature};
+
+/// Max blocks served per `/api/p2p/blocks` page.
+pub const PAGE_SIZE: i64 = 50;
+
+/// How far a message's `sent_at` may be from our clock, either way.
+const MESSAGE_MAX_AGE_SECS: i64 = 120;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NodeStatus {
+    pub node_id: String,
+    pub organization: String,
+    pub height: i64,
+    pub tip_hash: String,
+    pub public_key: String,
+    pub rejected_peers: Vec<RejectedPeer>,
+}
+
+/// Who sent a peer message to whom and when, signed with the sender's node key over that
+/// and the digest of what it carries, so a message cannot be sent in another authority's name.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Envelope {
+    pub from: String,
+    pub to: String,
+    pub sent_at: String,
+    pub signature: String,
+}
+
+impl Envelope {
+    fn signed_data(kind: &str, from: &str, to: &str, sent_at: &str, digest: &str) -> String {
+        format!("{}|{}|{}|{}|{}", kind, from, to, sent_at, digest)
+    }
+
+    /// Signs a message of `kind` carrying `digest` from this node to `to`.
+    pub fn seal(node: &Node, to: &str, kind: &str, digest: &str) -> Self {
+        let from = node.config.node_id.clone();
+        let sent_at = Utc::now().to_rfc3339();
+        let data = Self::signed_data(kind, &from, to, &sent_at, digest);
+        let signature = STANDARD.encode(sign_data(&node.private_key, data.as_bytes()));
+        Self { from, to: to.to_string(), sent_at, signature }
+    }
+
+    /// The authority that sent this message, once its signature, addressee and age check out.
+    pub fn open<'a>(&self, node: &'a Node, kind: &str, digest: &str) -> Result<&'a Authority, String> {
+        let sender = node
+            .authority(&self.from)
+            .ok_or_else(|| format!("{} is not an authority", self.from))?;
+        if self.to != node.config.node_id {
+            return Err(format!("Message is addressed to {}", self.to));
+        }
+        let sent_at = DateTime::parse_from_rfc3339(&self.sent_at).map_err(|_| "Unreadable sent_at".to_string())?;
+        if (Utc::now() - sent_at.with_timezone(&Utc)).num_seconds().abs() > MESSAGE_MAX_AGE_SECS {
+            return Err(format!("Message from {} is stale or from the future", self.from));
+        }
+        let public_key = decode_public_key(&sender.public_key)
+            .ok_or_else(|| format!("Unreadable public key for {}", sender.node_id))?;
+        let signature = STANDARD.decode(&self.signature).unwrap_or_default();
+        let data = Self::signed_data(kind, &self.from, &self.to, &self.sent_at, digest);
+        if !verify_signature(&public_key, data.as_bytes(), &signature) {
+            return Err(format!("Bad signature on message from {}", self.from));
+        }
+        Ok(sender)
+    }
+}
+
+/// A block pushed from one authority to another.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BlockMessage {
+    #[serde(flatten)]
+    pub envelope: Envelope,
+    pub block: Block,
+}
+
+/// A pending transaction gossiped so whichever authority holds the next slot can seal it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TxMessage {
+    #[serde(flatten)]
+    pub envelope: Envelope,
+    pub tx: LedgerTx,
+}
+
+pub async fn local_status(node: &Node) -> Result<NodeStatus, sqlx::Error> {
+    let tip = latest_block(&node.pool).await?;
+    Ok(NodeStatus {
+        node_id: node.config.node_id.clone(),
+        organization: node.config.organization.clone(),
+        height: tip.as_ref().map(|t| t.height).unwrap_or(0),
+        tip_hash: tip.map(|t| t.hash).unwrap_or_else(|| "GENESIS".to_string()),
+        public_key: node.public_key_b64.clone(),
+        rejected_peers: node.rejected_peers().await,
+    })
+}
+
+/// Pushes a freshly sealed block to every peer we still trust.
+pub async fn broadcast_block(node: &Node, block: &Block) {
+    post_to_peers(node, "/api/p2p/blocks", "block", &block.header.hash, |envelope| BlockMessage {
+        envelope,
+        block: block.clone(),
+    })
+    .await;
+}
+
+pub async fn broadcast_tx(node: &Node, tx: &LedgerTx) {
+    post_to_peers(node, "/api/p2p/transactions", "tx", tx.hash(), |envelope| TxMessage {
+        envelope,
+        tx: tx.clone(),
+    })
+    .await;
+}
+
+/// Posts to each peer the message `build` wraps around an envelope addressed to it. `kind`
+/// is signed too, so a block's envelope cannot be replayed as a transaction's.
+async fn post_to_peers<T: Serialize>(node: &Node, path: &str, kind: &str, digest: &str, build: impl Fn(Envelope) -> T) {
+    for peer in node.peers() {
+        if node.is_rejected(&peer.node_id).await {
+            continue;
+        }
+        let message = build(Envelope::seal(node, &peer.node_id, kind, digest));
+        let url = format!("{}{}", peer.url, path);
+        if let Err(e) = node.http.post(&url).json(&message).send().await {
+            eprintln!("Sending {} to {} failed: {}", path, peer.node_id, e);
+        }
+    }
+}
+
+/// Pulls blocks we are missing from one peer, following its branch if it forks from ours.
+/// A block its producer provably signed but that conflicts with our chain gets the producer
+/// rejected; the peer itself is not blamed, since its answers are not signed.
+pub async fn sync_from_peer(node: &Node, peer: &Authority) -> Result<(), String> {
+    let status: NodeStatus = node
+        .http
+        .get(format!("{}/api/p2p/status", peer.url))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if status.node_id != peer.node_id {
+        return Err(format!("{} answered as {}", peer.url, status.node_id));
+    }
+
+    let local_height = latest_block(&node.pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .map(|t| t.height)
+        .unwrap_or(0);
+
+    // Start at our own tip so a competing branch is seen even when the peer isn't ahead.
+    let mut from = local_height.max(1);
+    'pages: loop {
+        let page: Vec<Block> = node
+            .http
+            .get(format!("{}/api/p2p/blocks?from={}", peer.url, from))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if page.is_empty() {
+            return Ok(());
+        }
+
+        for block in &page {
+            match apply_block(node, block).await.map_err(|e| e.to_string())? {
+                ApplyResult::Applied | ApplyResult::AlreadyKnown | ApplyResult::SideChain => {}
+                ApplyResult::Gap if from > 1 => {
+                    // The peer's chain forks off ours further back; step back to find the fork point.
+                    from = (from - PAGE_SIZE).max(1);
+                    continue 'pages;
+                }
+                ApplyResult::Gap => return Ok(()),
+                ApplyResult::Invalid(reason) => return Err(reason),
+                ApplyResult::Diverged(reason) => {
+                    node.reject_peer(&block.header.producer, &reason).await;
+                    return Ok(());
+                }
+            }
+        }
+
+        if (page.len() as i64) < PAGE_SIZE {
+            return Ok(());
+        }
+        from = page.last().map(|b| b.header.height + 1).unwrap_or(from);
+    }
+}
+
+pub async fn sync_with_peers(node: &Node) {
+    for peer in node.peers() {
+        if node.is_rejected(&peer.node_id).await {
+            continue;
+        }
+        if let Err(e) = sync_from_peer(node, peer).await {
+            eprintln!("Sync with {} failed: {}", peer.node_id, e);
+        }
+    }
+}
+
+/// Background catch-up so a node that was offline converges on the shared chain.
+pub fn spawn_sync_loop(node: Arc<Node>) {
+    if node.peers().next().is_none() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(node.config.sync_interval_secs));
+        loop {
+            interval.tick().await;
+            sync_with_peers(&node).await;
+        }
+    });
+}
@@ -0,0 +1,148 @@
+use axum::{
+    middleware,
+    extract::{Extension, Json, Query, State},
+    routing::{get, post},
+    http::StatusCode,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::auth::access_log::{self, AccessLogVerification};
+use crate::auth::rbac::{authorize, Action, Role};
+use crate::auth::throttle::unlock_account;
+use crate::auth::Auth;
+use crate::db::{access_log, auth_events, find_user_by_email, revoke_user_sessions, update_user_role};
+use crate::models::{AccessLogEntry, AccessLogFilter, ApiResponse, AuthEventFilter, AuthEventRecord};
+use crate::db::entities::{chain_reorgs, find_forks, latest_block, orphaned_blocks, ChainReorg, Fork, ForkBlock};
+use crate::p2p::Node;
+
+#[derive(Serialize)]
+pub struct ForkReport {
+    pub canonical_height: i64,
+    pub canonical_tip: String,
+    pub forks: Vec<Fork>,
+    pub orphaned: Vec<ForkBlock>,
+    pub reorgs: Vec<ChainReorg>,
+}
+
+#[derive(Deserialize)]
+pub struct RoleChange {
+    pub email: String,
+    pub role: String,
+}
+
+#[derive(Deserialize)]
+pub struct UnlockRequest {
+    pub email: String,
+}
+
+#[derive(Serialize)]
+pub struct RoleChangeResponse {
+    pub message: String,
+    pub revoked_sessions: u64,
+}
+
+// GET /api/admin/forks
+async fn get_forks(
+    State(node): State<Arc<Node>>,
+) -> Result<Json<ForkReport>, (StatusCode, String)> {
+    let internal = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+
+    let tip = latest_block(&node.pool).await.map_err(internal)?;
+    Ok(Json(ForkReport {
+        canonical_height: tip.as_ref().map(|t| t.height).unwrap_or(0),
+        canonical_tip: tip.map(|t| t.hash).unwrap_or_else(|| "GENESIS".to_string()),
+        forks: find_forks(&node.pool).await.map_err(internal)?,
+        orphaned: orphaned_blocks(&node.pool).await.map_err(internal)?,
+        reorgs: chain_reorgs(&node.pool).await.map_err(internal)?,
+    }))
+}
+
+// POST /api/admin/users/role
+/// Grants a role, including the privileged ones signup refuses. The user's sessions are
+/// revoked so tokens carrying the old role stop working immediately.
+async fn set_user_role(
+    State(node): State<Arc<Node>>,
+    Json(change): Json<RoleChange>,
+) -> Result<Json<RoleChangeResponse>, (StatusCode, String)> {
+    let internal = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+
+    let role = change.role.parse::<Role>().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let user = find_user_by_email(&node.pool, &change.email)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))?;
+
+    update_user_role(&node.pool, &user.id, role.as_str()).await.map_err(internal)?;
+    let revoked_sessions = revoke_user_sessions(&node.pool, &user.id).await.map_err(internal)?;
+
+    Ok(Json(RoleChangeResponse {
+        message: format!("{} is now {}", user.username, role.as_str()),
+        revoked_sessions,
+    }))
+}
+
+// POST /api/admin/users/unlock
+/// Lifts a login lockout on an account and forgets its failed attempts.
+async fn unlock_user(
+    Extension(auth): Extension<Arc<Auth>>,
+    Json(request): Json<UnlockRequest>,
+) -> Result<Json<ApiResponse>, (StatusCode, String)> {
+    let message = if unlock_account(&auth, &request.email).await? {
+        format!("{} unlocked", request.email)
+    } else {
+        format!("{} had no failed logins", request.email)
+    };
+    Ok(Json(ApiResponse { message }))
+}
+
+// GET /api/admin/auth-events?event=&user_id=&email=&ip=&since=&before_id=&limit=
+/// The auth event log, newest first.
+async fn get_auth_events(
+    State(node): State<Arc<Node>>,
+    Query(filter): Query<AuthEventFilter>,
+) -> Result<Json<Vec<AuthEventRecord>>, (StatusCode, String)> {
+    auth_events(&node.pool, &filter)
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+// GET /api/admin/regulator-access?user_id=&since=&before_id=&limit=
+/// Every request regulators made, newest first.
+async fn get_regulator_access(
+    State(node): State<Arc<Node>>,
+    Query(filter): Query<AccessLogFilter>,
+) -> Result<Json<Vec<AccessLogEntry>>, (StatusCode, String)> {
+    access_log(&node.pool, &filter)
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+// GET /api/admin/regulator-access/verify
+/// Checks the regulator access log's hash chain from its first entry.
+async fn verify_regulator_access(
+    Extension(auth): Extension<Arc<Auth>>,
+) -> Result<Json<AccessLogVerification>, (StatusCode, String)> {
+    access_log::verify(&auth)
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+pub fn admin_routes(node: Arc<Node>) -> Router {
+    let guard = |action: Action| middleware::from_fn_with_state(action, authorize);
+    Router::new()
+        .route("/api/admin/forks", get(get_forks).route_layer(guard(Action::InspectChain)))
+        .route("/api/admin/users/role", post(set_user_role).route_layer(guard(Action::ManageUsers)))
+        .route("/api/admin/users/unlock", post(unlock_user).route_layer(guard(Action::ManageUsers)))
+        .route("/api/admin/auth-events", get(get_auth_events).route_layer(guard(Action::ViewAuthEvents)))
+        .route("/api/admin/regulator-access", get(get_regulator_access).route_layer(guard(Action::ViewAuthEvents)))
+        .route(
+            "/api/admin/regulator-access/verify",
+            get(verify_regulator_access).route_layer(guard(Action::ViewAuthEvents)),
+        )
+        .with_state(node)
+}
@@ -0,0 +1,352 @@
+use axum::{
+    middleware,
+    extract::{Json, Path, Query, State},
+    routing::{get, post},
+    http::StatusCode,
+    Router,
+};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::allocation::{self, allocate, AllocationVerification, Policy};
+use crate::auth::rbac::{authorize, Action, Role};
+use crate::auth::AuthUser;
+use crate::db::entities::{
+    allocation_lines, dispensed_since, find_organization, find_stock_allocation, medicine_key, open_demand, product_matches,
+    stock_allocations, supply_batches, AllocationLine, DemandClaim, StockAllocation,
+};
+use crate::licensing::require_active;
+
+type ApiError = (StatusCode, String);
+
+fn internal(e: sqlx::Error) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// Demand from organizations that have not recorded a site.
+const NO_REGION: &str = "unassigned";
+
+#[derive(Deserialize)]
+pub struct ShortageQuery {
+    /// Only products whose name starts with this, compared as in demand matching.
+    #[serde(default)]
+    pub medicine_name: Option<String>,
+    #[serde(default)]
+    pub region: Option<String>,
+    /// Leave out products and regions where supply meets demand.
+    #[serde(default)]
+    pub only_shortages: bool,
+}
+
+#[derive(Serialize)]
+pub struct RegionBalance {
+    pub region: String,
+    pub demand: i64,
+    pub supply: i64,
+    /// `demand - supply` when positive.
+    pub shortfall: i64,
+    pub shortage: bool,
+}
+
+#[derive(Serialize)]
+pub struct ProductShortage {
+    pub medicine_name: String,
+    pub strength: Option<String>,
+    pub demand: i64,
+    pub supply: i64,
+    pub shortfall: i64,
+    pub shortage: bool,
+    /// Open demand requests for the product.
+    pub requests: usize,
+    pub regions: Vec<RegionBalance>,
+}
+
+#[derive(Serialize)]
+pub struct ShortageReport {
+    pub products: Vec<ProductShortage>,
+    pub generated_at: String,
+}
+
+#[derive(Deserialize)]
+pub struct AllocationRequest {
+    pub medicine_name: String,
+    #[serde(default)]
+    pub strength: Option<String>,
+    /// `pro_rata`, `critical_care` or `historical_usage`.
+    pub policy: String,
+    /// Only demand from organizations in this region.
+    #[serde(default)]
+    pub region: Option<String>,
+    /// Units to share out; all the company's available stock of the product by default.
+    #[serde(default)]
+    pub quantity: Option<i64>,
+    /// Usage window for `historical_usage`, in days; 90 by default.
+    #[serde(default)]
+    pub usage_days: Option<i64>,
+    #[serde(default)]
+    pub note: Option<String>,
+    /// Work the allocation out without recording it.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize)]
+pub struct AllocationDetail {
+    #[serde(flatten)]
+    pub allocation: StockAllocation,
+    pub lines: Vec<AllocationLine>,
+}
+
+fn trimmed(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+fn balance(region: String, demand: i64, supply: i64) -> RegionBalance {
+    RegionBalance {
+        region,
+        demand,
+        supply,
+        shortfall: (demand - supply).max(0),
+        shortage: demand > supply,
+    }
+}
+
+// GET /api/shortages
+/// Open demand against available, unexpired company stock per product and region. Products are
+/// those with open demand, grouped as in demand matching; a region's supply is the stock held by
+/// companies whose site is in it.
+async fn get_shortages(
+    State(pool): State<Arc<SqlitePool>>,
+    Query(query): Query<ShortageQuery>,
+) -> Result<Json<ShortageReport>, ApiError> {
+    let region_filter = trimmed(query.region);
+    let name_filter = trimmed(query.medicine_name).map(|n| medicine_key(&n));
+
+    let mut products: BTreeMap<String, Vec<DemandClaim>> = BTreeMap::new();
+    for claim in open_demand(&pool).await.map_err(internal)? {
+        let key = format!("{}{}", medicine_key(&claim.medicine_name), claim.strength.as_deref().map(medicine_key).unwrap_or_default());
+        if name_filter.as_ref().is_some_and(|name| !key.starts_with(name.as_str())) {
+            continue;
+        }
+        products.entry(key).or_default().push(claim);
+    }
+
+    let today = Utc::now().date_naive().to_string();
+    let mut report = Vec::with_capacity(products.len());
+    for claims in products.into_values() {
+        let (medicine_name, strength) = (claims[0].medicine_name.clone(), claims[0].strength.clone());
+        let mut regions: BTreeMap<String, (i64, i64)> = BTreeMap::new();
+        for claim in &claims {
+            regions.entry(claim.region.clone().unwrap_or_else(|| NO_REGION.to_string())).or_default().0 += claim.quantity;
+        }
+        for batch in supply_batches(&pool, &medicine_name, strength.as_deref(), &today).await.map_err(internal)? {
+            regions.entry(batch.region.unwrap_or_else(|| NO_REGION.to_string())).or_default().1 += batch.available;
+        }
+        if let Some(region) = &region_filter {
+            regions.retain(|name, _| name.eq_ignore_ascii_case(region));
+        }
+
+        let demand = regions.values().map(|(d, _)| d).sum();
+        let supply = regions.values().map(|(_, s)| s).sum();
+        let mut regions: Vec<RegionBalance> = regions
+            .into_iter()
+            .map(|(region, (demand, supply))| balance(region, demand, supply))
+            .collect();
+        if query.only_shortages {
+            regions.retain(|r| r.shortage);
+        }
+        // A product short overall is short in at least one region
+        if demand == 0 || (query.only_shortages && regions.is_empty()) {
+            continue;
+        }
+        let total = balance(String::new(), demand, supply);
+        report.push(ProductShortage {
+            medicine_name,
+            strength,
+            demand,
+            supply,
+            shortfall: total.shortfall,
+            shortage: total.shortage,
+            requests: claims.len(),
+            regions,
+        });
+    }
+    report.sort_by(|a, b| b.shortfall.cmp(&a.shortfall).then(a.medicine_name.cmp(&b.medicine_name)));
+
+    Ok(Json(ShortageReport {
+        products: report,
+        generated_at: Utc::now().to_rfc3339(),
+    }))
+}
+
+// POST /api/allocations
+/// Shares the caller's company's stock of a product among the open demand for it under a policy,
+/// and records the result in the append-only allocation log unless `dry_run` is set.
+async fn create_allocation(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Json(request): Json<AllocationRequest>,
+) -> Result<Json<AllocationDetail>, ApiError> {
+    let company = match user.organization.as_deref() {
+        Some(id) => find_organization(&pool, id).await.map_err(internal)?,
+        None => None,
+    }
+    .filter(|o| o.organization_type == "company")
+    .ok_or((StatusCode::FORBIDDEN, "Register or join a company to allocate stock".to_string()))?;
+    require_active(&pool, &company.id).await?;
+
+    let policy: Policy = request.policy.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let medicine_name = request.medicine_name.trim().to_string();
+    if medicine_name.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "medicine_name is required".to_string()));
+    }
+    let strength = trimmed(request.strength);
+    let region = trimmed(request.region);
+    let usage_days = match policy {
+        Policy::HistoricalUsage => Some(request.usage_days.unwrap_or(90)),
+        _ => None,
+    };
+    if usage_days.is_some_and(|days| !(1..=3650).contains(&days)) {
+        return Err((StatusCode::BAD_REQUEST, "usage_days must be between 1 and 3650".to_string()));
+    }
+
+    let today = Utc::now().date_naive().to_string();
+    let available_quantity: i64 = supply_batches(&pool, &medicine_name, strength.as_deref(), &today)
+        .await
+        .map_err(internal)?
+        .iter()
+        .filter(|b| b.organization_id == company.id)
+        .map(|b| b.available)
+        .sum();
+    let quantity = request.quantity.unwrap_or(available_quantity);
+    if quantity <= 0 || quantity > available_quantity {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("{} has {} available to allocate", company.name, available_quantity),
+        ));
+    }
+
+    let key = format!("{}{}", medicine_key(&medicine_name), strength.as_deref().map(medicine_key).unwrap_or_default());
+    let claims: Vec<DemandClaim> = open_demand(&pool)
+        .await
+        .map_err(internal)?
+        .into_iter()
+        .filter(|c| c.organization != company.id)
+        .filter(|c| format!("{}{}", medicine_key(&c.medicine_name), c.strength.as_deref().map(medicine_key).unwrap_or_default()) == key)
+        .filter(|c| region.as_ref().is_none_or(|r| c.region.as_deref().is_some_and(|cr| cr.eq_ignore_ascii_case(r))))
+        .collect();
+    if claims.is_empty() {
+        return Err((StatusCode::NOT_FOUND, "No open demand for this product".to_string()));
+    }
+
+    let usage: Vec<i64> = match usage_days {
+        Some(days) => {
+            let since = (Utc::now() - Duration::days(days)).to_rfc3339();
+            let dispensed = dispensed_since(&pool, &since).await.map_err(internal)?;
+            claims
+                .iter()
+                .map(|c| {
+                    dispensed
+                        .iter()
+                        .filter(|(organization, batch_medicine, _)| {
+                            *organization == c.organization && product_matches(batch_medicine, &medicine_name, strength.as_deref())
+                        })
+                        .map(|(_, _, units)| units)
+                        .sum()
+                })
+                .collect()
+        }
+        None => Vec::new(),
+    };
+
+    let mut lines = allocate(policy, quantity, &claims, &usage);
+    lines.sort_by_key(|l| l.demand_id);
+    let allocation = StockAllocation {
+        id: 0,
+        organization: company.id,
+        medicine_name,
+        strength,
+        region,
+        policy: policy.to_string(),
+        usage_days,
+        available_quantity: quantity,
+        requested_quantity: claims.iter().map(|c| c.quantity).sum(),
+        allocated_quantity: lines.iter().map(|l| l.allocated_quantity).sum(),
+        note: trimmed(request.note),
+        created_by: user.user_id,
+        created_at: Utc::now().to_rfc3339(),
+        previous_hash: String::new(),
+        hash: String::new(),
+    };
+    if request.dry_run {
+        return Ok(Json(AllocationDetail { allocation, lines }));
+    }
+
+    let allocation = allocation::record(&pool, allocation, &lines).await.map_err(internal)?;
+    Ok(Json(AllocationDetail { allocation, lines }))
+}
+
+/// Regulators and admins see every allocation; others those their organization made or shares in.
+fn visible_to(user: &AuthUser) -> Result<Option<&str>, ApiError> {
+    match user.role.parse::<Role>() {
+        Ok(Role::Regulator | Role::Admin) => Ok(None),
+        _ => user
+            .organization
+            .as_deref()
+            .map(Some)
+            .ok_or((StatusCode::FORBIDDEN, "Register or join an organization to see its allocations".to_string())),
+    }
+}
+
+// GET /api/allocations
+async fn list_allocations(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+) -> Result<Json<Vec<StockAllocation>>, ApiError> {
+    stock_allocations(&pool, visible_to(&user)?)
+        .await
+        .map(Json)
+        .map_err(internal)
+}
+
+// GET /api/allocations/:id
+async fn get_allocation(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Path(id): Path<i64>,
+) -> Result<Json<AllocationDetail>, ApiError> {
+    let not_found = || (StatusCode::NOT_FOUND, "Allocation not found".to_string());
+    let allocation = find_stock_allocation(&pool, id).await.map_err(internal)?.ok_or_else(not_found)?;
+    let lines = allocation_lines(&pool, id).await.map_err(internal)?;
+    if let Some(organization) = visible_to(&user)?
+        && allocation.organization != organization
+        && !lines.iter().any(|l| l.organization == organization)
+    {
+        return Err(not_found());
+    }
+    Ok(Json(AllocationDetail { allocation, lines }))
+}
+
+// GET /api/allocations/verify
+/// Re-checks the allocation log's hash chain.
+async fn verify_allocations(State(pool): State<Arc<SqlitePool>>) -> Result<Json<AllocationVerification>, ApiError> {
+    allocation::verify(&pool).await.map(Json).map_err(internal)
+}
+
+pub fn allocation_routes(pool: Arc<SqlitePool>) -> Router {
+    let guard = |action: Action| middleware::from_fn_with_state(action, authorize);
+    Router::new()
+        .route("/api/shortages", get(get_shortages).route_layer(guard(Action::ViewShortages)))
+        .route(
+            "/api/allocations",
+            get(list_allocations)
+                .route_layer(guard(Action::ViewShortages))
+                .merge(post(create_allocation).route_layer(guard(Action::AllocateStock))),
+        )
+        .route("/api/allocations/verify", get(verify_allocations).route_layer(guard(Action::ViewShortages)))
+        .route("/api/allocations/:id", get(get_allocation).route_layer(guard(Action::ViewShortages)))
+        .with_state(pool)
+}
@@ -1,30 +1,168 @@
 use axum::{
-    extract::State,
-    routing::post,
+    extract::{ConnectInfo, Query, State},
+    http::{header::RETRY_AFTER, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
     Json, Router,
 };
-use std::sync::Arc;
-use sqlx::SqlitePool;
-use crate::models::{SignupData, ApiResponse, LoginData, LoginResponse};
-use crate::db::{add_user, find_user_by_email};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use crate::auth::audit::{self, AuthEvent, EventSubject};
+use crate::auth::email::{reset_target, send_password_reset, send_verification_email, verify_email};
+use crate::auth::throttle::{self, client_ip};
+use crate::auth::tokens::{elevate_session, issue_session, refresh_session};
+use crate::auth::totp::{self, hash_recovery_code, new_recovery_codes, verify_second_factor};
+use crate::auth::rbac::Role;
+use crate::auth::{Auth, AuthUser};
+use crate::routes::orgs::join_with_invite;
+use crate::models::{SignupData, ApiResponse, LoginData, LoginResponse, User};
+use crate::db::{
+    add_user, delete_totp, enable_totp, find_totp, find_user_by_email, find_user_by_id, last_auth_event_at,
+    replace_recovery_codes, revoke_session, revoke_user_sessions, save_pending_totp, update_password_hash,
+};
+use crate::utils::password::{hash_password, verify_password, PasswordCheck};
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailParams {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    /// From the link in the reset email.
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorCode {
+    /// A current TOTP code; `disable` and `recovery-codes` also take a recovery code.
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct EnrollResponse {
+    /// Base32 secret, for typing into an authenticator by hand.
+    pub secret: String,
+    pub otpauth_uri: String,
+    /// The same URI as an SVG QR code.
+    pub qr_svg: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    /// Shown once; each works a single time in place of a TOTP code.
+    pub recovery_codes: Vec<String>,
+    /// Access token for a session that has now passed two-factor (on confirm only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
 
-pub fn create_routes(pool: Arc<SqlitePool>) -> Router {
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+}
+
+pub fn create_routes(auth: Arc<Auth>) -> Router {
     Router::new()
         .route("/api/signup", post(signup))
         .route("/api/login", post(login))
-        .with_state(pool)
+        .route("/api/token/refresh", post(refresh))
+        .route("/api/logout", post(logout))
+        .route("/api/logout/all", post(logout_all))
+        .route("/api/me", get(me))
+        .route("/api/email/verify", get(verify_email_link))
+        .route("/api/email/verify/resend", post(resend_verification))
+        .route("/api/password/forgot", post(forgot_password))
+        .route("/api/password/reset", post(reset_password))
+        .route("/api/2fa/enroll", post(enroll_two_factor))
+        .route("/api/2fa/confirm", post(confirm_two_factor))
+        .route("/api/2fa/disable", post(disable_two_factor))
+        .route("/api/2fa/recovery-codes", post(regenerate_recovery_codes))
+        .with_state(auth)
+}
+
+/// Argon2 is deliberately slow; keep it off the async workers.
+async fn hash_off_thread(password: String) -> Result<String, String> {
+    tokio::task::spawn_blocking(move || hash_password(&password))
+        .await
+        .map_err(|e| e.to_string())?
 }
 
 async fn signup(
-    State(pool): State<Arc<SqlitePool>>,
+    State(auth): State<Arc<Auth>>,
     Json(payload): Json<SignupData>,
 ) -> Json<ApiResponse> {
-    let result = add_user(&pool, &payload.username, &payload.email, &payload.password).await;
+    // Regulators and admins are appointed, not self-registered; the ADMIN_EMAIL account
+    // becomes the first admin when it verifies its address.
+    let role = match payload.role.parse::<Role>() {
+        Ok(role) if role.is_privileged() => {
+            return Json(ApiResponse {
+                message: format!("Signup failed: the {} role is granted by an admin", role.as_str()),
+            });
+        }
+        Ok(role) => role,
+        Err(e) => {
+            return Json(ApiResponse {
+                message: format!("Signup failed: {}", e),
+            });
+        }
+    };
+
+    let password_hash = match hash_off_thread(payload.password.clone()).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("Signup error: {}", e);
+            return Json(ApiResponse {
+                message: "Signup failed".to_string(),
+            });
+        }
+    };
+
+    let result = add_user(&auth.pool, &payload.username, &payload.email, &password_hash, role.as_str()).await;
+
+    // The account exists either way; a failed email can be resent once logged in.
+    if let Ok(user_id) = &result {
+        let user = User {
+            id: user_id.clone(),
+            username: payload.username.clone(),
+            email: payload.email.clone(),
+            password: password_hash,
+            role: role.as_str().to_string(),
+            email_verified_at: None,
+        };
+        if let Err((_, e)) = send_verification_email(&auth, &user).await {
+            eprintln!("Verification email error: {}", e);
+        }
+    }
 
     match result {
-        Ok(_) => Json(ApiResponse {
-            message: "User signed up successfully".to_string(),
-        }),
+        Ok(user_id) => match payload.invite_code.as_deref() {
+            Some(code) => match join_with_invite(&auth, &user_id, role.as_str(), code).await {
+                Ok(_) => Json(ApiResponse {
+                    message: "User signed up and joined the organization".to_string(),
+                }),
+                Err((_, e)) => Json(ApiResponse {
+                    message: format!("User signed up, but could not join the organization: {}", e),
+                }),
+            },
+            None => Json(ApiResponse {
+                message: "User signed up successfully".to_string(),
+            }),
+        },
         Err(e) => {
             eprintln!("Signup error: {}", e);
             Json(ApiResponse {
@@ -34,34 +172,493 @@ async fn signup(
     }
 }
 
+fn failed_login(message: &str, two_factor: Option<&str>) -> Json<LoginResponse> {
+    Json(LoginResponse {
+        token: "".to_string(),
+        user: message.to_string(),
+        role: "".to_string(),
+        refresh_token: None,
+        expires_in: None,
+        two_factor: two_factor.map(str::to_string),
+    })
+}
+
+/// The same answer for an unknown email and a wrong password, so logins do not reveal
+/// which addresses have accounts.
+const INVALID_CREDENTIALS: &str = "Invalid email or password";
+
+/// A hash to check passwords against when the email has no account.
+fn unknown_account_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| hash_password("unknown-account").unwrap_or_default())
+}
+
+/// How a login attempt ended, for throttling and the auth event log.
+enum LoginOutcome {
+    Success { user_id: String },
+    /// Wrong credentials; counts towards backoff and lockout.
+    Failure { user_id: Option<String>, reason: &'static str },
+    /// Neither: a second factor is still needed, or something broke on our side.
+    Incomplete,
+}
+
+// POST /api/login
+/// Checks the password (and second factor, when enabled) and opens a session. Repeated
+/// failures for an account or from an address are slowed down, then locked out.
 async fn login(
-    State(pool): State<Arc<SqlitePool>>,
+    State(auth): State<Arc<Auth>>,
+    peer: ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(payload): Json<LoginData>,
-) -> Json<LoginResponse> {
-    match find_user_by_email(&pool, &payload.email).await {
-        Ok(Some(user)) => {
-            if user.password == payload.password {
-                Json(LoginResponse {
-                    token: "mock-token-123".to_string(),
-                    user: user.username,
-                })
-            } else {
-                Json(LoginResponse {
-                    token: "".to_string(),
-                    user: "Invalid password".to_string(),
-                })
+) -> Response {
+    let ip = client_ip(&auth.config, &headers, &peer);
+    let subject = EventSubject {
+        user_id: None,
+        email: Some(&payload.email),
+        ip: Some(&ip),
+    };
+
+    match throttle::retry_after(&auth, &payload.email, &ip).await {
+        Ok(Some(wait)) => {
+            audit::record(&auth, AuthEvent::LoginThrottled, subject, Some(&format!("retry after {}s", wait))).await;
+            let message = format!("Too many failed logins; try again in {} seconds", wait);
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, wait.to_string())],
+                failed_login(&message, None),
+            )
+                .into_response();
+        }
+        Ok(None) => {}
+        Err((_, e)) => {
+            eprintln!("Login error: {}", e);
+            return failed_login("Login failed", None).into_response();
+        }
+    }
+
+    let (response, outcome) = attempt_login(&auth, &payload).await;
+    match outcome {
+        LoginOutcome::Success { user_id } => {
+            if let Err((_, e)) = throttle::record_success(&auth, &payload.email).await {
+                eprintln!("Login throttle error: {}", e);
+            }
+            let subject = EventSubject {
+                user_id: Some(&user_id),
+                ..subject
+            };
+            audit::record(&auth, AuthEvent::LoginSuccess, subject, None).await;
+        }
+        LoginOutcome::Failure { user_id, reason } => {
+            if let Err((_, e)) = throttle::record_failure(&auth, &payload.email, &ip, user_id.as_deref()).await {
+                eprintln!("Login throttle error: {}", e);
             }
+            let subject = EventSubject {
+                user_id: user_id.as_deref(),
+                ..subject
+            };
+            audit::record(&auth, AuthEvent::LoginFailure, subject, Some(reason)).await;
+        }
+        LoginOutcome::Incomplete => {}
+    }
+    response.into_response()
+}
+
+async fn attempt_login(auth: &Auth, payload: &LoginData) -> (Json<LoginResponse>, LoginOutcome) {
+    let pool = &auth.pool;
+    let user = match find_user_by_email(pool, &payload.email).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            let outcome = LoginOutcome::Failure {
+                user_id: None,
+                reason: "unknown email",
+            };
+            // Spend the same effort as for a real account, so timing does not tell either.
+            let candidate = payload.password.clone();
+            let _ = tokio::task::spawn_blocking(move || verify_password(unknown_account_hash(), &candidate)).await;
+            return (failed_login(INVALID_CREDENTIALS, None), outcome);
         }
-        Ok(None) => Json(LoginResponse {
-            token: "".to_string(),
-            user: "User not found".to_string(),
-        }),
         Err(e) => {
             eprintln!("Login error: {}", e);
-            Json(LoginResponse {
-                token: "".to_string(),
-                user: "Login failed".to_string(),
-            })
+            return (failed_login("Login failed", None), LoginOutcome::Incomplete);
+        }
+    };
+
+    let stored = user.password.clone();
+    let candidate = payload.password.clone();
+    let check = tokio::task::spawn_blocking(move || verify_password(&stored, &candidate))
+        .await
+        .unwrap_or(PasswordCheck::Invalid);
+
+    if check == PasswordCheck::ValidNeedsRehash {
+        // Legacy plaintext (or weaker-cost) credential: store a fresh Argon2id hash.
+        let upgraded = match hash_off_thread(payload.password.clone()).await {
+            Ok(hash) => update_password_hash(pool, &user.id, &hash).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = upgraded {
+            eprintln!("Password upgrade error: {}", e);
         }
     }
+
+    if check == PasswordCheck::Invalid {
+        let outcome = LoginOutcome::Failure {
+            user_id: Some(user.id),
+            reason: "wrong password",
+        };
+        return (failed_login(INVALID_CREDENTIALS, None), outcome);
+    }
+
+    // With two-factor enabled the password alone is not enough.
+    let enrolled = match find_totp(pool, &user.id).await {
+        Ok(totp) => totp.is_some_and(|t| t.enabled_at.is_some()),
+        Err(e) => {
+            eprintln!("Login error: {}", e);
+            return (failed_login("Login failed", None), LoginOutcome::Incomplete);
+        }
+    };
+    let mfa = if enrolled {
+        let Some(code) = payload.otp.as_deref() else {
+            return (
+                failed_login("Two-factor code required", Some("code_required")),
+                LoginOutcome::Incomplete,
+            );
+        };
+        match verify_second_factor(auth, &user.id, &user.email, code).await {
+            Ok(true) => true,
+            Ok(false) => {
+                let outcome = LoginOutcome::Failure {
+                    user_id: Some(user.id),
+                    reason: "wrong two-factor code",
+                };
+                return (failed_login("Invalid two-factor code", Some("code_required")), outcome);
+            }
+            Err((_, e)) => {
+                eprintln!("Login error: {}", e);
+                return (failed_login("Login failed", None), LoginOutcome::Incomplete);
+            }
+        }
+    } else {
+        false
+    };
+
+    match issue_session(auth, &user, mfa).await {
+        Ok(tokens) => {
+            let outcome = LoginOutcome::Success {
+                user_id: user.id.clone(),
+            };
+            let response = Json(LoginResponse {
+                token: tokens.access_token,
+                user: user.username,
+                two_factor: (!mfa && auth.config.mfa_required(&user.role)).then(|| "enroll".to_string()),
+                role: user.role,
+                refresh_token: Some(tokens.refresh_token),
+                expires_in: Some(tokens.expires_in),
+            });
+            (response, outcome)
+        }
+        Err((_, e)) => {
+            eprintln!("Login error: {}", e);
+            (failed_login("Login failed", None), LoginOutcome::Incomplete)
+        }
+    }
+}
+
+// POST /api/token/refresh
+async fn refresh(
+    State(auth): State<Arc<Auth>>,
+    peer: ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<RefreshRequest>,
+) -> Result<Json<TokenResponse>, (StatusCode, String)> {
+    let ip = client_ip(&auth.config, &headers, &peer);
+    let subject = EventSubject {
+        ip: Some(&ip),
+        ..Default::default()
+    };
+    let tokens = match refresh_session(&auth, &payload.refresh_token).await {
+        Ok(tokens) => tokens,
+        Err((status, message)) => {
+            audit::record(&auth, AuthEvent::TokenRefreshFailure, subject, Some(&message)).await;
+            return Err((status, message));
+        }
+    };
+    let subject = EventSubject {
+        user_id: Some(&tokens.user_id),
+        ..subject
+    };
+    audit::record(&auth, AuthEvent::TokenRefresh, subject, None).await;
+
+    Ok(Json(TokenResponse {
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        expires_in: tokens.expires_in,
+    }))
+}
+
+// POST /api/logout
+/// Revokes the caller's session: its refresh token and every access token issued for it.
+async fn logout(
+    State(auth): State<Arc<Auth>>,
+    user: AuthUser,
+) -> Result<Json<ApiResponse>, (StatusCode, String)> {
+    revoke_session(&auth.pool, &user.session_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(ApiResponse {
+        message: "Logged out".to_string(),
+    }))
+}
+
+// POST /api/logout/all
+/// Revokes every session of the caller, e.g. after a device is lost.
+async fn logout_all(
+    State(auth): State<Arc<Auth>>,
+    user: AuthUser,
+) -> Result<Json<ApiResponse>, (StatusCode, String)> {
+    let revoked = revoke_user_sessions(&auth.pool, &user.user_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(ApiResponse {
+        message: format!("Revoked {} session(s)", revoked),
+    }))
+}
+
+// GET /api/me
+/// The caller as their access token describes them.
+async fn me(user: AuthUser) -> Json<AuthUser> {
+    Json(user)
+}
+
+fn internal(e: impl ToString) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// The account behind a user session; API keys have no account of their own.
+async fn session_user(auth: &Auth, caller: &AuthUser) -> Result<User, (StatusCode, String)> {
+    if caller.api_key_scopes.is_some() {
+        return Err((StatusCode::FORBIDDEN, "API keys cannot manage account settings".to_string()));
+    }
+    find_user_by_id(&auth.pool, &caller.user_id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::UNAUTHORIZED, "User no longer exists".to_string()))
+}
+
+/// Stores a fresh set of recovery codes, replacing any old ones, and returns them.
+async fn issue_recovery_codes(auth: &Auth, user_id: &str) -> Result<Vec<String>, (StatusCode, String)> {
+    let codes = new_recovery_codes();
+    let hashes: Vec<String> = codes.iter().map(|c| hash_recovery_code(c)).collect();
+    replace_recovery_codes(&auth.pool, user_id, &hashes).await.map_err(internal)?;
+    Ok(codes)
+}
+
+/// Checks the caller's code against their enabled second factor.
+async fn check_code(auth: &Auth, user: &User, code: &str) -> Result<(), (StatusCode, String)> {
+    let enabled = find_totp(&auth.pool, &user.id)
+        .await
+        .map_err(internal)?
+        .is_some_and(|t| t.enabled_at.is_some());
+    if !enabled {
+        return Err((StatusCode::CONFLICT, "Two-factor authentication is not enabled".to_string()));
+    }
+    if !verify_second_factor(auth, &user.id, &user.email, code).await? {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid two-factor code".to_string()));
+    }
+    Ok(())
+}
+
+// POST /api/2fa/enroll
+/// Starts enrollment with a new secret. Nothing changes at login until it is confirmed.
+async fn enroll_two_factor(
+    State(auth): State<Arc<Auth>>,
+    caller: AuthUser,
+) -> Result<Json<EnrollResponse>, (StatusCode, String)> {
+    let user = session_user(&auth, &caller).await?;
+    let secret = totp::new_secret();
+    if !save_pending_totp(&auth.pool, &user.id, &secret).await.map_err(internal)? {
+        return Err((StatusCode::CONFLICT, "Two-factor authentication is already enabled".to_string()));
+    }
+
+    let generator = totp::build(&secret, &auth.config.issuer, &user.email)?;
+    let (otpauth_uri, qr_svg) = totp::provisioning(&generator)?;
+    Ok(Json(EnrollResponse {
+        secret,
+        otpauth_uri,
+        qr_svg,
+    }))
+}
+
+// POST /api/2fa/confirm
+/// Finishes enrollment with the first code from the authenticator. Returns the recovery
+/// codes and an access token for the current session, which now counts as two-factor.
+async fn confirm_two_factor(
+    State(auth): State<Arc<Auth>>,
+    caller: AuthUser,
+    Json(payload): Json<TwoFactorCode>,
+) -> Result<Json<RecoveryCodesResponse>, (StatusCode, String)> {
+    let user = session_user(&auth, &caller).await?;
+    let pending = find_totp(&auth.pool, &user.id).await.map_err(internal)?;
+    match pending {
+        None => return Err((StatusCode::CONFLICT, "Start with /api/2fa/enroll".to_string())),
+        Some(t) if t.enabled_at.is_some() => {
+            return Err((StatusCode::CONFLICT, "Two-factor authentication is already enabled".to_string()));
+        }
+        Some(_) => {}
+    }
+    if !verify_second_factor(&auth, &user.id, &user.email, &payload.code).await? {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid two-factor code".to_string()));
+    }
+
+    enable_totp(&auth.pool, &user.id).await.map_err(internal)?;
+    let recovery_codes = issue_recovery_codes(&auth, &user.id).await?;
+    let token = elevate_session(&auth, &caller).await?;
+    Ok(Json(RecoveryCodesResponse {
+        recovery_codes,
+        token: Some(token),
+    }))
+}
+
+// POST /api/2fa/disable
+/// Turns two-factor off. Not possible for roles the policy requires it for.
+async fn disable_two_factor(
+    State(auth): State<Arc<Auth>>,
+    caller: AuthUser,
+    Json(payload): Json<TwoFactorCode>,
+) -> Result<Json<ApiResponse>, (StatusCode, String)> {
+    let user = session_user(&auth, &caller).await?;
+    if auth.config.mfa_required(&user.role) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("The {} role requires two-factor authentication", user.role),
+        ));
+    }
+    check_code(&auth, &user, &payload.code).await?;
+
+    delete_totp(&auth.pool, &user.id).await.map_err(internal)?;
+    Ok(Json(ApiResponse {
+        message: "Two-factor authentication disabled".to_string(),
+    }))
+}
+
+// POST /api/2fa/recovery-codes
+/// Replaces the caller's recovery codes, e.g. after using some up.
+async fn regenerate_recovery_codes(
+    State(auth): State<Arc<Auth>>,
+    caller: AuthUser,
+    Json(payload): Json<TwoFactorCode>,
+) -> Result<Json<RecoveryCodesResponse>, (StatusCode, String)> {
+    let user = session_user(&auth, &caller).await?;
+    check_code(&auth, &user, &payload.code).await?;
+
+    Ok(Json(RecoveryCodesResponse {
+        recovery_codes: issue_recovery_codes(&auth, &user.id).await?,
+        token: None,
+    }))
+}
+
+// GET /api/email/verify?token=
+/// The link in the verification email.
+async fn verify_email_link(
+    State(auth): State<Arc<Auth>>,
+    Query(params): Query<VerifyEmailParams>,
+) -> Result<Json<ApiResponse>, (StatusCode, String)> {
+    let (user, changed) = verify_email(&auth, &params.token).await?;
+    if changed {
+        let subject = EventSubject {
+            user_id: Some(&user.id),
+            email: Some(&user.email),
+            ip: None,
+        };
+        audit::record(&auth, AuthEvent::EmailVerified, subject, None).await;
+    }
+    let message = if user.role == Role::Admin.as_str() && changed {
+        format!("{} is verified and holds the admin role; log in again", user.email)
+    } else {
+        format!("{} is verified", user.email)
+    };
+    Ok(Json(ApiResponse { message }))
+}
+
+// POST /api/email/verify/resend
+async fn resend_verification(
+    State(auth): State<Arc<Auth>>,
+    caller: AuthUser,
+) -> Result<Json<ApiResponse>, (StatusCode, String)> {
+    let user = session_user(&auth, &caller).await?;
+    if user.email_verified_at.is_some() {
+        return Err((StatusCode::CONFLICT, "Your email is already verified".to_string()));
+    }
+    send_verification_email(&auth, &user).await?;
+    Ok(Json(ApiResponse {
+        message: format!("Verification email sent to {}", user.email),
+    }))
+}
+
+// POST /api/password/forgot
+/// Emails a reset link. The answer is the same whether or not the account exists, and at
+/// most one email per account goes out each minute.
+async fn forgot_password(
+    State(auth): State<Arc<Auth>>,
+    peer: ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<ForgotPasswordRequest>,
+) -> Result<Json<ApiResponse>, (StatusCode, String)> {
+    let response = Json(ApiResponse {
+        message: "If an account uses that email, a reset link is on its way".to_string(),
+    });
+    let Some(user) = find_user_by_email(&auth.pool, &request.email).await.map_err(internal)? else {
+        return Ok(response);
+    };
+
+    let recent = last_auth_event_at(&auth.pool, AuthEvent::PasswordResetRequested.as_str(), &user.id)
+        .await
+        .map_err(internal)?
+        .and_then(|at| chrono::DateTime::parse_from_rfc3339(&at).ok())
+        .is_some_and(|at| chrono::Utc::now().signed_duration_since(at) < chrono::Duration::seconds(60));
+    if recent {
+        return Ok(response);
+    }
+
+    let ip = client_ip(&auth.config, &headers, &peer);
+    let subject = EventSubject {
+        user_id: Some(&user.id),
+        email: Some(&user.email),
+        ip: Some(&ip),
+    };
+    audit::record(&auth, AuthEvent::PasswordResetRequested, subject, None).await;
+    if let Err((_, e)) = send_password_reset(&auth, &user).await {
+        eprintln!("Password reset email error: {}", e);
+    }
+    Ok(response)
+}
+
+// POST /api/password/reset
+/// Sets a new password from a reset link. Every session of the account is revoked, any
+/// login lockout is lifted, and the link stops working.
+async fn reset_password(
+    State(auth): State<Arc<Auth>>,
+    peer: ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<ResetPasswordRequest>,
+) -> Result<Json<ApiResponse>, (StatusCode, String)> {
+    if request.password.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "The new password is empty".to_string()));
+    }
+    let user = reset_target(&auth, &request.token).await?;
+
+    let password_hash = hash_off_thread(request.password).await.map_err(internal)?;
+    update_password_hash(&auth.pool, &user.id, &password_hash).await.map_err(internal)?;
+    let revoked = revoke_user_sessions(&auth.pool, &user.id).await.map_err(internal)?;
+    throttle::unlock_account(&auth, &user.email).await?;
+
+    let ip = client_ip(&auth.config, &headers, &peer);
+    let subject = EventSubject {
+        user_id: Some(&user.id),
+        email: Some(&user.email),
+        ip: Some(&ip),
+    };
+    audit::record(&auth, AuthEvent::PasswordReset, subject, Some(&format!("{} session(s) revoked", revoked))).await;
+    Ok(Json(ApiResponse {
+        message: "Password changed; log in with the new password".to_string(),
+    }))
 }
@@ -0,0 +1,335 @@
+use axum::{
+    middleware,
+    extract::{Json, Path, Query, State},
+    routing::{get, post, put},
+    http::StatusCode,
+    Router,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::auth::rbac::{authorize, Action, Role};
+use crate::auth::AuthUser;
+use crate::catalog::{self, check_product, normalize_gtin, normalize_schedule};
+use crate::db::entities::{
+    approve_catalog_product, find_catalog_product, find_catalog_product_by_ndc, find_organization, save_catalog_product, search_catalog, CatalogProduct,
+    CatalogSearch, ProductDetails,
+};
+use crate::licensing::require_active;
+
+type ApiError = (StatusCode, String);
+
+fn internal(e: sqlx::Error) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+#[derive(Deserialize)]
+pub struct CatalogQuery {
+    /// Digits from a GTIN or NDC, or part of a product name or of an active ingredient with its
+    /// strength, compared without case or spaces.
+    #[serde(default)]
+    pub q: Option<String>,
+    #[serde(default)]
+    pub schedule: Option<String>,
+    #[serde(default)]
+    pub dosage_form: Option<String>,
+    /// Only products with a controlled-substance schedule.
+    #[serde(default)]
+    pub controlled: bool,
+    /// Only entries awaiting review.
+    #[serde(default)]
+    pub pending: bool,
+    #[serde(default)]
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub offset: Option<i64>,
+}
+
+/// A reviewer's decision on an entry. The schedule must be given; empty means not controlled.
+#[derive(Deserialize)]
+pub struct CatalogApproval {
+    pub schedule: String,
+}
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize)]
+pub struct ImportError {
+    pub line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gtin: Option<String>,
+    pub message: String,
+}
+
+/// The outcome of a CSV import. Nothing is written when any row fails.
+#[derive(Serialize)]
+pub struct ImportReport {
+    pub rows: usize,
+    pub created: usize,
+    pub updated: usize,
+    pub imported: bool,
+    pub dry_run: bool,
+    pub errors: Vec<ImportError>,
+}
+
+/// The company editing the catalog, or `None` for an admin, who may edit any entry.
+async fn editor(pool: &SqlitePool, user: &AuthUser) -> Result<Option<String>, ApiError> {
+    if matches!(user.role.parse::<Role>(), Ok(Role::Admin)) {
+        return Ok(None);
+    }
+    let company = match user.organization.as_deref() {
+        Some(id) => find_organization(pool, id).await.map_err(internal)?,
+        None => None,
+    }
+    .filter(|o| o.organization_type == "company")
+    .ok_or((StatusCode::FORBIDDEN, "Register or join a company to edit the catalog".to_string()))?;
+    require_active(pool, &company.id).await?;
+    Ok(Some(company.id))
+}
+
+/// A company may only change entries it added, and may not change a schedule once one is set.
+fn may_change(editor: Option<&str>, existing: Option<&CatalogProduct>, product: &ProductDetails) -> Result<(), String> {
+    if let (Some(company), Some(existing)) = (editor, existing) {
+        if existing.organization.as_deref() != Some(company) {
+            return Err(format!("GTIN {} was added by another organization", product.gtin));
+        }
+        if existing.product.schedule.is_some() && existing.product.schedule != product.schedule {
+            return Err(format!("Only a reviewer or admin may change the schedule of GTIN {}", product.gtin));
+        }
+    }
+    Ok(())
+}
+
+/// Refuses an NDC that already belongs to another GTIN.
+async fn check_ndc(pool: &SqlitePool, product: &ProductDetails) -> Result<Result<(), String>, sqlx::Error> {
+    let Some(ndc) = &product.ndc else {
+        return Ok(Ok(()));
+    };
+    Ok(match find_catalog_product_by_ndc(pool, ndc).await? {
+        Some(other) if other.product.gtin != product.gtin => Err(format!("NDC {} already belongs to GTIN {}", ndc, other.product.gtin)),
+        _ => Ok(()),
+    })
+}
+
+/// The entry as it will be stored: who added it stays as it was. An admin's entry is approved
+/// as it stands; a company's, new or changed, awaits review again.
+fn entry(product: ProductDetails, editor: Option<&str>, existing: Option<&CatalogProduct>, user: &AuthUser, at: &str) -> CatalogProduct {
+    let approved = editor.is_none();
+    CatalogProduct {
+        product,
+        organization: existing.map_or(editor.map(str::to_string), |e| e.organization.clone()),
+        created_by: existing.map_or(user.user_id.clone(), |e| e.created_by.clone()),
+        created_at: existing.map_or(at.to_string(), |e| e.created_at.clone()),
+        updated_by: user.user_id.clone(),
+        updated_at: at.to_string(),
+        approved_by: approved.then(|| user.user_id.clone()),
+        approved_at: approved.then(|| at.to_string()),
+    }
+}
+
+/// Checks one entry from the API and stores it.
+async fn save_one(pool: &SqlitePool, user: &AuthUser, product: ProductDetails, existing: Option<CatalogProduct>) -> Result<CatalogProduct, ApiError> {
+    let editor = editor(pool, user).await?;
+    let product = check_product(product).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    may_change(editor.as_deref(), existing.as_ref(), &product).map_err(|e| (StatusCode::FORBIDDEN, e))?;
+    check_ndc(pool, &product).await.map_err(internal)?.map_err(|e| (StatusCode::CONFLICT, e))?;
+
+    let entry = entry(product, editor.as_deref(), existing.as_ref(), user, &Utc::now().to_rfc3339());
+    let mut conn = pool.acquire().await.map_err(internal)?;
+    save_catalog_product(&mut conn, &entry).await.map_err(internal)?;
+    Ok(entry)
+}
+
+fn gtin_param(gtin: &str) -> Result<String, ApiError> {
+    normalize_gtin(gtin).map_err(|e| (StatusCode::BAD_REQUEST, e))
+}
+
+// GET /api/catalog
+/// Searches the catalog, by name and ingredient first; at most `limit` (100 by default, 1000 at most).
+async fn list_catalog(
+    State(pool): State<Arc<SqlitePool>>,
+    Query(query): Query<CatalogQuery>,
+) -> Result<Json<Vec<CatalogProduct>>, ApiError> {
+    let text = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+    let schedule = match query.schedule.as_deref() {
+        Some(schedule) => normalize_schedule(schedule).map_err(|e| (StatusCode::BAD_REQUEST, e))?,
+        None => None,
+    };
+    let dosage_form = query.dosage_form.as_deref().map(str::trim).filter(|f| !f.is_empty());
+    search_catalog(&pool, &CatalogSearch {
+        text,
+        schedule: schedule.as_deref(),
+        dosage_form,
+        controlled_only: query.controlled,
+        pending_only: query.pending,
+        limit: query.limit.unwrap_or(100).clamp(1, 1000),
+        offset: query.offset.unwrap_or(0).max(0),
+    })
+    .await
+    .map(Json)
+    .map_err(internal)
+}
+
+// GET /api/catalog/:gtin
+async fn get_product(
+    State(pool): State<Arc<SqlitePool>>,
+    Path(gtin): Path<String>,
+) -> Result<Json<CatalogProduct>, ApiError> {
+    find_catalog_product(&pool, &gtin_param(&gtin)?)
+        .await
+        .map_err(internal)?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Product not in the catalog".to_string()))
+}
+
+// POST /api/catalog
+/// Adds a product. Its GTIN may be left out when a 10-digit NDC gives it.
+async fn create_product(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Json(product): Json<ProductDetails>,
+) -> Result<Json<CatalogProduct>, ApiError> {
+    let gtin = check_product(product.clone()).map_err(|e| (StatusCode::BAD_REQUEST, e))?.gtin;
+    if find_catalog_product(&pool, &gtin).await.map_err(internal)?.is_some() {
+        return Err((StatusCode::CONFLICT, format!("GTIN {} is already in the catalog", gtin)));
+    }
+    save_one(&pool, &user, product, None).await.map(Json)
+}
+
+// PUT /api/catalog/:gtin
+/// Replaces a product's details; the GTIN stays the one in the path.
+async fn update_product(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Path(gtin): Path<String>,
+    Json(product): Json<ProductDetails>,
+) -> Result<Json<CatalogProduct>, ApiError> {
+    let gtin = gtin_param(&gtin)?;
+    let existing = find_catalog_product(&pool, &gtin)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Product not in the catalog".to_string()))?;
+    save_one(&pool, &user, ProductDetails { gtin, ..product }, Some(existing)).await.map(Json)
+}
+
+// POST /api/catalog/:gtin/approve
+/// Approves an entry so batches may be made of it, with the schedule the reviewer settles on.
+async fn approve_product(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Path(gtin): Path<String>,
+    Json(approval): Json<CatalogApproval>,
+) -> Result<Json<CatalogProduct>, ApiError> {
+    let gtin = gtin_param(&gtin)?;
+    let schedule = normalize_schedule(&approval.schedule).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if !approve_catalog_product(&pool, &gtin, schedule.as_deref(), &user.user_id, &Utc::now().to_rfc3339())
+        .await
+        .map_err(internal)?
+    {
+        return Err((StatusCode::NOT_FOUND, "Product not in the catalog".to_string()));
+    }
+    find_catalog_product(&pool, &gtin)
+        .await
+        .map_err(internal)?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Product not in the catalog".to_string()))
+}
+
+// POST /api/catalog/import
+/// Adds or updates products from a CSV body with a header row. Every row is checked first;
+/// if any fails, nothing is written and the report says why.
+async fn import_catalog(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Query(query): Query<ImportQuery>,
+    body: String,
+) -> Result<Json<ImportReport>, ApiError> {
+    let editor = editor(&pool, &user).await?;
+    let rows = catalog::products_from_csv(&body).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let at = Utc::now().to_rfc3339();
+    let mut report = ImportReport {
+        rows: rows.len(),
+        created: 0,
+        updated: 0,
+        imported: false,
+        dry_run: query.dry_run,
+        errors: Vec::new(),
+    };
+    let mut entries = Vec::new();
+    let mut seen_gtins: HashMap<String, usize> = HashMap::new();
+    let mut seen_ndcs: HashMap<String, usize> = HashMap::new();
+    for (line, row) in rows {
+        let product = match row {
+            Ok(product) => product,
+            Err(message) => {
+                report.errors.push(ImportError { line, gtin: None, message });
+                continue;
+            }
+        };
+        let mut problem = None;
+        if let Some(first) = seen_gtins.insert(product.gtin.clone(), line) {
+            problem = Some(format!("GTIN {} is also on line {}", product.gtin, first));
+        }
+        if let Some(ndc) = &product.ndc
+            && let Some(first) = seen_ndcs.insert(ndc.clone(), line)
+        {
+            problem.get_or_insert(format!("NDC {} is also on line {}", ndc, first));
+        }
+        let existing = find_catalog_product(&pool, &product.gtin).await.map_err(internal)?;
+        if problem.is_none() {
+            problem = may_change(editor.as_deref(), existing.as_ref(), &product)
+                .and(check_ndc(&pool, &product).await.map_err(internal)?)
+                .err();
+        }
+        match problem {
+            Some(message) => report.errors.push(ImportError { line, gtin: Some(product.gtin), message }),
+            None => {
+                if existing.is_some() {
+                    report.updated += 1;
+                } else {
+                    report.created += 1;
+                }
+                entries.push(entry(product, editor.as_deref(), existing.as_ref(), &user, &at));
+            }
+        }
+    }
+    if !report.errors.is_empty() || query.dry_run {
+        return Ok(Json(report));
+    }
+
+    let mut tx = pool.begin().await.map_err(internal)?;
+    for entry in &entries {
+        save_catalog_product(&mut tx, entry).await.map_err(internal)?;
+    }
+    tx.commit().await.map_err(internal)?;
+    report.imported = true;
+    Ok(Json(report))
+}
+
+pub fn catalog_routes(pool: Arc<SqlitePool>) -> Router {
+    let guard = |action: Action| middleware::from_fn_with_state(action, authorize);
+    Router::new()
+        .route(
+            "/api/catalog",
+            get(list_catalog)
+                .route_layer(guard(Action::ViewCatalog))
+                .merge(post(create_product).route_layer(guard(Action::ManageCatalog))),
+        )
+        .route("/api/catalog/import", post(import_catalog).route_layer(guard(Action::ManageCatalog)))
+        .route(
+            "/api/catalog/:gtin",
+            get(get_product)
+                .route_layer(guard(Action::ViewCatalog))
+                .merge(put(update_product).route_layer(guard(Action::ManageCatalog))),
+        )
+        .route("/api/catalog/:gtin/approve", post(approve_product).route_layer(guard(Action::ReviewCatalog)))
+        .with_state(pool)
+}
@@ -1,55 +1,163 @@
 use axum::{
-    extract::{Json, State},
+    http::StatusCode,
+    middleware,
+    extract::{Extension, Json, State},
     routing::{get, post},
     Router,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use std::sync::Arc;
-use crate::db::entities::add_company;
-
+use crate::auth::rbac::{authorize, Action};
+use crate::auth::{Auth, AuthUser};
+use crate::db::entities::{
+    add_company, add_registration, batches_produced, find_organization, recall_counts, recalls, shipments_awaiting_acknowledgement,
+    shipments_in_transit, signature_failures, stock_by_medicine, MedicineBatches, NewRegistration, Organization, Recall, Shipment,
+    SignatureFailure, StockLevel,
+};
+use crate::licensing::{registration_error, Licensing};
+use crate::routes::orgs::register_owner;
 
 #[derive(Deserialize)]
 pub struct CompanySignup {
     pub name: String,
     pub location: String,
     pub license_id: String,
+    /// Where the license was issued (`US`, `US-CA`); defaults to `DEFAULT_JURISDICTION`.
+    #[serde(default)]
+    pub jurisdiction: Option<String>,
+    /// `YYYY-MM-DD`; required where the jurisdiction's rule says so.
+    #[serde(default)]
+    pub license_expires_at: Option<String>,
     pub stock_needed: String,
 }
 
 #[derive(Serialize)]
 pub struct CompanyResponse {
     pub message: String,
+    pub organization_id: String,
+    /// `approved`, or `pending` until a regulator reviews the license.
+    pub registration_status: String,
+    /// Fresh access token acting for the new company, when the caller's session had no organization yet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
 }
 
-// GET /api/company/dashboard
-async fn company_dashboard() -> String {
-    "Welcome to the Company Dashboard!".to_string()
+#[derive(Serialize)]
+pub struct CompanyDashboard {
+    pub organization: Organization,
+    /// Sealed batches the company created.
+    pub batches_produced: i64,
+    pub produced_by_medicine: Vec<MedicineBatches>,
+    /// Batches on their way to someone else, with the checkpoint they were last handed to.
+    pub in_transit: Vec<Shipment>,
+    /// Transfers to the company it has not yet acknowledged.
+    pub pending_acknowledgements: Vec<Shipment>,
+    pub recalls: RecallOverview,
+    pub signatures: SignatureOverview,
+    /// Batches the company holds now, per medicine, with their expiry.
+    pub stock: Vec<StockLevel>,
+    pub generated_at: String,
 }
 
+#[derive(Serialize)]
+pub struct RecallOverview {
+    pub active: i64,
+    pub closed: i64,
+    pub active_recalls: Vec<Recall>,
+}
 
-pub fn company_routes(pool: Arc<SqlitePool>) -> Router {
-    Router::new()
-        .route("/api/company/dashboard", get(company_dashboard))
-        .route("/api/company/signup", post(signup_company))
-        .with_state(pool)
+#[derive(Serialize)]
+pub struct SignatureOverview {
+    /// Sealed batch, custody and dispensing transactions, each checked once when its block was adopted.
+    pub checked: usize,
+    pub failures: Vec<SignatureFailure>,
+}
+
+// GET /api/company/dashboard
+/// The caller's company at a glance, computed from the tracker tables on each request.
+async fn company_dashboard(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+) -> Result<Json<CompanyDashboard>, (StatusCode, String)> {
+    let internal = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let organization = match user.organization.as_deref() {
+        Some(id) => find_organization(&pool, id).await.map_err(internal)?,
+        None => None,
+    }
+    .filter(|o| o.organization_type == "company")
+    .ok_or((StatusCode::FORBIDDEN, "Register or join a company to see its dashboard".to_string()))?;
+
+    let produced_by_medicine = batches_produced(&pool, &organization.id).await.map_err(internal)?;
+    let (active, closed) = recall_counts(&pool, &organization.id).await.map_err(internal)?;
+    let (checked, failures) = signature_failures(&pool, &organization.id).await.map_err(internal)?;
+
+    Ok(Json(CompanyDashboard {
+        batches_produced: produced_by_medicine.iter().map(|m| m.batches).sum(),
+        produced_by_medicine,
+        in_transit: shipments_in_transit(&pool, &organization).await.map_err(internal)?,
+        pending_acknowledgements: shipments_awaiting_acknowledgement(&pool, &organization).await.map_err(internal)?,
+        recalls: RecallOverview {
+            active,
+            closed,
+            active_recalls: recalls(&pool, Some("active"), None, Some(&organization.id)).await.map_err(internal)?,
+        },
+        signatures: SignatureOverview { checked, failures },
+        stock: stock_by_medicine(&pool, &organization).await.map_err(internal)?,
+        generated_at: chrono::Utc::now().to_rfc3339(),
+        organization,
+    }))
 }
+
+// POST /api/company/signup
 async fn signup_company(
     State(pool): State<Arc<SqlitePool>>,
+    Extension(auth): Extension<Arc<Auth>>,
+    Extension(licensing): Extension<Arc<Licensing>>,
+    user: AuthUser,
     Json(data): Json<CompanySignup>,
-) -> Result<Json<CompanyResponse>, (axum::http::StatusCode, String)> {
-    // Insert the company into the database
-    if let Err(err) = add_company(
-        &pool,
+) -> Result<Json<CompanyResponse>, (StatusCode, String)> {
+    let license = licensing
+        .check(&pool, "company", data.jurisdiction.as_deref(), &data.license_id, data.license_expires_at.as_deref())
+        .await?;
+    let registration_status = licensing.initial_status();
+
+    // The company and its registration are stored together so a taken license leaves nothing behind
+    let internal = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let mut tx = pool.begin().await.map_err(internal)?;
+    let organization_id = add_company(
+        &mut tx,
         &data.name,
         &data.location,
-        &data.license_id,
+        &license.number,
         &data.stock_needed,
-    ).await {
-        return Err((axum::http::StatusCode::INTERNAL_SERVER_ERROR, err.to_string()));
-    }
+    ).await.map_err(internal)?;
+    add_registration(&mut tx, &NewRegistration {
+        organization_id: &organization_id,
+        organization_type: "company",
+        jurisdiction: &license.jurisdiction,
+        license_number: &license.number,
+        license_expires_at: license.expires_at.as_deref(),
+        status: registration_status,
+        submitted_by: &user.user_id,
+    })
+    .await
+    .map_err(|e| registration_error(e, "company", &license))?;
+    tx.commit().await.map_err(internal)?;
+    let token = register_owner(&auth, &user, &organization_id, "company").await?;
 
     Ok(Json(CompanyResponse {
         message: "Company registered successfully".to_string(),
+        organization_id,
+        registration_status: registration_status.to_string(),
+        token,
     }))
 }
+
+pub fn company_routes(pool: Arc<SqlitePool>) -> Router {
+    let guard = |action: Action| middleware::from_fn_with_state(action, authorize);
+    Router::new()
+        .route("/api/company/dashboard", get(company_dashboard).route_layer(guard(Action::ViewCompanyDashboard)))
+        .route("/api/company/signup", post(signup_company).route_layer(guard(Action::RegisterCompany)))
+        .with_state(pool)
+}
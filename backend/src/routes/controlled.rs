@@ -0,0 +1,555 @@
+use axum::{
+    middleware,
+    extract::{Json, Path, Query, State},
+    routing::{get, post},
+    http::StatusCode,
+    Router,
+};
+use chrono::{DateTime, Datelike, Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::auth::rbac::{authorize, Action, Role};
+use crate::auth::AuthUser;
+use crate::catalog::normalize_schedule;
+use crate::controlled::{self, Period, SignedTerms, CONTROLLED_LOCK};
+use crate::db::entities::{
+    add_controlled_transfer, close_controlled_transfer, controlled_movements, controlled_quota_used, controlled_quotas,
+    controlled_transfers, countersign_controlled_transfer, find_controlled_license, find_controlled_transfer, find_organization,
+    find_organization_by_ref, ControlledLicense, ControlledMovement, ControlledQuota, ControlledTransfer, LedgerTx, Organization,
+};
+use crate::licensing::require_active;
+use crate::p2p::Node;
+use crate::routes::inventory::{caller_organization, held_stock};
+use crate::routes::tracker::{queue_custody, CustodyTransfer, TrackerResponse};
+
+type ApiError = (StatusCode, String);
+
+fn internal(e: sqlx::Error) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+#[derive(Deserialize)]
+pub struct TransferProposal {
+    pub batch_id: String,
+    /// Id or name of the receiving organization.
+    pub to_organization: String,
+    /// RFC 3339; when the batch should arrive.
+    #[serde(default)]
+    pub expected_arrival: Option<String>,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct CloseRequest {
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TransferQuery {
+    #[serde(default)]
+    pub status: Option<String>,
+    /// Regulators and admins only; one organization's transfers.
+    #[serde(default)]
+    pub organization: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CountersignedTransfer {
+    #[serde(flatten)]
+    pub transfer: ControlledTransfer,
+    pub custody: TrackerResponse,
+}
+
+#[derive(Serialize)]
+pub struct QuotaUsage {
+    #[serde(flatten)]
+    pub quota: ControlledQuota,
+    pub used: i64,
+    pub remaining: i64,
+    pub period_start: String,
+    pub period_end: String,
+}
+
+/// What an organization may handle: its license and how much of each quota is left.
+#[derive(Serialize)]
+pub struct ControlledStanding {
+    pub organization: Organization,
+    pub license: Option<ControlledLicense>,
+    pub quotas: Vec<QuotaUsage>,
+}
+
+#[derive(Deserialize)]
+pub struct ReportQuery {
+    /// `YYYY-MM-DD`, inclusive; defaults to the first of the current month.
+    #[serde(default)]
+    pub from: Option<String>,
+    /// `YYYY-MM-DD`, inclusive; defaults to today.
+    #[serde(default)]
+    pub to: Option<String>,
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// Regulators and admins only; everyone else gets their own organization's report.
+    #[serde(default)]
+    pub organization: Option<String>,
+}
+
+/// One product at one organization over the report's period. Outflows are positive.
+#[derive(Serialize, Default)]
+pub struct ReportLine {
+    pub gtin: String,
+    pub product_name: String,
+    pub active_ingredient: String,
+    pub strength: String,
+    pub schedule: String,
+    pub organization_id: String,
+    pub organization_name: String,
+    pub opening: i64,
+    pub created: i64,
+    pub received: i64,
+    pub shipped: i64,
+    pub dispensed: i64,
+    pub destroyed: i64,
+    /// Count corrections, signed.
+    pub adjusted: i64,
+    /// Net of splits, which only move stock between batches.
+    pub split: i64,
+    pub closing: i64,
+}
+
+#[derive(Serialize)]
+pub struct ControlledReport {
+    pub from: String,
+    pub to: String,
+    pub schedule: Option<String>,
+    pub organization: Option<String>,
+    pub generated_at: String,
+    pub lines: Vec<ReportLine>,
+    /// Every movement in the period, oldest first.
+    pub movements: Vec<ControlledMovement>,
+}
+
+fn oversees(user: &AuthUser) -> bool {
+    matches!(user.role.parse::<Role>(), Ok(Role::Regulator | Role::Admin))
+}
+
+fn terms(transfer: &ControlledTransfer) -> SignedTerms<'_> {
+    SignedTerms {
+        transfer_id: &transfer.id,
+        batch_id: &transfer.batch_id,
+        gtin: &transfer.gtin,
+        quantity: transfer.quantity,
+        from_organization: &transfer.from_organization,
+        to_organization: &transfer.to_organization,
+    }
+}
+
+/// Checks both signatures against the key the transfer records.
+fn verified(mut transfer: ControlledTransfer) -> ControlledTransfer {
+    let sender = controlled::signature_holds(
+        &transfer.public_key,
+        &terms(&transfer),
+        "sender",
+        &transfer.sender_signed_by,
+        &transfer.sender_signed_at,
+        &transfer.sender_signature,
+    );
+    let receiver = match (&transfer.receiver_signed_by, &transfer.receiver_signed_at, &transfer.receiver_signature) {
+        (Some(by), Some(at), Some(signature)) => {
+            controlled::signature_holds(&transfer.public_key, &terms(&transfer), "receiver", by, at, signature)
+        }
+        (None, None, None) => true,
+        _ => false,
+    };
+    transfer.signatures_valid = sender && receiver;
+    transfer
+}
+
+/// Refuses while a split or custody transfer of the batch waits for the next block, since the
+/// sealed position would not be the batch's latest.
+async fn require_settled(node: &Node, batch_id: &str) -> Result<(), ApiError> {
+    let pending = node.mempool.lock().await.pending().iter().any(|tx| match tx {
+        LedgerTx::Custody(c) => c.batch_id == batch_id,
+        LedgerTx::Batch(b) => b.batch_id == batch_id || b.terms.parent_batch_id.as_deref() == Some(batch_id),
+        LedgerTx::Dispensing(_) => false,
+    });
+    if pending {
+        return Err((StatusCode::CONFLICT, format!("Batch {} has changes waiting for the next block; try again shortly", batch_id)));
+    }
+    Ok(())
+}
+
+async fn organization(node: &Node, id: &str) -> Result<Organization, ApiError> {
+    find_organization(&node.pool, id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, format!("Organization {} not found", id)))
+}
+
+async fn open_transfer(node: &Node, id: &str) -> Result<ControlledTransfer, ApiError> {
+    let transfer = find_controlled_transfer(&node.pool, id)
+        .await
+        .map_err(internal)?
+        .map(verified)
+        .ok_or((StatusCode::NOT_FOUND, "Transfer not found".to_string()))?;
+    if transfer.status != "awaiting_countersignature" {
+        return Err((StatusCode::CONFLICT, format!("The transfer is already {}", transfer.status)));
+    }
+    Ok(transfer)
+}
+
+// POST /api/controlled/transfers
+/// Proposes sending a held batch of a scheduled product, signed by the caller for the sender.
+/// Both organizations must be licensed for the schedule. Nothing moves until the receiver countersigns.
+async fn propose_transfer(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Json(proposal): Json<TransferProposal>,
+) -> Result<Json<ControlledTransfer>, ApiError> {
+    let sender = caller_organization(&node.pool, &user).await?;
+    require_active(&node.pool, &sender.id).await?;
+    let receiver = find_organization_by_ref(&node.pool, proposal.to_organization.trim())
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, format!("Organization {} not found", proposal.to_organization.trim())))?;
+    if receiver.id == sender.id {
+        return Err((StatusCode::BAD_REQUEST, "The batch is already held by the receiver".to_string()));
+    }
+    require_active(&node.pool, &receiver.id).await?;
+
+    require_settled(&node, &proposal.batch_id).await?;
+    let (stock, quantity) = held_stock(&node, &sender, &proposal.batch_id).await?;
+    let product = controlled::batch_schedule(&node, &stock.batch_id)
+        .await?
+        .ok_or((StatusCode::BAD_REQUEST, format!("Batch {} is not a scheduled product; use /api/tracker/custody", stock.batch_id)))?;
+    let schedule = product.schedule.clone().unwrap_or_default();
+    controlled::require_licensed(&node.pool, &sender, &schedule).await?;
+    controlled::require_licensed(&node.pool, &receiver, &schedule).await?;
+    if quantity <= 0 {
+        return Err((StatusCode::CONFLICT, format!("Batch {} has nothing left", stock.batch_id)));
+    }
+    let expected_arrival = proposal
+        .expected_arrival
+        .as_deref()
+        .map(|t| {
+            DateTime::parse_from_rfc3339(t.trim())
+                .map(|t| t.with_timezone(&Utc).to_rfc3339())
+                .map_err(|_| (StatusCode::BAD_REQUEST, format!("expected_arrival '{}' is not an RFC 3339 time", t)))
+        })
+        .transpose()?;
+
+    let mut transfer = ControlledTransfer {
+        id: uuid::Uuid::new_v4().to_string(),
+        batch_id: stock.batch_id,
+        gtin: product.gtin,
+        schedule,
+        active_ingredient: product.active_ingredient,
+        quantity,
+        from_organization: sender.id,
+        to_organization: receiver.id,
+        from_location: stock.holder,
+        expected_arrival,
+        note: proposal.note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty()),
+        status: "awaiting_countersignature".to_string(),
+        sender_signed_by: user.user_id.clone(),
+        sender_signed_at: Utc::now().to_rfc3339(),
+        sender_signature: String::new(),
+        public_key: node.public_key_b64.clone(),
+        receiver_signed_by: None,
+        receiver_signed_at: None,
+        receiver_signature: None,
+        custody_hash: None,
+        closed_by: None,
+        closed_at: None,
+        close_reason: None,
+        signatures_valid: false,
+    };
+    transfer.sender_signature =
+        controlled::signature(&node.private_key, &terms(&transfer), "sender", &user.user_id, &transfer.sender_signed_at);
+    add_controlled_transfer(&node.pool, &transfer).await.map_err(|e| match e {
+        sqlx::Error::Database(db) if db.is_unique_violation() => (
+            StatusCode::CONFLICT,
+            format!("Batch {} already has a transfer awaiting countersignature", transfer.batch_id),
+        ),
+        e => internal(e),
+    })?;
+    Ok(Json(verified(transfer)))
+}
+
+// POST /api/controlled/transfers/:id/countersign
+/// The receiver's signature. Licenses, the batch's position and the receiver's quota are checked
+/// again, then the custody transfer is queued.
+async fn countersign(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Json<CountersignedTransfer>, ApiError> {
+    let receiver = caller_organization(&node.pool, &user).await?;
+    require_active(&node.pool, &receiver.id).await?;
+    let transfer = open_transfer(&node, &id).await?;
+    if transfer.to_organization != receiver.id {
+        return Err((StatusCode::FORBIDDEN, "Only the receiving organization may countersign".to_string()));
+    }
+    if !transfer.signatures_valid {
+        return Err((StatusCode::CONFLICT, "The sender's signature does not verify; the transfer cannot be countersigned".to_string()));
+    }
+    let sender = organization(&node, &transfer.from_organization).await?;
+    require_active(&node.pool, &sender.id).await?;
+    controlled::require_licensed(&node.pool, &sender, &transfer.schedule).await?;
+    controlled::require_licensed(&node.pool, &receiver, &transfer.schedule).await?;
+
+    let _guard = CONTROLLED_LOCK.lock().await;
+    require_settled(&node, &transfer.batch_id).await?;
+    let (stock, quantity) = held_stock(&node, &sender, &transfer.batch_id).await?;
+    if stock.holder != transfer.from_location || quantity != transfer.quantity {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Batch {} changed after the sender signed; cancel and propose the transfer again", transfer.batch_id),
+        ));
+    }
+    controlled::check_quota(&node, &receiver, &transfer.active_ingredient, transfer.quantity).await?;
+
+    let custody = queue_custody(&node, &sender, CustodyTransfer {
+        batch_id: transfer.batch_id.clone(),
+        from_location: transfer.from_location.clone(),
+        to_location: receiver.id.clone(),
+        expected_arrival: transfer.expected_arrival.clone(),
+    })
+    .await?;
+    let signed_at = Utc::now().to_rfc3339();
+    let signature = controlled::signature(&node.private_key, &terms(&transfer), "receiver", &user.user_id, &signed_at);
+    countersign_controlled_transfer(&node.pool, &transfer.id, &user.user_id, &signed_at, &signature, &custody.batch_hash)
+        .await
+        .map_err(internal)?;
+
+    let transfer = find_controlled_transfer(&node.pool, &id)
+        .await
+        .map_err(internal)?
+        .map(verified)
+        .ok_or((StatusCode::NOT_FOUND, "Transfer not found".to_string()))?;
+    Ok(Json(CountersignedTransfer { transfer, custody }))
+}
+
+/// Closes a transfer still awaiting countersignature: the receiver rejects, the sender cancels.
+async fn close(node: &Node, user: &AuthUser, id: &str, status: &str, request: Option<Json<CloseRequest>>) -> Result<Json<ControlledTransfer>, ApiError> {
+    let organization = caller_organization(&node.pool, user).await?;
+    let transfer = open_transfer(node, id).await?;
+    let party = if status == "rejected" { &transfer.to_organization } else { &transfer.from_organization };
+    if *party != organization.id {
+        let who = if status == "rejected" { "receiving" } else { "sending" };
+        return Err((StatusCode::FORBIDDEN, format!("Only the {} organization may do that", who)));
+    }
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    let reason = request.reason.as_deref().map(str::trim).filter(|r| !r.is_empty());
+    if !close_controlled_transfer(&node.pool, id, status, &user.user_id, reason)
+        .await
+        .map_err(internal)?
+    {
+        return Err((StatusCode::CONFLICT, "The transfer is no longer awaiting countersignature".to_string()));
+    }
+    find_controlled_transfer(&node.pool, id)
+        .await
+        .map_err(internal)?
+        .map(|t| Json(verified(t)))
+        .ok_or((StatusCode::NOT_FOUND, "Transfer not found".to_string()))
+}
+
+// POST /api/controlled/transfers/:id/reject
+async fn reject(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+    request: Option<Json<CloseRequest>>,
+) -> Result<Json<ControlledTransfer>, ApiError> {
+    close(&node, &user, &id, "rejected", request).await
+}
+
+// POST /api/controlled/transfers/:id/cancel
+async fn cancel(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+    request: Option<Json<CloseRequest>>,
+) -> Result<Json<ControlledTransfer>, ApiError> {
+    close(&node, &user, &id, "cancelled", request).await
+}
+
+// GET /api/controlled/transfers
+/// Transfers the caller's organization sent or is to receive; regulators and admins see all.
+async fn list_transfers(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Query(query): Query<TransferQuery>,
+) -> Result<Json<Vec<ControlledTransfer>>, ApiError> {
+    let organization = if oversees(&user) {
+        query.organization
+    } else {
+        Some(caller_organization(&node.pool, &user).await?.id)
+    };
+    controlled_transfers(&node.pool, organization.as_deref(), query.status.as_deref())
+        .await
+        .map(|transfers| Json(transfers.into_iter().map(verified).collect()))
+        .map_err(internal)
+}
+
+// GET /api/controlled/transfers/:id
+async fn get_transfer(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Json<ControlledTransfer>, ApiError> {
+    let transfer = find_controlled_transfer(&node.pool, &id)
+        .await
+        .map_err(internal)?
+        .map(verified)
+        .ok_or((StatusCode::NOT_FOUND, "Transfer not found".to_string()))?;
+    if !oversees(&user) {
+        let organization = caller_organization(&node.pool, &user).await?;
+        if organization.id != transfer.from_organization && organization.id != transfer.to_organization {
+            return Err((StatusCode::NOT_FOUND, "Transfer not found".to_string()));
+        }
+    }
+    Ok(Json(transfer))
+}
+
+// GET /api/controlled/license
+/// The caller's organization's license, and each quota with what is used this period.
+async fn get_standing(State(node): State<Arc<Node>>, user: AuthUser) -> Result<Json<ControlledStanding>, ApiError> {
+    let organization = caller_organization(&node.pool, &user).await?;
+    let license = find_controlled_license(&node.pool, &organization.id).await.map_err(internal)?;
+    let mut quotas = Vec::new();
+    for quota in controlled_quotas(&node.pool, Some(&organization.id)).await.map_err(internal)? {
+        let period: Period = quota.period.parse().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        let (start, end) = period.bounds(Utc::now());
+        let (start, end) = (start.to_rfc3339(), end.to_rfc3339());
+        let used = controlled_quota_used(&node.pool, &organization.id, &quota.ingredient_key, &start, &end)
+            .await
+            .map_err(internal)?;
+        quotas.push(QuotaUsage {
+            used,
+            remaining: (quota.quantity - used).max(0),
+            period_start: start,
+            period_end: end,
+            quota,
+        });
+    }
+    Ok(Json(ControlledStanding { organization, license, quotas }))
+}
+
+fn parse_date(field: &str, value: &str) -> Result<NaiveDate, ApiError> {
+    NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d")
+        .map_err(|_| (StatusCode::BAD_REQUEST, format!("{} '{}' is not a YYYY-MM-DD date", field, value)))
+}
+
+// GET /api/controlled/report
+/// Sums every movement of scheduled products over a period, per product and organization, from
+/// the opening balance to the closing one, and lists the movements.
+async fn get_report(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Query(query): Query<ReportQuery>,
+) -> Result<Json<ControlledReport>, ApiError> {
+    let today = Utc::now().date_naive();
+    let from = match query.from.as_deref() {
+        Some(from) => parse_date("from", from)?,
+        None => today.with_day0(0).unwrap_or(today),
+    };
+    let to = match query.to.as_deref() {
+        Some(to) => parse_date("to", to)?,
+        None => today,
+    };
+    if to < from {
+        return Err((StatusCode::BAD_REQUEST, "to is before from".to_string()));
+    }
+    let schedule = match query.schedule.as_deref() {
+        Some(schedule) => normalize_schedule(schedule).map_err(|e| (StatusCode::BAD_REQUEST, e))?,
+        None => None,
+    };
+    let organization = if oversees(&user) {
+        match query.organization.as_deref() {
+            Some(id) => Some(organization(&node, id).await?),
+            None => None,
+        }
+    } else {
+        let own = caller_organization(&node.pool, &user).await?;
+        if query.organization.as_deref().is_some_and(|id| id != own.id) {
+            return Err((StatusCode::FORBIDDEN, "Only regulators and admins may report on other organizations".to_string()));
+        }
+        Some(own)
+    };
+
+    let start = from.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+    let until = to.checked_add_days(Days::new(1)).unwrap_or(to).and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+    let all = controlled_movements(&node.pool, schedule.as_deref(), &until.to_rfc3339(), organization.as_ref())
+        .await
+        .map_err(internal)?;
+
+    let mut lines: BTreeMap<(String, String), ReportLine> = BTreeMap::new();
+    let mut movements = Vec::new();
+    for movement in all {
+        let line = lines
+            .entry((movement.gtin.clone(), movement.organization_id.clone()))
+            .or_insert_with(|| ReportLine {
+                gtin: movement.gtin.clone(),
+                product_name: movement.product_name.clone(),
+                active_ingredient: movement.active_ingredient.clone(),
+                strength: movement.strength.clone(),
+                schedule: movement.schedule.clone(),
+                organization_id: movement.organization_id.clone(),
+                organization_name: movement.organization_name.clone(),
+                ..Default::default()
+            });
+        line.closing += movement.quantity;
+        let before = DateTime::parse_from_rfc3339(&movement.occurred_at).is_ok_and(|t| t < start);
+        if before {
+            line.opening += movement.quantity;
+            continue;
+        }
+        match movement.kind.as_str() {
+            "created" => line.created += movement.quantity,
+            "transfer_in" => line.received += movement.quantity,
+            "transfer_out" => line.shipped -= movement.quantity,
+            "dispensed" => line.dispensed -= movement.quantity,
+            "destroyed" => line.destroyed -= movement.quantity,
+            "adjusted" => line.adjusted += movement.quantity,
+            _ => line.split += movement.quantity,
+        }
+        movements.push(movement);
+    }
+    // A product that was all gone before the period, and did not move in it, is left out.
+    let lines = lines
+        .into_values()
+        .filter(|l| l.opening != 0 || movements.iter().any(|m| m.gtin == l.gtin && m.organization_id == l.organization_id))
+        .collect();
+
+    Ok(Json(ControlledReport {
+        from: from.to_string(),
+        to: to.to_string(),
+        schedule,
+        organization: organization.map(|o| o.id),
+        generated_at: Utc::now().to_rfc3339(),
+        lines,
+        movements,
+    }))
+}
+
+pub fn controlled_routes(node: Arc<Node>) -> Router {
+    let guard = |action: Action| middleware::from_fn_with_state(action, authorize);
+    Router::new()
+        .route(
+            "/api/controlled/transfers",
+            get(list_transfers)
+                .route_layer(guard(Action::ViewControlledReports))
+                .merge(post(propose_transfer).route_layer(guard(Action::TransferControlled))),
+        )
+        .route("/api/controlled/transfers/:id", get(get_transfer).route_layer(guard(Action::ViewControlledReports)))
+        .route("/api/controlled/transfers/:id/countersign", post(countersign).route_layer(guard(Action::TransferControlled)))
+        .route("/api/controlled/transfers/:id/reject", post(reject).route_layer(guard(Action::TransferControlled)))
+        .route("/api/controlled/transfers/:id/cancel", post(cancel).route_layer(guard(Action::TransferControlled)))
+        .route("/api/controlled/license", get(get_standing).route_layer(guard(Action::ViewControlledReports)))
+        .route("/api/controlled/report", get(get_report).route_layer(guard(Action::ViewControlledReports)))
+        .with_state(node)
+}
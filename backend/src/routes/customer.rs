@@ -1,23 +1,40 @@
 use axum::{
-    extract::{Json, State},
+    middleware,
+    extract::{Extension, Json, State},
     routing::{get, post},
     Router,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use std::sync::Arc;
-use crate::db::entities::add_customer;
+use crate::auth::rbac::{authorize, Action};
+use crate::auth::{Auth, AuthUser};
+use crate::db::entities::{add_customer, add_registration, NewRegistration};
+use crate::licensing::{registration_error, Licensing};
+use crate::routes::orgs::register_owner;
 
 #[derive(Deserialize)]
 pub struct CustomerSignup {
     pub name: String,
     pub location: String,
     pub registration_id: String,
+    /// Where the license was issued (`US`, `US-CA`); defaults to `DEFAULT_JURISDICTION`.
+    #[serde(default)]
+    pub jurisdiction: Option<String>,
+    /// `YYYY-MM-DD`; required where the jurisdiction's rule says so.
+    #[serde(default)]
+    pub license_expires_at: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct CustomerResponse {
     pub message: String,
+    pub organization_id: String,
+    /// `approved`, or `pending` until a regulator reviews the license.
+    pub registration_status: String,
+    /// Fresh access token acting for the new customer, when the caller's session had no organization yet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
 }
 
 // GET /api/customer/dashboard
@@ -28,25 +45,51 @@ async fn customer_dashboard() -> String {
 // POST /api/customer/signup
 async fn signup_customer(
     State(pool): State<Arc<SqlitePool>>,
+    Extension(auth): Extension<Arc<Auth>>,
+    Extension(licensing): Extension<Arc<Licensing>>,
+    user: AuthUser,
     Json(data): Json<CustomerSignup>,
 ) -> Result<Json<CustomerResponse>, (axum::http::StatusCode, String)> {
-    if let Err(err) = add_customer(
-        &pool,
+    let license = licensing
+        .check(&pool, "customer", data.jurisdiction.as_deref(), &data.registration_id, data.license_expires_at.as_deref())
+        .await?;
+    let registration_status = licensing.initial_status();
+
+    // The customer and its registration are stored together so a taken license leaves nothing behind
+    let internal = |e: sqlx::Error| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let mut tx = pool.begin().await.map_err(internal)?;
+    let organization_id = add_customer(
+        &mut tx,
         &data.name,
         &data.location,
-        &data.registration_id,
-    ).await {
-        return Err((axum::http::StatusCode::INTERNAL_SERVER_ERROR, err.to_string()));
-    }
+        &license.number,
+    ).await.map_err(internal)?;
+    add_registration(&mut tx, &NewRegistration {
+        organization_id: &organization_id,
+        organization_type: "customer",
+        jurisdiction: &license.jurisdiction,
+        license_number: &license.number,
+        license_expires_at: license.expires_at.as_deref(),
+        status: registration_status,
+        submitted_by: &user.user_id,
+    })
+    .await
+    .map_err(|e| registration_error(e, "customer", &license))?;
+    tx.commit().await.map_err(internal)?;
+    let token = register_owner(&auth, &user, &organization_id, "customer").await?;
 
     Ok(Json(CustomerResponse {
         message: "Customer registered successfully".to_string(),
+        organization_id,
+        registration_status: registration_status.to_string(),
+        token,
     }))
 }
 
 pub fn customer_routes(pool: Arc<SqlitePool>) -> Router {
+    let guard = |action: Action| middleware::from_fn_with_state(action, authorize);
     Router::new()
-        .route("/api/customer/dashboard", get(customer_dashboard))
-        .route("/api/customer/signup", post(signup_customer))
+        .route("/api/customer/dashboard", get(customer_dashboard).route_layer(guard(Action::ViewCustomerDashboard)))
+        .route("/api/customer/signup", post(signup_customer).route_layer(guard(Action::RegisterCustomer)))
         .with_state(pool)
 }
@@ -0,0 +1,576 @@
+use axum::{
+    middleware,
+    extract::{Json, Path, Query, State},
+    routing::{get, post, put},
+    http::StatusCode,
+    Router,
+};
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::sync::Arc;
+
+use crate::auth::rbac::{authorize, Action};
+use crate::auth::AuthUser;
+use crate::catalog;
+use crate::db::entities::{
+    add_demand_request, close_demand_request, demand_requests, find_catalog_product, find_demand_request, find_organization_by_ref,
+    find_organization_site, medicine_key, save_supply_price, supply_batches, supply_prices, DemandRequest, NewDemandRequest,
+    SupplyBatch, SupplyPrice,
+};
+use crate::licensing::require_active;
+use crate::routes::inventory::caller_organization;
+
+type ApiError = (StatusCode, String);
+
+fn internal(e: sqlx::Error) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+#[derive(Deserialize)]
+pub struct DemandInput {
+    #[serde(default)]
+    pub medicine_name: String,
+    #[serde(default)]
+    pub strength: Option<String>,
+    /// A catalog product; its active ingredient and strength stand in for `medicine_name` and
+    /// `strength`, so any pack of it matches.
+    #[serde(default)]
+    pub gtin: Option<String>,
+    pub quantity: i64,
+    /// `YYYY-MM-DD`.
+    #[serde(default)]
+    pub needed_by: Option<String>,
+    #[serde(default)]
+    pub note: Option<String>,
+    /// ISO 4217 code suppliers' prices are compared in; defaults to `USD`.
+    #[serde(default)]
+    pub currency: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DemandQuery {
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CloseDemand {
+    /// `fulfilled` or `cancelled`.
+    pub status: String,
+}
+
+/// How much each criterion counts when ranking suppliers; each defaults to 1.
+#[derive(Deserialize)]
+pub struct MatchQuery {
+    #[serde(default)]
+    pub distance_weight: Option<f64>,
+    #[serde(default)]
+    pub expiry_weight: Option<f64>,
+    #[serde(default)]
+    pub price_weight: Option<f64>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct PriceInput {
+    pub medicine_name: String,
+    pub unit_price: f64,
+    /// ISO 4217; defaults to `USD`.
+    #[serde(default)]
+    pub currency: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PriceQuery {
+    /// Company id or name; the caller's own organization when unset.
+    #[serde(default)]
+    pub company: Option<String>,
+}
+
+/// A company that could meet a demand, with the batches it would draw on.
+#[derive(Serialize)]
+pub struct SupplierMatch {
+    pub organization_id: String,
+    pub organization_name: String,
+    pub region: Option<String>,
+    /// Great-circle distance; unset when either side has not recorded its site.
+    pub distance_km: Option<f64>,
+    pub available_quantity: i64,
+    /// Whether `available_quantity` covers the whole demand.
+    pub covers_demand: bool,
+    /// The soonest expiry among the matching batches; unset if none expire.
+    pub earliest_expiry: Option<String>,
+    /// The lowest price among the matching products in the demand's currency.
+    pub unit_price: Option<f64>,
+    pub currency: Option<String>,
+    /// 0 (best) to 1 (worst) across the suppliers proposed.
+    pub score: f64,
+    pub batches: Vec<SupplyBatch>,
+}
+
+#[derive(Serialize)]
+pub struct MatchReport {
+    pub demand: DemandRequest,
+    pub suppliers: Vec<SupplierMatch>,
+    pub generated_at: String,
+}
+
+fn trimmed(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+/// An ISO 4217 code in upper case; `USD` when unset.
+fn currency_code(value: Option<String>) -> Result<String, ApiError> {
+    let currency = trimmed(value).unwrap_or_else(|| "USD".to_string()).to_ascii_uppercase();
+    if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err((StatusCode::BAD_REQUEST, "currency must be a three-letter code".to_string()));
+    }
+    Ok(currency)
+}
+
+/// Great-circle distance between two points, in kilometres.
+fn haversine_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (from.0.to_radians(), from.1.to_radians());
+    let (lat2, lon2) = (to.0.to_radians(), to.1.to_radians());
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    6371.0 * 2.0 * a.sqrt().asin()
+}
+
+/// Scales `values` to 0..=1, lowest first; a missing value counts as `missing`.
+fn normalized(values: &[Option<f64>], missing: f64) -> Vec<f64> {
+    let known = values.iter().flatten();
+    let min = known.clone().copied().fold(f64::INFINITY, f64::min);
+    let max = known.copied().fold(f64::NEG_INFINITY, f64::max);
+    values
+        .iter()
+        .map(|value| match value {
+            Some(v) if max > min => (v - min) / (max - min),
+            Some(_) => 0.0,
+            None => missing,
+        })
+        .collect()
+}
+
+/// One candidate per company other than the requester, from batches grouped by company.
+/// Only prices in the demand's currency count; there is no conversion between currencies.
+fn gather(batches: Vec<SupplyBatch>, demand: &DemandRequest, origin: Option<(f64, f64)>) -> Vec<SupplierMatch> {
+    let mut suppliers: Vec<SupplierMatch> = Vec::new();
+    for batch in batches {
+        if batch.organization_id == demand.organization {
+            continue;
+        }
+        if suppliers.last().is_none_or(|s| s.organization_id != batch.organization_id) {
+            let distance_km = match (origin, batch.latitude, batch.longitude) {
+                (Some(origin), Some(latitude), Some(longitude)) => Some(haversine_km(origin, (latitude, longitude))),
+                _ => None,
+            };
+            suppliers.push(SupplierMatch {
+                organization_id: batch.organization_id.clone(),
+                organization_name: batch.organization_name.clone(),
+                region: batch.region.clone(),
+                distance_km,
+                available_quantity: 0,
+                covers_demand: false,
+                earliest_expiry: None,
+                unit_price: None,
+                currency: None,
+                score: 0.0,
+                batches: Vec::new(),
+            });
+        }
+        let Some(supplier) = suppliers.last_mut() else { continue };
+        supplier.available_quantity += batch.available;
+        if let Some(expires_at) = &batch.expires_at
+            && supplier.earliest_expiry.as_ref().is_none_or(|earliest| expires_at < earliest)
+        {
+            supplier.earliest_expiry = Some(expires_at.clone());
+        }
+        if let Some(price) = batch.unit_price
+            && batch.currency.as_deref() == Some(demand.currency.as_str())
+            && supplier.unit_price.is_none_or(|lowest| price < lowest)
+        {
+            supplier.unit_price = Some(price);
+            supplier.currency = batch.currency.clone();
+        }
+        supplier.batches.push(batch);
+    }
+    suppliers
+}
+
+/// Scores `suppliers` with `weights` for distance, expiry and price and sorts them best first,
+/// those covering `quantity` ahead of the rest.
+fn rank(suppliers: &mut [SupplierMatch], weights: [f64; 3], quantity: i64, today: NaiveDate) {
+    // Lower is better for every criterion; no expiry is best, and unknown distance or price worst
+    let distances: Vec<Option<f64>> = suppliers.iter().map(|s| s.distance_km).collect();
+    let shelf_lives: Vec<Option<f64>> = suppliers
+        .iter()
+        .map(|s| {
+            s.earliest_expiry
+                .as_deref()
+                .and_then(|e| NaiveDate::parse_from_str(e, "%Y-%m-%d").ok())
+                .map(|e| -((e - today).num_days() as f64))
+        })
+        .collect();
+    let prices: Vec<Option<f64>> = suppliers.iter().map(|s| s.unit_price).collect();
+    let criteria = [normalized(&distances, 1.0), normalized(&shelf_lives, 0.0), normalized(&prices, 1.0)];
+    let total: f64 = weights.iter().sum();
+    for (i, supplier) in suppliers.iter_mut().enumerate() {
+        supplier.score = weights.iter().zip(&criteria).map(|(w, c)| w * c[i]).sum::<f64>() / total;
+        supplier.covers_demand = supplier.available_quantity >= quantity;
+    }
+    suppliers.sort_by(|a, b| {
+        b.covers_demand
+            .cmp(&a.covers_demand)
+            .then(a.score.total_cmp(&b.score))
+            .then(b.available_quantity.cmp(&a.available_quantity))
+            .then(a.organization_name.cmp(&b.organization_name))
+    });
+}
+
+// POST /api/demand
+/// Records how much of a product the caller's organization needs.
+async fn create_demand(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Json(input): Json<DemandInput>,
+) -> Result<Json<DemandRequest>, ApiError> {
+    let organization = caller_organization(&pool, &user).await?;
+    require_active(&pool, &organization.id).await?;
+    let (medicine_name, strength) = match trimmed(input.gtin) {
+        Some(gtin) => {
+            let gtin = catalog::normalize_gtin(&gtin).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            let product = find_catalog_product(&pool, &gtin)
+                .await
+                .map_err(internal)?
+                .ok_or((StatusCode::BAD_REQUEST, format!("GTIN {} is not in the catalog", gtin)))?
+                .product;
+            (product.active_ingredient, Some(product.strength))
+        }
+        None => (input.medicine_name.trim().to_string(), trimmed(input.strength)),
+    };
+    if medicine_name.is_empty() || input.quantity <= 0 {
+        return Err((StatusCode::BAD_REQUEST, "medicine_name or gtin, and a positive quantity, are required".to_string()));
+    }
+    let needed_by = trimmed(input.needed_by);
+    if let Some(date) = &needed_by {
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| (StatusCode::BAD_REQUEST, "needed_by must be YYYY-MM-DD".to_string()))?;
+        if date < Utc::now().date_naive() {
+            return Err((StatusCode::BAD_REQUEST, "needed_by is in the past".to_string()));
+        }
+    }
+    let note = trimmed(input.note);
+    let currency = currency_code(input.currency)?;
+
+    add_demand_request(&pool, &NewDemandRequest {
+        organization: &organization.id,
+        medicine_name: &medicine_name,
+        strength: strength.as_deref(),
+        quantity: input.quantity,
+        needed_by: needed_by.as_deref(),
+        note: note.as_deref(),
+        currency: &currency,
+        created_by: &user.user_id,
+        created_at: &Utc::now().to_rfc3339(),
+    })
+    .await
+    .map(Json)
+    .map_err(internal)
+}
+
+// GET /api/demand
+async fn list_demand(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Query(query): Query<DemandQuery>,
+) -> Result<Json<Vec<DemandRequest>>, ApiError> {
+    let organization = caller_organization(&pool, &user).await?;
+    demand_requests(&pool, &organization.id, query.status.as_deref())
+        .await
+        .map(Json)
+        .map_err(internal)
+}
+
+async fn own_demand(pool: &SqlitePool, user: &AuthUser, id: i64) -> Result<DemandRequest, ApiError> {
+    let organization = caller_organization(pool, user).await?;
+    find_demand_request(pool, &organization.id, id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Demand request not found".to_string()))
+}
+
+// GET /api/demand/:id
+async fn get_demand(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Path(id): Path<i64>,
+) -> Result<Json<DemandRequest>, ApiError> {
+    own_demand(&pool, &user, id).await.map(Json)
+}
+
+// POST /api/demand/:id/close
+async fn close_demand(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Path(id): Path<i64>,
+    Json(request): Json<CloseDemand>,
+) -> Result<Json<DemandRequest>, ApiError> {
+    let demand = own_demand(&pool, &user, id).await?;
+    let status = request.status.trim();
+    if status != "fulfilled" && status != "cancelled" {
+        return Err((StatusCode::BAD_REQUEST, "status must be fulfilled or cancelled".to_string()));
+    }
+    if !close_demand_request(&pool, demand.id, status, &Utc::now().to_rfc3339()).await.map_err(internal)? {
+        return Err((StatusCode::CONFLICT, format!("The demand request is already {}", demand.status)));
+    }
+    own_demand(&pool, &user, id).await.map(Json)
+}
+
+// GET /api/demand/:id/matches
+/// Proposes companies holding available, unexpired stock for a demand request. Suppliers that
+/// can cover the whole quantity come first; within each group they are ranked by a weighted
+/// score of distance, expiry (later is better) and price, each scaled across the candidates.
+async fn match_demand(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Path(id): Path<i64>,
+    Query(query): Query<MatchQuery>,
+) -> Result<Json<MatchReport>, ApiError> {
+    let demand = own_demand(&pool, &user, id).await?;
+    let weights = [
+        query.distance_weight.unwrap_or(1.0),
+        query.expiry_weight.unwrap_or(1.0),
+        query.price_weight.unwrap_or(1.0),
+    ];
+    if weights.iter().any(|w| !w.is_finite() || *w < 0.0) || weights.iter().sum::<f64>() <= 0.0 {
+        return Err((StatusCode::BAD_REQUEST, "Weights must be non-negative and not all zero".to_string()));
+    }
+
+    let today = Utc::now().date_naive();
+    let usable_until = demand.needed_by.clone().unwrap_or_else(|| today.to_string());
+    let batches = supply_batches(&pool, &demand.medicine_name, demand.strength.as_deref(), &usable_until)
+        .await
+        .map_err(internal)?;
+    let origin = find_organization_site(&pool, &demand.organization)
+        .await
+        .map_err(internal)?
+        .map(|site| (site.latitude, site.longitude));
+
+    let mut suppliers = gather(batches, &demand, origin);
+    rank(&mut suppliers, weights, demand.quantity, today);
+    suppliers.truncate(query.limit.unwrap_or(20).clamp(1, 100));
+
+    Ok(Json(MatchReport {
+        demand,
+        suppliers,
+        generated_at: Utc::now().to_rfc3339(),
+    }))
+}
+
+// PUT /api/supply/prices
+/// Sets the caller's company's price per unit for a product.
+async fn set_price(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Json(input): Json<PriceInput>,
+) -> Result<Json<SupplyPrice>, ApiError> {
+    let organization = caller_organization(&pool, &user).await?;
+    let medicine_name = input.medicine_name.trim();
+    if medicine_name.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "medicine_name is required".to_string()));
+    }
+    if !input.unit_price.is_finite() || input.unit_price < 0.0 {
+        return Err((StatusCode::BAD_REQUEST, "unit_price must be zero or more".to_string()));
+    }
+    let currency = currency_code(input.currency)?;
+
+    let price = SupplyPrice {
+        organization: organization.id,
+        medicine_key: medicine_key(medicine_name),
+        medicine_name: medicine_name.to_string(),
+        unit_price: input.unit_price,
+        currency,
+        updated_by: user.user_id,
+        updated_at: Utc::now().to_rfc3339(),
+    };
+    save_supply_price(&pool, &price).await.map_err(internal)?;
+    Ok(Json(price))
+}
+
+// GET /api/supply/prices
+async fn list_prices(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Query(query): Query<PriceQuery>,
+) -> Result<Json<Vec<SupplyPrice>>, ApiError> {
+    let organization = match query.company.as_deref() {
+        Some(company) => find_organization_by_ref(&pool, company.trim())
+            .await
+            .map_err(internal)?
+            .filter(|o| o.organization_type == "company")
+            .ok_or((StatusCode::NOT_FOUND, format!("No company named {}", company)))?,
+        None => caller_organization(&pool, &user).await?,
+    };
+    supply_prices(&pool, &organization.id).await.map(Json).map_err(internal)
+}
+
+pub fn demand_routes(pool: Arc<SqlitePool>) -> Router {
+    let guard = |action: Action| middleware::from_fn_with_state(action, authorize);
+    Router::new()
+        .route(
+            "/api/demand",
+            get(list_demand)
+                .route_layer(guard(Action::ViewDemand))
+                .merge(post(create_demand).route_layer(guard(Action::ManageDemand))),
+        )
+        .route("/api/demand/:id", get(get_demand).route_layer(guard(Action::ViewDemand)))
+        .route("/api/demand/:id/close", post(close_demand).route_layer(guard(Action::ManageDemand)))
+        .route("/api/demand/:id/matches", get(match_demand).route_layer(guard(Action::ViewDemand)))
+        .route(
+            "/api/supply/prices",
+            get(list_prices)
+                .route_layer(guard(Action::ViewDemand))
+                .merge(put(set_price).route_layer(guard(Action::SetPrices))),
+        )
+        .with_state(pool)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn demand(currency: &str) -> DemandRequest {
+        DemandRequest {
+            id: 1,
+            organization: "ward".to_string(),
+            medicine_name: "Paracetamol".to_string(),
+            strength: None,
+            quantity: 10,
+            needed_by: None,
+            note: None,
+            currency: currency.to_string(),
+            status: "open".to_string(),
+            created_by: "user".to_string(),
+            created_at: String::new(),
+            updated_at: String::new(),
+        }
+    }
+
+    fn batch(company: &str, available: i64, expires_at: Option<&str>, price: Option<(f64, &str)>, site: Option<(f64, f64)>) -> SupplyBatch {
+        SupplyBatch {
+            organization_id: company.to_string(),
+            organization_name: company.to_string(),
+            batch_id: format!("{}-{}", company, available),
+            medicine_name: "Paracetamol".to_string(),
+            gtin: None,
+            expires_at: expires_at.map(str::to_string),
+            available,
+            unit_price: price.map(|(p, _)| p),
+            currency: price.map(|(_, c)| c.to_string()),
+            latitude: site.map(|s| s.0),
+            longitude: site.map(|s| s.1),
+            region: None,
+        }
+    }
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2030, 1, 1).unwrap()
+    }
+
+    fn ranked(batches: Vec<SupplyBatch>, currency: &str, weights: [f64; 3], origin: Option<(f64, f64)>) -> Vec<String> {
+        let mut suppliers = gather(batches, &demand(currency), origin);
+        rank(&mut suppliers, weights, 10, today());
+        suppliers.into_iter().map(|s| s.organization_id).collect()
+    }
+
+    const LONDON: (f64, f64) = (51.5074, -0.1278);
+    const OXFORD: (f64, f64) = (51.7520, -1.2577);
+    const EDINBURGH: (f64, f64) = (55.9533, -3.1883);
+
+    #[test]
+    fn haversine_matches_known_distances() {
+        assert!((haversine_km(LONDON, EDINBURGH) - 534.0).abs() < 5.0);
+        assert!(haversine_km(LONDON, LONDON).abs() < 1e-9);
+    }
+
+    #[test]
+    fn nearer_suppliers_rank_first_and_unknown_sites_last() {
+        let batches = vec![
+            batch("far", 10, None, None, Some(EDINBURGH)),
+            batch("near", 10, None, None, Some(OXFORD)),
+            batch("nowhere", 10, None, None, None),
+        ];
+        assert_eq!(ranked(batches, "USD", [1.0, 0.0, 0.0], Some(LONDON)), ["near", "far", "nowhere"]);
+    }
+
+    #[test]
+    fn later_expiry_ranks_first_and_no_expiry_counts_as_the_latest() {
+        let batches = vec![
+            batch("soon", 10, Some("2030-02-01"), None, None),
+            batch("sooner", 10, Some("2030-01-10"), None, None),
+            batch("never", 10, None, None, None),
+            batch("later", 10, Some("2031-01-01"), None, None),
+        ];
+        let mut suppliers = gather(batches, &demand("USD"), None);
+        rank(&mut suppliers, [0.0, 1.0, 0.0], 10, today());
+        let order: Vec<(&str, f64)> = suppliers.iter().map(|s| (s.organization_id.as_str(), s.score)).collect();
+        assert_eq!(order[0], ("later", 0.0));
+        assert_eq!(order[1], ("never", 0.0));
+        assert_eq!(order[2].0, "soon");
+        assert_eq!(order[3], ("sooner", 1.0));
+    }
+
+    #[test]
+    fn a_supplier_is_judged_by_its_soonest_expiring_batch() {
+        let batches = vec![
+            batch("mixed", 5, Some("2035-01-01"), None, None),
+            batch("mixed", 5, Some("2030-01-15"), None, None),
+            batch("steady", 10, Some("2031-01-01"), None, None),
+        ];
+        assert_eq!(ranked(batches, "USD", [0.0, 1.0, 0.0], None), ["steady", "mixed"]);
+    }
+
+    #[test]
+    fn cheaper_suppliers_rank_first_and_unpriced_last() {
+        let batches = vec![
+            batch("dear", 10, None, Some((2.0, "USD")), None),
+            batch("unpriced", 10, None, None, None),
+            batch("cheap", 10, None, Some((1.0, "USD")), None),
+        ];
+        assert_eq!(ranked(batches, "USD", [0.0, 0.0, 1.0], None), ["cheap", "dear", "unpriced"]);
+    }
+
+    #[test]
+    fn prices_in_other_currencies_are_not_compared() {
+        let batches = vec![
+            batch("dollars", 10, None, Some((5.0, "USD")), None),
+            batch("yen", 10, None, Some((1.0, "JPY")), None),
+        ];
+        let mut suppliers = gather(batches, &demand("USD"), None);
+        assert_eq!(suppliers[1].unit_price, None);
+        rank(&mut suppliers, [0.0, 0.0, 1.0], 10, today());
+        assert_eq!(suppliers[0].organization_id, "dollars");
+        assert_eq!(suppliers[0].currency.as_deref(), Some("USD"));
+    }
+
+    #[test]
+    fn suppliers_covering_the_demand_come_first_and_the_requester_is_left_out() {
+        let batches = vec![
+            batch("ward", 50, None, Some((0.1, "USD")), Some(LONDON)),
+            batch("partial", 4, None, Some((0.5, "USD")), Some(OXFORD)),
+            batch("whole", 6, None, Some((3.0, "USD")), Some(EDINBURGH)),
+            batch("whole", 6, None, Some((3.0, "USD")), Some(EDINBURGH)),
+        ];
+        assert_eq!(ranked(batches, "USD", [1.0, 1.0, 1.0], Some(LONDON)), ["whole", "partial"]);
+    }
+
+    #[test]
+    fn currency_codes_are_three_letters() {
+        assert_eq!(currency_code(None).unwrap(), "USD");
+        assert_eq!(currency_code(Some(" eur ".to_string())).unwrap(), "EUR");
+        assert!(currency_code(Some("EURO".to_string())).is_err());
+        assert!(currency_code(Some("U$D".to_string())).is_err());
+    }
+}
@@ -0,0 +1,400 @@
+use axum::{
+    middleware,
+    extract::{Extension, Json, Path, Query, State},
+    routing::{get, post},
+    http::StatusCode,
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::auth::rbac::{authorize, Action, Role};
+use crate::auth::AuthUser;
+use crate::catalog::normalize_gtin;
+use crate::controlled;
+use crate::db::entities::{
+    add_dispensing, add_inventory_event, batch_custody_history, compute_dispensing_hash, dispensing_records, dispensing_serials,
+    dispensings_by_serial, find_batch, find_dispensing, CustodyRecord, DispensingEvent, DispensingFilter, DispensingRecord, LedgerTx,
+    MedicineBatch, NewInventoryEvent,
+};
+use crate::dispensing::{normalize_serials, serials_in_batch, PatientHasher};
+use crate::licensing::require_active;
+use crate::p2p::Node;
+use crate::routes::inventory::{batch_serials, caller_organization, held_stock, STOCK_LOCK};
+use crate::routes::tracker::submit;
+use crate::utils::signatures::sign_data;
+
+type ApiError = (StatusCode, String);
+
+fn internal(e: sqlx::Error) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+#[derive(Deserialize)]
+pub struct DispensingRequest {
+    pub batch_id: String,
+    pub prescription_reference: String,
+    /// Hashed with the node's salt before anything is stored.
+    pub patient_id: String,
+    /// Required unless `serials` are given, in which case it is their count.
+    #[serde(default)]
+    pub quantity: Option<i64>,
+    /// Serials of the packs handed over, each decommissioned. Required for, and only for, batches
+    /// that list their packs' serials.
+    #[serde(default)]
+    pub serials: Vec<String>,
+    #[serde(default)]
+    pub prescriber: Option<String>,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DispensingQuery {
+    #[serde(default)]
+    pub batch_id: Option<String>,
+    #[serde(default)]
+    pub prescription_reference: Option<String>,
+    /// RFC 3339.
+    #[serde(default)]
+    pub since: Option<String>,
+    /// RFC 3339.
+    #[serde(default)]
+    pub until: Option<String>,
+    #[serde(default)]
+    pub limit: Option<i64>,
+    /// Regulators and admins only; one organization's dispensings.
+    #[serde(default)]
+    pub organization: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PatientQuery {
+    pub patient_id: String,
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct SerialQuery {
+    #[serde(default)]
+    pub gtin: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DispensingDetail {
+    #[serde(flatten)]
+    pub record: DispensingRecord,
+    pub serials: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct DispensingReceipt {
+    #[serde(flatten)]
+    pub dispensing: DispensingDetail,
+    /// What is left of the batch afterwards.
+    pub remaining: i64,
+}
+
+#[derive(Serialize)]
+pub struct TracedBatch {
+    pub batch: MedicineBatch,
+    pub custody: Vec<CustodyRecord>,
+}
+
+/// A dispensed pack and the way it came: the batch it was dispensed from, then each batch that
+/// one was split from, back to the one its maker created.
+#[derive(Serialize)]
+pub struct DispensingTrace {
+    #[serde(flatten)]
+    pub dispensing: DispensingDetail,
+    pub chain: Vec<TracedBatch>,
+}
+
+fn oversees(user: &AuthUser) -> bool {
+    matches!(user.role.parse::<Role>(), Ok(Role::Regulator | Role::Admin))
+}
+
+fn parse_time(field: &str, value: Option<&str>) -> Result<Option<String>, ApiError> {
+    value
+        .map(|v| {
+            DateTime::parse_from_rfc3339(v.trim())
+                .map(|t| t.with_timezone(&Utc).to_rfc3339())
+                .map_err(|_| (StatusCode::BAD_REQUEST, format!("{} '{}' is not an RFC 3339 time", field, v)))
+        })
+        .transpose()
+}
+
+/// The organization whose dispensings the caller may see, or `None` for all of them.
+async fn visible_to(node: &Node, user: &AuthUser, requested: Option<String>) -> Result<Option<String>, ApiError> {
+    if oversees(user) {
+        return Ok(requested);
+    }
+    Ok(Some(caller_organization(&node.pool, user).await?.id))
+}
+
+async fn detail(node: &Node, record: DispensingRecord) -> Result<DispensingDetail, ApiError> {
+    let serials = dispensing_serials(&node.pool, &record.id).await.map_err(internal)?;
+    Ok(DispensingDetail { record, serials })
+}
+
+/// The dispensing, as long as the caller may see it.
+async fn visible_dispensing(node: &Node, user: &AuthUser, id: &str) -> Result<DispensingRecord, ApiError> {
+    let not_found = || (StatusCode::NOT_FOUND, "Dispensing record not found".to_string());
+    let record = find_dispensing(&node.pool, id).await.map_err(internal)?.ok_or_else(not_found)?;
+    if let Some(organization) = visible_to(node, user, None).await?
+        && organization != record.organization
+    {
+        return Err(not_found());
+    }
+    Ok(record)
+}
+
+async fn trace(node: &Node, record: DispensingRecord) -> Result<DispensingTrace, ApiError> {
+    let mut chain: Vec<TracedBatch> = Vec::new();
+    let mut next = Some(record.batch_id.clone());
+    while let Some(batch_id) = next {
+        if chain.iter().any(|t| t.batch.batch_id == batch_id) {
+            break;
+        }
+        let Some(batch) = find_batch(&node.pool, &batch_id).await.map_err(internal)? else {
+            break;
+        };
+        let custody = batch_custody_history(&node.pool, &batch_id).await.map_err(internal)?;
+        next = batch.terms.parent_batch_id.clone();
+        chain.push(TracedBatch { batch, custody });
+    }
+    Ok(DispensingTrace { dispensing: detail(node, record).await?, chain })
+}
+
+// POST /api/dispensing
+/// Dispenses from a held batch to a patient against a prescription, and queues a ledger
+/// transaction recording the units and serials. A batch that lists its packs is dispensed by
+/// serial, each decommissioned; either way the batch's quantity goes down.
+async fn dispense(
+    State(node): State<Arc<Node>>,
+    Extension(hasher): Extension<Arc<PatientHasher>>,
+    user: AuthUser,
+    Json(request): Json<DispensingRequest>,
+) -> Result<Json<DispensingReceipt>, ApiError> {
+    let organization = caller_organization(&node.pool, &user).await?;
+    require_active(&node.pool, &organization.id).await?;
+    let prescription_reference = request.prescription_reference.trim().to_string();
+    if prescription_reference.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "prescription_reference is required".to_string()));
+    }
+    let patient_hash = hasher.hash(&request.patient_id).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let serials = normalize_serials(&request.serials).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let quantity = if serials.is_empty() {
+        request
+            .quantity
+            .filter(|q| *q > 0)
+            .ok_or((StatusCode::BAD_REQUEST, "A positive quantity or serials are required".to_string()))?
+    } else {
+        let count = serials.len() as i64;
+        if let Some(quantity) = request.quantity.filter(|q| *q != count) {
+            return Err((StatusCode::BAD_REQUEST, format!("quantity is {} but {} serials are listed", quantity, count)));
+        }
+        count
+    };
+
+    let _guard = STOCK_LOCK.lock().await;
+    let (stock, held) = held_stock(&node, &organization, &request.batch_id).await?;
+    controlled::require_licensed_batch(&node, &organization, &stock.batch_id).await?;
+    if quantity > held {
+        return Err((StatusCode::CONFLICT, format!("Batch {} has only {} left", stock.batch_id, held)));
+    }
+    let batch = find_batch(&node.pool, &stock.batch_id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Batch not found or not sealed yet".to_string()))?;
+    for serial in &serials {
+        if !dispensings_by_serial(&node.pool, serial, batch.terms.gtin.as_deref()).await.map_err(internal)?.is_empty() {
+            return Err((StatusCode::CONFLICT, format!("Serial {} has already been dispensed", serial)));
+        }
+    }
+    match batch_serials(&node, &batch).await? {
+        Some(_) if serials.is_empty() => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Batch {} lists its packs; give the serial of each pack dispensed", batch.batch_id),
+            ));
+        }
+        Some(in_batch) => serials_in_batch(&serials, &batch.batch_id, &in_batch).map_err(|e| (StatusCode::BAD_REQUEST, e))?,
+        None if !serials.is_empty() => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Batch {} lists no pack serials; dispense it by quantity", batch.batch_id),
+            ));
+        }
+        None => {}
+    }
+
+    let dispensed_at = Utc::now().to_rfc3339();
+    let mut event = DispensingEvent {
+        dispensing_id: uuid::Uuid::new_v4().to_string(),
+        batch_id: stock.batch_id.clone(),
+        location: stock.holder,
+        quantity,
+        serials: serials.clone(),
+        timestamp: dispensed_at.clone(),
+        hash: String::new(),
+        signature: String::new(),
+        public_key: node.public_key_b64.clone(),
+    };
+    event.hash = compute_dispensing_hash(&event);
+    event.signature = STANDARD.encode(sign_data(&node.private_key, event.hash.as_bytes()));
+    let record = DispensingRecord {
+        id: event.dispensing_id.clone(),
+        organization: organization.id.clone(),
+        batch_id: stock.batch_id.clone(),
+        gtin: batch.terms.gtin,
+        quantity,
+        prescription_reference,
+        prescriber: request.prescriber.map(|p| p.trim().to_string()).filter(|p| !p.is_empty()),
+        patient_hash,
+        note: request.note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty()),
+        dispensed_by: user.user_id.clone(),
+        dispensed_at,
+        ledger_hash: event.hash.clone(),
+    };
+    let mut tx = node.pool.begin().await.map_err(internal)?;
+    add_inventory_event(&mut tx, &NewInventoryEvent {
+        organization: &organization.id,
+        batch_id: &record.batch_id,
+        kind: "dispensed",
+        quantity: -quantity,
+        reason: None,
+        reference: Some(&record.id),
+        recorded_by: &user.user_id,
+        occurred_at: &record.dispensed_at,
+    })
+    .await
+    .map_err(internal)?;
+    add_dispensing(&mut tx, &record, &serials).await.map_err(|e| match e {
+        sqlx::Error::Database(db) if db.is_unique_violation() => {
+            (StatusCode::CONFLICT, "One of the serials has already been dispensed".to_string())
+        }
+        e => internal(e),
+    })?;
+    // Queued before the records are committed, so a refused transaction leaves nothing behind.
+    submit(&node, LedgerTx::Dispensing(event)).await?;
+    tx.commit().await.map_err(internal)?;
+
+    Ok(Json(DispensingReceipt {
+        dispensing: DispensingDetail { record, serials },
+        remaining: held - quantity,
+    }))
+}
+
+// GET /api/dispensing
+/// The caller's organization's dispensings, newest first; regulators and admins see every one.
+async fn list_dispensings(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Query(query): Query<DispensingQuery>,
+) -> Result<Json<Vec<DispensingRecord>>, ApiError> {
+    let organization = visible_to(&node, &user, query.organization).await?;
+    let since = parse_time("since", query.since.as_deref())?;
+    let until = parse_time("until", query.until.as_deref())?;
+    dispensing_records(&node.pool, &DispensingFilter {
+        organization: organization.as_deref(),
+        batch_id: query.batch_id.as_deref(),
+        prescription_reference: query.prescription_reference.as_deref().map(str::trim),
+        since: since.as_deref(),
+        until: until.as_deref(),
+        limit: query.limit.unwrap_or(500).clamp(1, 5000),
+        ..Default::default()
+    })
+    .await
+    .map(Json)
+    .map_err(internal)
+}
+
+// POST /api/dispensing/patient
+/// Dispensings to one patient. The identifier travels in the body, is hashed like at
+/// dispensing time, and is not kept.
+async fn patient_dispensings(
+    State(node): State<Arc<Node>>,
+    Extension(hasher): Extension<Arc<PatientHasher>>,
+    user: AuthUser,
+    Json(query): Json<PatientQuery>,
+) -> Result<Json<Vec<DispensingRecord>>, ApiError> {
+    let organization = visible_to(&node, &user, None).await?;
+    let patient_hash = hasher.hash(&query.patient_id).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    dispensing_records(&node.pool, &DispensingFilter {
+        organization: organization.as_deref(),
+        patient_hash: Some(&patient_hash),
+        limit: query.limit.unwrap_or(500).clamp(1, 5000),
+        ..Default::default()
+    })
+    .await
+    .map(Json)
+    .map_err(internal)
+}
+
+// GET /api/dispensing/:id
+async fn get_dispensing(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Json<DispensingDetail>, ApiError> {
+    let record = visible_dispensing(&node, &user, &id).await?;
+    detail(&node, record).await.map(Json)
+}
+
+// GET /api/dispensing/:id/trace
+async fn trace_dispensing(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Json<DispensingTrace>, ApiError> {
+    let record = visible_dispensing(&node, &user, &id).await?;
+    trace(&node, record).await.map(Json)
+}
+
+// GET /api/dispensing/serials/:serial
+/// Traces a dispensed pack by its serial; `gtin` picks the product when several share the serial.
+async fn trace_serial(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(serial): Path<String>,
+    Query(query): Query<SerialQuery>,
+) -> Result<Json<DispensingTrace>, ApiError> {
+    let gtin = query
+        .gtin
+        .as_deref()
+        .map(|g| normalize_gtin(g).map_err(|e| (StatusCode::BAD_REQUEST, e)))
+        .transpose()?;
+    let organization = visible_to(&node, &user, None).await?;
+    let mut records = dispensings_by_serial(&node.pool, serial.trim(), gtin.as_deref())
+        .await
+        .map_err(internal)?
+        .into_iter()
+        .filter(|d| organization.as_ref().is_none_or(|o| *o == d.organization));
+    let record = records
+        .next()
+        .ok_or((StatusCode::NOT_FOUND, format!("No dispensing of serial {}", serial.trim())))?;
+    if records.next().is_some() {
+        return Err((StatusCode::CONFLICT, format!("Serial {} was dispensed for several products; give a gtin", serial.trim())));
+    }
+    trace(&node, record).await.map(Json)
+}
+
+pub fn dispensing_routes(node: Arc<Node>) -> Router {
+    let guard = |action: Action| middleware::from_fn_with_state(action, authorize);
+    Router::new()
+        .route(
+            "/api/dispensing",
+            get(list_dispensings)
+                .route_layer(guard(Action::ViewDispensing))
+                .merge(post(dispense).route_layer(guard(Action::DispenseToPatient))),
+        )
+        .route("/api/dispensing/patient", post(patient_dispensings).route_layer(guard(Action::ViewDispensing)))
+        .route("/api/dispensing/serials/:serial", get(trace_serial).route_layer(guard(Action::ViewDispensing)))
+        .route("/api/dispensing/:id", get(get_dispensing).route_layer(guard(Action::ViewDispensing)))
+        .route("/api/dispensing/:id/trace", get(trace_dispensing).route_layer(guard(Action::ViewDispensing)))
+        .with_state(node)
+}
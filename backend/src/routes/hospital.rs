@@ -1,52 +1,149 @@
 use axum::{
-    extract::{Json, State},
+    http::StatusCode,
+    middleware,
+    extract::{Extension, Json, State},
     routing::{get, post},
     Router,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use std::sync::Arc;
-use crate::db::entities::add_hospital;
+use crate::auth::rbac::{authorize, Action};
+use crate::auth::{Auth, AuthUser};
+use crate::db::entities::{
+    add_hospital, add_registration, excursions_affecting, find_organization, recalls_affecting, shipments_awaiting_acknowledgement,
+    stock_by_medicine, ConditionReading, NewRegistration, Organization, Recall, Shipment, StockLevel,
+};
+use crate::licensing::{registration_error, Licensing};
+use crate::routes::orgs::register_owner;
 
 #[derive(Deserialize)]
 pub struct HospitalSignup {
     pub name: String,
     pub location: String,
     pub registration_id: String,
+    /// Where the license was issued (`US`, `US-CA`); defaults to `DEFAULT_JURISDICTION`.
+    #[serde(default)]
+    pub jurisdiction: Option<String>,
+    /// `YYYY-MM-DD`; required where the jurisdiction's rule says so.
+    #[serde(default)]
+    pub license_expires_at: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct HospitalResponse {
     pub message: String,
+    pub organization_id: String,
+    /// `approved`, or `pending` until a regulator reviews the license.
+    pub registration_status: String,
+    /// Fresh access token acting for the new hospital, when the caller's session had no organization yet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct HospitalDashboard {
+    pub organization: Organization,
+    /// Transfers to the hospital whose expected arrival is still ahead, soonest first.
+    pub incoming: Vec<Shipment>,
+    /// Transfers to the hospital that are due or have no arrival time, not yet acknowledged.
+    pub awaiting_acknowledgement: Vec<Shipment>,
+    /// Batches the hospital holds now, per medicine, with their expiry.
+    pub stock: Vec<StockLevel>,
+    /// Active recalls on batches the hospital holds.
+    pub recalls: Vec<Recall>,
+    /// Temperature readings outside the storage range of batches the hospital holds.
+    pub excursions: Vec<ConditionReading>,
+    pub generated_at: String,
 }
 
 // GET /api/hospital/dashboard
-async fn hospital_dashboard() -> String {
-    "Welcome to the Hospital Dashboard!".to_string()
+/// The caller's hospital at a glance, computed from the tracker tables on each request.
+async fn hospital_dashboard(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+) -> Result<Json<HospitalDashboard>, (StatusCode, String)> {
+    let internal = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let organization = match user.organization.as_deref() {
+        Some(id) => find_organization(&pool, id).await.map_err(internal)?,
+        None => None,
+    }
+    .filter(|o| o.organization_type == "hospital")
+    .ok_or((StatusCode::FORBIDDEN, "Register or join a hospital to see its dashboard".to_string()))?;
+
+    let now = chrono::Utc::now();
+    let (mut incoming, awaiting_acknowledgement): (Vec<Shipment>, Vec<Shipment>) =
+        shipments_awaiting_acknowledgement(&pool, &organization)
+            .await
+            .map_err(internal)?
+            .into_iter()
+            .partition(|s| {
+                s.expected_arrival
+                    .as_deref()
+                    .and_then(|eta| chrono::DateTime::parse_from_rfc3339(eta).ok())
+                    .is_some_and(|eta| eta > now)
+            });
+    incoming.sort_by(|a, b| a.expected_arrival.cmp(&b.expected_arrival));
+
+    Ok(Json(HospitalDashboard {
+        incoming,
+        awaiting_acknowledgement,
+        stock: stock_by_medicine(&pool, &organization).await.map_err(internal)?,
+        recalls: recalls_affecting(&pool, &organization).await.map_err(internal)?,
+        excursions: excursions_affecting(&pool, &organization).await.map_err(internal)?,
+        generated_at: now.to_rfc3339(),
+        organization,
+    }))
 }
 
 // POST /api/hospital/signup
 async fn signup_hospital(
     State(pool): State<Arc<SqlitePool>>,
+    Extension(auth): Extension<Arc<Auth>>,
+    Extension(licensing): Extension<Arc<Licensing>>,
+    user: AuthUser,
     Json(data): Json<HospitalSignup>,
-) -> Result<Json<HospitalResponse>, (axum::http::StatusCode, String)> {
-    if let Err(err) = add_hospital(
-        &pool,
+) -> Result<Json<HospitalResponse>, (StatusCode, String)> {
+    let license = licensing
+        .check(&pool, "hospital", data.jurisdiction.as_deref(), &data.registration_id, data.license_expires_at.as_deref())
+        .await?;
+    let registration_status = licensing.initial_status();
+
+    // The hospital and its registration are stored together so a taken license leaves nothing behind
+    let internal = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let mut tx = pool.begin().await.map_err(internal)?;
+    let organization_id = add_hospital(
+        &mut tx,
         &data.name,
         &data.location,
-        &data.registration_id,
-    ).await {
-        return Err((axum::http::StatusCode::INTERNAL_SERVER_ERROR, err.to_string()));
-    }
+        &license.number,
+    ).await.map_err(internal)?;
+    add_registration(&mut tx, &NewRegistration {
+        organization_id: &organization_id,
+        organization_type: "hospital",
+        jurisdiction: &license.jurisdiction,
+        license_number: &license.number,
+        license_expires_at: license.expires_at.as_deref(),
+        status: registration_status,
+        submitted_by: &user.user_id,
+    })
+    .await
+    .map_err(|e| registration_error(e, "hospital", &license))?;
+    tx.commit().await.map_err(internal)?;
+    let token = register_owner(&auth, &user, &organization_id, "hospital").await?;
 
     Ok(Json(HospitalResponse {
         message: "Hospital registered successfully".to_string(),
+        organization_id,
+        registration_status: registration_status.to_string(),
+        token,
     }))
 }
 
 pub fn hospital_routes(pool: Arc<SqlitePool>) -> Router {
+    let guard = |action: Action| middleware::from_fn_with_state(action, authorize);
     Router::new()
-        .route("/api/hospital/dashboard", get(hospital_dashboard))
-        .route("/api/hospital/signup", post(signup_hospital))
+        .route("/api/hospital/dashboard", get(hospital_dashboard).route_layer(guard(Action::ViewHospitalDashboard)))
+        .route("/api/hospital/signup", post(signup_hospital).route_layer(guard(Action::RegisterHospital)))
         .with_state(pool)
 }
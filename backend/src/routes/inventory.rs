@@ -0,0 +1,510 @@
+use axum::{
+    middleware,
+    extract::{Json, Path, Query, State},
+    routing::{get, post},
+    http::StatusCode,
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::auth::rbac::{authorize, Action};
+use crate::auth::AuthUser;
+use crate::controlled;
+use crate::db::entities::{
+    add_inventory_event, add_reconciliation, batch_chain_head, batch_stock, compute_batch_hash, find_batch, find_organization,
+    find_reconciliation, inventory_balances, inventory_counts, inventory_movements, reconciliations, serials_gone_from, BatchBalance, BatchStock,
+    BatchTerms, InventoryCount, InventoryMovement, LedgerTx, MedicineBatch, MovementFilter, NewInventoryEvent, Organization,
+    Reconciliation,
+};
+use crate::dispensing::{normalize_serials, serials_in_batch};
+use crate::licensing::require_active;
+use crate::p2p::Node;
+use crate::routes::tracker::{submit, TrackerResponse};
+use crate::utils::signatures::sign_data;
+
+type ApiError = (StatusCode, String);
+
+fn internal(e: sqlx::Error) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// Quantities are checked and changed one request at a time, so two withdrawals cannot both
+/// spend the same stock.
+pub(crate) static STOCK_LOCK: Mutex<()> = Mutex::const_new(());
+
+#[derive(Deserialize)]
+pub struct BalanceQuery {
+    /// RFC 3339; defaults to now.
+    #[serde(default)]
+    pub at: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct MedicineTotal {
+    pub medicine_name: String,
+    pub quantity: i64,
+    pub batches: usize,
+}
+
+#[derive(Serialize)]
+pub struct InventoryReport {
+    pub organization: Organization,
+    pub at: String,
+    pub medicines: Vec<MedicineTotal>,
+    pub batches: Vec<BatchBalance>,
+}
+
+#[derive(Deserialize)]
+pub struct SplitRequest {
+    pub batch_id: String,
+    /// Id of the batch split off.
+    pub new_batch_id: String,
+    pub quantity: i64,
+    /// The packs split off, one per unit; required when the batch lists serials.
+    #[serde(default)]
+    pub serials: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct WithdrawalRequest {
+    pub batch_id: String,
+    pub quantity: i64,
+    /// Why the stock is destroyed; required.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// A destruction certificate or other reference.
+    #[serde(default)]
+    pub reference: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct StockChange {
+    pub batch_id: String,
+    pub kind: String,
+    /// Signed change to the batch.
+    pub quantity: i64,
+    /// What is left of the batch afterwards.
+    pub remaining: i64,
+    pub occurred_at: String,
+}
+
+#[derive(Deserialize)]
+pub struct CountedBatch {
+    pub batch_id: String,
+    pub counted_quantity: i64,
+    /// Required when the count differs from the derived balance.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ReconcileRequest {
+    pub counts: Vec<CountedBatch>,
+    #[serde(default)]
+    pub note: Option<String>,
+    /// Compare only; record nothing.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize)]
+pub struct ReconciliationReport {
+    /// Unset for a dry run.
+    pub reconciliation: Option<Reconciliation>,
+    pub counts: Vec<InventoryCount>,
+    /// Batches the organization holds according to the ledger that the count did not cover.
+    pub uncounted: Vec<BatchBalance>,
+}
+
+#[derive(Serialize)]
+pub struct ReconciliationDetail {
+    #[serde(flatten)]
+    pub reconciliation: Reconciliation,
+    pub counts: Vec<InventoryCount>,
+}
+
+/// The organization the caller's session acts for.
+pub(crate) async fn caller_organization(pool: &SqlitePool, user: &AuthUser) -> Result<Organization, ApiError> {
+    let organization = match user.organization.as_deref() {
+        Some(id) => find_organization(pool, id).await.map_err(internal)?,
+        None => None,
+    };
+    organization.ok_or((StatusCode::FORBIDDEN, "Register or join an organization to do this".to_string()))
+}
+
+/// The batch's position, as long as `organization` holds it and it has a quantity.
+pub(crate) async fn held_stock(node: &Node, organization: &Organization, batch_id: &str) -> Result<(BatchStock, i64), ApiError> {
+    let stock = batch_stock(&node.pool, batch_id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Batch not found or not sealed yet".to_string()))?;
+    if stock.holder != organization.id {
+        return Err((StatusCode::FORBIDDEN, format!("Batch {} is not held by {}", batch_id, organization.name)));
+    }
+    let quantity = stock
+        .quantity
+        .ok_or((StatusCode::CONFLICT, format!("Batch {} was created without a quantity", batch_id)))?;
+    Ok((stock, quantity))
+}
+
+/// Serials of the packs still in a batch: those it lists, less those split off (sealed or waiting
+/// for a block) and dispensed. `None` if the batch lists none.
+pub(crate) async fn batch_serials(node: &Node, batch: &MedicineBatch) -> Result<Option<Vec<String>>, ApiError> {
+    let Some(serials) = &batch.terms.serials else {
+        return Ok(None);
+    };
+    let mut gone = serials_gone_from(&node.pool, &batch.batch_id).await.map_err(internal)?;
+    gone.extend(node.mempool.lock().await.pending().iter().flat_map(|tx| match tx {
+        LedgerTx::Batch(b) if b.terms.parent_batch_id.as_deref() == Some(batch.batch_id.as_str()) => {
+            b.terms.serials.clone().unwrap_or_default()
+        }
+        _ => Vec::new(),
+    }));
+    Ok(Some(serials.iter().filter(|s| !gone.contains(s)).cloned().collect()))
+}
+
+fn parse_time(value: &str) -> Result<String, ApiError> {
+    DateTime::parse_from_rfc3339(value.trim())
+        .map(|t| t.with_timezone(&Utc).to_rfc3339())
+        .map_err(|_| (StatusCode::BAD_REQUEST, format!("'{}' is not an RFC 3339 time", value)))
+}
+
+// GET /api/inventory
+/// What the caller's organization holds, now or at `at`, per batch and per medicine.
+async fn get_inventory(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Query(query): Query<BalanceQuery>,
+) -> Result<Json<InventoryReport>, ApiError> {
+    let organization = caller_organization(&node.pool, &user).await?;
+    let at = match query.at.as_deref() {
+        Some(at) => parse_time(at)?,
+        None => Utc::now().to_rfc3339(),
+    };
+    let batches = inventory_balances(&node.pool, &organization, &at).await.map_err(internal)?;
+
+    let mut totals: BTreeMap<&str, MedicineTotal> = BTreeMap::new();
+    for batch in &batches {
+        let total = totals.entry(&batch.medicine_name).or_insert_with(|| MedicineTotal {
+            medicine_name: batch.medicine_name.clone(),
+            quantity: 0,
+            batches: 0,
+        });
+        total.quantity += batch.quantity;
+        total.batches += 1;
+    }
+    let medicines = totals.into_values().collect();
+
+    Ok(Json(InventoryReport { organization, at, medicines, batches }))
+}
+
+// GET /api/inventory/movements
+async fn list_movements(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Query(filter): Query<MovementFilter>,
+) -> Result<Json<Vec<InventoryMovement>>, ApiError> {
+    let organization = caller_organization(&node.pool, &user).await?;
+    inventory_movements(&node.pool, &organization, &filter)
+        .await
+        .map(Json)
+        .map_err(internal)
+}
+
+// POST /api/inventory/split
+/// Splits part of a held batch into a new ledger batch at the same location. The new batch keeps
+/// the original's maker, medicine and terms, and names the original as its parent. A batch that
+/// lists its packs hands the serials split off to the new batch.
+async fn split_batch(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Json(request): Json<SplitRequest>,
+) -> Result<Json<TrackerResponse>, ApiError> {
+    let organization = caller_organization(&node.pool, &user).await?;
+    require_active(&node.pool, &organization.id).await?;
+    if request.quantity <= 0 {
+        return Err((StatusCode::BAD_REQUEST, "quantity must be positive".to_string()));
+    }
+    let new_batch_id = request.new_batch_id.trim().to_string();
+    if new_batch_id.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "new_batch_id is required".to_string()));
+    }
+
+    let _guard = STOCK_LOCK.lock().await;
+    let (_, quantity) = held_stock(&node, &organization, &request.batch_id).await?;
+    controlled::require_licensed_batch(&node, &organization, &request.batch_id).await?;
+    // Splits still waiting to be sealed have already spent their share.
+    let pending: i64 = node
+        .mempool
+        .lock()
+        .await
+        .pending()
+        .iter()
+        .filter_map(|tx| match tx {
+            LedgerTx::Batch(b) if b.terms.parent_batch_id.as_deref() == Some(request.batch_id.as_str()) => b.terms.quantity,
+            _ => None,
+        })
+        .sum();
+    if request.quantity >= quantity - pending {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Batch {} has {} left; a split must leave some behind", request.batch_id, quantity - pending),
+        ));
+    }
+
+    let parent = find_batch(&node.pool, &request.batch_id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Batch not found or not sealed yet".to_string()))?;
+    let (_, location) = batch_chain_head(&node.pool, &parent.batch_id, i64::MAX)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Batch not found or not sealed yet".to_string()))?;
+    let serials = match batch_serials(&node, &parent).await? {
+        Some(in_batch) => {
+            let serials = normalize_serials(&request.serials).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            if serials.len() as i64 != request.quantity {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Batch {} lists its packs; give the serial of each unit split off", parent.batch_id),
+                ));
+            }
+            serials_in_batch(&serials, &parent.batch_id, &in_batch).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            Some(serials)
+        }
+        None if !request.serials.is_empty() => {
+            return Err((StatusCode::BAD_REQUEST, format!("Batch {} lists no pack serials", parent.batch_id)));
+        }
+        None => None,
+    };
+
+    let previous_hash = "GENESIS".to_string();
+    let mut record = MedicineBatch {
+        batch_id: new_batch_id,
+        organization: parent.organization,
+        medicine_name: parent.medicine_name,
+        source: parent.source,
+        destination: location,
+        timestamp: Utc::now().to_rfc3339(),
+        hash: String::new(),
+        previous_hash: previous_hash.clone(),
+        signature: None,
+        public_key: Some(node.public_key_b64.clone()),
+        terms: BatchTerms {
+            quantity: Some(request.quantity),
+            parent_batch_id: Some(parent.batch_id),
+            serials,
+            ..parent.terms
+        },
+    };
+    let batch_hash = compute_batch_hash(&record);
+    let signature_base64 = STANDARD.encode(sign_data(&node.private_key, batch_hash.as_bytes()));
+    record.hash = batch_hash.clone();
+    record.signature = Some(signature_base64.clone());
+    submit(&node, LedgerTx::Batch(record)).await?;
+
+    Ok(Json(TrackerResponse {
+        message: "Split batch signed and queued for the next block".to_string(),
+        batch_hash,
+        previous_hash,
+        signature: signature_base64,
+        public_key: node.public_key_b64.clone(),
+    }))
+}
+
+// POST /api/inventory/destroy
+/// Takes `quantity` units out of a held batch, recording why. Patients are dispensed to through
+/// `/api/dispensing`, which ties the units to a prescription and puts them on the ledger.
+async fn destroy(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Json(request): Json<WithdrawalRequest>,
+) -> Result<Json<StockChange>, ApiError> {
+    let organization = caller_organization(&node.pool, &user).await?;
+    require_active(&node.pool, &organization.id).await?;
+    if request.quantity <= 0 {
+        return Err((StatusCode::BAD_REQUEST, "quantity must be positive".to_string()));
+    }
+    let reason = request
+        .reason
+        .as_deref()
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .ok_or((StatusCode::BAD_REQUEST, "A reason is required to destroy stock".to_string()))?;
+
+    let _guard = STOCK_LOCK.lock().await;
+    let (stock, quantity) = held_stock(&node, &organization, &request.batch_id).await?;
+    controlled::require_licensed_batch(&node, &organization, &stock.batch_id).await?;
+    if request.quantity > quantity {
+        return Err((StatusCode::CONFLICT, format!("Batch {} has only {} left", stock.batch_id, quantity)));
+    }
+
+    let occurred_at = Utc::now().to_rfc3339();
+    let mut conn = node.pool.acquire().await.map_err(internal)?;
+    add_inventory_event(&mut conn, &NewInventoryEvent {
+        organization: &organization.id,
+        batch_id: &stock.batch_id,
+        kind: "destroyed",
+        quantity: -request.quantity,
+        reason: Some(reason),
+        reference: request.reference.as_deref(),
+        recorded_by: &user.user_id,
+        occurred_at: &occurred_at,
+    })
+    .await
+    .map_err(internal)?;
+
+    Ok(Json(StockChange {
+        batch_id: stock.batch_id,
+        kind: "destroyed".to_string(),
+        quantity: -request.quantity,
+        remaining: quantity - request.quantity,
+        occurred_at,
+    }))
+}
+
+// POST /api/inventory/reconcile
+/// Compares counted stock with the derived balances and, unless `dry_run`, records the count
+/// and an adjustment for every difference. Batches can only be counted by their holder.
+async fn reconcile(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Json(request): Json<ReconcileRequest>,
+) -> Result<Json<ReconciliationReport>, ApiError> {
+    let organization = caller_organization(&node.pool, &user).await?;
+    if !request.dry_run {
+        require_active(&node.pool, &organization.id).await?;
+    }
+    if request.counts.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "counts is empty".to_string()));
+    }
+
+    let _guard = STOCK_LOCK.lock().await;
+    let now = Utc::now().to_rfc3339();
+    let mut balances: HashMap<String, BatchBalance> = inventory_balances(&node.pool, &organization, &now)
+        .await
+        .map_err(internal)?
+        .into_iter()
+        .map(|b| (b.batch_id.clone(), b))
+        .collect();
+
+    let mut counts = Vec::with_capacity(request.counts.len());
+    for counted in request.counts {
+        if counted.counted_quantity < 0 {
+            return Err((StatusCode::BAD_REQUEST, format!("The count for batch {} is negative", counted.batch_id)));
+        }
+        if counts.iter().any(|c: &InventoryCount| c.batch_id == counted.batch_id) {
+            return Err((StatusCode::BAD_REQUEST, format!("Batch {} is counted twice", counted.batch_id)));
+        }
+        let (medicine_name, expected_quantity) = match balances.remove(&counted.batch_id) {
+            Some(balance) => (balance.medicine_name, balance.quantity),
+            // Held but used up, or not held at all
+            None => {
+                let (stock, quantity) = held_stock(&node, &organization, &counted.batch_id).await?;
+                (stock.medicine_name, quantity)
+            }
+        };
+        let difference = counted.counted_quantity - expected_quantity;
+        let reason = counted.reason.map(|r| r.trim().to_string()).filter(|r| !r.is_empty());
+        if difference != 0 && reason.is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Batch {} counts {} against {} expected; give a reason", counted.batch_id, counted.counted_quantity, expected_quantity),
+            ));
+        }
+        counts.push(InventoryCount {
+            batch_id: counted.batch_id,
+            medicine_name,
+            expected_quantity,
+            counted_quantity: counted.counted_quantity,
+            difference,
+            reason,
+        });
+    }
+    let mut uncounted: Vec<BatchBalance> = balances.into_values().collect();
+    uncounted.sort_by(|a, b| (&a.medicine_name, &a.batch_id).cmp(&(&b.medicine_name, &b.batch_id)));
+
+    if request.dry_run {
+        return Ok(Json(ReconciliationReport { reconciliation: None, counts, uncounted }));
+    }
+
+    for count in counts.iter().filter(|c| c.difference != 0) {
+        controlled::require_licensed_batch(&node, &organization, &count.batch_id).await?;
+    }
+    let note = request.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    let mut tx = node.pool.begin().await.map_err(internal)?;
+    let reconciliation = add_reconciliation(&mut tx, &organization.id, note, &user.user_id, &now, &counts)
+        .await
+        .map_err(internal)?;
+    let reference = reconciliation.id.to_string();
+    for count in counts.iter().filter(|c| c.difference != 0) {
+        add_inventory_event(&mut tx, &NewInventoryEvent {
+            organization: &organization.id,
+            batch_id: &count.batch_id,
+            kind: "adjusted",
+            quantity: count.difference,
+            reason: count.reason.as_deref(),
+            reference: Some(&reference),
+            recorded_by: &user.user_id,
+            occurred_at: &now,
+        })
+        .await
+        .map_err(internal)?;
+    }
+    tx.commit().await.map_err(internal)?;
+
+    Ok(Json(ReconciliationReport {
+        reconciliation: Some(reconciliation),
+        counts,
+        uncounted,
+    }))
+}
+
+// GET /api/inventory/reconciliations
+async fn list_reconciliations(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+) -> Result<Json<Vec<Reconciliation>>, ApiError> {
+    let organization = caller_organization(&node.pool, &user).await?;
+    reconciliations(&node.pool, &organization.id)
+        .await
+        .map(Json)
+        .map_err(internal)
+}
+
+// GET /api/inventory/reconciliations/:id
+async fn get_reconciliation(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(id): Path<i64>,
+) -> Result<Json<ReconciliationDetail>, ApiError> {
+    let organization = caller_organization(&node.pool, &user).await?;
+    let reconciliation = find_reconciliation(&node.pool, &organization.id, id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Reconciliation not found".to_string()))?;
+    let counts = inventory_counts(&node.pool, reconciliation.id).await.map_err(internal)?;
+    Ok(Json(ReconciliationDetail { reconciliation, counts }))
+}
+
+pub fn inventory_routes(node: Arc<Node>) -> Router {
+    let guard = |action: Action| middleware::from_fn_with_state(action, authorize);
+    let read_routes = Router::new()
+        .route("/api/inventory", get(get_inventory))
+        .route("/api/inventory/movements", get(list_movements))
+        .route("/api/inventory/reconciliations", get(list_reconciliations))
+        .route("/api/inventory/reconciliations/:id", get(get_reconciliation))
+        .route_layer(guard(Action::ViewInventory));
+    let write_routes = Router::new()
+        .route("/api/inventory/split", post(split_batch))
+        .route("/api/inventory/destroy", post(destroy))
+        .route("/api/inventory/reconcile", post(reconcile))
+        .route_layer(guard(Action::ManageInventory));
+    read_routes.merge(write_routes).with_state(node)
+}
@@ -2,17 +2,56 @@ pub mod company;
 pub mod customer;
 pub mod hospital;
 pub mod tracker;
-pub mod entities;
+pub mod auth;
+pub mod p2p;
+pub mod admin;
+pub mod orgs;
+pub mod sso;
+pub mod regulator;
+pub mod inventory;
+pub mod orders;
+pub mod demand;
+pub mod allocation;
+pub mod catalog;
+pub mod controlled;
+pub mod dispensing;
 
-use axum::Router;
+use axum::{Extension, Router};
 use std::sync::Arc;
 use sqlx::SqlitePool;
 
-pub fn create_routes(pool: Arc<SqlitePool>) -> Router {
+use crate::auth::Auth;
+use crate::dispensing::PatientHasher;
+use crate::licensing::Licensing;
+use crate::p2p::Node;
+
+pub fn create_routes(
+    pool: Arc<SqlitePool>,
+    node: Arc<Node>,
+    auth: Arc<Auth>,
+    licensing: Arc<Licensing>,
+    patients: Arc<PatientHasher>,
+) -> Router {
     Router::new()
+        .merge(auth::create_routes(auth.clone()))
+        .merge(orgs::org_routes())
+        .merge(sso::sso_routes())
         .merge(company::company_routes(pool.clone()))
         .merge(customer::customer_routes(pool.clone()))
         .merge(hospital::hospital_routes(pool.clone()))
-        .merge(tracker::tracker_routes(pool.clone())) // ✅ Add tracker routes
+        .merge(regulator::regulator_routes(node.clone()))
+        .merge(tracker::tracker_routes(node.clone())) // ✅ Add tracker routes
+        .merge(inventory::inventory_routes(node.clone()))
+        .merge(orders::order_routes(pool.clone()))
+        .merge(demand::demand_routes(pool.clone()))
+        .merge(allocation::allocation_routes(pool.clone()))
+        .merge(catalog::catalog_routes(pool.clone()))
+        .merge(controlled::controlled_routes(node.clone()))
+        .merge(dispensing::dispensing_routes(node.clone()))
+        .merge(p2p::p2p_routes(node.clone()))
+        .merge(admin::admin_routes(node.clone()))
+        .layer(Extension(auth))
+        .layer(Extension(licensing))
+        .layer(Extension(patients))
 }
 
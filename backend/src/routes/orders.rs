@@ -0,0 +1,397 @@
+use axum::{
+    middleware,
+    extract::{Json, Path, Query, State},
+    routing::{get, post},
+    http::StatusCode,
+    Router,
+};
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::sync::Arc;
+
+use crate::auth::rbac::{authorize, Action};
+use crate::auth::AuthUser;
+use crate::db::entities::{
+    add_order_shipment, add_purchase_order, batch_stock, cancel_purchase_order, delivered_to, find_batch, find_order_shipment,
+    find_organization, find_organization_by_ref, find_purchase_order, order_shipments, purchase_order_lines, purchase_orders,
+    record_order_delivery, respond_to_purchase_order, NewOrderLine, NewPurchaseOrder, OrderShipment, PurchaseOrder,
+    PurchaseOrderLine,
+};
+use crate::licensing::require_active;
+use crate::routes::inventory::caller_organization;
+
+type ApiError = (StatusCode, String);
+
+fn internal(e: sqlx::Error) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+#[derive(Deserialize)]
+pub struct OrderRequest {
+    /// The supplying company, by id or name.
+    pub company: String,
+    pub lines: Vec<NewOrderLine>,
+    /// `YYYY-MM-DD`.
+    #[serde(default)]
+    pub needed_by: Option<String>,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct OrderQuery {
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AcceptedLine {
+    pub line_no: i64,
+    pub accepted_quantity: i64,
+}
+
+#[derive(Deserialize)]
+pub struct AcceptRequest {
+    /// Lines filled only in part; the others are accepted in full.
+    #[serde(default)]
+    pub lines: Vec<AcceptedLine>,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RejectRequest {
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ShipmentLink {
+    pub line_no: i64,
+    pub batch_id: String,
+    /// Defaults to what is left of the batch.
+    #[serde(default)]
+    pub quantity: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct OrderDetail {
+    #[serde(flatten)]
+    pub order: PurchaseOrder,
+    pub lines: Vec<PurchaseOrderLine>,
+    pub shipments: Vec<OrderShipment>,
+}
+
+/// The order `id`, as long as the caller's organization is one of its parties.
+async fn party_order(pool: &SqlitePool, user: &AuthUser, id: &str) -> Result<PurchaseOrder, ApiError> {
+    let order = find_purchase_order(pool, id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Purchase order not found".to_string()))?;
+    match user.organization.as_deref() {
+        Some(organization) if organization == order.hospital || organization == order.company => Ok(order),
+        _ => Err((StatusCode::NOT_FOUND, "Purchase order not found".to_string())),
+    }
+}
+
+async fn order_detail(pool: &SqlitePool, order: PurchaseOrder) -> Result<OrderDetail, ApiError> {
+    let lines = purchase_order_lines(pool, &order.id).await.map_err(internal)?;
+    let shipments = order_shipments(pool, &order.id).await.map_err(internal)?;
+    Ok(OrderDetail { order, lines, shipments })
+}
+
+fn trimmed(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+// POST /api/orders
+/// Raises an order from the caller's hospital to a company.
+async fn raise_order(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Json(request): Json<OrderRequest>,
+) -> Result<Json<OrderDetail>, ApiError> {
+    let hospital = caller_organization(&pool, &user).await?;
+    if hospital.organization_type != "hospital" {
+        return Err((StatusCode::FORBIDDEN, "Register or join a hospital to do this".to_string()));
+    }
+    require_active(&pool, &hospital.id).await?;
+    let company = find_organization_by_ref(&pool, request.company.trim())
+        .await
+        .map_err(internal)?
+        .filter(|o| o.organization_type == "company")
+        .ok_or((StatusCode::BAD_REQUEST, format!("No company named {}", request.company)))?;
+    require_active(&pool, &company.id).await?;
+
+    if request.lines.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "An order needs at least one line".to_string()));
+    }
+    let mut lines = Vec::with_capacity(request.lines.len());
+    for line in request.lines {
+        let medicine_name = line.medicine_name.trim().to_string();
+        if medicine_name.is_empty() || line.quantity <= 0 {
+            return Err((StatusCode::BAD_REQUEST, "Each line needs a medicine_name and a positive quantity".to_string()));
+        }
+        lines.push(NewOrderLine { medicine_name, quantity: line.quantity });
+    }
+    let needed_by = trimmed(request.needed_by);
+    if let Some(date) = &needed_by {
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| (StatusCode::BAD_REQUEST, "needed_by must be YYYY-MM-DD".to_string()))?;
+        if date < Utc::now().date_naive() {
+            return Err((StatusCode::BAD_REQUEST, "needed_by is in the past".to_string()));
+        }
+    }
+
+    let now = Utc::now().to_rfc3339();
+    let note = trimmed(request.note);
+    let mut tx = pool.begin().await.map_err(internal)?;
+    let id = add_purchase_order(&mut tx, &NewPurchaseOrder {
+        hospital: &hospital.id,
+        company: &company.id,
+        needed_by: needed_by.as_deref(),
+        note: note.as_deref(),
+        created_by: &user.user_id,
+        created_at: &now,
+        lines: &lines,
+    })
+    .await
+    .map_err(internal)?;
+    tx.commit().await.map_err(internal)?;
+
+    let order = find_purchase_order(&pool, &id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Order vanished".to_string()))?;
+    Ok(Json(order_detail(&pool, order).await?))
+}
+
+// GET /api/orders
+/// Orders the caller's organization raised or received.
+async fn list_orders(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Query(query): Query<OrderQuery>,
+) -> Result<Json<Vec<PurchaseOrder>>, ApiError> {
+    let organization = user
+        .organization
+        .as_deref()
+        .ok_or((StatusCode::FORBIDDEN, "Register or join an organization to see its orders".to_string()))?;
+    purchase_orders(&pool, organization, query.status.as_deref())
+        .await
+        .map(Json)
+        .map_err(internal)
+}
+
+// GET /api/orders/:id
+async fn get_order(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Json<OrderDetail>, ApiError> {
+    let order = party_order(&pool, &user, &id).await?;
+    Ok(Json(order_detail(&pool, order).await?))
+}
+
+// POST /api/orders/:id/cancel
+/// The hospital withdraws an order the company has not answered yet.
+async fn cancel_order(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Json<OrderDetail>, ApiError> {
+    let order = party_order(&pool, &user, &id).await?;
+    if user.organization.as_deref() != Some(order.hospital.as_str()) {
+        return Err((StatusCode::FORBIDDEN, "Only the hospital that raised an order can cancel it".to_string()));
+    }
+    if !cancel_purchase_order(&pool, &order.id, &Utc::now().to_rfc3339()).await.map_err(internal)? {
+        return Err((StatusCode::CONFLICT, format!("The order is already {}", order.status)));
+    }
+    let order = party_order(&pool, &user, &id).await?;
+    Ok(Json(order_detail(&pool, order).await?))
+}
+
+/// The order `id`, as long as it was sent to the caller's company.
+async fn received_order(pool: &SqlitePool, user: &AuthUser, id: &str) -> Result<PurchaseOrder, ApiError> {
+    let order = party_order(pool, user, id).await?;
+    if user.organization.as_deref() != Some(order.company.as_str()) {
+        return Err((StatusCode::FORBIDDEN, "Only the company an order was sent to can fill it".to_string()));
+    }
+    require_active(pool, &order.company).await?;
+    Ok(order)
+}
+
+// POST /api/orders/:id/accept
+/// Accepts an order in full, or in part for the lines listed with a smaller quantity.
+async fn accept_order(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+    Json(request): Json<AcceptRequest>,
+) -> Result<Json<OrderDetail>, ApiError> {
+    let order = received_order(&pool, &user, &id).await?;
+    let lines = purchase_order_lines(&pool, &order.id).await.map_err(internal)?;
+
+    let mut accepted: Vec<(i64, i64)> = lines.iter().map(|l| (l.line_no, l.quantity)).collect();
+    for partial in &request.lines {
+        let line = lines
+            .iter()
+            .find(|l| l.line_no == partial.line_no)
+            .ok_or((StatusCode::BAD_REQUEST, format!("The order has no line {}", partial.line_no)))?;
+        if !(0..=line.quantity).contains(&partial.accepted_quantity) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Line {} can accept between 0 and {}", line.line_no, line.quantity),
+            ));
+        }
+        if let Some(entry) = accepted.iter_mut().find(|(line_no, _)| *line_no == line.line_no) {
+            entry.1 = partial.accepted_quantity;
+        }
+    }
+    if accepted.iter().all(|(_, quantity)| *quantity == 0) {
+        return Err((StatusCode::BAD_REQUEST, "Nothing accepted; reject the order instead".to_string()));
+    }
+    let status = if lines.iter().zip(&accepted).all(|(line, (_, quantity))| line.quantity == *quantity) {
+        "accepted"
+    } else {
+        "partially_accepted"
+    };
+
+    let note = trimmed(request.note);
+    let mut tx = pool.begin().await.map_err(internal)?;
+    let responded = respond_to_purchase_order(&mut tx, &order.id, status, &accepted, note.as_deref(), &user.user_id, &Utc::now().to_rfc3339())
+        .await
+        .map_err(internal)?;
+    if !responded {
+        return Err((StatusCode::CONFLICT, format!("The order is already {}", order.status)));
+    }
+    tx.commit().await.map_err(internal)?;
+
+    let order = party_order(&pool, &user, &id).await?;
+    Ok(Json(order_detail(&pool, order).await?))
+}
+
+// POST /api/orders/:id/reject
+async fn reject_order(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+    Json(request): Json<RejectRequest>,
+) -> Result<Json<OrderDetail>, ApiError> {
+    let order = received_order(&pool, &user, &id).await?;
+    let note = trimmed(request.note);
+    let mut tx = pool.begin().await.map_err(internal)?;
+    let responded = respond_to_purchase_order(&mut tx, &order.id, "rejected", &[], note.as_deref(), &user.user_id, &Utc::now().to_rfc3339())
+        .await
+        .map_err(internal)?;
+    if !responded {
+        return Err((StatusCode::CONFLICT, format!("The order is already {}", order.status)));
+    }
+    tx.commit().await.map_err(internal)?;
+
+    let order = party_order(&pool, &user, &id).await?;
+    Ok(Json(order_detail(&pool, order).await?))
+}
+
+// POST /api/orders/:id/shipments
+/// Links one of the company's sealed batches to an accepted order line. The line counts as
+/// delivered once the hospital acknowledges the transfer that brings the batch to it.
+async fn link_shipment(
+    State(pool): State<Arc<SqlitePool>>,
+    user: AuthUser,
+    Path(id): Path<String>,
+    Json(link): Json<ShipmentLink>,
+) -> Result<Json<OrderDetail>, ApiError> {
+    let order = received_order(&pool, &user, &id).await?;
+    if !matches!(order.status.as_str(), "accepted" | "partially_accepted" | "partially_fulfilled") {
+        return Err((StatusCode::CONFLICT, format!("Cannot ship against an order that is {}", order.status)));
+    }
+    let line = purchase_order_lines(&pool, &order.id)
+        .await
+        .map_err(internal)?
+        .into_iter()
+        .find(|l| l.line_no == link.line_no)
+        .ok_or((StatusCode::BAD_REQUEST, format!("The order has no line {}", link.line_no)))?;
+
+    let batch = find_batch(&pool, &link.batch_id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Batch not found or not sealed yet".to_string()))?;
+    if batch.organization != order.company {
+        return Err((StatusCode::FORBIDDEN, "Only batches the company created can fill its orders".to_string()));
+    }
+    if !batch.medicine_name.trim().eq_ignore_ascii_case(&line.medicine_name) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Batch {} is {}, but line {} is for {}", batch.batch_id, batch.medicine_name, line.line_no, line.medicine_name),
+        ));
+    }
+    if let Some(existing) = find_order_shipment(&pool, &batch.batch_id).await.map_err(internal)? {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Batch {} already ships against order {}", batch.batch_id, existing.order_id),
+        ));
+    }
+
+    let remaining = batch_stock(&pool, &batch.batch_id).await.map_err(internal)?.and_then(|s| s.quantity);
+    let quantity = match (link.quantity, remaining) {
+        (Some(quantity), Some(remaining)) if quantity > remaining => {
+            return Err((StatusCode::BAD_REQUEST, format!("Batch {} has only {} left", batch.batch_id, remaining)));
+        }
+        (Some(quantity), _) => quantity,
+        (None, Some(remaining)) => remaining,
+        (None, None) => {
+            return Err((StatusCode::BAD_REQUEST, "The batch has no quantity; give one".to_string()));
+        }
+    };
+    let open = line.accepted_quantity.unwrap_or(0) - line.shipped_quantity;
+    if quantity <= 0 || quantity > open {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Line {} has {} left to ship; the shipment must be between 1 and that", line.line_no, open.max(0)),
+        ));
+    }
+
+    add_order_shipment(&pool, &OrderShipment {
+        batch_id: batch.batch_id.clone(),
+        order_id: order.id.clone(),
+        line_no: line.line_no,
+        quantity,
+        linked_by: user.user_id.clone(),
+        linked_at: Utc::now().to_rfc3339(),
+        delivered_at: None,
+    })
+    .await
+    .map_err(internal)?;
+
+    // Linked after the hospital already took delivery
+    let hospital = find_organization(&pool, &order.hospital).await.map_err(internal)?;
+    if let Some(hospital) = hospital
+        && let Some(acknowledged_at) = delivered_to(&pool, &batch.batch_id, &hospital).await.map_err(internal)?
+    {
+        record_order_delivery(&pool, &batch.batch_id, &hospital.id, &acknowledged_at)
+            .await
+            .map_err(internal)?;
+    }
+
+    let order = party_order(&pool, &user, &id).await?;
+    Ok(Json(order_detail(&pool, order).await?))
+}
+
+pub fn order_routes(pool: Arc<SqlitePool>) -> Router {
+    let guard = |action: Action| middleware::from_fn_with_state(action, authorize);
+    Router::new()
+        .route(
+            "/api/orders",
+            get(list_orders)
+                .route_layer(guard(Action::ViewOrders))
+                .merge(post(raise_order).route_layer(guard(Action::RaiseOrder))),
+        )
+        .route("/api/orders/:id", get(get_order).route_layer(guard(Action::ViewOrders)))
+        .route("/api/orders/:id/cancel", post(cancel_order).route_layer(guard(Action::RaiseOrder)))
+        .route("/api/orders/:id/accept", post(accept_order).route_layer(guard(Action::FillOrder)))
+        .route("/api/orders/:id/reject", post(reject_order).route_layer(guard(Action::FillOrder)))
+        .route("/api/orders/:id/shipments", post(link_shipment).route_layer(guard(Action::FillOrder)))
+        .with_state(pool)
+}
@@ -0,0 +1,435 @@
+use axum::{
+    extract::{Extension, Json, Path},
+    http::StatusCode,
+    routing::{delete, get, post},
+    Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+use crate::auth::api_keys::{generate_key, hash_key_secret};
+use crate::auth::rbac::{permits, Role, Scope};
+use crate::auth::tokens::switch_organization;
+use crate::auth::{Auth, AuthUser};
+use crate::db::{
+    add_membership, create_api_key, create_invite, find_invite, find_membership, organization_api_keys,
+    redeem_invite, revoke_api_key, user_memberships,
+};
+use crate::db::entities::{find_organization_site, find_registration, renew_registration, save_organization_site, OrganizationSite, Registration};
+use crate::licensing::{registration_error, Licensing};
+use crate::models::{ApiKey, ApiResponse, Membership};
+
+#[derive(Deserialize)]
+pub struct SwitchRequest {
+    pub organization_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct InviteRequest {
+    /// `member` (default) or `owner`.
+    #[serde(default)]
+    pub role: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct JoinRequest {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct ApiKeyRequest {
+    pub name: String,
+    /// Any of `batch:write`, `checkpoint:write` and `read`.
+    pub scopes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LicenseRenewal {
+    /// The renewed license number; the current one when omitted.
+    #[serde(default)]
+    pub license_id: Option<String>,
+    pub license_expires_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SiteRequest {
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(default)]
+    pub region: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyResponse {
+    /// Shown once; only its hash is kept.
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+}
+
+#[derive(Serialize)]
+pub struct OrganizationsResponse {
+    pub active: Option<String>,
+    pub memberships: Vec<Membership>,
+}
+
+#[derive(Serialize)]
+pub struct SwitchResponse {
+    pub token: String,
+    pub organization_id: String,
+}
+
+#[derive(Serialize)]
+pub struct InviteResponse {
+    pub code: String,
+    pub organization_id: String,
+    pub role: String,
+    pub expires_at: String,
+}
+
+#[derive(Serialize)]
+pub struct JoinResponse {
+    pub message: String,
+    pub organization_id: String,
+    /// Fresh access token acting for the organization, when the session had none yet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+type ApiError = (StatusCode, String);
+
+fn internal(e: sqlx::Error) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn hash_code(code: &str) -> String {
+    hex::encode(Sha256::digest(code.trim().as_bytes()))
+}
+
+/// Makes the caller the owner of an organization they just registered. Returns an access token
+/// acting for it if the caller's session had no organization yet.
+pub async fn register_owner(
+    auth: &Auth,
+    user: &AuthUser,
+    organization_id: &str,
+    organization_type: &str,
+) -> Result<Option<String>, ApiError> {
+    add_membership(&auth.pool, &user.user_id, organization_id, organization_type, "owner")
+        .await
+        .map_err(internal)?;
+    if user.organization.is_some() {
+        return Ok(None);
+    }
+    switch_organization(auth, user, organization_id).await.map(Some)
+}
+
+/// Redeems an invite code for a user whose account role is `user_role`.
+/// The organization must be of the kind that role acts for: customers cannot join a company.
+pub async fn join_with_invite(auth: &Auth, user_id: &str, user_role: &str, code: &str) -> Result<String, ApiError> {
+    let code_hash = hash_code(code);
+    let unavailable = || (StatusCode::NOT_FOUND, "Invite code is invalid, used or expired".to_string());
+
+    // Check the kind first so a mismatched account does not use up the code.
+    let invite = find_invite(&auth.pool, &code_hash).await.map_err(internal)?.ok_or_else(unavailable)?;
+    if invite.organization_type != user_role {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("A {} account cannot join a {}", user_role, invite.organization_type),
+        ));
+    }
+    let invite = redeem_invite(&auth.pool, &code_hash, user_id)
+        .await
+        .map_err(internal)?
+        .ok_or_else(unavailable)?;
+
+    add_membership(&auth.pool, user_id, &invite.organization_id, &invite.organization_type, &invite.role)
+        .await
+        .map_err(internal)?;
+    Ok(invite.organization_id)
+}
+
+// GET /api/orgs
+async fn list_organizations(
+    Extension(auth): Extension<Arc<Auth>>,
+    user: AuthUser,
+) -> Result<Json<OrganizationsResponse>, ApiError> {
+    Ok(Json(OrganizationsResponse {
+        active: user.organization,
+        memberships: user_memberships(&auth.pool, &user.user_id).await.map_err(internal)?,
+    }))
+}
+
+// POST /api/orgs/switch
+async fn switch(
+    Extension(auth): Extension<Arc<Auth>>,
+    user: AuthUser,
+    Json(request): Json<SwitchRequest>,
+) -> Result<Json<SwitchResponse>, ApiError> {
+    let token = switch_organization(&auth, &user, &request.organization_id).await?;
+    Ok(Json(SwitchResponse {
+        token,
+        organization_id: request.organization_id,
+    }))
+}
+
+/// The caller's membership in the organization their session acts for, if they own it.
+async fn owned_organization(auth: &Auth, user: &AuthUser) -> Result<Membership, ApiError> {
+    if user.api_key_scopes.is_some() {
+        return Err((StatusCode::FORBIDDEN, "API keys cannot manage organizations".to_string()));
+    }
+    auth.require_mfa(user)?;
+    let organization_id = user
+        .organization
+        .as_deref()
+        .ok_or((StatusCode::FORBIDDEN, "Your session is not acting for an organization".to_string()))?;
+    find_membership(&auth.pool, &user.user_id, organization_id)
+        .await
+        .map_err(internal)?
+        .filter(|m| m.role == "owner")
+        .ok_or((StatusCode::FORBIDDEN, "Only owners can do that".to_string()))
+}
+
+// POST /api/orgs/invites
+/// Creates a single-use code for joining the caller's active organization. Owners only.
+async fn invite(
+    Extension(auth): Extension<Arc<Auth>>,
+    user: AuthUser,
+    Json(request): Json<InviteRequest>,
+) -> Result<Json<InviteResponse>, ApiError> {
+    let membership = owned_organization(&auth, &user).await?;
+
+    let role = request.role.unwrap_or_else(|| "member".to_string());
+    if role != "member" && role != "owner" {
+        return Err((StatusCode::BAD_REQUEST, format!("Unknown organization role '{}'", role)));
+    }
+
+    let mut code = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut code);
+    let code = URL_SAFE_NO_PAD.encode(code);
+    let expires_at = (Utc::now() + Duration::days(7)).to_rfc3339();
+    create_invite(
+        &auth.pool,
+        &hash_code(&code),
+        &membership.organization_id,
+        &membership.organization_type,
+        &role,
+        &user.user_id,
+        &expires_at,
+    )
+    .await
+    .map_err(internal)?;
+
+    Ok(Json(InviteResponse {
+        code,
+        organization_id: membership.organization_id,
+        role,
+        expires_at,
+    }))
+}
+
+// POST /api/orgs/join
+async fn join(
+    Extension(auth): Extension<Arc<Auth>>,
+    user: AuthUser,
+    Json(request): Json<JoinRequest>,
+) -> Result<Json<JoinResponse>, ApiError> {
+    let organization_id = join_with_invite(&auth, &user.user_id, &user.role, &request.code).await?;
+    let token = match user.organization {
+        Some(_) => None,
+        None => Some(switch_organization(&auth, &user, &organization_id).await?),
+    };
+
+    Ok(Json(JoinResponse {
+        message: "Joined organization".to_string(),
+        organization_id,
+        token,
+    }))
+}
+
+// POST /api/orgs/keys
+/// Issues an API key for the caller's active organization. Owners only; the key can only
+/// hold scopes the organization's role is allowed to use.
+async fn create_key(
+    Extension(auth): Extension<Arc<Auth>>,
+    user: AuthUser,
+    Json(request): Json<ApiKeyRequest>,
+) -> Result<Json<ApiKeyResponse>, ApiError> {
+    let membership = owned_organization(&auth, &user).await?;
+    let role = membership
+        .organization_type
+        .parse::<Role>()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    let mut scopes: Vec<Scope> = Vec::new();
+    for name in &request.scopes {
+        let scope = name.parse::<Scope>().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        if !permits(role, scope.action()) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("A {} cannot hold the {} scope", role.as_str(), scope.as_str()),
+            ));
+        }
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    if scopes.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "An API key needs at least one scope".to_string()));
+    }
+
+    let (id, secret, key) = generate_key();
+    let api_key = ApiKey {
+        id,
+        organization_id: membership.organization_id,
+        organization_type: membership.organization_type,
+        name: request.name,
+        scopes: scopes.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(","),
+        key_hash: hash_key_secret(&secret),
+        created_by: user.user_id,
+        created_at: Utc::now().to_rfc3339(),
+        last_used_at: None,
+        revoked_at: None,
+    };
+    create_api_key(&auth.pool, &api_key).await.map_err(internal)?;
+
+    Ok(Json(ApiKeyResponse { key, api_key }))
+}
+
+// GET /api/orgs/keys
+async fn list_keys(
+    Extension(auth): Extension<Arc<Auth>>,
+    user: AuthUser,
+) -> Result<Json<Vec<ApiKey>>, ApiError> {
+    let membership = owned_organization(&auth, &user).await?;
+    organization_api_keys(&auth.pool, &membership.organization_id)
+        .await
+        .map(Json)
+        .map_err(internal)
+}
+
+// DELETE /api/orgs/keys/:id
+async fn revoke_key(
+    Extension(auth): Extension<Arc<Auth>>,
+    user: AuthUser,
+    Path(key_id): Path<String>,
+) -> Result<Json<ApiResponse>, ApiError> {
+    let membership = owned_organization(&auth, &user).await?;
+    if !revoke_api_key(&auth.pool, &key_id, &membership.organization_id).await.map_err(internal)? {
+        return Err((StatusCode::NOT_FOUND, "No such live key for this organization".to_string()));
+    }
+    Ok(Json(ApiResponse {
+        message: format!("API key {} revoked", key_id),
+    }))
+}
+
+// GET /api/orgs/license
+/// The active organization's registration and where its review stands.
+async fn get_license(
+    Extension(auth): Extension<Arc<Auth>>,
+    user: AuthUser,
+) -> Result<Json<Registration>, ApiError> {
+    let organization_id = user
+        .organization
+        .as_deref()
+        .ok_or((StatusCode::FORBIDDEN, "Your session is not acting for an organization".to_string()))?;
+    find_registration(&auth.pool, organization_id)
+        .await
+        .map_err(internal)?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "No registration on file".to_string()))
+}
+
+// PUT /api/orgs/license
+/// Submits a renewed license for the caller's organization, which goes back to the regulator
+/// for review. Owners only; suspended organizations must be reinstated first.
+async fn renew_license(
+    Extension(auth): Extension<Arc<Auth>>,
+    Extension(licensing): Extension<Arc<Licensing>>,
+    user: AuthUser,
+    Json(request): Json<LicenseRenewal>,
+) -> Result<Json<Registration>, ApiError> {
+    let membership = owned_organization(&auth, &user).await?;
+    let current = find_registration(&auth.pool, &membership.organization_id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "No registration on file".to_string()))?;
+    if current.status == "suspended" {
+        return Err((StatusCode::FORBIDDEN, "A suspended organization must be reinstated by a regulator".to_string()));
+    }
+
+    let number = request.license_id.as_deref().unwrap_or(&current.license_number);
+    let license = licensing
+        .validate(&current.organization_type, Some(&current.jurisdiction), number, request.license_expires_at.as_deref())
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    renew_registration(
+        &auth.pool,
+        &membership.organization_id,
+        &license.number,
+        license.expires_at.as_deref(),
+        &user.user_id,
+    )
+    .await
+    .map_err(|e| registration_error(e, &current.organization_type, &license))?;
+
+    find_registration(&auth.pool, &membership.organization_id)
+        .await
+        .map_err(internal)?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "No registration on file".to_string()))
+}
+
+// GET /api/orgs/site
+/// Where the active organization is, as used for supplier distances and regional demand.
+async fn get_site(
+    Extension(auth): Extension<Arc<Auth>>,
+    user: AuthUser,
+) -> Result<Json<OrganizationSite>, ApiError> {
+    let organization_id = user
+        .organization
+        .as_deref()
+        .ok_or((StatusCode::FORBIDDEN, "Your session is not acting for an organization".to_string()))?;
+    find_organization_site(&auth.pool, organization_id)
+        .await
+        .map_err(internal)?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "No site on file".to_string()))
+}
+
+// PUT /api/orgs/site
+/// Records the active organization's coordinates and region. Owners only.
+async fn set_site(
+    Extension(auth): Extension<Arc<Auth>>,
+    user: AuthUser,
+    Json(request): Json<SiteRequest>,
+) -> Result<Json<OrganizationSite>, ApiError> {
+    let membership = owned_organization(&auth, &user).await?;
+    if !(-90.0..=90.0).contains(&request.latitude) || !(-180.0..=180.0).contains(&request.longitude) {
+        return Err((StatusCode::BAD_REQUEST, "latitude must be within ±90 and longitude within ±180".to_string()));
+    }
+    let site = OrganizationSite {
+        organization_id: membership.organization_id,
+        latitude: request.latitude,
+        longitude: request.longitude,
+        region: request.region.map(|r| r.trim().to_string()).filter(|r| !r.is_empty()),
+        updated_by: user.user_id,
+        updated_at: Utc::now().to_rfc3339(),
+    };
+    save_organization_site(&auth.pool, &site).await.map_err(internal)?;
+    Ok(Json(site))
+}
+
+pub fn org_routes() -> Router {
+    Router::new()
+        .route("/api/orgs", get(list_organizations))
+        .route("/api/orgs/switch", post(switch))
+        .route("/api/orgs/invites", post(invite))
+        .route("/api/orgs/join", post(join))
+        .route("/api/orgs/keys", get(list_keys).post(create_key))
+        .route("/api/orgs/keys/:id", delete(revoke_key))
+        .route("/api/orgs/license", get(get_license).put(renew_license))
+        .route("/api/orgs/site", get(get_site).put(set_site))
+}
@@ -0,0 +1,134 @@
+use axum::{
+    extract::{Json, Query, State},
+    routing::{get, post},
+    http::StatusCode,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::db::entities::{blocks_from_height, Block};
+use crate::p2p::poa::{apply_block, ApplyResult};
+use crate::p2p::sync::{local_status, sync_from_peer, BlockMessage, NodeStatus, TxMessage, PAGE_SIZE};
+use crate::p2p::Node;
+
+#[derive(Deserialize)]
+pub struct BlocksQuery {
+    pub from: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ReceiveResponse {
+    pub accepted: bool,
+    pub message: String,
+}
+
+// GET /api/p2p/status
+async fn status(
+    State(node): State<Arc<Node>>,
+) -> Result<Json<NodeStatus>, (StatusCode, String)> {
+    local_status(&node)
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+// GET /api/p2p/blocks?from=<height>
+async fn get_blocks(
+    State(node): State<Arc<Node>>,
+    Query(query): Query<BlocksQuery>,
+) -> Result<Json<Vec<Block>>, (StatusCode, String)> {
+    blocks_from_height(&node.pool, query.from.unwrap_or(1), PAGE_SIZE)
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+// POST /api/p2p/blocks
+/// Takes a block from the authority that signed the envelope. A block failing its seal check
+/// gets that sender rejected; a sealed block conflicting with our chain gets its producer rejected.
+async fn receive_block(
+    State(node): State<Arc<Node>>,
+    Json(message): Json<BlockMessage>,
+) -> Result<Json<ReceiveResponse>, (StatusCode, String)> {
+    let sender = message
+        .envelope
+        .open(&node, "block", &message.block.header.hash)
+        .map_err(|reason| (StatusCode::FORBIDDEN, reason))?
+        .clone();
+    if node.is_rejected(&sender.node_id).await {
+        return Err((StatusCode::FORBIDDEN, format!("{} has been rejected", sender.node_id)));
+    }
+
+    let height = message.block.header.height;
+    let result = apply_block(&node, &message.block)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    match result {
+        ApplyResult::Applied => Ok(Json(ReceiveResponse {
+            accepted: true,
+            message: format!("Applied height {}", height),
+        })),
+        ApplyResult::AlreadyKnown => Ok(Json(ReceiveResponse {
+            accepted: true,
+            message: format!("Height {} already known", height),
+        })),
+        ApplyResult::SideChain => Ok(Json(ReceiveResponse {
+            accepted: true,
+            message: format!("Height {} kept as a side branch", height),
+        })),
+        ApplyResult::Gap => {
+            // We missed earlier blocks or the block's branch; catch up from the sender, which must have them.
+            sync_from_peer(&node, &sender)
+                .await
+                .map_err(|e| (StatusCode::BAD_GATEWAY, e))?;
+            Ok(Json(ReceiveResponse {
+                accepted: true,
+                message: format!("Synced up to height {} from {}", height, sender.node_id),
+            }))
+        }
+        ApplyResult::Invalid(reason) => {
+            node.reject_peer(&sender.node_id, &reason).await;
+            Err((StatusCode::CONFLICT, reason))
+        }
+        ApplyResult::Diverged(reason) => {
+            node.reject_peer(&message.block.header.producer, &reason).await;
+            Err((StatusCode::CONFLICT, reason))
+        }
+    }
+}
+
+// POST /api/p2p/transactions
+async fn receive_transaction(
+    State(node): State<Arc<Node>>,
+    Json(message): Json<TxMessage>,
+) -> Result<Json<ReceiveResponse>, (StatusCode, String)> {
+    let sender = message
+        .envelope
+        .open(&node, "tx", message.tx.hash())
+        .map_err(|reason| (StatusCode::FORBIDDEN, reason))?;
+    if node.is_rejected(&sender.node_id).await {
+        return Err((StatusCode::FORBIDDEN, format!("{} has been rejected", sender.node_id)));
+    }
+
+    // A transaction that lost a race (e.g. two custody moves of the same batch) is
+    // simply refused; it says nothing about the sender's honesty.
+    let added = node
+        .submit_tx(message.tx)
+        .await
+        .map_err(|reason| (StatusCode::CONFLICT, reason))?;
+
+    Ok(Json(ReceiveResponse {
+        accepted: true,
+        message: if added { "Queued" } else { "Already pending" }.to_string(),
+    }))
+}
+
+pub fn p2p_routes(node: Arc<Node>) -> Router {
+    Router::new()
+        .route("/api/p2p/status", get(status))
+        .route("/api/p2p/blocks", get(get_blocks).post(receive_block))
+        .route("/api/p2p/transactions", post(receive_transaction))
+        .with_state(node)
+}
@@ -0,0 +1,703 @@
+use axum::{
+    middleware,
+    extract::{Json, Path, Query, State},
+    routing::{delete, get, post, put},
+    http::StatusCode,
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::sync::Arc;
+
+use crate::auth::rbac::{authorize, Action};
+use crate::auth::AuthUser;
+use crate::controlled::{license_schedules, Period};
+use crate::db::entities::{
+    controlled_licenses, controlled_quotas, medicine_key, remove_controlled_license, remove_controlled_quota,
+    save_controlled_license, save_controlled_quota, ControlledLicense, ControlledQuota,
+    batch_custody_history, batch_summaries, critical_care_designations, designate_critical_care, remove_critical_care, find_batch, find_onchain_batch, find_organization, find_registration,
+    latest_block, ledger_merkle_root, organization_custody_history, recalls, registrations, review_registration,
+    verify_batch_signature, verify_custody_chain, BatchFilter, BatchSummary, CustodyRecord, MedicineBatch, OnchainBatch,
+    CriticalCareDesignation, Organization, Recall, Registration,
+};
+use crate::p2p::{decode_public_key, Node};
+use crate::routes::tracker::{check_chain, ChainVerifyResponse};
+use crate::timestamp::{verify_batch_timestamp, TimestampVerification};
+use crate::utils::signatures::{sign_data, verify_signature};
+
+/// Identifies the layout of exported audit bundles.
+const BUNDLE_FORMAT: &str = "pharmachain-audit/1";
+
+#[derive(Deserialize)]
+pub struct RegistrationQuery {
+    /// `pending` (default), `approved`, `rejected`, `suspended` or `all`.
+    #[serde(default)]
+    pub status: Option<String>,
+    /// Only licenses expiring on or before this `YYYY-MM-DD` date.
+    #[serde(default)]
+    pub expiring_by: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ReviewRequest {
+    /// Required when rejecting or suspending.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Expiry date the regulator confirmed, replacing the one submitted.
+    #[serde(default)]
+    pub license_expires_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CustodyQuery {
+    /// RFC 3339; only transfers at or after it.
+    #[serde(default)]
+    pub since: Option<String>,
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct RecallQuery {
+    /// `active` or `closed`; both when unset.
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub organization: Option<String>,
+    #[serde(default)]
+    pub batch_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    /// Only this organization's batches and registration.
+    #[serde(default)]
+    pub organization: Option<String>,
+    #[serde(default)]
+    pub since: Option<String>,
+    #[serde(default)]
+    pub until: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    pub valid: bool,
+    pub message: String,
+}
+
+/// Everything known about one batch: its record, where it was sealed, every transfer and
+/// receipt, the checks that vouch for it and its recalls.
+#[derive(Serialize)]
+pub struct BatchDossier {
+    pub batch: MedicineBatch,
+    pub sealed: Option<OnchainBatch>,
+    pub custody: Vec<CustodyRecord>,
+    /// Batch hash, Merkle proof and node signature.
+    pub signature: Check,
+    /// Links, hashes and signatures of the batch's transfers.
+    pub custody_chain: Check,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<TimestampVerification>,
+    pub recalls: Vec<Recall>,
+}
+
+#[derive(Serialize)]
+pub struct OrganizationCustody {
+    pub organization: Organization,
+    pub registration: Option<Registration>,
+    pub transfers: Vec<CustodyRecord>,
+}
+
+#[derive(Serialize)]
+pub struct BatchCheck {
+    pub batch_id: String,
+    pub organization: String,
+    pub signature: Check,
+    pub custody_chain: Check,
+}
+
+#[derive(Serialize)]
+pub struct VerificationReport {
+    pub chain: ChainVerifyResponse,
+    pub batches_checked: usize,
+    /// Batches whose signature or custody chain did not verify.
+    pub failures: Vec<BatchCheck>,
+}
+
+/// A signed export. `content_sha256` is the SHA-256 of `contents` serialized as compact JSON
+/// with keys sorted, and `signature` is the node's RSA signature over that hex digest.
+#[derive(Serialize, Deserialize)]
+pub struct AuditBundle {
+    pub contents: Value,
+    pub content_sha256: String,
+    pub signer: String,
+    pub public_key: String,
+    pub signature: String,
+}
+
+#[derive(Serialize)]
+pub struct BundleVerification {
+    pub valid: bool,
+    /// Whether the signing key belongs to this node or one of its authorities.
+    pub trusted_signer: bool,
+    pub message: String,
+}
+
+type ApiError = (StatusCode, String);
+
+fn internal(e: sqlx::Error) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+// GET /api/regulator/registrations?status=&expiring_by=
+/// The approval queue: pending registrations, oldest first, unless another status is asked for.
+async fn list_registrations(
+    State(node): State<Arc<Node>>,
+    Query(query): Query<RegistrationQuery>,
+) -> Result<Json<Vec<Registration>>, ApiError> {
+    let status = match query.status.as_deref().unwrap_or("pending") {
+        "all" => None,
+        status @ ("pending" | "approved" | "rejected" | "suspended") => Some(status),
+        other => return Err((StatusCode::BAD_REQUEST, format!("Unknown registration status '{}'", other))),
+    };
+    registrations(&node.pool, status, query.expiring_by.as_deref())
+        .await
+        .map(Json)
+        .map_err(internal)
+}
+
+// GET /api/regulator/registrations/:organization_id
+async fn get_registration(
+    State(node): State<Arc<Node>>,
+    Path(organization_id): Path<String>,
+) -> Result<Json<Registration>, ApiError> {
+    find_registration(&node.pool, &organization_id)
+        .await
+        .map_err(internal)?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "No registration for this organization".to_string()))
+}
+
+/// Moves a registration between statuses on behalf of the reviewing regulator.
+async fn review(
+    pool: &SqlitePool,
+    user: &AuthUser,
+    organization_id: &str,
+    from: &[&str],
+    to: &str,
+    request: ReviewRequest,
+) -> Result<Json<Registration>, ApiError> {
+    let reason = request.reason.map(|r| r.trim().to_string()).filter(|r| !r.is_empty());
+    if matches!(to, "rejected" | "suspended") && reason.is_none() {
+        return Err((StatusCode::BAD_REQUEST, "A reason is required".to_string()));
+    }
+    let expires_at = match request.license_expires_at.as_deref() {
+        Some(date) => Some(
+            NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+                .map_err(|_| (StatusCode::BAD_REQUEST, format!("'{}' is not a YYYY-MM-DD date", date)))?
+                .to_string(),
+        ),
+        None => None,
+    };
+
+    let current = find_registration(pool, organization_id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "No registration for this organization".to_string()))?;
+    if !review_registration(pool, organization_id, from, to, reason.as_deref(), &user.user_id, expires_at.as_deref())
+        .await
+        .map_err(internal)?
+    {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("The registration is {} and cannot become {}", current.status, to),
+        ));
+    }
+    find_registration(pool, organization_id)
+        .await
+        .map_err(internal)?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "No registration for this organization".to_string()))
+}
+
+// POST /api/regulator/registrations/:organization_id/approve
+async fn approve(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(organization_id): Path<String>,
+    request: Option<Json<ReviewRequest>>,
+) -> Result<Json<Registration>, ApiError> {
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    review(&node.pool, &user, &organization_id, &["pending", "rejected"], "approved", request).await
+}
+
+// POST /api/regulator/registrations/:organization_id/reject
+async fn reject(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(organization_id): Path<String>,
+    Json(request): Json<ReviewRequest>,
+) -> Result<Json<Registration>, ApiError> {
+    review(&node.pool, &user, &organization_id, &["pending"], "rejected", request).await
+}
+
+// POST /api/regulator/registrations/:organization_id/suspend
+/// Stops an organization from creating, sending or receiving batches until it is reinstated.
+async fn suspend(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(organization_id): Path<String>,
+    Json(request): Json<ReviewRequest>,
+) -> Result<Json<Registration>, ApiError> {
+    review(&node.pool, &user, &organization_id, &["approved", "pending"], "suspended", request).await
+}
+
+// POST /api/regulator/registrations/:organization_id/reinstate
+async fn reinstate(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(organization_id): Path<String>,
+    request: Option<Json<ReviewRequest>>,
+) -> Result<Json<Registration>, ApiError> {
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    review(&node.pool, &user, &organization_id, &["suspended"], "approved", request).await
+}
+
+fn bundle_digest(contents: &Value) -> String {
+    // serde_json keeps object keys sorted, so the digest does not depend on field order.
+    hex::encode(Sha256::digest(contents.to_string().as_bytes()))
+}
+
+async fn batch_dossier(node: &Node, batch: MedicineBatch) -> Result<BatchDossier, ApiError> {
+    let sealed = find_onchain_batch(&node.pool, &batch.batch_id).await.map_err(internal)?;
+    let signature = match &sealed {
+        Some(_) => {
+            let (valid, message) = verify_batch_signature(&node.pool, &batch.batch_id).await.map_err(internal)?;
+            Check { valid, message }
+        }
+        None => Check {
+            valid: false,
+            message: "Batch is not sealed into a canonical block".to_string(),
+        },
+    };
+    let (valid, message) = verify_custody_chain(&node.pool, &batch.batch_id).await.map_err(internal)?;
+    let timestamp = verify_batch_timestamp(node, &batch.batch_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    Ok(BatchDossier {
+        custody: batch_custody_history(&node.pool, &batch.batch_id).await.map_err(internal)?,
+        recalls: recalls(&node.pool, None, Some(&batch.batch_id), None).await.map_err(internal)?,
+        sealed,
+        signature,
+        custody_chain: Check { valid, message },
+        timestamp,
+        batch,
+    })
+}
+
+// GET /api/regulator/batches?organization=&medicine_name=&since=&until=&limit=&offset=
+/// Batches of every organization, newest first, with their last location and recall status.
+async fn list_batches(
+    State(node): State<Arc<Node>>,
+    Query(filter): Query<BatchFilter>,
+) -> Result<Json<Vec<BatchSummary>>, ApiError> {
+    batch_summaries(&node.pool, &filter).await.map(Json).map_err(internal)
+}
+
+// GET /api/regulator/batches/:batch_id
+async fn get_batch(
+    State(node): State<Arc<Node>>,
+    Path(batch_id): Path<String>,
+) -> Result<Json<BatchDossier>, ApiError> {
+    let batch = find_batch(&node.pool, &batch_id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Batch not found or not sealed yet".to_string()))?;
+    batch_dossier(&node, batch).await.map(Json)
+}
+
+// GET /api/regulator/organizations/:organization_id/custody?since=&limit=
+/// Transfers an organization sent or received, newest first, with its registration.
+async fn get_organization_custody(
+    State(node): State<Arc<Node>>,
+    Path(organization_id): Path<String>,
+    Query(query): Query<CustodyQuery>,
+) -> Result<Json<OrganizationCustody>, ApiError> {
+    let organization = find_organization(&node.pool, &organization_id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "No such organization".to_string()))?;
+    let transfers = organization_custody_history(&node.pool, &organization, query.since.as_deref(), query.limit.unwrap_or(100))
+        .await
+        .map_err(internal)?;
+    Ok(Json(OrganizationCustody {
+        registration: find_registration(&node.pool, &organization.id).await.map_err(internal)?,
+        organization,
+        transfers,
+    }))
+}
+
+// GET /api/regulator/verification
+/// Verifies the chain and every sealed batch's signature and custody chain, reporting failures.
+async fn get_verification(State(node): State<Arc<Node>>) -> Result<Json<VerificationReport>, ApiError> {
+    let chain = check_chain(&node).await?;
+    let batch_ids: Vec<(String, String)> = sqlx::query_as(
+        "SELECT b.batch_id, b.organization FROM medicine_batches b JOIN onchain_batches o ON o.batch_id = b.batch_id ORDER BY b.id",
+    )
+    .fetch_all(node.pool.as_ref())
+    .await
+    .map_err(internal)?;
+
+    let mut failures = Vec::new();
+    for (batch_id, organization) in &batch_ids {
+        let (signature_valid, signature_message) = verify_batch_signature(&node.pool, batch_id).await.map_err(internal)?;
+        let (custody_valid, custody_message) = verify_custody_chain(&node.pool, batch_id).await.map_err(internal)?;
+        if !(signature_valid && custody_valid) {
+            failures.push(BatchCheck {
+                batch_id: batch_id.clone(),
+                organization: organization.clone(),
+                signature: Check { valid: signature_valid, message: signature_message },
+                custody_chain: Check { valid: custody_valid, message: custody_message },
+            });
+        }
+    }
+
+    Ok(Json(VerificationReport {
+        chain,
+        batches_checked: batch_ids.len(),
+        failures,
+    }))
+}
+
+// GET /api/regulator/recalls?status=&organization=&batch_id=
+async fn list_recalls(
+    State(node): State<Arc<Node>>,
+    Query(query): Query<RecallQuery>,
+) -> Result<Json<Vec<Recall>>, ApiError> {
+    recalls(&node.pool, query.status.as_deref(), query.batch_id.as_deref(), query.organization.as_deref())
+        .await
+        .map(Json)
+        .map_err(internal)
+}
+
+// GET /api/regulator/export?organization=&since=&until=
+/// A signed bundle of batch dossiers, registrations, recalls and the ledger's state, for
+/// handing over outside the system. At most 1000 batches; narrow the window for more.
+async fn export_bundle(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Query(query): Query<ExportQuery>,
+) -> Result<Json<AuditBundle>, ApiError> {
+    let filter = BatchFilter {
+        organization: query.organization.clone(),
+        since: query.since.clone(),
+        until: query.until.clone(),
+        limit: Some(1000),
+        ..Default::default()
+    };
+    let summaries = batch_summaries(&node.pool, &filter).await.map_err(internal)?;
+    let truncated = summaries.len() >= 1000;
+    let mut batches = Vec::with_capacity(summaries.len());
+    for summary in &summaries {
+        if let Some(batch) = find_batch(&node.pool, &summary.batch_id).await.map_err(internal)? {
+            batches.push(batch_dossier(&node, batch).await?);
+        }
+    }
+
+    let mut organization_registrations = registrations(&node.pool, None, None).await.map_err(internal)?;
+    if let Some(organization) = &query.organization {
+        organization_registrations.retain(|r| &r.organization_id == organization);
+    }
+    let tip = latest_block(&node.pool).await.map_err(internal)?;
+    let height = tip.as_ref().map_or(0, |b| b.height);
+    let (merkle_root, batch_count) = ledger_merkle_root(&node.pool, height).await.map_err(internal)?;
+
+    let contents = json!({
+        "format": BUNDLE_FORMAT,
+        "generated_at": Utc::now().to_rfc3339(),
+        "generated_by": user.user_id,
+        "node_id": node.config.node_id,
+        "scope": { "organization": query.organization, "since": query.since, "until": query.until, "truncated": truncated },
+        "ledger": {
+            "height": height,
+            "tip_hash": tip.map(|b| b.hash),
+            "merkle_root": merkle_root,
+            "sealed_batches": batch_count,
+        },
+        "chain": check_chain(&node).await?,
+        "batches": batches,
+        "registrations": organization_registrations,
+        "recalls": recalls(&node.pool, None, None, query.organization.as_deref()).await.map_err(internal)?,
+    });
+    let content_sha256 = bundle_digest(&contents);
+    let signature = STANDARD.encode(sign_data(&node.private_key, content_sha256.as_bytes()));
+
+    Ok(Json(AuditBundle {
+        contents,
+        content_sha256,
+        signer: node.config.node_id.clone(),
+        public_key: node.public_key_b64.clone(),
+        signature,
+    }))
+}
+
+// POST /api/regulator/export/verify
+/// Checks an exported bundle's digest and signature, and whether a known node signed it.
+async fn verify_bundle(
+    State(node): State<Arc<Node>>,
+    Json(bundle): Json<AuditBundle>,
+) -> Json<BundleVerification> {
+    let trusted_signer = bundle.public_key == node.public_key_b64
+        || node.authorities.iter().any(|a| a.public_key == bundle.public_key);
+    let answer = |valid: bool, message: &str| {
+        Json(BundleVerification {
+            valid,
+            trusted_signer,
+            message: message.to_string(),
+        })
+    };
+
+    if bundle_digest(&bundle.contents) != bundle.content_sha256 {
+        return answer(false, "The contents do not match content_sha256; the bundle was altered");
+    }
+    let signature = STANDARD.decode(&bundle.signature).unwrap_or_default();
+    let signed = decode_public_key(&bundle.public_key)
+        .is_some_and(|key| verify_signature(&key, bundle.content_sha256.as_bytes(), &signature));
+    match (signed, trusted_signer) {
+        (false, _) => answer(false, "The signature does not match the public key"),
+        (true, false) => answer(true, "Intact and signed, but by a key this node does not know"),
+        (true, true) => answer(true, "Intact and signed by a known node"),
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct DesignationRequest {
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+// GET /api/regulator/critical-care
+async fn list_critical_care(State(node): State<Arc<Node>>) -> Result<Json<Vec<CriticalCareDesignation>>, ApiError> {
+    critical_care_designations(&node.pool)
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+// PUT /api/regulator/critical-care/:organization_id
+/// Designates a hospital as critical care, which the `critical_care` allocation policy serves first.
+async fn designate(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(organization_id): Path<String>,
+    request: Option<Json<DesignationRequest>>,
+) -> Result<Json<Vec<CriticalCareDesignation>>, ApiError> {
+    let internal = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    find_organization(&node.pool, &organization_id)
+        .await
+        .map_err(internal)?
+        .filter(|o| o.organization_type == "hospital")
+        .ok_or((StatusCode::NOT_FOUND, "Hospital not found".to_string()))?;
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    let note = request.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    designate_critical_care(&node.pool, &organization_id, note, &user.user_id)
+        .await
+        .map_err(internal)?;
+    list_critical_care(State(node)).await
+}
+
+// DELETE /api/regulator/critical-care/:organization_id
+async fn undesignate(
+    State(node): State<Arc<Node>>,
+    Path(organization_id): Path<String>,
+) -> Result<Json<Vec<CriticalCareDesignation>>, ApiError> {
+    if !remove_critical_care(&node.pool, &organization_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    {
+        return Err((StatusCode::NOT_FOUND, "The hospital is not designated as critical care".to_string()));
+    }
+    list_critical_care(State(node)).await
+}
+
+#[derive(Deserialize)]
+pub struct LicenseRequest {
+    pub license_number: String,
+    /// Schedules the license covers: `II`, `CIII`, `4`…
+    pub schedules: Vec<String>,
+    /// `YYYY-MM-DD`.
+    #[serde(default)]
+    pub expires_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct QuotaRequest {
+    pub active_ingredient: String,
+    /// `month`, `quarter` or `year`.
+    pub period: String,
+    pub quantity: i64,
+}
+
+#[derive(Deserialize)]
+pub struct QuotaQuery {
+    #[serde(default)]
+    pub organization: Option<String>,
+}
+
+// GET /api/regulator/controlled-licenses
+async fn list_controlled_licenses(State(node): State<Arc<Node>>) -> Result<Json<Vec<ControlledLicense>>, ApiError> {
+    controlled_licenses(&node.pool).await.map(Json).map_err(internal)
+}
+
+// PUT /api/regulator/controlled-licenses/:organization_id
+/// Grants or replaces an organization's license to handle scheduled products.
+async fn grant_controlled_license(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(organization_id): Path<String>,
+    Json(request): Json<LicenseRequest>,
+) -> Result<Json<ControlledLicense>, ApiError> {
+    find_organization(&node.pool, &organization_id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Organization not found".to_string()))?;
+    let license_number = request.license_number.trim().to_string();
+    if license_number.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "license_number is required".to_string()));
+    }
+    let schedules = license_schedules(&request.schedules).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let expires_at = match request.expires_at.as_deref().map(str::trim).filter(|d| !d.is_empty()) {
+        Some(date) => Some(
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| (StatusCode::BAD_REQUEST, format!("expires_at '{}' is not a YYYY-MM-DD date", date)))?
+                .to_string(),
+        ),
+        None => None,
+    };
+    let license = ControlledLicense {
+        organization_id,
+        license_number,
+        schedules,
+        expires_at,
+        granted_by: user.user_id,
+        granted_at: Utc::now().to_rfc3339(),
+    };
+    save_controlled_license(&node.pool, &license).await.map_err(internal)?;
+    Ok(Json(license))
+}
+
+// DELETE /api/regulator/controlled-licenses/:organization_id
+async fn revoke_controlled_license(
+    State(node): State<Arc<Node>>,
+    Path(organization_id): Path<String>,
+) -> Result<Json<Vec<ControlledLicense>>, ApiError> {
+    if !remove_controlled_license(&node.pool, &organization_id).await.map_err(internal)? {
+        return Err((StatusCode::NOT_FOUND, "The organization holds no controlled-substance license".to_string()));
+    }
+    list_controlled_licenses(State(node)).await
+}
+
+// GET /api/regulator/controlled-quotas
+async fn list_controlled_quotas(
+    State(node): State<Arc<Node>>,
+    Query(query): Query<QuotaQuery>,
+) -> Result<Json<Vec<ControlledQuota>>, ApiError> {
+    controlled_quotas(&node.pool, query.organization.as_deref())
+        .await
+        .map(Json)
+        .map_err(internal)
+}
+
+// PUT /api/regulator/controlled-quotas/:organization_id
+/// Sets how many units of an active ingredient the organization may make or receive per period.
+async fn set_controlled_quota(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Path(organization_id): Path<String>,
+    Json(request): Json<QuotaRequest>,
+) -> Result<Json<ControlledQuota>, ApiError> {
+    find_organization(&node.pool, &organization_id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Organization not found".to_string()))?;
+    let active_ingredient = request.active_ingredient.trim().to_string();
+    if active_ingredient.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "active_ingredient is required".to_string()));
+    }
+    let period: Period = request.period.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if request.quantity < 0 {
+        return Err((StatusCode::BAD_REQUEST, "quantity may not be negative".to_string()));
+    }
+    let quota = ControlledQuota {
+        organization_id,
+        ingredient_key: medicine_key(&active_ingredient),
+        active_ingredient,
+        period: period.to_string(),
+        quantity: request.quantity,
+        set_by: user.user_id,
+        set_at: Utc::now().to_rfc3339(),
+    };
+    save_controlled_quota(&node.pool, &quota).await.map_err(internal)?;
+    Ok(Json(quota))
+}
+
+// DELETE /api/regulator/controlled-quotas/:organization_id/:active_ingredient
+async fn remove_quota(
+    State(node): State<Arc<Node>>,
+    Path((organization_id, active_ingredient)): Path<(String, String)>,
+) -> Result<Json<Vec<ControlledQuota>>, ApiError> {
+    let key = medicine_key(&active_ingredient);
+    if !remove_controlled_quota(&node.pool, &organization_id, &key).await.map_err(internal)? {
+        return Err((StatusCode::NOT_FOUND, "No such quota".to_string()));
+    }
+    list_controlled_quotas(State(node), Query(QuotaQuery { organization: Some(organization_id) })).await
+}
+
+pub fn regulator_routes(node: Arc<Node>) -> Router {
+    let guard = |action: Action| middleware::from_fn_with_state(action, authorize);
+    let review_routes = Router::new()
+        .route("/api/regulator/registrations", get(list_registrations))
+        .route("/api/regulator/registrations/:organization_id", get(get_registration))
+        .route("/api/regulator/registrations/:organization_id/approve", post(approve))
+        .route("/api/regulator/registrations/:organization_id/reject", post(reject))
+        .route("/api/regulator/registrations/:organization_id/suspend", post(suspend))
+        .route("/api/regulator/registrations/:organization_id/reinstate", post(reinstate))
+        .route("/api/regulator/critical-care", get(list_critical_care))
+        .route("/api/regulator/controlled-licenses", get(list_controlled_licenses))
+        .route("/api/regulator/controlled-quotas", get(list_controlled_quotas))
+        .route_layer(guard(Action::ReviewRegistrations))
+        .route(
+            "/api/regulator/critical-care/:organization_id",
+            put(designate).delete(undesignate).route_layer(guard(Action::DesignateCriticalCare)),
+        )
+        .route(
+            "/api/regulator/controlled-licenses/:organization_id",
+            put(grant_controlled_license)
+                .delete(revoke_controlled_license)
+                .route_layer(guard(Action::LicenseControlled)),
+        )
+        .route(
+            "/api/regulator/controlled-quotas/:organization_id",
+            put(set_controlled_quota).route_layer(guard(Action::LicenseControlled)),
+        )
+        .route(
+            "/api/regulator/controlled-quotas/:organization_id/:active_ingredient",
+            delete(remove_quota).route_layer(guard(Action::LicenseControlled)),
+        );
+    // Read-only inspection across every organization
+    let portal_routes = Router::new()
+        .route("/api/regulator/batches", get(list_batches))
+        .route("/api/regulator/batches/:batch_id", get(get_batch))
+        .route("/api/regulator/organizations/:organization_id/custody", get(get_organization_custody))
+        .route("/api/regulator/verification", get(get_verification))
+        .route("/api/regulator/recalls", get(list_recalls))
+        .route("/api/regulator/export", get(export_bundle))
+        .route("/api/regulator/export/verify", post(verify_bundle))
+        .route_layer(guard(Action::InspectOrganizations));
+    review_routes.merge(portal_routes).with_state(node)
+}
@@ -0,0 +1,117 @@
+use axum::{
+    extract::{ConnectInfo, Extension, Query},
+    http::{HeaderMap, StatusCode},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::auth::audit::{self, AuthEvent, EventSubject};
+use crate::auth::oidc::{passed_mfa, OidcClient};
+use crate::auth::throttle::client_ip;
+use crate::auth::tokens::{issue_session, SessionTokens};
+use crate::auth::Auth;
+use crate::models::{LoginResponse, User};
+
+#[derive(Serialize)]
+pub struct SsoLoginResponse {
+    /// Send the user's browser here.
+    pub authorization_url: String,
+    pub state: String,
+}
+
+#[derive(Deserialize)]
+pub struct CallbackParams {
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub state: Option<String>,
+    /// Set by the provider instead of `code` when the login failed or was refused.
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub error_description: Option<String>,
+}
+
+type ApiError = (StatusCode, String);
+
+fn client(auth: &Auth) -> Result<&OidcClient, ApiError> {
+    auth.oidc
+        .as_ref()
+        .ok_or((StatusCode::NOT_FOUND, "Single sign-on is not configured".to_string()))
+}
+
+// GET /api/sso/login
+/// Starts an authorization-code login with PKCE at the configured OpenID provider.
+async fn sso_login(Extension(auth): Extension<Arc<Auth>>) -> Result<Json<SsoLoginResponse>, ApiError> {
+    let request = client(&auth)?.authorization_request(&auth).await?;
+    Ok(Json(SsoLoginResponse {
+        authorization_url: request.url,
+        state: request.state,
+    }))
+}
+
+// GET /api/sso/callback
+/// Where the provider sends the user back. Answers like `/api/login`, and is logged like it.
+async fn sso_callback(
+    Extension(auth): Extension<Arc<Auth>>,
+    peer: ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(params): Query<CallbackParams>,
+) -> Result<Json<LoginResponse>, ApiError> {
+    let ip = client_ip(&auth.config, &headers, &peer);
+    let subject = EventSubject {
+        ip: Some(&ip),
+        ..Default::default()
+    };
+    let result = complete_sso_login(&auth, params).await;
+    match &result {
+        Ok((user, _, _)) => {
+            let subject = EventSubject {
+                user_id: Some(&user.id),
+                email: Some(&user.email),
+                ..subject
+            };
+            audit::record(&auth, AuthEvent::LoginSuccess, subject, Some("sso")).await;
+        }
+        Err((status, message)) if *status != StatusCode::NOT_FOUND => {
+            audit::record(&auth, AuthEvent::LoginFailure, subject, Some(&format!("sso: {}", message))).await;
+        }
+        Err(_) => {}
+    }
+    let (user, tokens, mfa) = result?;
+
+    Ok(Json(LoginResponse {
+        token: tokens.access_token,
+        user: user.username,
+        two_factor: (!mfa && auth.config.mfa_required(&user.role)).then(|| "enroll".to_string()),
+        role: user.role,
+        refresh_token: Some(tokens.refresh_token),
+        expires_in: Some(tokens.expires_in),
+    }))
+}
+
+async fn complete_sso_login(auth: &Auth, params: CallbackParams) -> Result<(User, SessionTokens, bool), ApiError> {
+    let oidc = client(auth)?;
+    if let Some(error) = params.error {
+        let detail = params.error_description.map(|d| format!(": {}", d)).unwrap_or_default();
+        return Err((StatusCode::UNAUTHORIZED, format!("Identity provider refused the login ({}){}", error, detail)));
+    }
+    let (Some(code), Some(state)) = (params.code, params.state) else {
+        return Err((StatusCode::BAD_REQUEST, "Missing code or state".to_string()));
+    };
+
+    let claims = oidc.exchange_code(auth, &code, &state).await?;
+    let user = oidc.sign_in(auth, &claims).await?;
+    let mfa = passed_mfa(&claims);
+    let tokens = issue_session(auth, &user, mfa).await?;
+    Ok((user, tokens, mfa))
+}
+
+pub fn sso_routes() -> Router {
+    Router::new()
+        .route("/api/sso/login", get(sso_login))
+        .route("/api/sso/callback", get(sso_callback))
+}
@@ -1,24 +1,91 @@
 use axum::{
+    middleware,
     extract::{Json, Path, State},
     routing::{get, post},
     http::StatusCode,
     Router,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::SqlitePool;
 use std::sync::Arc;
-use chrono::Utc;
+use chrono::{DateTime, NaiveDate, Utc};
+use base64::{engine::general_purpose::STANDARD, Engine};
 
-use crate::entities::compute_batch_hash;
+use crate::anchor::{verify_anchor, AnchorVerification};
+use crate::catalog;
+use crate::controlled::{self, CONTROLLED_LOCK};
+use crate::auth::rbac::{authorize, Action};
+use crate::auth::AuthUser;
+use crate::licensing::{require_active, require_active_receiver};
+use crate::db::entities::{
+    acknowledge_custody, add_condition_reading, add_recall, batch_chain_head, block_at_height, condition_readings, blocks_from_height, close_recall, compute_batch_hash, compute_block_hash, compute_custody_hash,
+    evm_anchors, find_batch, find_catalog_product, find_forks, find_onchain_batch, find_organization, find_organization_by_ref, latest_custody_event, ledger_merkle_root, record_order_delivery, stored_block, verify_batch_signature, Block, CustodyEvent, EvmAnchor, Fork, LedgerTx,
+    BatchTerms, ConditionReading, MedicineBatch, OnchainBatch, Organization, Recall,
+};
+use crate::dispensing::normalize_serials;
+use crate::p2p::mempool::LedgerView;
+use crate::p2p::sync::broadcast_tx;
+use crate::p2p::Node;
+use crate::timestamp::{verify_batch_timestamp, TimestampVerification};
 use crate::utils::merkle::build_merkle_root;
-use crate::utils::signatures::{generate_keys, sign_data, verify_signature};
+use crate::utils::signatures::sign_data;
 
 #[derive(Deserialize)]
 pub struct Batch {
     pub batch_id: String,
+    /// May be left out, and the catalog product then names the batch.
+    #[serde(default)]
     pub medicine_name: String,
     pub source: String,
     pub destination: String,
+    /// The catalog GTIN, which is required, and the optional expiry, storage range and quantity.
+    #[serde(flatten)]
+    pub terms: BatchTerms,
+}
+
+#[derive(Deserialize)]
+pub struct CustodyTransfer {
+    pub batch_id: String,
+    pub from_location: String,
+    pub to_location: String,
+    /// RFC 3339; when the batch should reach `to_location`.
+    #[serde(default)]
+    pub expected_arrival: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ConditionReport {
+    pub batch_id: String,
+    pub temperature_celsius: f64,
+    /// Defaults to where the batch is now.
+    #[serde(default)]
+    pub location: Option<String>,
+    /// RFC 3339; defaults to now.
+    #[serde(default)]
+    pub recorded_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TransferAcknowledgement {
+    pub batch_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct RecallRequest {
+    pub batch_id: String,
+    /// Why the batch is recalled, or why the recall is closed.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AcknowledgementResponse {
+    pub message: String,
+    pub custody_hash: String,
+    pub organization: String,
+    pub acknowledged_at: String,
+    /// Purchase orders the batch was shipped against whose fulfilment this delivery updated.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub purchase_orders: Vec<String>,
 }
 
 #[derive(Serialize)]
@@ -34,6 +101,15 @@ pub struct TrackerResponse {
 pub struct VerifyResponse {
     pub valid: bool,
     pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<TimestampVerification>,
+}
+
+#[derive(Serialize)]
+pub struct ChainVerifyResponse {
+    pub valid: bool,
+    pub message: String,
+    pub forks: Vec<Fork>,
 }
 
 #[derive(Serialize)]
@@ -42,176 +118,647 @@ pub struct MerkleResponse {
     pub total_batches: usize,
 }
 
+/// Queues a transaction this node has signed and gossips it to peers.
+pub(crate) async fn submit(node: &Arc<Node>, tx: LedgerTx) -> Result<(), (StatusCode, String)> {
+    node.submit_tx(tx.clone())
+        .await
+        .map_err(|reason| (StatusCode::CONFLICT, reason))?;
+
+    let node = node.clone();
+    tokio::spawn(async move { broadcast_tx(&node, &tx).await });
+    Ok(())
+}
+
+/// Normalizes a batch's terms: a future `YYYY-MM-DD` expiry, a storage range that makes sense,
+/// a positive quantity, a valid GTIN and a serial for each unit if any are listed. Splits go through `/api/inventory/split`.
+fn check_terms(terms: BatchTerms) -> Result<BatchTerms, (StatusCode, String)> {
+    if terms.parent_batch_id.is_some() {
+        return Err((StatusCode::BAD_REQUEST, "Split batches with /api/inventory/split".to_string()));
+    }
+    if terms.quantity.is_some_and(|q| q <= 0) {
+        return Err((StatusCode::BAD_REQUEST, "quantity must be positive".to_string()));
+    }
+    let expires_at = match terms.expires_at.as_deref() {
+        Some(date) => {
+            let date = NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+                .map_err(|_| (StatusCode::BAD_REQUEST, format!("Expiry '{}' is not a YYYY-MM-DD date", date)))?;
+            if date <= Utc::now().date_naive() {
+                return Err((StatusCode::BAD_REQUEST, format!("The batch would already be expired on {}", date)));
+            }
+            Some(date.to_string())
+        }
+        None => None,
+    };
+    let limits = [terms.storage_min_celsius, terms.storage_max_celsius];
+    if limits.iter().flatten().any(|t| !t.is_finite()) {
+        return Err((StatusCode::BAD_REQUEST, "Storage temperatures must be numbers".to_string()));
+    }
+    if let [Some(min), Some(max)] = limits
+        && min > max
+    {
+        return Err((StatusCode::BAD_REQUEST, "storage_min_celsius is above storage_max_celsius".to_string()));
+    }
+    let gtin = terms
+        .gtin
+        .as_deref()
+        .map(|gtin| catalog::normalize_gtin(gtin).map_err(|e| (StatusCode::BAD_REQUEST, e)))
+        .transpose()?;
+    let serials = match terms.serials.as_deref() {
+        Some(serials) if !serials.is_empty() => {
+            let serials = normalize_serials(serials).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            if terms.quantity != Some(serials.len() as i64) {
+                return Err((StatusCode::BAD_REQUEST, format!("{} serials are listed; quantity must match", serials.len())));
+            }
+            Some(serials)
+        }
+        _ => None,
+    };
+    Ok(BatchTerms { expires_at, gtin, serials, ..terms })
+}
+
+fn parse_time(field: &str, value: &str) -> Result<DateTime<Utc>, (StatusCode, String)> {
+    DateTime::parse_from_rfc3339(value.trim())
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| (StatusCode::BAD_REQUEST, format!("{} '{}' is not an RFC 3339 time", field, value)))
+}
+
+/// The id of the organization `location` names, by id or by name, or `location` itself when it
+/// names none. Locations are resolved once on the way in so custody checks compare ids only:
+/// organization names are not unique.
+async fn organization_location(node: &Node, location: &str) -> Result<String, (StatusCode, String)> {
+    let organization = find_organization_by_ref(&node.pool, location.trim())
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(organization.map(|o| o.id).unwrap_or_else(|| location.to_string()))
+}
+
+/// The batch is attributed to the caller's active organization, which its hash and signature cover.
+/// It must be of a catalog product a reviewer has approved, and takes the product's name and
+/// storage range unless it gives its own.
+/// A scheduled product needs a license covering its schedule, a quantity within the maker's
+/// quota, and must start at its maker.
 async fn add_batch(
-    State(pool): State<Arc<SqlitePool>>,
-    Json(batch): Json<Batch>,
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Json(mut batch): Json<Batch>,
 ) -> Result<Json<TrackerResponse>, (StatusCode, String)> {
+    batch.destination = organization_location(&node, &batch.destination).await?;
+    let organization = user
+        .organization
+        .ok_or((StatusCode::FORBIDDEN, "Register or join a company before creating batches".to_string()))?;
+    require_active(&node.pool, &organization).await?;
+    require_active_receiver(&node.pool, &batch.destination).await?;
+    let mut terms = check_terms(batch.terms)?;
+    let mut medicine_name = batch.medicine_name.trim().to_string();
+    let gtin = terms
+        .gtin
+        .clone()
+        .ok_or((StatusCode::BAD_REQUEST, "gtin is required; batches are made of catalog products".to_string()))?;
+    let entry = find_catalog_product(&node.pool, &gtin)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::BAD_REQUEST, format!("GTIN {} is not in the catalog", gtin)))?;
+    if entry.approved_at.is_none() {
+        return Err((StatusCode::CONFLICT, format!("GTIN {} is awaiting review by a regulator", gtin)));
+    }
+    let product = entry.product;
+    let mut controlled = None;
+    if let Some(schedule) = &product.schedule {
+        let maker = find_organization(&node.pool, &organization)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .ok_or((StatusCode::FORBIDDEN, "Register or join a company before creating batches".to_string()))?;
+        controlled::require_licensed(&node.pool, &maker, schedule).await?;
+        let quantity = terms
+            .quantity
+            .ok_or((StatusCode::BAD_REQUEST, format!("A batch of a schedule {} product needs a quantity", schedule)))?;
+        if batch.destination != maker.id {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("A batch of a schedule {} product starts at its maker; send it on with /api/controlled/transfers", schedule),
+            ));
+        }
+        controlled = Some((maker, product.active_ingredient.clone(), quantity));
+    }
+    if medicine_name.is_empty() {
+        medicine_name = product.name;
+    }
+    if terms.storage_min_celsius.is_none() && terms.storage_max_celsius.is_none() {
+        terms.storage_min_celsius = product.storage_min_celsius;
+        terms.storage_max_celsius = product.storage_max_celsius;
+    }
     let timestamp = Utc::now().to_rfc3339();
 
-    let previous_hash: Option<String> = sqlx::query_scalar(
-        "SELECT hash FROM medicine_batches ORDER BY timestamp DESC LIMIT 1"
-    )
-    .fetch_optional(pool.as_ref())
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    // Each batch starts its own custody chain; ledger-wide ordering comes from blocks.
+    let previous_hash = "GENESIS".to_string();
 
-    let previous_hash = previous_hash.unwrap_or_else(|| "GENESIS".to_string());
+    let mut record = MedicineBatch {
+        batch_id: batch.batch_id,
+        organization,
+        medicine_name,
+        source: batch.source,
+        destination: batch.destination,
+        timestamp,
+        hash: String::new(),
+        previous_hash: previous_hash.clone(),
+        signature: None,
+        public_key: Some(node.public_key_b64.clone()),
+        terms,
+    };
+    let batch_hash = compute_batch_hash(&record);
+    let signature_base64 = STANDARD.encode(sign_data(&node.private_key, batch_hash.as_bytes()));
+    record.hash = batch_hash.clone();
+    record.signature = Some(signature_base64.clone());
 
-    let batch_hash = compute_batch_hash(
-        &batch.batch_id,
-        &batch.medicine_name,
-        &batch.source,
-        &batch.destination,
+    let tx = LedgerTx::Batch(record);
+    match controlled {
+        Some((maker, active_ingredient, quantity)) => {
+            let _guard = CONTROLLED_LOCK.lock().await;
+            controlled::check_quota(&node, &maker, &active_ingredient, quantity).await?;
+            submit(&node, tx).await?;
+        }
+        None => submit(&node, tx).await?,
+    }
+
+    Ok(Json(TrackerResponse {
+        message: "Batch signed and queued for the next block".to_string(),
+        batch_hash,
+        previous_hash,
+        signature: signature_base64,
+        public_key: node.public_key_b64.clone(),
+    }))
+}
+
+/// Only the organization holding the batch may send it on. Neither it nor a registered
+/// recipient may be suspended or unapproved. Scheduled products move only through
+/// `/api/controlled/transfers`.
+async fn transfer_custody(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Json(transfer): Json<CustodyTransfer>,
+) -> Result<Json<TrackerResponse>, (StatusCode, String)> {
+    let organization = user
+        .organization
+        .ok_or((StatusCode::FORBIDDEN, "Register or join an organization before transferring custody".to_string()))?;
+    require_active(&node.pool, &organization).await?;
+    let holder = find_organization(&node.pool, &organization)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::FORBIDDEN, "Register or join an organization before transferring custody".to_string()))?;
+    require_active_receiver(&node.pool, &transfer.to_location).await?;
+    if let Some(product) = controlled::batch_schedule(&node, &transfer.batch_id).await? {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!(
+                "Batch {} is a schedule {} product; transfer it with /api/controlled/transfers",
+                transfer.batch_id,
+                product.schedule.unwrap_or_default()
+            ),
+        ));
+    }
+    queue_custody(&node, &holder, transfer).await.map(Json)
+}
+
+/// Signs a custody transfer chained onto the batch's latest transaction and queues it.
+/// `holder` must be where the batch is, counting transfers still pending here.
+pub(crate) async fn queue_custody(
+    node: &Arc<Node>,
+    holder: &Organization,
+    mut transfer: CustodyTransfer,
+) -> Result<TrackerResponse, (StatusCode, String)> {
+    transfer.from_location = organization_location(node, &transfer.from_location).await?;
+    transfer.to_location = organization_location(node, &transfer.to_location).await?;
+    let expected_arrival = transfer
+        .expected_arrival
+        .as_deref()
+        .map(|t| parse_time("expected_arrival", t).map(|t| t.to_rfc3339()))
+        .transpose()?;
+
+    // Chain onto the batch's latest transaction, whether sealed or still pending here.
+    let (previous_hash, location) = {
+        let mempool = node.mempool.lock().await;
+        let mut view = LedgerView::new(&node.pool);
+        for pending in mempool.pending() {
+            view.apply(&node.authorities, pending)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        }
+        view.current(&transfer.batch_id)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .ok_or((StatusCode::NOT_FOUND, "Batch not found".to_string()))?
+    };
+    if location != holder.id {
+        return Err((StatusCode::FORBIDDEN, format!("Batch {} is not held by {}", transfer.batch_id, holder.name)));
+    }
+    if transfer.from_location != location {
+        return Err((StatusCode::CONFLICT, format!("Batch {} is at {}, not {}", transfer.batch_id, location, transfer.from_location)));
+    }
+
+    let timestamp = Utc::now().to_rfc3339();
+    let hash = compute_custody_hash(
+        &transfer.batch_id,
+        &transfer.from_location,
+        &transfer.to_location,
         &timestamp,
         &previous_hash,
+        expected_arrival.as_deref(),
     );
+    let signature_base64 = STANDARD.encode(sign_data(&node.private_key, hash.as_bytes()));
 
-    let (private_key, public_key) = generate_keys();
-    let signature_bytes = sign_data(&private_key, batch_hash.as_bytes());
-    let signature_base64 = base64::encode(signature_bytes);
-    let public_key_base64 = base64::encode(public_key.to_pkcs1_der().unwrap());
-
-    sqlx::query(
-        "INSERT INTO medicine_batches 
-        (batch_id, medicine_name, source, destination, timestamp, hash, previous_hash, signature, public_key) 
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
-    )
-    .bind(&batch.batch_id)
-    .bind(&batch.medicine_name)
-    .bind(&batch.source)
-    .bind(&batch.destination)
-    .bind(&timestamp)
-    .bind(&batch_hash)
-    .bind(&previous_hash)
-    .bind(&signature_base64)
-    .bind(&public_key_base64)
-    .execute(pool.as_ref())
-    .await
-    .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    let tx = LedgerTx::Custody(CustodyEvent {
+        batch_id: transfer.batch_id,
+        from_location: transfer.from_location,
+        to_location: transfer.to_location,
+        timestamp,
+        hash: hash.clone(),
+        previous_hash: previous_hash.clone(),
+        signature: signature_base64.clone(),
+        public_key: node.public_key_b64.clone(),
+        expected_arrival,
+    });
+    submit(node, tx).await?;
 
-    Ok(Json(TrackerResponse {
-        message: "Batch added with chained hash + signature + public key".to_string(),
-        batch_hash,
+    Ok(TrackerResponse {
+        message: "Custody transfer signed and queued for the next block".to_string(),
+        batch_hash: hash,
         previous_hash,
         signature: signature_base64,
-        public_key: public_key_base64,
-    }))
+        public_key: node.public_key_b64.clone(),
+    })
 }
 
 async fn verify_batch(
-    State(pool): State<Arc<SqlitePool>>,
+    State(node): State<Arc<Node>>,
     Path(batch_id): Path<String>,
 ) -> Result<Json<VerifyResponse>, (StatusCode, String)> {
-    let row = sqlx::query!(
-        "SELECT medicine_name, source, destination, timestamp, hash, previous_hash, signature, public_key
-         FROM medicine_batches WHERE batch_id = ?",
-        batch_id
-    )
-    .fetch_optional(pool.as_ref())
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-    if let Some(batch) = row {
-        let recomputed_hash = compute_batch_hash(
-            &batch_id,
-            &batch.medicine_name,
-            &batch.source,
-            &batch.destination,
-            &batch.timestamp,
-            &batch.previous_hash,
-        );
-
-        let is_valid_hash = recomputed_hash == batch.hash;
-
-        let is_signature_valid = if let (Some(sig_b64), Some(pubkey_b64)) = (batch.signature, batch.public_key) {
-            let sig_bytes = base64::decode(sig_b64).unwrap_or_default();
-            let pubkey_der = base64::decode(pubkey_b64).unwrap_or_default();
-            let public_key = rsa::RsaPublicKey::from_pkcs1_der(&pubkey_der).unwrap_or_else(|_| {
-                return rsa::RsaPublicKey::new(rsa::BigUint::default(), rsa::BigUint::default()).unwrap();
-            });
+    let sealed = find_onchain_batch(&node.pool, &batch_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .is_some();
 
-            verify_signature(&public_key, recomputed_hash.as_bytes(), &sig_bytes)
-        } else {
-            false
-        };
+    if sealed {
+        let (valid, message) = verify_batch_signature(&node.pool, &batch_id)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        // A trusted timestamp, when present, must also hold up; an unverified one proves nothing either way.
+        let timestamp = verify_batch_timestamp(&node, &batch_id)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        let valid = valid && timestamp.as_ref().is_none_or(|t| t.valid || !t.trusted);
+        return Ok(Json(VerifyResponse { valid, message, timestamp }));
+    }
 
-        let msg = if is_valid_hash && is_signature_valid {
-            "Hash + signature both valid"
-        } else if is_valid_hash {
-            "Hash valid, but signature failed"
-        } else {
-            "Hash mismatch — possible tampering!"
-        };
+    let pending = node
+        .mempool
+        .lock()
+        .await
+        .pending()
+        .iter()
+        .any(|tx| matches!(tx, LedgerTx::Batch(b) if b.batch_id == batch_id));
 
+    if pending {
         Ok(Json(VerifyResponse {
-            valid: is_valid_hash && is_signature_valid,
-            message: msg.to_string(),
+            valid: false,
+            message: "Batch is pending and not yet sealed into a block".to_string(),
+            timestamp: None,
         }))
     } else {
         Err((StatusCode::NOT_FOUND, "Batch not found".to_string()))
     }
 }
 
-async fn verify_chain(
-    State(pool): State<Arc<SqlitePool>>,
-) -> Result<Json<VerifyResponse>, (StatusCode, String)> {
-    let batches = sqlx::query!(
-        "SELECT batch_id, medicine_name, source, destination, timestamp, hash, previous_hash 
-         FROM medicine_batches ORDER BY timestamp ASC"
-    )
-    .fetch_all(pool.as_ref())
-    .await
-    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+/// Describes a fork for humans: which blocks compete on the same parent and which one won.
+fn describe_fork(fork: &Fork) -> String {
+    let branches: Vec<String> = fork
+        .blocks
+        .iter()
+        .map(|b| format!("{} by {} ({})", &b.hash[..b.hash.len().min(12)], b.producer, b.status))
+        .collect();
+    format!("fork after height {}: {}", fork.fork_height, branches.join(" vs "))
+}
+
+/// Re-checks every canonical block: header hashes, links, transaction hashes and Merkle roots.
+pub(crate) async fn check_chain(node: &Node) -> Result<ChainVerifyResponse, (StatusCode, String)> {
+    let blocks = blocks_from_height(&node.pool, 1, i64::MAX)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let forks = find_forks(&node.pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
     let mut expected_prev_hash = "GENESIS".to_string();
 
-    for batch in batches {
-        let recomputed_hash = compute_batch_hash(
-            &batch.batch_id,
-            &batch.medicine_name,
-            &batch.source,
-            &batch.destination,
-            &batch.timestamp,
-            &expected_prev_hash,
+    for block in &blocks {
+        let header = &block.header;
+        let recomputed_hash = compute_block_hash(
+            header.height,
+            &header.previous_hash,
+            &header.merkle_root,
+            &header.timestamp,
+            &header.producer,
         );
 
-        if recomputed_hash != batch.hash || batch.previous_hash != expected_prev_hash {
-            return Ok(Json(VerifyResponse {
+        if recomputed_hash != header.hash {
+            return Ok(ChainVerifyResponse {
+                valid: false,
+                message: format!("Block {} was altered: its hash no longer matches its header", header.height),
+                forks,
+            });
+        }
+
+        if header.previous_hash != expected_prev_hash {
+            // Tell a block that builds on a losing fork branch apart from a plain broken link.
+            let parent = stored_block(&node.pool, &header.previous_hash)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            let message = match parent {
+                Some((parent, status)) if status == "orphaned" => format!(
+                    "Block {} builds on orphaned block {} (sealed by {}) instead of canonical block {}",
+                    header.height, parent.header.hash, parent.header.producer, expected_prev_hash
+                ),
+                _ => format!(
+                    "Chain broken at block {}: previous hash {} is not the canonical block {}",
+                    header.height, header.previous_hash, expected_prev_hash
+                ),
+            };
+            return Ok(ChainVerifyResponse { valid: false, message, forks });
+        }
+
+        if let Some(tx) = block.transactions.iter().find(|tx| tx.recompute_hash() != tx.hash()) {
+            return Ok(ChainVerifyResponse {
                 valid: false,
-                message: format!("Chain broken at batch ID: {}", batch.batch_id),
-            }));
+                message: format!("Transaction for batch {} in block {} was altered", tx.batch_id(), header.height),
+                forks,
+            });
         }
 
-        expected_prev_hash = batch.hash.clone();
+        let merkle_root = build_merkle_root(block.transactions.iter().map(|tx| tx.hash().to_string()).collect());
+        if merkle_root != header.merkle_root {
+            return Ok(ChainVerifyResponse {
+                valid: false,
+                message: format!("Merkle root mismatch in block {}", header.height),
+                forks,
+            });
+        }
+
+        expected_prev_hash = header.hash.clone();
     }
 
-    Ok(Json(VerifyResponse {
+    let mut message = format!("All {} blocks, their transactions and chaining are valid.", blocks.len());
+    if !forks.is_empty() {
+        let details: Vec<String> = forks.iter().map(describe_fork).collect();
+        message.push_str(&format!(
+            " {} fork(s) resolved in favour of the canonical chain; {}.",
+            forks.len(),
+            details.join("; ")
+        ));
+    }
+
+    Ok(ChainVerifyResponse {
         valid: true,
-        message: "All batch hashes and chaining are valid.".to_string(),
-    }))
+        message,
+        forks,
+    })
+}
+
+async fn verify_chain(
+    State(node): State<Arc<Node>>,
+) -> Result<Json<ChainVerifyResponse>, (StatusCode, String)> {
+    check_chain(&node).await.map(Json)
 }
 
 async fn get_merkle_root(
-    State(pool): State<Arc<SqlitePool>>,
+    State(node): State<Arc<Node>>,
 ) -> Result<Json<MerkleResponse>, (StatusCode, String)> {
-    let hashes = sqlx::query_scalar("SELECT hash FROM medicine_batches ORDER BY timestamp ASC")
-        .fetch_all(pool.as_ref())
+    let (root, total_batches) = ledger_merkle_root(&node.pool, i64::MAX)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    let root = build_merkle_root(hashes.clone());
-
     Ok(Json(MerkleResponse {
         merkle_root: root,
-        total_batches: hashes.len(),
+        total_batches,
+    }))
+}
+
+// GET /api/tracker/block/:height
+async fn get_block(
+    State(node): State<Arc<Node>>,
+    Path(height): Path<i64>,
+) -> Result<Json<Block>, (StatusCode, String)> {
+    block_at_height(&node.pool, height)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Block not found".to_string()))
+}
+
+// GET /api/tracker/proof/:batch_id — which block and index hold a batch
+async fn get_batch_proof(
+    State(node): State<Arc<Node>>,
+    Path(batch_id): Path<String>,
+) -> Result<Json<OnchainBatch>, (StatusCode, String)> {
+    find_onchain_batch(&node.pool, &batch_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Batch not sealed in any block".to_string()))
+}
+
+// POST /api/tracker/custody/acknowledge
+/// Confirms receipt of a batch's latest sealed transfer. Only the receiving organization may.
+async fn acknowledge_transfer(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Json(ack): Json<TransferAcknowledgement>,
+) -> Result<Json<AcknowledgementResponse>, (StatusCode, String)> {
+    let internal = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+
+    let event = latest_custody_event(&node.pool, &ack.batch_id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "No sealed custody transfer for this batch".to_string()))?;
+    let organization = match user.organization.as_deref() {
+        Some(id) => find_organization(&node.pool, id).await.map_err(internal)?,
+        None => None,
+    };
+    let organization = organization
+        .filter(|o| o.id == event.to_location)
+        .ok_or((
+            StatusCode::FORBIDDEN,
+            format!("Only {} can acknowledge this transfer", event.to_location),
+        ))?;
+    require_active(&node.pool, &organization.id).await?;
+
+    let acknowledged_at = acknowledge_custody(&node.pool, &event.hash, &event.batch_id, &event.to_location, &user.user_id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::CONFLICT, "Transfer already acknowledged".to_string()))?;
+    let purchase_orders = record_order_delivery(&node.pool, &event.batch_id, &organization.id, &acknowledged_at)
+        .await
+        .map_err(internal)?;
+
+    Ok(Json(AcknowledgementResponse {
+        message: format!("{} acknowledged receipt of batch {}", event.to_location, event.batch_id),
+        custody_hash: event.hash,
+        organization: event.to_location,
+        acknowledged_at,
+        purchase_orders,
     }))
 }
 
-pub fn tracker_routes(pool: Arc<SqlitePool>) -> Router {
+/// The sealed batch `batch_id`, as long as the caller's organization created it.
+async fn own_batch(node: &Node, user: &AuthUser, batch_id: &str) -> Result<MedicineBatch, (StatusCode, String)> {
+    let batch = find_batch(&node.pool, batch_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Batch not found or not sealed yet".to_string()))?;
+    if user.organization.as_deref() != Some(batch.organization.as_str()) {
+        return Err((StatusCode::FORBIDDEN, "Only the company that created a batch can recall it".to_string()));
+    }
+    Ok(batch)
+}
+
+// POST /api/tracker/recall
+/// Recalls one of the caller's batches. Allowed even while the company is suspended.
+async fn recall_batch(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Json(request): Json<RecallRequest>,
+) -> Result<Json<Recall>, (StatusCode, String)> {
+    let reason = request
+        .reason
+        .map(|r| r.trim().to_string())
+        .filter(|r| !r.is_empty())
+        .ok_or((StatusCode::BAD_REQUEST, "A reason is required".to_string()))?;
+    let batch = own_batch(&node, &user, &request.batch_id).await?;
+    add_recall(&node.pool, &batch.batch_id, &batch.organization, &reason, &user.user_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map(Json)
+        .ok_or((StatusCode::CONFLICT, "This batch is already under an active recall".to_string()))
+}
+
+// POST /api/tracker/recall/close
+async fn close_batch_recall(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Json(request): Json<RecallRequest>,
+) -> Result<Json<Recall>, (StatusCode, String)> {
+    let batch = own_batch(&node, &user, &request.batch_id).await?;
+    close_recall(&node.pool, &batch.batch_id, &user.user_id, request.reason.as_deref())
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "This batch has no active recall".to_string()))
+}
+
+// POST /api/tracker/conditions
+/// Records a temperature reading for a sealed batch. The caller's organization must have created
+/// the batch, hold it, or have sent it on its latest, still unconfirmed way.
+async fn record_conditions(
+    State(node): State<Arc<Node>>,
+    user: AuthUser,
+    Json(report): Json<ConditionReport>,
+) -> Result<Json<ConditionReading>, (StatusCode, String)> {
+    let internal = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    if !report.temperature_celsius.is_finite() {
+        return Err((StatusCode::BAD_REQUEST, "temperature_celsius must be a number".to_string()));
+    }
+    let recorded_at = match report.recorded_at.as_deref() {
+        Some(t) => parse_time("recorded_at", t)?,
+        None => Utc::now(),
+    };
+    if recorded_at > Utc::now() {
+        return Err((StatusCode::BAD_REQUEST, "recorded_at is in the future".to_string()));
+    }
+
+    let batch = find_batch(&node.pool, &report.batch_id)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Batch not found or not sealed yet".to_string()))?;
+    let (_, current_location) = batch_chain_head(&node.pool, &batch.batch_id, i64::MAX)
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Batch not found or not sealed yet".to_string()))?;
+    let sender = latest_custody_event(&node.pool, &batch.batch_id)
+        .await
+        .map_err(internal)?
+        .map(|event| event.from_location);
+
+    let organization = match user.organization.as_deref() {
+        Some(id) => find_organization(&node.pool, id).await.map_err(internal)?,
+        None => None,
+    };
+    let involved = organization.is_some_and(|o| {
+        batch.organization == o.id || current_location == o.id || sender.is_some_and(|sender| sender == o.id)
+    });
+    if !involved {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Only the batch's maker, holder or last sender can record its conditions".to_string(),
+        ));
+    }
+
+    let location = report.location.unwrap_or(current_location);
+    add_condition_reading(&node.pool, &batch, &location, report.temperature_celsius, &recorded_at.to_rfc3339(), &user.user_id)
+        .await
+        .map(Json)
+        .map_err(internal)
+}
+
+// GET /api/tracker/conditions/:batch_id
+async fn list_conditions(
+    State(node): State<Arc<Node>>,
+    Path(batch_id): Path<String>,
+) -> Result<Json<Vec<ConditionReading>>, (StatusCode, String)> {
+    condition_readings(&node.pool, &batch_id)
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+// GET /api/tracker/anchor/:batch_id — check the batch's root against the EVM chain
+async fn verify_batch_anchor(
+    State(node): State<Arc<Node>>,
+    Path(batch_id): Path<String>,
+) -> Result<Json<AnchorVerification>, (StatusCode, String)> {
+    verify_anchor(&node, &batch_id)
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e))?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Batch not sealed in any block".to_string()))
+}
+
+// GET /api/tracker/anchors
+async fn list_anchors(
+    State(node): State<Arc<Node>>,
+) -> Result<Json<Vec<EvmAnchor>>, (StatusCode, String)> {
+    evm_anchors(&node.pool)
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+pub fn tracker_routes(node: Arc<Node>) -> Router {
+    let guard = |action: Action| middleware::from_fn_with_state(action, authorize);
     Router::new()
-        .route("/api/tracker/add", post(add_batch))
-        .route("/api/tracker/verify/:batch_id", get(verify_batch))
-        .route("/api/tracker/verifychain", get(verify_chain))
-        .route("/api/tracker/merkleroot", get(get_merkle_root))
-        .with_state(pool)
+        .route("/api/tracker/add", post(add_batch).route_layer(guard(Action::CreateBatch)))
+        .route("/api/tracker/custody", post(transfer_custody).route_layer(guard(Action::TransferCustody)))
+        .route(
+            "/api/tracker/custody/acknowledge",
+            post(acknowledge_transfer).route_layer(guard(Action::AcknowledgeTransfer)),
+        )
+        .route("/api/tracker/recall", post(recall_batch).route_layer(guard(Action::IssueRecall)))
+        .route("/api/tracker/recall/close", post(close_batch_recall).route_layer(guard(Action::IssueRecall)))
+        .route("/api/tracker/conditions", post(record_conditions).route_layer(guard(Action::RecordConditions)))
+        .route("/api/tracker/conditions/:batch_id", get(list_conditions).route_layer(guard(Action::ReadLedger)))
+        .route("/api/tracker/verify/:batch_id", get(verify_batch).route_layer(guard(Action::ReadLedger)))
+        .route("/api/tracker/verifychain", get(verify_chain).route_layer(guard(Action::ReadLedger)))
+        .route("/api/tracker/merkleroot", get(get_merkle_root).route_layer(guard(Action::ReadLedger)))
+        .route("/api/tracker/block/:height", get(get_block).route_layer(guard(Action::ReadLedger)))
+        .route("/api/tracker/proof/:batch_id", get(get_batch_proof).route_layer(guard(Action::ReadLedger)))
+        .route("/api/tracker/anchor/:batch_id", get(verify_batch_anchor).route_layer(guard(Action::ReadLedger)))
+        .route("/api/tracker/anchors", get(list_anchors).route_layer(guard(Action::ReadLedger)))
+        .with_state(node)
 }
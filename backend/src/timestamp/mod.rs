@@ -0,0 +1,197 @@
+pub mod tsp;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use der::DecodePem;
+use rand::RngCore;
+use serde::Serialize;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use x509_cert::Certificate;
+
+use crate::db::entities::{
+    batches_without_timestamp, find_batch, find_timestamp_token, save_timestamp_token,
+};
+use crate::p2p::Node;
+
+/// Settings for RFC 3161 timestamping of batch hashes. Off unless `TSA_URL` is set.
+#[derive(Debug, Clone)]
+pub struct TimestampConfig {
+    pub tsa_url: String,
+    /// PEM certificate the TSA must sign with. Without it tokens are still collected, but only
+    /// checked against the certificate they carry and so reported as unverified.
+    pub tsa_cert_path: Option<String>,
+    pub interval_secs: u64,
+    /// How far a batch's own timestamp may precede its token before it counts as backdated.
+    pub max_delay_secs: i64,
+}
+
+impl TimestampConfig {
+    pub fn from_env() -> Option<Self> {
+        let tsa_url = env::var("TSA_URL").ok().filter(|v| !v.is_empty())?;
+        let tsa_cert_path = env::var("TSA_CERT_PATH").ok().filter(|v| !v.is_empty());
+        if tsa_cert_path.is_none() {
+            eprintln!("TSA_CERT_PATH is not set: timestamp tokens will be reported as unverified");
+        }
+        Some(Self {
+            tsa_url,
+            tsa_cert_path,
+            interval_secs: env::var("TSA_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v: &u64| *v > 0)
+                .unwrap_or(10),
+            max_delay_secs: env::var("TSA_MAX_DELAY_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|v: &i64| *v >= 0)
+                .unwrap_or(300),
+        })
+    }
+
+    fn trusted_certificate(&self) -> Result<Option<Certificate>, String> {
+        let Some(path) = &self.tsa_cert_path else {
+            return Ok(None);
+        };
+        let pem = std::fs::read(path).map_err(|e| format!("Reading {}: {}", path, e))?;
+        Certificate::from_pem(&pem)
+            .map(Some)
+            .map_err(|e| format!("Parsing {}: {}", path, e))
+    }
+}
+
+#[derive(Serialize)]
+pub struct TimestampVerification {
+    pub valid: bool,
+    /// Whether the token was checked against the configured TSA certificate. An untrusted
+    /// token is never `valid`, but does not count against the batch either.
+    pub trusted: bool,
+    pub message: String,
+    pub gen_time: Option<String>,
+    pub tsa: Option<String>,
+}
+
+/// Requests a token over a batch hash (a hex SHA-256 digest, used directly as the imprint)
+/// and checks it before returning it.
+async fn request_token(node: &Node, config: &TimestampConfig, batch_hash: &str) -> Result<(Vec<u8>, DateTime<Utc>), String> {
+    let digest = hex::decode(batch_hash).map_err(|e| format!("Batch hash is not hex: {}", e))?;
+    let mut nonce = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let response = node
+        .http
+        .post(&config.tsa_url)
+        .header(reqwest::header::CONTENT_TYPE, "application/timestamp-query")
+        .body(tsp::build_request(&digest, &nonce)?)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .error_for_status()
+        .map_err(|e| e.to_string())?
+        .bytes()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let token = tsp::parse_response(&response)?;
+    let info = tsp::verify_token(&token, &digest, Some(&nonce), config.trusted_certificate()?.as_ref())?;
+    Ok((token, info.gen_time))
+}
+
+/// Timestamps every sealed batch that has no token yet.
+pub async fn timestamp_pending(node: &Node, config: &TimestampConfig) -> Result<(), String> {
+    let pending = batches_without_timestamp(&node.pool, 50).await.map_err(|e| e.to_string())?;
+    for (batch_id, batch_hash) in pending {
+        let (token, gen_time) = request_token(node, config, &batch_hash).await?;
+        save_timestamp_token(
+            &node.pool,
+            &batch_id,
+            &batch_hash,
+            &STANDARD.encode(token),
+            &gen_time.to_rfc3339(),
+            &config.tsa_url,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+pub fn spawn_timestamp_service(node: Arc<Node>) {
+    let Some(config) = node.config.timestamp.clone() else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+        loop {
+            interval.tick().await;
+            if let Err(e) = timestamp_pending(&node, &config).await {
+                eprintln!("Timestamping failed: {}", e);
+            }
+        }
+    });
+}
+
+/// Verifies a batch's stored token and that the batch's own timestamp agrees with it.
+/// Returns `None` when the batch has no token.
+pub async fn verify_batch_timestamp(node: &Node, batch_id: &str) -> Result<Option<TimestampVerification>, String> {
+    let db = |e: sqlx::Error| e.to_string();
+    let Some(token) = find_timestamp_token(&node.pool, batch_id).await.map_err(db)? else {
+        return Ok(None);
+    };
+    let Some(batch) = find_batch(&node.pool, batch_id).await.map_err(db)? else {
+        return Ok(None);
+    };
+
+    let invalid = |message: String| {
+        Ok(Some(TimestampVerification { valid: false, trusted: true, message, gen_time: None, tsa: None }))
+    };
+
+    if token.batch_hash != batch.hash {
+        return invalid("Timestamp token was issued for a different batch hash".to_string());
+    }
+    let trusted = match &node.config.timestamp {
+        Some(config) => config.trusted_certificate()?,
+        None => None,
+    };
+    let digest = hex::decode(&batch.hash).map_err(|e| e.to_string())?;
+    let der = STANDARD.decode(&token.token).map_err(|e| e.to_string())?;
+    let info = match tsp::verify_token(&der, &digest, None, trusted.as_ref()) {
+        Ok(info) => info,
+        Err(reason) => return invalid(reason),
+    };
+
+    let claimed = DateTime::parse_from_rfc3339(&batch.timestamp)
+        .map_err(|e| format!("Bad batch timestamp {}: {}", batch.timestamp, e))?
+        .with_timezone(&Utc);
+    let max_delay = node.config.timestamp.as_ref().map(|c| c.max_delay_secs).unwrap_or(300);
+    let delay = (info.gen_time - claimed).num_seconds();
+
+    let (valid, message) = if !info.trusted {
+        (false, format!(
+            "Timestamp token from {} is unverified: no TSA certificate is configured (TSA_CERT_PATH) to check it against",
+            info.tsa_subject
+        ))
+    } else if delay < -max_delay {
+        (false, format!("Batch claims {} but the TSA saw it at {}", batch.timestamp, info.gen_time.to_rfc3339()))
+    } else if delay > max_delay {
+        (false, format!(
+            "Batch claims {} but was first timestamped {}s later; the claimed time is not corroborated",
+            batch.timestamp, delay
+        ))
+    } else {
+        (true, format!(
+            "Timestamp token valid: hash existed at {} (serial {}, policy {})",
+            info.gen_time.to_rfc3339(), info.serial_number, info.policy
+        ))
+    };
+
+    Ok(Some(TimestampVerification {
+        valid,
+        trusted: info.trusted,
+        message,
+        gen_time: Some(info.gen_time.to_rfc3339()),
+        tsa: Some(info.tsa_subject),
+    }))
+}
@@ -0,0 +1,269 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use cms::content_info::ContentInfo;
+use cms::signed_data::{SignedData, SignerIdentifier};
+use der::asn1::{Any, ObjectIdentifier, OctetString, Uint};
+use der::{Decode, Encode, Sequence};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::RsaPublicKey;
+use sha2::{Digest, Sha256};
+use x509_cert::ext::Extensions;
+use x509_cert::spki::AlgorithmIdentifierOwned;
+use x509_cert::Certificate;
+
+use crate::utils::signatures::verify_signature;
+
+const ID_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+const ID_SIGNED_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.2");
+const ID_CT_TST_INFO: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.16.1.4");
+const ID_CONTENT_TYPE: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.3");
+const ID_MESSAGE_DIGEST: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.4");
+const RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const SHA256_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.11");
+
+/// RFC 3161 `MessageImprint`.
+#[derive(Sequence)]
+struct MessageImprint {
+    hash_algorithm: AlgorithmIdentifierOwned,
+    hashed_message: OctetString,
+}
+
+/// RFC 3161 `TimeStampReq`.
+#[derive(Sequence)]
+struct TimeStampReq {
+    version: u8,
+    message_imprint: MessageImprint,
+    #[asn1(optional = "true")]
+    req_policy: Option<ObjectIdentifier>,
+    #[asn1(optional = "true")]
+    nonce: Option<Uint>,
+    #[asn1(default = "Default::default")]
+    cert_req: bool,
+    #[asn1(context_specific = "0", tag_mode = "IMPLICIT", constructed = "true", optional = "true")]
+    extensions: Option<Extensions>,
+}
+
+/// RFC 3161 `PKIStatusInfo`.
+#[derive(Sequence)]
+struct PkiStatusInfo {
+    status: u8,
+    #[asn1(optional = "true")]
+    status_string: Option<Vec<String>>,
+    #[asn1(optional = "true")]
+    fail_info: Option<der::asn1::BitString>,
+}
+
+/// RFC 3161 `TimeStampResp`.
+#[derive(Sequence)]
+struct TimeStampResp {
+    status: PkiStatusInfo,
+    #[asn1(optional = "true")]
+    time_stamp_token: Option<ContentInfo>,
+}
+
+#[derive(Sequence)]
+struct Accuracy {
+    #[asn1(optional = "true")]
+    seconds: Option<u64>,
+    #[asn1(context_specific = "0", tag_mode = "IMPLICIT", optional = "true")]
+    millis: Option<u16>,
+    #[asn1(context_specific = "1", tag_mode = "IMPLICIT", optional = "true")]
+    micros: Option<u16>,
+}
+
+/// RFC 3161 `TSTInfo`, the content the TSA signs.
+#[derive(Sequence)]
+struct TstInfo {
+    version: u8,
+    policy: ObjectIdentifier,
+    message_imprint: MessageImprint,
+    serial_number: Uint,
+    /// GeneralizedTime, kept raw because TSAs may add fractional seconds.
+    gen_time: Any,
+    #[asn1(optional = "true")]
+    accuracy: Option<Accuracy>,
+    #[asn1(default = "Default::default")]
+    ordering: bool,
+    #[asn1(optional = "true")]
+    nonce: Option<Uint>,
+    #[asn1(context_specific = "0", tag_mode = "EXPLICIT", optional = "true")]
+    tsa: Option<Any>,
+    #[asn1(context_specific = "1", tag_mode = "IMPLICIT", constructed = "true", optional = "true")]
+    extensions: Option<Extensions>,
+}
+
+/// What a verified token attests.
+#[derive(Debug)]
+pub struct TokenInfo {
+    pub gen_time: DateTime<Utc>,
+    pub serial_number: String,
+    pub policy: String,
+    pub tsa_subject: String,
+    /// Whether the signature checked out against a configured TSA certificate rather than
+    /// the one the token carries, which anyone can mint.
+    pub trusted: bool,
+}
+
+fn sha256_algorithm() -> AlgorithmIdentifierOwned {
+    AlgorithmIdentifierOwned {
+        oid: ID_SHA256,
+        parameters: None,
+    }
+}
+
+/// DER `TimeStampReq` for a SHA-256 digest, asking the TSA to include its certificate.
+pub fn build_request(digest: &[u8], nonce: &[u8]) -> Result<Vec<u8>, String> {
+    let request = TimeStampReq {
+        version: 1,
+        message_imprint: MessageImprint {
+            hash_algorithm: sha256_algorithm(),
+            hashed_message: OctetString::new(digest).map_err(|e| e.to_string())?,
+        },
+        req_policy: None,
+        nonce: Some(Uint::new(nonce).map_err(|e| e.to_string())?),
+        cert_req: true,
+        extensions: None,
+    };
+    request.to_der().map_err(|e| e.to_string())
+}
+
+/// Checks the TSA granted the request and returns the DER `TimeStampToken`.
+pub fn parse_response(response: &[u8]) -> Result<Vec<u8>, String> {
+    let response = TimeStampResp::from_der(response).map_err(|e| format!("Malformed TimeStampResp: {}", e))?;
+
+    // 0 = granted, 1 = grantedWithMods
+    if response.status.status > 1 {
+        let detail = response.status.status_string.unwrap_or_default().join("; ");
+        return Err(format!("TSA refused the request (status {}): {}", response.status.status, detail));
+    }
+
+    response
+        .time_stamp_token
+        .ok_or_else(|| "TSA response carries no token".to_string())?
+        .to_der()
+        .map_err(|e| e.to_string())
+}
+
+fn parse_generalized_time(value: &Any) -> Result<DateTime<Utc>, String> {
+    let text = std::str::from_utf8(value.value()).map_err(|e| e.to_string())?;
+    let trimmed = text.trim_end_matches('Z');
+    let whole = trimmed.split('.').next().unwrap_or(trimmed);
+    NaiveDateTime::parse_from_str(whole, "%Y%m%d%H%M%S")
+        .map(|t| t.and_utc())
+        .map_err(|e| format!("Bad genTime {}: {}", text, e))
+}
+
+fn signer_matches(sid: &SignerIdentifier, certificate: &Certificate) -> bool {
+    match sid {
+        SignerIdentifier::IssuerAndSerialNumber(id) => {
+            id.issuer == certificate.tbs_certificate.issuer
+                && id.serial_number == certificate.tbs_certificate.serial_number
+        }
+        // Matched by trying the key: the signature check below is what binds it.
+        SignerIdentifier::SubjectKeyIdentifier(_) => true,
+    }
+}
+
+/// Verifies a `TimeStampToken` over `digest`: the TSTInfo imprint and nonce, the signed
+/// attributes, and the TSA's signature. With `trusted` set, only that certificate is accepted;
+/// otherwise the certificate embedded in the token is used, and the result is not `trusted`.
+pub fn verify_token(
+    token: &[u8],
+    digest: &[u8],
+    nonce: Option<&[u8]>,
+    trusted: Option<&Certificate>,
+) -> Result<TokenInfo, String> {
+    let content_info = ContentInfo::from_der(token).map_err(|e| format!("Malformed token: {}", e))?;
+    if content_info.content_type != ID_SIGNED_DATA {
+        return Err("Token is not CMS SignedData".to_string());
+    }
+    let signed_data: SignedData = content_info
+        .content
+        .decode_as()
+        .map_err(|e| format!("Malformed SignedData: {}", e))?;
+
+    let encap = &signed_data.encap_content_info;
+    if encap.econtent_type != ID_CT_TST_INFO {
+        return Err("Token does not contain a TSTInfo".to_string());
+    }
+    let tst_der = encap
+        .econtent
+        .as_ref()
+        .ok_or("Token has no TSTInfo content")?
+        .decode_as::<OctetString>()
+        .map_err(|e| e.to_string())?
+        .into_bytes();
+    let tst_info = TstInfo::from_der(&tst_der).map_err(|e| format!("Malformed TSTInfo: {}", e))?;
+
+    if tst_info.message_imprint.hash_algorithm.oid != ID_SHA256
+        || tst_info.message_imprint.hashed_message.as_bytes() != digest
+    {
+        return Err("Token is for a different hash".to_string());
+    }
+    if let Some(nonce) = nonce {
+        let expected = Uint::new(nonce).map_err(|e| e.to_string())?;
+        if tst_info.nonce.as_ref() != Some(&expected) {
+            return Err("Token nonce does not match the request".to_string());
+        }
+    }
+
+    let signer = signed_data.signer_infos.0.iter().next().ok_or("Token has no signer")?;
+    if signer.digest_alg.oid != ID_SHA256 {
+        return Err(format!("Unsupported token digest algorithm {}", signer.digest_alg.oid));
+    }
+    if signer.signature_algorithm.oid != RSA_ENCRYPTION && signer.signature_algorithm.oid != SHA256_WITH_RSA {
+        return Err(format!("Unsupported token signature algorithm {}", signer.signature_algorithm.oid));
+    }
+
+    let signed_attrs = signer.signed_attrs.as_ref().ok_or("Token has no signed attributes")?;
+    let attribute = |oid: ObjectIdentifier| {
+        signed_attrs
+            .iter()
+            .find(|a| a.oid == oid)
+            .and_then(|a| a.values.iter().next())
+    };
+    let content_type = attribute(ID_CONTENT_TYPE).and_then(|v| v.decode_as::<ObjectIdentifier>().ok());
+    if content_type != Some(ID_CT_TST_INFO) {
+        return Err("Signed content type is not TSTInfo".to_string());
+    }
+    let message_digest = attribute(ID_MESSAGE_DIGEST).and_then(|v| v.decode_as::<OctetString>().ok());
+    if message_digest.as_ref().map(|d| d.as_bytes()) != Some(Sha256::digest(&tst_der).as_slice()) {
+        return Err("TSTInfo does not match the signed digest".to_string());
+    }
+
+    let embedded: Vec<Certificate> = signed_data
+        .certificates
+        .iter()
+        .flat_map(|set| set.0.iter())
+        .filter_map(|choice| match choice {
+            cms::cert::CertificateChoices::Certificate(c) => Some(c.clone()),
+            _ => None,
+        })
+        .collect();
+    let candidates: Vec<Certificate> = match trusted {
+        Some(certificate) => vec![certificate.clone()],
+        None => embedded,
+    };
+
+    let signed_bytes = signed_attrs.to_der().map_err(|e| e.to_string())?;
+    let signature = signer.signature.as_bytes();
+    let certificate = candidates
+        .iter()
+        .filter(|c| signer_matches(&signer.sid, c))
+        .find(|c| {
+            c.tbs_certificate
+                .subject_public_key_info
+                .to_der()
+                .ok()
+                .and_then(|der| RsaPublicKey::from_public_key_der(&der).ok())
+                .is_some_and(|key| verify_signature(&key, &signed_bytes, signature))
+        })
+        .ok_or("TSA signature does not verify")?;
+
+    Ok(TokenInfo {
+        gen_time: parse_generalized_time(&tst_info.gen_time)?,
+        serial_number: hex::encode(tst_info.serial_number.as_bytes()),
+        policy: tst_info.policy.to_string(),
+        tsa_subject: certificate.tbs_certificate.subject.to_string(),
+        trusted: trusted.is_some(),
+    })
+}
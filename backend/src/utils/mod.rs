@@ -1 +1,3 @@
 pub mod merkle;
+pub mod password;
+pub mod signatures;
@@ -0,0 +1,78 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use sha2::{Digest, Sha256};
+use std::env;
+use subtle::ConstantTimeEq;
+
+/// Outcome of checking a login password against the stored credential.
+#[derive(Debug, PartialEq)]
+pub enum PasswordCheck {
+    Valid,
+    /// Correct, but stored as legacy plaintext or with weaker Argon2 parameters than
+    /// currently configured; the caller should store a fresh hash.
+    ValidNeedsRehash,
+    Invalid,
+}
+
+/// Argon2id cost, tunable through `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM`
+/// (defaults follow the OWASP minimum: 19 MiB, 2 passes, 1 lane).
+fn params() -> Params {
+    let read = |name: &str, default: u32| {
+        env::var(name)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default)
+    };
+    Params::new(
+        read("ARGON2_MEMORY_KIB", 19 * 1024),
+        read("ARGON2_ITERATIONS", 2),
+        read("ARGON2_PARALLELISM", 1),
+        None,
+    )
+    .unwrap_or_default()
+}
+
+fn argon2() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params())
+}
+
+/// Hashes a password with Argon2id and a fresh random salt, as a PHC string.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    argon2()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Checks `candidate` against a stored credential, which is either an Argon2 PHC string
+/// or a legacy plaintext password from before hashing was introduced.
+pub fn verify_password(stored: &str, candidate: &str) -> PasswordCheck {
+    match PasswordHash::new(stored) {
+        Ok(hash) => {
+            if argon2().verify_password(candidate.as_bytes(), &hash).is_err() {
+                return PasswordCheck::Invalid;
+            }
+            let current = params();
+            let outdated = hash.algorithm != Algorithm::Argon2id.ident()
+                || Params::try_from(&hash).map_or(true, |p| {
+                    p.m_cost() < current.m_cost() || p.t_cost() < current.t_cost() || p.p_cost() < current.p_cost()
+                });
+            if outdated {
+                PasswordCheck::ValidNeedsRehash
+            } else {
+                PasswordCheck::Valid
+            }
+        }
+        Err(_) => {
+            // Compare digests so neither the contents nor the length leak through timing.
+            let matches = Sha256::digest(stored.as_bytes()).ct_eq(&Sha256::digest(candidate.as_bytes()));
+            if bool::from(matches) {
+                PasswordCheck::ValidNeedsRehash
+            } else {
+                PasswordCheck::Invalid
+            }
+        }
+    }
+}
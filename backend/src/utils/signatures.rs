@@ -1,4 +1,4 @@
-use rsa::{RsaPrivateKey, RsaPublicKey, PaddingScheme};
+use rsa::{Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
 use rand::rngs::OsRng;
 use sha2::{Sha256, Digest};
 
@@ -14,10 +14,7 @@ pub fn generate_keys() -> (RsaPrivateKey, RsaPublicKey) {
 pub fn sign_data(private_key: &RsaPrivateKey, data: &[u8]) -> Vec<u8> {
     let hashed = Sha256::digest(data);
     private_key
-        .sign(
-            PaddingScheme::PKCS1v15Sign { hash: Some(rsa::Hash::SHA2_256) },
-            &hashed,
-        )
+        .sign(Pkcs1v15Sign::new::<Sha256>(), &hashed)
         .expect("failed to sign")
 }
 
@@ -25,10 +22,6 @@ pub fn sign_data(private_key: &RsaPrivateKey, data: &[u8]) -> Vec<u8> {
 pub fn verify_signature(public_key: &RsaPublicKey, data: &[u8], signature: &[u8]) -> bool {
     let hashed = Sha256::digest(data);
     public_key
-        .verify(
-            PaddingScheme::PKCS1v15Sign { hash: Some(rsa::Hash::SHA2_256) },
-            &hashed,
-            signature,
-        )
+        .verify(Pkcs1v15Sign::new::<Sha256>(), &hashed, signature)
         .is_ok()
 }
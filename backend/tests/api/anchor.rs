@@ -0,0 +1,86 @@
+use reqwest::Client;
+use serde_json::{json, Value};
+use sha3::{Digest, Keccak256};
+use std::process::{Child, Command, Stdio};
+
+use crate::common::{free_port, start_cluster, wait_until};
+
+/// A local anvil chain, stopped when dropped.
+struct Anvil {
+    url: String,
+    child: Child,
+}
+
+impl Drop for Anvil {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Anvil {
+    /// Starts `anvil` (or `ANVIL_BIN`) on a free port with its unlocked dev accounts.
+    async fn start() -> Self {
+        let port = free_port();
+        let child = Command::new(std::env::var("ANVIL_BIN").unwrap_or_else(|_| "anvil".to_string()))
+            .args(["--port", &port.to_string(), "--silent"])
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("anvil on PATH (install Foundry) or ANVIL_BIN");
+        let anvil = Self { url: format!("http://127.0.0.1:{}", port), child };
+        wait_until(30, "anvil to start", || async { anvil.rpc("eth_chainId", json!([])).await.is_some() }).await;
+        anvil
+    }
+
+    async fn rpc(&self, method: &str, params: Value) -> Option<Value> {
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response: Value = Client::new().post(&self.url).json(&body).send().await.ok()?.json().await.ok()?;
+        Some(response)
+    }
+}
+
+fn selector(signature: &str) -> String {
+    hex::encode(&Keccak256::digest(signature.as_bytes())[..4])
+}
+
+#[tokio::test]
+#[ignore = "needs anvil from Foundry on PATH; run with `cargo test -- --ignored`"]
+async fn roots_are_anchored_in_a_deployed_contract_and_read_back() {
+    let anvil = Anvil::start().await;
+    let nodes = start_cluster(1, &[("ANCHOR_RPC_URL", &anvil.url), ("ANCHOR_INTERVAL_SECS", "1")]).await;
+    let node = &nodes[0];
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+    let aspirin = node.product("Aspirin", None).await;
+    node.sealed_batch(&acme, json!({ "batch_id": "A1", "gtin": aspirin, "source": "Plant", "destination": acme_id }))
+        .await;
+
+    // The node deploys the contract, anchors the root, and verification reads it back with anchoredAt.
+    wait_until(60, "the batch to be anchored", || async {
+        node.get("/api/tracker/anchor/A1", &acme).await.1["valid"] == json!(true)
+    })
+    .await;
+    let verification = node.get("/api/tracker/anchor/A1", &acme).await.1;
+    let anchor = &verification["anchor"];
+    let contract = anchor["contract"].as_str().unwrap();
+    let root = anchor["merkle_root"].as_str().unwrap();
+
+    let code = anvil.rpc("eth_getCode", json!([contract, "latest"])).await.unwrap();
+    assert_ne!(code["result"], json!("0x"), "no contract deployed: {}", code);
+
+    let query = format!("0x{}{}", selector("anchoredAt(bytes32)"), root);
+    let stored = anvil.rpc("eth_call", json!([{ "to": contract, "data": query }, "latest"])).await.unwrap();
+    let block = u64::from_str_radix(stored["result"].as_str().unwrap().trim_start_matches("0x"), 16).unwrap();
+    assert_eq!(Some(block as i64), anchor["evm_block_number"].as_i64());
+
+    // A root can be anchored only once.
+    let accounts = anvil.rpc("eth_accounts", json!([])).await.unwrap();
+    let again = format!("0x{}{}{:064x}", selector("anchor(bytes32,uint256)"), root, 1);
+    let tx = json!({ "from": accounts["result"][0], "to": contract, "data": again, "gas": "0x30000" });
+    let sent = anvil.rpc("eth_sendTransaction", json!([tx])).await.unwrap();
+    if let Some(hash) = sent["result"].as_str() {
+        let receipt = anvil.rpc("eth_getTransactionReceipt", json!([hash])).await.unwrap();
+        assert_eq!(receipt["result"]["status"], json!("0x0"), "re-anchoring succeeded: {}", receipt);
+    } else {
+        assert!(sent["error"].is_object(), "{}", sent);
+    }
+}
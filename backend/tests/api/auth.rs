@@ -0,0 +1,208 @@
+use reqwest::StatusCode;
+use serde_json::json;
+
+use crate::common::start_cluster;
+
+#[tokio::test]
+async fn the_admin_email_is_promoted_only_once_verified() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+
+    let (_, refused) = node
+        .call(
+            reqwest::Method::POST,
+            "/api/signup",
+            None,
+            Some(json!({ "username": "admin", "email": "admin@test", "password": "pw-123456", "role": "admin" })),
+        )
+        .await;
+    assert!(refused["message"].as_str().unwrap().contains("granted by an admin"), "{}", refused);
+
+    // Signing up with the address grants nothing yet.
+    let unverified = node.user("admin", "customer").await;
+    let (_, me) = node.get("/api/me", &unverified).await;
+    assert_eq!(me["role"], json!("customer"), "{}", me);
+    let (status, _) = node.get("/api/admin/auth-events", &unverified).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let admin = node.admin().await;
+    let (status, events) = node.get("/api/admin/auth-events", &admin).await;
+    assert_eq!(status, StatusCode::OK, "{}", events);
+    // Tokens from before the promotion stop working.
+    let (status, _) = node.get("/api/me", &unverified).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn a_replayed_verification_link_does_not_restore_a_demoted_admin() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let admin = node.admin().await;
+    node.user("deputy", "customer").await;
+    let (status, _) = node.post("/api/admin/users/role", &admin, json!({ "email": "deputy@test", "role": "admin" })).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, deputy) = node.login("deputy@test", "pw-123456").await;
+    let deputy = deputy["token"].as_str().unwrap();
+    let demote = json!({ "email": "admin@test", "role": "customer" });
+    let (status, body) = node.post("/api/admin/users/role", deputy, demote).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let (status, _) = node.verify_email("admin@test").await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, login) = node.login("admin@test", "pw-123456").await;
+    assert_eq!(login["role"], json!("customer"), "{}", login);
+}
+
+#[tokio::test]
+async fn other_addresses_stay_as_signed_up_when_verified() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    node.user("nurse", "hospital").await;
+    node.verify_email("nurse@test").await;
+    let (_, login) = node.login("nurse@test", "pw-123456").await;
+    assert_eq!(login["role"], json!("hospital"), "{}", login);
+}
+
+#[tokio::test]
+async fn failed_logins_do_not_reveal_whether_an_account_exists() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    node.user("nurse", "hospital").await;
+
+    let (unknown_status, unknown) = node.login("nobody@test", "pw-123456").await;
+    let (wrong_status, wrong) = node.login("nurse@test", "not-the-password").await;
+    assert_eq!(unknown_status, wrong_status);
+    assert_eq!(unknown, wrong);
+    assert_eq!(unknown["user"], json!("Invalid email or password"));
+}
+
+#[tokio::test]
+async fn a_locked_account_stays_locked_until_an_admin_unlocks_it() {
+    let nodes = start_cluster(1, &[("LOGIN_MAX_FAILURES", "3"), ("LOGIN_BACKOFF_BASE_SECS", "0")]).await;
+    let node = &nodes[0];
+    node.user("nurse", "hospital").await;
+    node.user("porter", "hospital").await;
+    let admin = node.admin().await;
+
+    for _ in 0..3 {
+        let (_, failed) = node.login("nurse@test", "not-the-password").await;
+        assert_eq!(failed["user"], json!("Invalid email or password"));
+    }
+    let (status, refused) = node.login("nurse@test", "pw-123456").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{}", refused);
+
+    // The lockout is the account's: another account from the same address still logs in.
+    let (_, login) = node.login("porter@test", "pw-123456").await;
+    assert_ne!(login["token"], json!(""), "{}", login);
+
+    let (status, unlocked) = node.post("/api/admin/users/unlock", &admin, json!({ "email": "nurse@test" })).await;
+    assert_eq!(status, StatusCode::OK, "{}", unlocked);
+    let (_, login) = node.login("nurse@test", "pw-123456").await;
+    assert_ne!(login["token"], json!(""), "{}", login);
+}
+
+#[tokio::test]
+async fn reusing_a_rotated_refresh_token_revokes_the_session() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    node.user("nurse", "hospital").await;
+    let (_, login) = node.login("nurse@test", "pw-123456").await;
+    let first = login["refresh_token"].clone();
+
+    let refresh = |token: &serde_json::Value| {
+        node.call(reqwest::Method::POST, "/api/token/refresh", None, Some(json!({ "refresh_token": token })))
+    };
+    let (status, rotated) = refresh(&first).await;
+    assert_eq!(status, StatusCode::OK, "{}", rotated);
+    assert_ne!(rotated["refresh_token"], first);
+    let (status, _) = node.get("/api/me", rotated["token"].as_str().unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The old token again: the whole session goes, including what it was rotated into.
+    let (status, _) = refresh(&first).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = refresh(&rotated["refresh_token"]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = node.get("/api/me", rotated["token"].as_str().unwrap()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn a_read_only_api_key_is_refused_on_write_routes() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+    let aspirin = node.product("Aspirin", None).await;
+    let batch = |id: &str| json!({ "batch_id": id, "gtin": aspirin, "source": "Plant", "destination": acme_id });
+    node.sealed_batch(&acme, batch("K1")).await;
+
+    let (status, created) = node.post("/api/orgs/keys", &acme, json!({ "name": "erp", "scopes": ["read"] })).await;
+    assert_eq!(status, StatusCode::OK, "{}", created);
+    let key = created["key"].as_str().unwrap();
+    let (_, listed) = node.get("/api/orgs/keys", &acme).await;
+    assert!(!listed.to_string().contains(key), "{}", listed);
+
+    let (status, proof) = node.get("/api/tracker/proof/K1", key).await;
+    assert_eq!(status, StatusCode::OK, "{}", proof);
+    let (status, _) = node.post("/api/tracker/add", key, batch("K2")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = node.post("/api/orgs/keys", key, json!({ "name": "more", "scopes": ["batch:write"] })).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+/// Codes as an authenticator app holding `secret` would show them.
+fn authenticator(secret: &str) -> totp_rs::TOTP {
+    let bytes = totp_rs::Secret::Encoded(secret.to_string()).to_bytes().unwrap();
+    totp_rs::TOTP::new(totp_rs::Algorithm::SHA1, 6, 1, 30, bytes, None, "nurse".to_string()).unwrap()
+}
+
+#[tokio::test]
+async fn two_factor_codes_are_accepted_once() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let session = node.user("nurse", "hospital").await;
+    let login = |otp: Option<&str>| {
+        let body = json!({ "email": "nurse@test", "password": "pw-123456", "otp": otp });
+        node.call(reqwest::Method::POST, "/api/login", None, Some(body))
+    };
+
+    let (status, enrollment) = node.post("/api/2fa/enroll", &session, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", enrollment);
+    assert!(enrollment["otpauth_uri"].as_str().unwrap().starts_with("otpauth://totp/"), "{}", enrollment);
+    let app = authenticator(enrollment["secret"].as_str().unwrap());
+    // Until confirmed, the password is still enough.
+    let (_, pending) = login(None).await;
+    assert!(pending["two_factor"].is_null(), "{}", pending);
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    let code = app.generate(now);
+    let wrong = if code == "000000" { "111111" } else { "000000" };
+    let (status, _) = node.post("/api/2fa/confirm", &session, json!({ "code": wrong })).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, confirmed) = node.post("/api/2fa/confirm", &session, json!({ "code": code })).await;
+    assert_eq!(status, StatusCode::OK, "{}", confirmed);
+    let recovery: Vec<String> = serde_json::from_value(confirmed["recovery_codes"].clone()).unwrap();
+    assert_eq!(recovery.len(), 10);
+
+    let (_, challenged) = login(None).await;
+    assert_eq!(challenged["two_factor"], json!("code_required"), "{}", challenged);
+    assert_eq!(challenged["token"], json!(""), "{}", challenged);
+    // The code spent on confirming cannot be replayed, but the next step's code works once.
+    let (_, replayed) = login(Some(&code)).await;
+    assert_eq!(replayed["token"], json!(""), "{}", replayed);
+    let next = app.generate(now + 30);
+    let (_, signed_in) = login(Some(&next)).await;
+    assert!(!signed_in["token"].as_str().unwrap().is_empty(), "{}", signed_in);
+    let (_, again) = login(Some(&next)).await;
+    assert_eq!(again["token"], json!(""), "{}", again);
+
+    // Recovery codes work once each, however they are typed, and are stored only as hashes.
+    let (_, recovered) = login(Some(&recovery[0].to_uppercase().replace('-', " "))).await;
+    assert!(!recovered["token"].as_str().unwrap().is_empty(), "{}", recovered);
+    let (_, spent) = login(Some(&recovery[0])).await;
+    assert_eq!(spent["token"], json!(""), "{}", spent);
+    let database = sqlx::SqlitePool::connect(&format!("sqlite://{}", node.dir.join("node.db").display())).await.unwrap();
+    let stored: Vec<(String,)> = sqlx::query_as("SELECT code_hash FROM recovery_codes").fetch_all(&database).await.unwrap();
+    assert_eq!(stored.len(), 10);
+    assert!(stored.iter().all(|(hash,)| hash.len() == 64 && !recovery.iter().any(|code| hash.contains(code.as_str()))));
+}
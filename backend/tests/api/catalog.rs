@@ -0,0 +1,89 @@
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+use crate::common::{gtin, start_cluster};
+
+fn product(gtin: &str, name: &str) -> Value {
+    json!({
+        "gtin": gtin, "name": name, "active_ingredient": name, "strength": "5mg", "dosage_form": "tablet", "pack_size": 28,
+    })
+}
+
+#[tokio::test]
+async fn a_companys_entry_waits_for_a_reviewer_to_settle_its_schedule() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+    let regulator = node.regulator().await;
+    let gtin = gtin();
+
+    let (status, added) = node.post("/api/catalog", &acme, product(&gtin, "Diazepam")).await;
+    assert_eq!(status, StatusCode::OK, "{}", added);
+    assert_eq!(added["approved_at"], Value::Null);
+    let batch = json!({ "batch_id": "D1", "gtin": gtin, "source": "Plant", "destination": acme_id });
+    let (status, body) = node.post("/api/tracker/add", &acme, batch.clone()).await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+
+    let (_, pending) = node.get("/api/catalog?pending=true", &regulator).await;
+    assert!(pending.as_array().unwrap().iter().any(|p| p["gtin"] == json!(gtin)), "{}", pending);
+    // The reviewer must state the schedule, even when there is none.
+    let path = format!("/api/catalog/{}/approve", gtin);
+    let (status, _) = node.post(&path, &regulator, json!({})).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, approved) = node.post(&path, &regulator, json!({ "schedule": "C-IV" })).await;
+    assert_eq!(status, StatusCode::OK, "{}", approved);
+    assert_eq!(approved["schedule"], json!("IV"));
+    assert!(approved["approved_at"].is_string());
+
+    // The schedule now stands, and a company's edit sends the entry back for review.
+    let mut edit = product(&gtin, "Diazepam");
+    let (status, body) = node.call(Method::PUT, &format!("/api/catalog/{}", gtin), Some(&acme), Some(edit.clone())).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    edit["schedule"] = json!("IV");
+    edit["storage_conditions"] = json!("protect from light");
+    let (status, edited) = node.call(Method::PUT, &format!("/api/catalog/{}", gtin), Some(&acme), Some(edit)).await;
+    assert_eq!(status, StatusCode::OK, "{}", edited);
+    assert_eq!(edited["approved_at"], Value::Null);
+}
+
+#[tokio::test]
+async fn batches_must_be_of_an_approved_catalog_product() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+
+    let (status, body) = node
+        .post("/api/tracker/add", &acme, json!({ "batch_id": "N1", "medicine_name": "Aspirin", "source": "Plant", "destination": acme_id }))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.as_str().unwrap().contains("gtin is required"), "{}", body);
+
+    let unknown = gtin();
+    let (status, _) = node
+        .post("/api/tracker/add", &acme, json!({ "batch_id": "N2", "gtin": unknown, "source": "Plant", "destination": acme_id }))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // An admin's entry is approved as it stands.
+    let aspirin = node.product("Aspirin", None).await;
+    let response = node
+        .sealed_batch(&acme, json!({ "batch_id": "N3", "gtin": aspirin, "source": "Plant", "destination": acme_id }))
+        .await;
+    assert!(response["batch_hash"].is_string(), "{}", response);
+}
+
+#[tokio::test]
+async fn an_import_by_a_company_awaits_review() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let (acme, _) = node.organization("company", "Acme").await;
+    let gtin = gtin();
+    let csv = format!("gtin,name,active_ingredient,strength,dosage_form,pack_size\n{},\"Aspirin, coated\",Aspirin,75mg,tablet,28\n", gtin);
+
+    let (status, report) = node.post_text("/api/catalog/import", &acme, csv).await;
+    assert_eq!(status, StatusCode::OK, "{}", report);
+    assert_eq!(report["imported"], json!(true), "{}", report);
+    let (_, entry) = node.get(&format!("/api/catalog/{}", gtin), &acme).await;
+    assert_eq!(entry["name"], json!("Aspirin, coated"));
+    assert_eq!(entry["approved_at"], Value::Null);
+}
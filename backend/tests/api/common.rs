@@ -0,0 +1,303 @@
+//! Runs backend nodes as child processes, each in its own directory on a free localhost port,
+//! and talks to them over HTTP.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::{Client, Method, StatusCode};
+use rsa::pkcs1::{DecodeRsaPrivateKey, EncodeRsaPublicKey};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+/// Test keys for node-1 to node-3, so nodes need not generate RSA keys on every run.
+pub fn node_key_path(index: usize) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("tests/api/keys/node-{}.pem", index))
+}
+
+pub fn node_private_key(index: usize) -> RsaPrivateKey {
+    let pem = std::fs::read_to_string(node_key_path(index)).expect("test node key");
+    RsaPrivateKey::from_pkcs1_pem(&pem).expect("PKCS#1 test node key")
+}
+
+fn public_key_b64(index: usize) -> String {
+    let der = RsaPublicKey::from(&node_private_key(index)).to_pkcs1_der().unwrap();
+    STANDARD.encode(der.as_bytes())
+}
+
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// A fresh scratch directory under the system temp dir.
+pub fn scratch_dir(label: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pharmachain-{}-{}", label, uuid::Uuid::new_v4().simple()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A fresh GTIN-14 with a valid check digit.
+pub fn gtin() -> String {
+    let body: String = uuid::Uuid::new_v4().as_u128().to_string().chars().take(13).collect();
+    let sum: u32 = body.chars().rev().enumerate().map(|(i, d)| d.to_digit(10).unwrap() * if i % 2 == 0 { 3 } else { 1 }).sum();
+    format!("{}{}", body, (10 - sum % 10) % 10)
+}
+
+/// Settings every test node shares: quick blocks, no email verification or second factor,
+/// registrations approved on submission and `admin@test` as the bootstrap admin.
+const BASE_ENV: [(&str, &str); 8] = [
+    ("SLOT_SECS", "1"),
+    ("BLOCK_INTERVAL_SECS", "1"),
+    ("SYNC_INTERVAL_SECS", "1"),
+    ("REGISTRATION_AUTO_APPROVE", "true"),
+    ("REQUIRE_EMAIL_VERIFICATION", "false"),
+    ("MFA_REQUIRED_ROLES", ""),
+    ("ADMIN_EMAIL", "admin@test"),
+    ("MAIL_TRANSPORT", "file"),
+];
+
+pub struct TestNode {
+    pub node_id: String,
+    pub url: String,
+    pub dir: PathBuf,
+    child: Child,
+    http: Client,
+}
+
+impl Drop for TestNode {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Starts `count` nodes (at most 3) sharing one authority set, with `env` on top of the defaults.
+pub async fn start_cluster(count: usize, env: &[(&str, &str)]) -> Vec<TestNode> {
+    let base = scratch_dir("cluster");
+    let ports: Vec<u16> = (0..count).map(|_| free_port()).collect();
+    let authorities: Vec<Value> = (1..=count)
+        .map(|i| {
+            json!({
+                "node_id": format!("node-{}", i),
+                "organization": format!("Org {}", i),
+                "url": format!("http://127.0.0.1:{}", ports[i - 1]),
+                "public_key": public_key_b64(i),
+            })
+        })
+        .collect();
+    let authorities_path = base.join("authorities.json");
+    std::fs::write(&authorities_path, serde_json::to_vec_pretty(&authorities).unwrap()).unwrap();
+
+    let mut nodes = Vec::new();
+    for i in 1..=count {
+        let dir = base.join(format!("node-{}", i));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = std::fs::File::create(dir.join("log")).unwrap();
+        let mut command = Command::new(env!("CARGO_BIN_EXE_backend"));
+        command
+            .current_dir(&dir)
+            .env("NODE_ID", format!("node-{}", i))
+            .env("NODE_ORG", format!("Org {}", i))
+            .env("BIND_ADDR", format!("127.0.0.1:{}", ports[i - 1]))
+            .env("DATABASE_URL", "sqlite://node.db?mode=rwc")
+            .env("NODE_KEY_PATH", node_key_path(i))
+            .env("AUTHORITIES_PATH", &authorities_path)
+            .stdout(log.try_clone().unwrap())
+            .stderr(log);
+        for (key, value) in BASE_ENV.iter().chain(env) {
+            command.env(key, value);
+        }
+        nodes.push(TestNode {
+            node_id: format!("node-{}", i),
+            url: format!("http://127.0.0.1:{}", ports[i - 1]),
+            dir,
+            child: command.spawn().expect("start backend"),
+            http: Client::new(),
+        });
+    }
+    for node in &nodes {
+        node.wait_ready().await;
+    }
+    nodes
+}
+
+/// Polls `check` until it returns true, failing the test after `secs` seconds.
+pub async fn wait_until<F, Fut>(secs: u64, what: &str, mut check: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let deadline = Instant::now() + Duration::from_secs(secs);
+    while !check().await {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+impl TestNode {
+    async fn wait_ready(&self) {
+        wait_until(60, &format!("{} to start", self.node_id), || async {
+            self.http.get(format!("{}/api/p2p/status", self.url)).send().await.is_ok()
+        })
+        .await;
+    }
+
+    /// The node's log so far, for assertion messages.
+    pub fn log(&self) -> String {
+        std::fs::read_to_string(self.dir.join("log")).unwrap_or_default()
+    }
+
+    /// Sends a request and returns the status with the body as JSON, or as a JSON string
+    /// when it is not JSON.
+    pub async fn call(&self, method: Method, path: &str, token: Option<&str>, body: Option<Value>) -> (StatusCode, Value) {
+        let mut request = self.http.request(method, format!("{}{}", self.url, path));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await.expect("request reaches the node");
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        (status, serde_json::from_str(&text).unwrap_or(Value::String(text)))
+    }
+
+    pub async fn get(&self, path: &str, token: &str) -> (StatusCode, Value) {
+        self.call(Method::GET, path, Some(token), None).await
+    }
+
+    pub async fn post(&self, path: &str, token: &str, body: Value) -> (StatusCode, Value) {
+        self.call(Method::POST, path, Some(token), Some(body)).await
+    }
+
+    /// Posts `body` as plain text, such as a CSV file.
+    pub async fn post_text(&self, path: &str, token: &str, body: String) -> (StatusCode, Value) {
+        let response = self
+            .http
+            .post(format!("{}{}", self.url, path))
+            .bearer_auth(token)
+            .header("content-type", "text/csv")
+            .body(body)
+            .send()
+            .await
+            .expect("request reaches the node");
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        (status, serde_json::from_str(&text).unwrap_or(Value::String(text)))
+    }
+
+    pub async fn status(&self) -> Value {
+        self.call(Method::GET, "/api/p2p/status", None, None).await.1
+    }
+
+    pub async fn login(&self, email: &str, password: &str) -> (StatusCode, Value) {
+        self.call(Method::POST, "/api/login", None, Some(json!({ "email": email, "password": password })))
+            .await
+    }
+
+    /// Signs up `name@test` with `role` and logs in, returning the access token.
+    pub async fn user(&self, name: &str, role: &str) -> String {
+        let email = format!("{}@test", name);
+        self.call(
+            Method::POST,
+            "/api/signup",
+            None,
+            Some(json!({ "username": name, "email": email, "password": "pw-123456", "role": role })),
+        )
+        .await;
+        let (_, body) = self.login(&email, "pw-123456").await;
+        let token = body["token"].as_str().unwrap_or_default().to_string();
+        assert!(!token.is_empty(), "login of {} failed: {}", email, body);
+        token
+    }
+
+    /// Signs up an owner and registers an organization of `kind` named `name`.
+    /// Returns a token acting for it and its id.
+    pub async fn organization(&self, kind: &str, name: &str) -> (String, String) {
+        let owner = self.user(&format!("{}-owner", name.to_lowercase().replace(' ', "-")), kind).await;
+        let license = format!("LIC-{}", &uuid::Uuid::new_v4().simple().to_string()[..10]);
+        let body = match kind {
+            "company" => json!({
+                "name": name, "location": format!("{} site", name), "license_id": license, "stock_needed": "1",
+                "jurisdiction": "GB", "license_expires_at": "2099-01-01",
+            }),
+            _ => json!({ "name": name, "location": format!("{} site", name), "registration_id": license, "jurisdiction": "GB" }),
+        };
+        let (status, body) = self.post(&format!("/api/{}/signup", kind), &owner, body).await;
+        assert!(status.is_success(), "registering {} failed: {}", name, body);
+        (body["token"].as_str().unwrap().to_string(), body["organization_id"].as_str().unwrap().to_string())
+    }
+
+    /// The token in the latest verification link mailed to `email`.
+    pub fn verification_token(&self, email: &str) -> Option<String> {
+        let mut mails: Vec<PathBuf> = std::fs::read_dir(self.dir.join("mail")).ok()?.flatten().map(|e| e.path()).collect();
+        mails.sort();
+        mails.iter().rev().find_map(|path| {
+            // Undo quoted-printable soft line breaks and escapes.
+            let mail = std::fs::read_to_string(path).ok()?.replace("=\r\n", "").replace("=3D", "=");
+            if !mail.contains(&format!("To: {}", email)) {
+                return None;
+            }
+            let start = mail.find("verify?token=")? + "verify?token=".len();
+            Some(mail[start..].chars().take_while(|c| c.is_ascii_alphanumeric() || "-_.".contains(*c)).collect())
+        })
+    }
+
+    /// Opens the verification link mailed to `email`.
+    pub async fn verify_email(&self, email: &str) -> (StatusCode, Value) {
+        let token = self.verification_token(email).expect("a verification email");
+        self.call(Method::GET, &format!("/api/email/verify?token={}", token), None, None).await
+    }
+
+    /// A token for the bootstrap admin, `admin@test`, which becomes admin once verified.
+    pub async fn admin(&self) -> String {
+        self.user("admin", "customer").await;
+        self.verify_email("admin@test").await;
+        let (_, body) = self.login("admin@test", "pw-123456").await;
+        body["token"].as_str().unwrap().to_string()
+    }
+
+    /// A token for `inspector@test`, appointed regulator by the admin.
+    pub async fn regulator(&self) -> String {
+        self.user("inspector", "customer").await;
+        let body = json!({ "email": "inspector@test", "role": "regulator" });
+        let (status, response) = self.post("/api/admin/users/role", &self.admin().await, body).await;
+        assert!(status.is_success(), "appointing the regulator failed: {}", response);
+        let (_, login) = self.login("inspector@test", "pw-123456").await;
+        login["token"].as_str().unwrap().to_string()
+    }
+
+    /// Adds an approved catalog entry for `name` as the admin, with `schedule` if controlled,
+    /// and returns its GTIN.
+    pub async fn product(&self, name: &str, schedule: Option<&str>) -> String {
+        let gtin = gtin();
+        let body = json!({
+            "gtin": gtin, "name": name, "active_ingredient": name, "strength": "10mg", "dosage_form": "tablet",
+            "pack_size": 10, "schedule": schedule,
+        });
+        let (status, response) = self.post("/api/catalog", &self.admin().await, body).await;
+        assert!(status.is_success(), "adding {} to the catalog failed: {}", name, response);
+        gtin
+    }
+
+    /// Creates a batch for the company behind `token` and waits for it to be sealed.
+    pub async fn sealed_batch(&self, token: &str, body: Value) -> Value {
+        let batch_id = body["batch_id"].as_str().unwrap().to_string();
+        let (status, response) = self.post("/api/tracker/add", token, body).await;
+        assert!(status.is_success(), "creating {} failed: {}", batch_id, response);
+        self.wait_sealed(token, &batch_id).await;
+        response
+    }
+
+    /// Waits until `batch_id` is in a block on this node.
+    pub async fn wait_sealed(&self, token: &str, batch_id: &str) {
+        wait_until(20, &format!("{} to be sealed", batch_id), || async {
+            self.get(&format!("/api/tracker/proof/{}", batch_id), token).await.0.is_success()
+        })
+        .await;
+    }
+}
@@ -0,0 +1,157 @@
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+use crate::common::{start_cluster, TestNode};
+
+/// Licenses `organization_id` for schedule II as the regulator.
+async fn license(node: &TestNode, regulator: &str, organization_id: &str) {
+    let path = format!("/api/regulator/controlled-licenses/{}", organization_id);
+    let body = json!({ "license_number": format!("DEA-{}", organization_id), "schedules": ["II"] });
+    let (status, response) = node.call(Method::PUT, &path, Some(regulator), Some(body)).await;
+    assert_eq!(status, StatusCode::OK, "{}", response);
+}
+
+async fn set_quota(node: &TestNode, regulator: &str, organization_id: &str, quantity: i64) {
+    let path = format!("/api/regulator/controlled-quotas/{}", organization_id);
+    let body = json!({ "active_ingredient": "Morphine", "period": "month", "quantity": quantity });
+    let (status, response) = node.call(Method::PUT, &path, Some(regulator), Some(body)).await;
+    assert_eq!(status, StatusCode::OK, "{}", response);
+}
+
+fn morphine_batch(batch_id: &str, gtin: &str, quantity: i64, maker_id: &str) -> Value {
+    json!({ "batch_id": batch_id, "gtin": gtin, "quantity": quantity, "source": "Plant", "destination": maker_id })
+}
+
+#[tokio::test]
+async fn a_transfer_moves_only_once_the_receiver_countersigns() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+    let (mary, mary_id) = node.organization("hospital", "St Mary").await;
+    let regulator = node.regulator().await;
+    let morphine = node.product("Morphine", Some("II")).await;
+
+    let (status, _) = node.post("/api/tracker/add", &acme, morphine_batch("M1", &morphine, 100, &acme_id)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    license(node, &regulator, &acme_id).await;
+    node.sealed_batch(&acme, morphine_batch("M1", &morphine, 100, &acme_id)).await;
+
+    let custody = json!({ "batch_id": "M1", "from_location": acme_id, "to_location": mary_id });
+    let (status, _) = node.post("/api/tracker/custody", &acme, custody).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let proposal = json!({ "batch_id": "M1", "to_organization": mary_id });
+    let (status, _) = node.post("/api/controlled/transfers", &acme, proposal.clone()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "the receiver holds no license yet");
+    license(node, &regulator, &mary_id).await;
+
+    let (status, proposed) = node.post("/api/controlled/transfers", &acme, proposal).await;
+    assert_eq!(status, StatusCode::OK, "{}", proposed);
+    assert_eq!(proposed["status"], json!("awaiting_countersignature"));
+    assert_eq!(proposed["quantity"], json!(100));
+    assert_eq!(proposed["signatures_valid"], json!(true));
+    let id = proposed["id"].as_str().unwrap();
+
+    let countersign = format!("/api/controlled/transfers/{}/countersign", id);
+    let (status, _) = node.post(&countersign, &acme, json!({})).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "the sender may not countersign");
+    let (status, signed) = node.post(&countersign, &mary, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", signed);
+    assert_eq!(signed["status"], json!("submitted"));
+    assert!(signed["receiver_signature"].is_string());
+    assert!(signed["custody"]["batch_hash"].is_string());
+
+    let (status, read) = node.get(&format!("/api/controlled/transfers/{}", id), &regulator).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(read["signatures_valid"], json!(true));
+    let (_, again) = node.post(&countersign, &mary, json!({})).await;
+    assert!(again.as_str().unwrap().contains("already submitted"), "{}", again);
+}
+
+#[tokio::test]
+async fn altered_terms_no_longer_verify() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+    let (mary, mary_id) = node.organization("hospital", "St Mary").await;
+    let regulator = node.regulator().await;
+    let morphine = node.product("Morphine", Some("II")).await;
+    license(node, &regulator, &acme_id).await;
+    license(node, &regulator, &mary_id).await;
+    node.sealed_batch(&acme, morphine_batch("T1", &morphine, 40, &acme_id)).await;
+
+    let (_, proposed) = node.post("/api/controlled/transfers", &acme, json!({ "batch_id": "T1", "to_organization": mary_id })).await;
+    let id = proposed["id"].as_str().unwrap();
+    let database = sqlx::SqlitePool::connect(&format!("sqlite://{}", node.dir.join("node.db").display())).await.unwrap();
+    sqlx::query("UPDATE controlled_transfers SET quantity = 4 WHERE id = ?")
+        .bind(id)
+        .execute(&database)
+        .await
+        .unwrap();
+    let (_, read) = node.get(&format!("/api/controlled/transfers/{}", id), &regulator).await;
+    assert_eq!(read["signatures_valid"], json!(false));
+    let (status, body) = node.post(&format!("/api/controlled/transfers/{}/countersign", id), &mary, json!({})).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert!(body.as_str().unwrap().contains("does not verify"), "{}", body);
+}
+
+#[tokio::test]
+async fn quotas_cap_what_an_organization_makes_and_receives() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+    let (mary, mary_id) = node.organization("hospital", "St Mary").await;
+    let regulator = node.regulator().await;
+    let morphine = node.product("Morphine", Some("II")).await;
+    license(node, &regulator, &acme_id).await;
+    license(node, &regulator, &mary_id).await;
+    set_quota(node, &regulator, &acme_id, 150).await;
+
+    node.sealed_batch(&acme, morphine_batch("Q1", &morphine, 100, &acme_id)).await;
+    let (status, body) = node.post("/api/tracker/add", &acme, morphine_batch("Q2", &morphine, 60, &acme_id)).await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+    node.sealed_batch(&acme, morphine_batch("Q3", &morphine, 50, &acme_id)).await;
+    let (_, standing) = node.get("/api/controlled/license", &acme).await;
+    assert_eq!(standing["quotas"][0]["used"], json!(150), "{}", standing);
+    assert_eq!(standing["quotas"][0]["remaining"], json!(0));
+
+    // The receiver's quota is checked when it countersigns.
+    set_quota(node, &regulator, &mary_id, 10).await;
+    let (_, proposed) = node.post("/api/controlled/transfers", &acme, json!({ "batch_id": "Q1", "to_organization": mary_id })).await;
+    let countersign = format!("/api/controlled/transfers/{}/countersign", proposed["id"].as_str().unwrap());
+    let (status, body) = node.post(&countersign, &mary, json!({})).await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+
+    let path = format!("/api/regulator/controlled-quotas/{}/morphine", mary_id);
+    let (status, _) = node.call(Method::DELETE, &path, Some(&acme), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, remaining) = node.call(Method::DELETE, &path, Some(&regulator), None).await;
+    assert_eq!(status, StatusCode::OK, "{}", remaining);
+    let (status, signed) = node.post(&countersign, &mary, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", signed);
+}
+
+#[tokio::test]
+async fn scheduled_stock_only_moves_under_a_license() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+    let regulator = node.regulator().await;
+    let morphine = node.product("Morphine", Some("II")).await;
+    license(node, &regulator, &acme_id).await;
+    node.sealed_batch(&acme, morphine_batch("L1", &morphine, 30, &acme_id)).await;
+
+    let path = format!("/api/regulator/controlled-licenses/{}", acme_id);
+    let (status, _) = node.call(Method::DELETE, &path, Some(&regulator), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let destroy = json!({ "batch_id": "L1", "quantity": 5, "reason": "broken vials" });
+    let (status, body) = node.post("/api/inventory/destroy", &acme, destroy.clone()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    let split = json!({ "batch_id": "L1", "new_batch_id": "L1-a", "quantity": 10 });
+    let (status, body) = node.post("/api/inventory/split", &acme, split).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+
+    license(node, &regulator, &acme_id).await;
+    let (status, body) = node.post("/api/inventory/destroy", &acme, destroy).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["remaining"], json!(25));
+}
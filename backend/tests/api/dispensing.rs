@@ -0,0 +1,106 @@
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+use crate::common::{start_cluster, wait_until, TestNode};
+
+/// Every transaction sealed on `node` so far.
+async fn ledger(node: &TestNode) -> Vec<Value> {
+    let (_, blocks) = node.call(Method::GET, "/api/p2p/blocks?from=1", None, None).await;
+    blocks
+        .as_array()
+        .unwrap()
+        .iter()
+        .flat_map(|b| b["transactions"].as_array().unwrap().clone())
+        .collect()
+}
+
+#[tokio::test]
+async fn packs_are_dispensed_only_from_their_batch_and_recorded_on_the_ledger() {
+    let nodes = start_cluster(2, &[]).await;
+    let node = &nodes[0];
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+    let (ward, ward_id) = node.organization("hospital", "Ward").await;
+    let aspirin = node.product("Aspirin", None).await;
+    let batch = |id: &str, serials: &[&str]| {
+        json!({
+            "batch_id": id, "gtin": aspirin, "quantity": serials.len(), "serials": serials, "source": "Plant",
+            "destination": acme_id,
+        })
+    };
+
+    let mut short = batch("S0", &["P1", "P2"]);
+    short["quantity"] = json!(3);
+    let (status, body) = node.post("/api/tracker/add", &acme, short).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    node.sealed_batch(&acme, batch("S1", &["P1", "P2", "P3"])).await;
+    node.sealed_batch(&acme, batch("S2", &["Q1", "Q2"])).await;
+
+    let send = json!({ "batch_id": "S1", "from_location": acme_id, "to_location": ward_id });
+    let (status, body) = node.post("/api/tracker/custody", &acme, send).await;
+    assert!(status.is_success(), "{}", body);
+    wait_until(20, "S1 to reach the ward", || async {
+        let (_, stock) = node.get("/api/inventory", &ward).await;
+        stock["batches"].as_array().is_some_and(|b| b.iter().any(|b| b["batch_id"] == "S1"))
+    })
+    .await;
+
+    let dispense = |serials: &[&str]| {
+        json!({ "batch_id": "S1", "prescription_reference": "RX-1", "patient_id": "MRN-1", "serials": serials })
+    };
+    let (status, body) = node.post("/api/dispensing", &ward, dispense(&["P1", "Q1"])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert!(body.as_str().unwrap().contains("Q1 is not a pack left in batch S1"), "{}", body);
+    let by_quantity = json!({ "batch_id": "S1", "prescription_reference": "RX-1", "patient_id": "MRN-1", "quantity": 1 });
+    let (status, body) = node.post("/api/dispensing", &ward, by_quantity).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+    let (status, receipt) = node.post("/api/dispensing", &ward, dispense(&["P1", "P2"])).await;
+    assert_eq!(status, StatusCode::OK, "{}", receipt);
+    assert_eq!(receipt["remaining"], json!(1));
+    let (status, body) = node.post("/api/dispensing", &ward, dispense(&["P2"])).await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+
+    // The dispensing reaches the other node's ledger without the patient or prescription.
+    let id = receipt["id"].clone();
+    wait_until(20, "the dispensing to be sealed on node-2", || async {
+        ledger(&nodes[1]).await.iter().any(|tx| tx["kind"] == "dispensing" && tx["dispensing_id"] == id)
+    })
+    .await;
+    let sealed = ledger(&nodes[1]).await.into_iter().find(|tx| tx["dispensing_id"] == id).unwrap();
+    assert_eq!(sealed["hash"], receipt["ledger_hash"]);
+    assert_eq!(sealed["serials"], json!(["P1", "P2"]));
+    assert_eq!(sealed["location"], json!(ward_id));
+    assert!(!sealed.to_string().contains("RX-1") && sealed.get("patient_hash").is_none(), "{}", sealed);
+
+    let withdraw = json!({ "batch_id": "S1", "quantity": 1 });
+    let (status, _) = node.post("/api/inventory/dispense", &ward, withdraw).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn a_split_takes_its_packs_with_it() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+    let aspirin = node.product("Aspirin", None).await;
+    let body = json!({
+        "batch_id": "T1", "gtin": aspirin, "quantity": 3, "serials": ["A1", "A2", "A3"], "source": "Plant", "destination": acme_id,
+    });
+    node.sealed_batch(&acme, body).await;
+
+    let split = |serials: &[&str]| json!({ "batch_id": "T1", "new_batch_id": "T1-a", "quantity": 1, "serials": serials });
+    let (status, body) = node.post("/api/inventory/split", &acme, split(&[])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    let (status, body) = node.post("/api/inventory/split", &acme, split(&["Z9"])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    let (status, body) = node.post("/api/inventory/split", &acme, split(&["A3"])).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    // A3 left with the pending split, so it cannot be split off again.
+    let again = json!({ "batch_id": "T1", "new_batch_id": "T1-b", "quantity": 1, "serials": ["A3"] });
+    let (status, body) = node.post("/api/inventory/split", &acme, again).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+    node.wait_sealed(&acme, "T1-a").await;
+    let child = ledger(node).await.into_iter().find(|tx| tx["batch_id"] == "T1-a").unwrap();
+    assert_eq!(child["serials"], json!(["A3"]));
+}
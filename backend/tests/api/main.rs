@@ -0,0 +1,13 @@
+//! End-to-end tests against real backend nodes on localhost.
+
+mod anchor;
+mod auth;
+mod catalog;
+mod common;
+mod controlled;
+mod dispensing;
+mod p2p;
+mod rbac;
+mod sso;
+mod timestamp;
+mod upgrade;
@@ -0,0 +1,225 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use reqwest::{Method, StatusCode};
+use rsa::Pkcs1v15Sign;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::common::{node_private_key, start_cluster, wait_until, TestNode};
+
+/// An envelope as node `from` (one of the test keys) would sign it.
+fn envelope(from: usize, to: &str, kind: &str, sent_at: &str, digest: &str) -> Value {
+    let data = format!("{}|node-{}|{}|{}|{}", kind, from, to, sent_at, digest);
+    let signature = node_private_key(from)
+        .sign(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(data.as_bytes()))
+        .unwrap();
+    json!({ "from": format!("node-{}", from), "to": to, "sent_at": sent_at, "signature": STANDARD.encode(signature) })
+}
+
+/// A block whose seal is junk: nobody signed it.
+fn junk_block() -> Value {
+    json!({
+        "header": {
+            "height": 1, "hash": "ab".repeat(32), "previous_hash": "GENESIS", "merkle_root": "cd".repeat(32),
+            "timestamp": Utc::now().to_rfc3339(), "producer": "node-2", "signature": "AAAA", "tx_count": 0,
+        },
+        "transactions": [],
+    })
+}
+
+/// An empty block at `height` on `previous_hash`, sealed in `slot` by whichever of the two
+/// test authorities leads it. Test nodes use one-second slots.
+fn empty_block(height: i64, previous_hash: &str, slot: i64) -> Value {
+    let producer = slot.rem_euclid(2) as usize + 1;
+    let timestamp = DateTime::from_timestamp(slot, 0).unwrap().to_rfc3339();
+    let data = format!("{}|{}|EMPTY_TREE|{}|node-{}", height, previous_hash, timestamp, producer);
+    let hash = format!("{:x}", Sha256::digest(data.as_bytes()));
+    let signature = node_private_key(producer)
+        .sign(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(hash.as_bytes()))
+        .unwrap();
+    json!({
+        "header": {
+            "height": height, "hash": hash, "previous_hash": previous_hash, "merkle_root": "EMPTY_TREE",
+            "timestamp": timestamp, "producer": format!("node-{}", producer), "signature": STANDARD.encode(signature),
+            "tx_count": 0,
+        },
+        "transactions": [],
+    })
+}
+
+/// The first empty block at `height` on `previous_hash`, sealed in a slot from `*slot` on,
+/// whose hash sorts above `tip_hash` if `above`, or below it if not. Moves `*slot` past it.
+fn empty_block_beside(height: i64, previous_hash: &str, slot: &mut i64, tip_hash: &str, above: bool) -> Value {
+    loop {
+        let block = empty_block(height, previous_hash, *slot);
+        *slot += 1;
+        if (block["header"]["hash"].as_str().unwrap() > tip_hash) == above {
+            return block;
+        }
+    }
+}
+
+/// Pushes `block` to node-1 as node-2 and returns how it was taken.
+async fn offer(node: &TestNode, block: &Value) -> String {
+    let hash = block["header"]["hash"].as_str().unwrap();
+    let mut message = envelope(2, "node-1", "block", &Utc::now().to_rfc3339(), hash);
+    message["block"] = block.clone();
+    let (status, body) = node.call(Method::POST, "/api/p2p/blocks", None, Some(message)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body["message"].as_str().unwrap().to_string()
+}
+
+fn hash_of(block: &Value) -> Value {
+    block["header"]["hash"].clone()
+}
+
+/// Hashes of the blocks `node` keeps off its canonical chain.
+async fn orphaned(node: &TestNode) -> Vec<Value> {
+    let (status, forks) = node.get("/api/admin/forks", &node.admin().await).await;
+    assert_eq!(status, StatusCode::OK, "{}", forks);
+    forks["orphaned"].as_array().unwrap().iter().map(|b| b["hash"].clone()).collect()
+}
+
+async fn push_block(node: &TestNode, mut message: Value, block: Value) -> StatusCode {
+    message["block"] = block;
+    node.call(Method::POST, "/api/p2p/blocks", None, Some(message)).await.0
+}
+
+async fn rejected_peers(node: &TestNode) -> Vec<String> {
+    node.status().await["rejected_peers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["node_id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn blocks_replicate_across_three_nodes() {
+    let nodes = start_cluster(3, &[]).await;
+    let (acme, acme_id) = nodes[0].organization("company", "Acme").await;
+    let aspirin = nodes[0].product("Aspirin", None).await;
+    nodes[0]
+        .sealed_batch(&acme, json!({ "batch_id": "R1", "gtin": aspirin, "source": "Plant", "destination": acme_id }))
+        .await;
+
+    wait_until(20, "every node to reach the same tip", || async {
+        let tips: Vec<Value> = tips_of(&nodes).await;
+        tips[0]["height"].as_i64() >= Some(1) && tips.iter().all(|t| t["tip_hash"] == tips[0]["tip_hash"])
+    })
+    .await;
+
+    let reader = nodes[2].user("reader", "customer").await;
+    let (status, proof) = nodes[2].get("/api/tracker/proof/R1", &reader).await;
+    assert_eq!(status, StatusCode::OK, "{}", proof);
+    for node in &nodes {
+        assert!(rejected_peers(node).await.is_empty(), "{}", node.log());
+    }
+}
+
+async fn tips_of(nodes: &[TestNode]) -> Vec<Value> {
+    let mut tips = Vec::new();
+    for node in nodes {
+        tips.push(node.status().await);
+    }
+    tips
+}
+
+#[tokio::test]
+async fn unsigned_or_misaddressed_messages_blame_nobody() {
+    let nodes = start_cluster(2, &[]).await;
+    let now = Utc::now().to_rfc3339();
+
+    // Claiming to be node-2 without its key.
+    let mut forged = envelope(1, "node-1", "block", &now, &"ab".repeat(32));
+    forged["from"] = json!("node-2");
+    assert_eq!(push_block(&nodes[0], forged, junk_block()).await, StatusCode::FORBIDDEN);
+
+    // Signed by node-2, but for another node, of another kind, or long ago.
+    let elsewhere = envelope(2, "node-3", "block", &now, &"ab".repeat(32));
+    assert_eq!(push_block(&nodes[0], elsewhere, junk_block()).await, StatusCode::FORBIDDEN);
+    let as_tx = envelope(2, "node-1", "tx", &now, &"ab".repeat(32));
+    assert_eq!(push_block(&nodes[0], as_tx, junk_block()).await, StatusCode::FORBIDDEN);
+    let stale = envelope(2, "node-1", "block", &(Utc::now() - Duration::minutes(10)).to_rfc3339(), &"ab".repeat(32));
+    assert_eq!(push_block(&nodes[0], stale, junk_block()).await, StatusCode::FORBIDDEN);
+
+    assert!(rejected_peers(&nodes[0]).await.is_empty());
+}
+
+#[tokio::test]
+async fn a_sender_proven_to_send_a_bad_block_is_shut_out_for_a_while() {
+    let nodes = start_cluster(2, &[("PEER_REJECTION_SECS", "3")]).await;
+
+    let signed = envelope(2, "node-1", "block", &Utc::now().to_rfc3339(), &"ab".repeat(32));
+    assert_eq!(push_block(&nodes[0], signed, junk_block()).await, StatusCode::CONFLICT);
+    assert_eq!(rejected_peers(&nodes[0]).await, vec!["node-2".to_string()]);
+
+    // While shut out, even well-formed messages from it are refused.
+    let again = envelope(2, "node-1", "block", &Utc::now().to_rfc3339(), &"ab".repeat(32));
+    assert_eq!(push_block(&nodes[0], again, junk_block()).await, StatusCode::FORBIDDEN);
+
+    wait_until(10, "the rejection to lapse", || async { rejected_peers(&nodes[0]).await.is_empty() }).await;
+}
+
+#[tokio::test]
+async fn the_longest_chain_wins_and_then_the_lowest_tip_hash() {
+    let nodes = start_cluster(2, &[]).await;
+    let node = &nodes[0];
+    let mut slot = Utc::now().timestamp() - 600;
+
+    let first = empty_block(1, "GENESIS", slot);
+    slot += 1;
+    assert!(offer(node, &first).await.starts_with("Applied"));
+    let tip = first["header"]["hash"].as_str().unwrap();
+
+    // As long as the tip but sorting higher: kept aside.
+    let higher = empty_block_beside(1, "GENESIS", &mut slot, tip, true);
+    assert!(offer(node, &higher).await.contains("side branch"));
+    assert_eq!(node.status().await["tip_hash"], hash_of(&first));
+
+    // As long as the tip and sorting lower: adopted.
+    let lower = empty_block_beside(1, "GENESIS", &mut slot, tip, false);
+    assert!(offer(node, &lower).await.starts_with("Applied"));
+    assert_eq!(node.status().await["tip_hash"], hash_of(&lower));
+
+    // Longer beats lower, whatever the hashes.
+    let longer = empty_block(2, higher["header"]["hash"].as_str().unwrap(), slot);
+    assert!(offer(node, &longer).await.starts_with("Applied"));
+    let status = node.status().await;
+    assert_eq!((status["height"].clone(), status["tip_hash"].clone()), (json!(2), hash_of(&longer)));
+    let orphaned = orphaned(node).await;
+    assert!(orphaned.contains(&hash_of(&first)) && orphaned.contains(&hash_of(&lower)), "{:?}", orphaned);
+}
+
+#[tokio::test]
+async fn a_reorganization_keeps_the_orphaned_block_and_reseals_its_transactions() {
+    let nodes = start_cluster(2, &[]).await;
+    let node = &nodes[0];
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+    let aspirin = node.product("Aspirin", None).await;
+    node.sealed_batch(&acme, json!({ "batch_id": "O1", "gtin": aspirin, "source": "Plant", "destination": acme_id }))
+        .await;
+    let sealed = node.status().await;
+    assert_eq!(sealed["height"], json!(1), "{}", sealed);
+
+    // A longer branch without the batch, whose first block loses the tie so the switch
+    // happens only once the branch is longer.
+    let mut slot = Utc::now().timestamp() - 600;
+    let fork = empty_block_beside(1, "GENESIS", &mut slot, sealed["tip_hash"].as_str().unwrap(), true);
+    assert!(offer(node, &fork).await.contains("side branch"));
+    let longer = empty_block(2, fork["header"]["hash"].as_str().unwrap(), slot);
+    assert!(offer(node, &longer).await.starts_with("Applied"));
+
+    // The batch went back to the mempool and is sealed again on top of the new chain.
+    wait_until(20, "O1 to be sealed again", || async {
+        node.status().await["height"].as_i64() >= Some(3) && node.get("/api/tracker/proof/O1", &acme).await.0.is_success()
+    })
+    .await;
+    assert!(orphaned(node).await.contains(&sealed["tip_hash"]));
+    let (_, forks) = node.get("/api/admin/forks", &node.admin().await).await;
+    assert!(forks["reorgs"].as_array().unwrap().iter().any(|r| r["old_tip"] == sealed["tip_hash"]), "{}", forks);
+    // Its signature was checked when sealed, and the check still stands after resealing.
+    let (_, dashboard) = node.get("/api/company/dashboard", &acme).await;
+    assert_eq!(dashboard["signatures"]["checked"], json!(1), "{}", dashboard);
+    assert_eq!(dashboard["signatures"]["failures"], json!([]), "{}", dashboard);
+}
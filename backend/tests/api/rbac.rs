@@ -0,0 +1,195 @@
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+use crate::common::{start_cluster, wait_until};
+
+/// Guarded routes and the roles allowed through: `c`ompany, `h`ospital, c`u`stomer,
+/// `r`egulator and `a`dmin.
+const ROUTES: &[(&str, &str, &str)] = &[
+    ("GET", "/api/tracker/verifychain", "chura"),
+    ("GET", "/api/tracker/proof/X", "chura"),
+    ("POST", "/api/tracker/add", "c"),
+    ("POST", "/api/tracker/custody", "ch"),
+    ("POST", "/api/tracker/custody/acknowledge", "ch"),
+    ("POST", "/api/tracker/recall", "c"),
+    ("POST", "/api/tracker/conditions", "ch"),
+    ("POST", "/api/company/signup", "c"),
+    ("GET", "/api/company/dashboard", "c"),
+    ("POST", "/api/hospital/signup", "h"),
+    ("GET", "/api/hospital/dashboard", "h"),
+    ("POST", "/api/customer/signup", "u"),
+    ("GET", "/api/customer/dashboard", "u"),
+    ("GET", "/api/inventory", "chu"),
+    ("POST", "/api/inventory/split", "chu"),
+    ("POST", "/api/inventory/destroy", "chu"),
+    ("GET", "/api/orders", "ch"),
+    ("POST", "/api/orders", "h"),
+    ("POST", "/api/orders/X/accept", "c"),
+    ("GET", "/api/demand", "ch"),
+    ("POST", "/api/demand", "ch"),
+    ("PUT", "/api/supply/prices", "c"),
+    ("GET", "/api/shortages", "chra"),
+    ("POST", "/api/allocations", "c"),
+    ("GET", "/api/catalog", "chura"),
+    ("POST", "/api/catalog", "ca"),
+    ("PUT", "/api/catalog/X", "ca"),
+    ("POST", "/api/catalog/import", "ca"),
+    ("POST", "/api/catalog/X/approve", "ra"),
+    ("GET", "/api/controlled/report", "chura"),
+    ("POST", "/api/controlled/transfers", "chu"),
+    ("GET", "/api/dispensing", "hura"),
+    ("POST", "/api/dispensing", "hu"),
+    ("GET", "/api/admin/forks", "ra"),
+    ("POST", "/api/admin/users/role", "a"),
+    ("GET", "/api/admin/auth-events", "a"),
+    ("GET", "/api/regulator/registrations", "ra"),
+    ("PUT", "/api/regulator/critical-care/X", "ra"),
+    ("DELETE", "/api/regulator/critical-care/X", "ra"),
+    ("PUT", "/api/regulator/controlled-licenses/X", "ra"),
+    ("DELETE", "/api/regulator/controlled-licenses/X", "ra"),
+    ("GET", "/api/regulator/controlled-quotas", "ra"),
+    ("PUT", "/api/regulator/controlled-quotas/X", "ra"),
+    ("DELETE", "/api/regulator/controlled-quotas/X/Y", "ra"),
+    ("GET", "/api/regulator/batches", "r"),
+];
+
+fn refused_for_role(status: StatusCode, body: &Value) -> bool {
+    status == StatusCode::FORBIDDEN && body.as_str().is_some_and(|b| b.contains("role may not"))
+}
+
+#[tokio::test]
+async fn every_route_admits_exactly_the_roles_its_policy_names() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let tokens = [
+        ('c', node.user("maker", "company").await),
+        ('h', node.user("nurse", "hospital").await),
+        ('u', node.user("patient", "customer").await),
+        ('r', node.regulator().await),
+        ('a', node.admin().await),
+    ];
+
+    for (method, path, allowed) in ROUTES {
+        for (role, token) in &tokens {
+            let method = Method::from_bytes(method.as_bytes()).unwrap();
+            let (status, body) = node.call(method.clone(), path, Some(token), None).await;
+            assert_eq!(
+                !refused_for_role(status, &body),
+                allowed.contains(*role),
+                "{} {} as '{}': {} {}",
+                method,
+                path,
+                role,
+                status,
+                body
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn anonymous_callers_are_turned_away() {
+    let nodes = start_cluster(1, &[]).await;
+    for (method, path, _) in ROUTES {
+        let (status, _) = nodes[0].call(Method::from_bytes(method.as_bytes()).unwrap(), path, None, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{} {}", method, path);
+    }
+}
+
+#[tokio::test]
+async fn only_the_holder_transfers_custody() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+    let aspirin = node.product("Aspirin", None).await;
+    let (rival, rival_id) = node.organization("company", "Rival").await;
+    let (_, ward_id) = node.organization("hospital", "Ward").await;
+    node.sealed_batch(&acme, json!({ "batch_id": "C1", "gtin": aspirin, "source": "Plant", "destination": acme_id }))
+        .await;
+    let send = |from: &str, to: &str| json!({ "batch_id": "C1", "from_location": from, "to_location": to });
+
+    let (status, body) = node.post("/api/tracker/custody", &rival, send(&acme_id, &rival_id)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    assert!(body.as_str().unwrap().contains("not held by Rival"), "{}", body);
+
+    let drifter = node.user("drifter", "hospital").await;
+    let (status, body) = node.post("/api/tracker/custody", &drifter, send(&acme_id, &ward_id)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    assert!(body.as_str().unwrap().contains("Register or join"), "{}", body);
+
+    let (status, body) = node.post("/api/tracker/custody", &acme, send(&acme_id, &ward_id)).await;
+    assert!(status.is_success(), "{}", body);
+    // A transfer still waiting for a block already moves the batch out of Acme's hands.
+    let (status, body) = node.post("/api/tracker/custody", &acme, send(&acme_id, &rival_id)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+}
+
+#[tokio::test]
+async fn a_namesake_organization_holds_nothing() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+    let (ward, ward_id) = node.organization("hospital", "Ward").await;
+    let aspirin = node.product("Aspirin", None).await;
+    let owner = node.user("namesake", "hospital").await;
+    let registration = json!({ "name": "Ward", "location": "Elsewhere", "registration_id": "LIC-NAMESAKE", "jurisdiction": "GB" });
+    let (status, body) = node.post("/api/hospital/signup", &owner, registration).await;
+    assert!(status.is_success(), "{}", body);
+    let namesake = body["token"].as_str().unwrap().to_string();
+
+    // A destination given by name is recorded as the organization's id.
+    let batch = json!({ "batch_id": "N1", "gtin": aspirin, "quantity": 5, "source": "Plant", "destination": "Acme" });
+    node.sealed_batch(&acme, batch).await;
+    let send = json!({ "batch_id": "N1", "from_location": "Acme", "to_location": ward_id });
+    let (status, body) = node.post("/api/tracker/custody", &acme, send).await;
+    assert!(status.is_success(), "{}", body);
+    wait_until(20, "N1 to reach the ward", || async {
+        let (_, stock) = node.get("/api/inventory", &ward).await;
+        stock["batches"].as_array().is_some_and(|b| b.iter().any(|b| b["batch_id"] == "N1"))
+    })
+    .await;
+
+    let (status, _) = node.post("/api/tracker/custody/acknowledge", &namesake, json!({ "batch_id": "N1" })).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let onward = json!({ "batch_id": "N1", "from_location": "Ward", "to_location": acme_id });
+    let (status, _) = node.post("/api/tracker/custody", &namesake, onward).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let reading = json!({ "batch_id": "N1", "temperature_celsius": 4.0 });
+    let (status, _) = node.post("/api/tracker/conditions", &namesake, reading).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, body) = node.post("/api/tracker/custody/acknowledge", &ward, json!({ "batch_id": "N1" })).await;
+    assert!(status.is_success(), "{}", body);
+
+    // Nor may it touch the stock.
+    let split = json!({ "batch_id": "N1", "new_batch_id": "N1-a", "quantity": 1 });
+    let (status, _) = node.post("/api/inventory/split", &namesake, split).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let destroy = json!({ "batch_id": "N1", "quantity": 1, "reason": "broken" });
+    let (status, _) = node.post("/api/inventory/destroy", &namesake, destroy).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let count = json!({ "counts": [{ "batch_id": "N1", "counted_quantity": 0, "reason": "missing" }] });
+    let (status, _) = node.post("/api/inventory/reconcile", &namesake, count).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn a_regulator_is_answered_only_once_the_access_is_logged() {
+    let nodes = start_cluster(1, &[]).await;
+    let node = &nodes[0];
+    let regulator = node.regulator().await;
+    let (status, _) = node.get("/api/admin/forks", &regulator).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Stand in for a full disk: the log refuses new entries.
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", node.dir.join("node.db").display())).await.unwrap();
+    sqlx::query(
+        "CREATE TRIGGER refuse_access_log BEFORE INSERT ON regulator_access_log
+         BEGIN SELECT RAISE(ABORT, 'database or disk is full'); END",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let (status, body) = node.get("/api/admin/forks", &regulator).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", body);
+    assert!(body.get("canonical_tip").is_none(), "{}", body);
+}
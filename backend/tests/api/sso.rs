@@ -0,0 +1,148 @@
+use reqwest::{redirect::Policy, Client, Method, StatusCode, Url};
+use serde_json::{json, Value};
+use std::process::{Child, Command, Stdio};
+
+use crate::common::{free_port, start_cluster, wait_until, TestNode};
+
+const CLIENT_ID: &str = "pharmachain";
+
+/// The `mock_idp` binary on a free port, stopped when dropped.
+struct MockIdp {
+    issuer: String,
+    child: Child,
+}
+
+impl Drop for MockIdp {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl MockIdp {
+    async fn start() -> Self {
+        let addr = format!("127.0.0.1:{}", free_port());
+        let child = Command::new(env!("CARGO_BIN_EXE_mock_idp"))
+            .env("MOCK_IDP_ADDR", &addr)
+            .env("MOCK_IDP_CLIENT_ID", CLIENT_ID)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("start mock_idp");
+        let idp = Self { issuer: format!("http://{}", addr), child };
+        // Generating its RSA key takes a while in a debug build.
+        wait_until(120, "mock_idp to start", || async {
+            Client::new()
+                .get(format!("{}/.well-known/openid-configuration", idp.issuer))
+                .send()
+                .await
+                .is_ok()
+        })
+        .await;
+        idp
+    }
+}
+
+/// A backend node relying on `idp`, mapping the `pharmacists` group to the hospital role.
+async fn relying_node(idp: &MockIdp) -> Vec<TestNode> {
+    start_cluster(
+        1,
+        &[
+            ("OIDC_ISSUER", &idp.issuer),
+            ("OIDC_CLIENT_ID", CLIENT_ID),
+            ("OIDC_REDIRECT_URI", "http://127.0.0.1/api/sso/callback"),
+            ("OIDC_ROLE_MAP", "pharmacists=hospital"),
+            ("OIDC_DEFAULT_ROLE", ""),
+        ],
+    )
+    .await
+}
+
+/// `url` with the query parameter `key` set to `value`.
+fn with_param(url: &Url, key: &str, value: &str) -> Url {
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(k, _)| k != key)
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    let mut url = url.clone();
+    url.query_pairs_mut().clear().extend_pairs(pairs).append_pair(key, value);
+    url
+}
+
+/// Starts a login on `node` and plays the browser at the provider, after `tamper` has had
+/// its way with the authorization URL. Returns the `code` and `state` the provider sent back.
+async fn authorize(node: &TestNode, tamper: impl FnOnce(Url) -> Url) -> (String, String) {
+    let (status, login) = node.call(Method::GET, "/api/sso/login", None, None).await;
+    assert_eq!(status, StatusCode::OK, "{}", login);
+    let url = Url::parse(login["authorization_url"].as_str().unwrap()).unwrap();
+    let url = with_param(&url, "login_hint", "ana@hospital.test");
+    let url = tamper(with_param(&url, "groups", "pharmacists"));
+
+    let browser = Client::builder().redirect(Policy::none()).build().unwrap();
+    let response = browser.get(url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let back = Url::parse(response.headers()["location"].to_str().unwrap()).unwrap();
+    let param = |key: &str| back.query_pairs().find(|(k, _)| k == key).unwrap().1.into_owned();
+    (param("code"), param("state"))
+}
+
+async fn callback(node: &TestNode, code: &str, state: &str) -> (StatusCode, Value) {
+    let query = format!("/api/sso/callback?code={}&state={}", code, state);
+    node.call(Method::GET, &query, None, None).await
+}
+
+#[tokio::test]
+async fn pkce_login_through_the_mock_provider() {
+    let idp = MockIdp::start().await;
+    let nodes = relying_node(&idp).await;
+    let node = &nodes[0];
+
+    let (code, state) = authorize(node, |url| url).await;
+    let (status, login) = callback(node, &code, &state).await;
+    assert_eq!(status, StatusCode::OK, "{}", login);
+    assert_eq!(login["user"], json!("ana"));
+    let (status, me) = node.get("/api/me", login["token"].as_str().unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["role"], json!("hospital"), "{}", me);
+
+    // The state is spent, and so is the code.
+    let (status, _) = callback(node, &code, &state).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn a_callback_with_another_state_is_refused() {
+    let idp = MockIdp::start().await;
+    let nodes = relying_node(&idp).await;
+    let node = &nodes[0];
+
+    let (code, _) = authorize(node, |url| url).await;
+    let (status, body) = callback(node, &code, "not-the-state-we-issued").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.as_str().unwrap().contains("Unknown or expired login state"), "{}", body);
+}
+
+#[tokio::test]
+async fn an_id_token_for_another_nonce_is_refused() {
+    let idp = MockIdp::start().await;
+    let nodes = relying_node(&idp).await;
+    let node = &nodes[0];
+
+    let (code, state) = authorize(node, |url| with_param(&url, "nonce", "replayed-nonce")).await;
+    let (status, body) = callback(node, &code, &state).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(body.as_str().unwrap().contains("nonce does not match"), "{}", body);
+}
+
+#[tokio::test]
+async fn a_code_issued_for_another_verifier_is_refused() {
+    let idp = MockIdp::start().await;
+    let nodes = relying_node(&idp).await;
+    let node = &nodes[0];
+
+    let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+    let (code, state) = authorize(node, |url| with_param(&url, "code_challenge", challenge)).await;
+    let (status, body) = callback(node, &code, &state).await;
+    assert!(status.is_client_error() || status.is_server_error(), "{} {}", status, body);
+    assert!(body.as_str().unwrap().contains("PKCE"), "{}", body);
+}
@@ -0,0 +1,125 @@
+use axum::{body::Bytes, extract::State, routing::post, Router};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use crate::common::{scratch_dir, start_cluster, wait_until, TestNode};
+
+const TSA_CONFIG: &str = "[ tsa ]
+default_tsa = tsa_config
+[ tsa_config ]
+serial = ./serial
+signer_digest = sha256
+default_policy = 1.2.3.4.1
+digests = sha256
+accuracy = secs:1
+ess_cert_id_alg = sha256
+";
+
+fn openssl(dir: &Path, args: &[&str]) {
+    let output = Command::new(std::env::var("OPENSSL_BIN").unwrap_or_else(|_| "openssl".to_string()))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .expect("openssl on PATH or OPENSSL_BIN");
+    assert!(output.status.success(), "openssl {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+}
+
+/// A self-signed timestamping certificate and key in `dir`, as `<name>.pem` and `<name>.key`.
+fn tsa_certificate(dir: &Path, name: &str) -> PathBuf {
+    openssl(
+        dir,
+        &[
+            "req", "-x509", "-newkey", "rsa:2048", "-nodes", "-days", "2",
+            "-keyout", &format!("{}.key", name), "-out", &format!("{}.pem", name),
+            "-subj", &format!("/CN={}", name), "-addext", "extendedKeyUsage=critical,timeStamping",
+        ],
+    );
+    dir.join(format!("{}.pem", name))
+}
+
+/// A local RFC 3161 TSA: `openssl ts -reply` behind an HTTP endpoint, signing as `tsa`.
+/// Returns its URL and the certificate it signs with.
+async fn start_tsa() -> (String, PathBuf) {
+    let dir = scratch_dir("tsa");
+    std::fs::write(dir.join("tsa.cnf"), TSA_CONFIG).unwrap();
+    std::fs::write(dir.join("serial"), "01\n").unwrap();
+    let certificate = tsa_certificate(&dir, "tsa");
+
+    async fn reply(State(dir): State<Arc<PathBuf>>, query: Bytes) -> Vec<u8> {
+        let name = uuid::Uuid::new_v4().simple().to_string();
+        std::fs::write(dir.join(format!("{}.tsq", name)), &query).unwrap();
+        openssl(
+            &dir,
+            &[
+                "ts", "-reply", "-config", "tsa.cnf", "-queryfile", &format!("{}.tsq", name),
+                "-signer", "tsa.pem", "-inkey", "tsa.key", "-out", &format!("{}.tsr", name),
+            ],
+        );
+        std::fs::read(dir.join(format!("{}.tsr", name))).unwrap()
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let app = Router::new().route("/", post(reply)).with_state(Arc::new(dir));
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, certificate)
+}
+
+/// Creates a sealed batch on `node` and returns a token to read it with.
+async fn batch(node: &TestNode) -> String {
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+    let aspirin = node.product("Aspirin", None).await;
+    node.sealed_batch(&acme, json!({ "batch_id": "T1", "gtin": aspirin, "source": "Plant", "destination": acme_id }))
+        .await;
+    acme
+}
+
+async fn stamped(node: &TestNode, token: &str) -> Value {
+    wait_until(30, "a timestamp token", || async {
+        !node.get("/api/tracker/verify/T1", token).await.1["timestamp"].is_null()
+    })
+    .await;
+    node.get("/api/tracker/verify/T1", token).await.1
+}
+
+#[tokio::test]
+async fn tokens_from_the_configured_tsa_verify() {
+    let (url, certificate) = start_tsa().await;
+    let certificate = certificate.to_str().unwrap();
+    let nodes = start_cluster(1, &[("TSA_URL", &url), ("TSA_CERT_PATH", certificate), ("TSA_INTERVAL_SECS", "1")]).await;
+    let token = batch(&nodes[0]).await;
+
+    let verification = stamped(&nodes[0], &token).await;
+    assert_eq!(verification["valid"], json!(true), "{}", verification);
+    assert_eq!(verification["timestamp"]["valid"], json!(true), "{}", verification);
+    assert_eq!(verification["timestamp"]["trusted"], json!(true));
+}
+
+#[tokio::test]
+async fn tokens_without_a_trust_anchor_are_unverified() {
+    let (url, _) = start_tsa().await;
+    let nodes = start_cluster(1, &[("TSA_URL", &url), ("TSA_INTERVAL_SECS", "1")]).await;
+    let token = batch(&nodes[0]).await;
+
+    // The token's own certificate vouches for it, which anyone could arrange.
+    let verification = stamped(&nodes[0], &token).await;
+    assert_eq!(verification["timestamp"]["valid"], json!(false), "{}", verification);
+    assert_eq!(verification["timestamp"]["trusted"], json!(false));
+    assert!(verification["timestamp"]["message"].as_str().unwrap().contains("unverified"));
+    // It does not make the batch itself invalid.
+    assert_eq!(verification["valid"], json!(true), "{}", verification);
+}
+
+#[tokio::test]
+async fn tokens_from_another_tsa_are_refused() {
+    let (url, _) = start_tsa().await;
+    let other = tsa_certificate(&scratch_dir("other-tsa"), "other");
+    let nodes = start_cluster(1, &[("TSA_URL", &url), ("TSA_CERT_PATH", other.to_str().unwrap()), ("TSA_INTERVAL_SECS", "1")]).await;
+    let token = batch(&nodes[0]).await;
+
+    wait_until(30, "the token to be refused", || async { nodes[0].log().contains("TSA signature does not verify") }).await;
+    let verification = nodes[0].get("/api/tracker/verify/T1", &token).await.1;
+    assert!(verification["timestamp"].is_null(), "{}", verification);
+}
@@ -0,0 +1,54 @@
+use serde_json::json;
+use sqlx::SqlitePool;
+
+use crate::common::{scratch_dir, start_cluster};
+
+/// The tables as the first release created them.
+const FIRST_RELEASE_SCHEMA: [&str; 3] = [
+    "CREATE TABLE users (id TEXT PRIMARY KEY, username TEXT NOT NULL, email TEXT NOT NULL UNIQUE, password TEXT NOT NULL, role TEXT NOT NULL)",
+    "CREATE TABLE medicine_batches (
+        id INTEGER PRIMARY KEY AUTOINCREMENT, batch_id TEXT NOT NULL UNIQUE, medicine_name TEXT NOT NULL, source TEXT NOT NULL,
+        destination TEXT NOT NULL, timestamp TEXT NOT NULL, hash TEXT NOT NULL, previous_hash TEXT NOT NULL, signature TEXT NOT NULL,
+        public_key TEXT NOT NULL
+    )",
+    "CREATE TABLE onchain_batches (batch_id TEXT PRIMARY KEY, batch_hash TEXT NOT NULL, merkle_root TEXT NOT NULL, timestamp TEXT NOT NULL)",
+];
+
+/// A database in the first release's schema, holding one user with a plaintext password.
+/// Returns its `DATABASE_URL`.
+async fn first_release_database() -> String {
+    let url = format!("sqlite://{}?mode=rwc", scratch_dir("legacy").join("node.db").display());
+    let pool = SqlitePool::connect(&url).await.unwrap();
+    for statement in FIRST_RELEASE_SCHEMA {
+        sqlx::query(statement).execute(&pool).await.unwrap();
+    }
+    sqlx::query("INSERT INTO users VALUES ('legacy-1', 'nurse', 'nurse@test', 'old-password', 'hospital')")
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+    url
+}
+
+#[tokio::test]
+async fn a_first_release_database_upgrades_in_place() {
+    let url = first_release_database().await;
+    let nodes = start_cluster(1, &[("DATABASE_URL", &url)]).await;
+    let node = &nodes[0];
+
+    let (status, login) = node.login("nurse@test", "old-password").await;
+    assert!(status.is_success(), "{}", login);
+    assert_eq!(login["role"], json!("hospital"), "{}", login);
+    let pool = SqlitePool::connect(&url).await.unwrap();
+    let (stored,): (String,) = sqlx::query_as("SELECT password FROM users WHERE id = 'legacy-1'").fetch_one(&pool).await.unwrap();
+    assert!(stored.starts_with("$argon2id$"), "{}", stored);
+
+    // Batches are recorded and sealed into the upgraded tables.
+    let (acme, acme_id) = node.organization("company", "Acme").await;
+    let aspirin = node.product("Aspirin", None).await;
+    let batch = json!({ "batch_id": "U1", "gtin": aspirin, "quantity": 10, "source": "Plant", "destination": acme_id });
+    node.sealed_batch(&acme, batch).await;
+    let (status, stock) = node.get("/api/inventory", &acme).await;
+    assert!(status.is_success(), "{}", stock);
+    assert_eq!(stock["batches"][0]["batch_id"], json!("U1"), "{}", stock);
+}